@@ -0,0 +1,500 @@
+/*
+ * Named actions bound to whatever the player likes. Ditties ask whether "pan_left" is
+ * held or "pause" was just pressed instead of looking for particular keys, so controls
+ * can be changed without touching them.
+ *
+ * Bindings live in a text file, one action per line with its bindings separated by
+ * commas. A binding is one or more triggers joined by `+`, all of which must be held:
+ *
+ *     zoom_in = wheel_up, =, pad_rb
+ *     undo = ctrl+z
+ *     drag = mouse_middle, space+mouse_left
+ *
+ * Triggers are keys (by character, or `space`, `escape`, `f1`...), `mouse_left`,
+ * `mouse_middle`, `mouse_right`, `mouse4`..., `wheel_up`, `wheel_down` and controller
+ * buttons (`pad_a`, `pad_start`, `pad_lb`, `pad_up`...). `shift`, `ctrl` and `alt` in a
+ * binding with other triggers are modifiers, which must match exactly: `z` does not fire
+ * while ctrl is down. Actions missing from the file keep their default bindings.
+ */
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+use input::InputEvent;
+use input::Key;
+use input::Modifiers;
+use input::MouseButton;
+use input::PadButton;
+
+pub const BINDINGS_CONFIG: &'static str = "bindings.cfg";
+
+const DEFAULT_BINDINGS: &'static str = "\
+pan_left = left, a, pad_left
+pan_right = right, d, pad_right
+pan_up = up, w, pad_up
+pan_down = down, s, pad_down
+drag = mouse_middle, space+mouse_left
+zoom_in = wheel_up, =, pad_rb
+zoom_out = wheel_down, -, pad_lb
+build = mouse_left, return, pad_a
+cancel = mouse_right, backspace, pad_b
+pause = escape, pad_start
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Trigger {
+    Key(Key),
+    Mouse(MouseButton),
+    /*
+     * Wheel clicks are over as soon as they happen, so they can start an action but
+     * never hold one down.
+     */
+    WheelUp,
+    WheelDown,
+    Pad(PadButton)
+}
+
+impl Trigger {
+    pub fn name(&self) -> String {
+        match *self {
+            Trigger::Key(key) => key.name(),
+            Trigger::Mouse(MouseButton::Left) => "mouse_left".to_string(),
+            Trigger::Mouse(MouseButton::Middle) => "mouse_middle".to_string(),
+            Trigger::Mouse(MouseButton::Right) => "mouse_right".to_string(),
+            Trigger::Mouse(MouseButton::Other(n)) => format!("mouse{}", n),
+            Trigger::WheelUp => "wheel_up".to_string(),
+            Trigger::WheelDown => "wheel_down".to_string(),
+            Trigger::Pad(button) => format!("pad_{}", button.name())
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Trigger> {
+        match name {
+            "mouse_left" => Some(Trigger::Mouse(MouseButton::Left)),
+            "mouse_middle" => Some(Trigger::Mouse(MouseButton::Middle)),
+            "mouse_right" => Some(Trigger::Mouse(MouseButton::Right)),
+            "wheel_up" => Some(Trigger::WheelUp),
+            "wheel_down" => Some(Trigger::WheelDown),
+            _ if name.starts_with("pad_") => PadButton::from_name(&name[4..]).map(Trigger::Pad),
+            _ if name.starts_with("mouse") && name.len() > 5 => {
+                name[5..].parse::<u8>().ok().map(|n| Trigger::Mouse(MouseButton::Other(n)))
+            },
+            _ => Key::from_name(name).map(Trigger::Key)
+        }
+    }
+
+    fn modifier(&self) -> Option<Modifiers> {
+        let mut m = Modifiers::default();
+        match *self {
+            Trigger::Key(Key::Shift) => m.shift = true,
+            Trigger::Key(Key::Ctrl) => m.ctrl = true,
+            Trigger::Key(Key::Alt) => m.alt = true,
+            _ => return None
+        }
+        Some(m)
+    }
+}
+
+/*
+ * Triggers that fire an action when all are held, with exactly these modifiers.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub triggers: Vec<Trigger>,
+    pub modifiers: Modifiers
+}
+
+impl Binding {
+    pub fn new(trigger: Trigger) -> Binding {
+        Binding { triggers: vec![trigger], modifiers: Modifiers::default() }
+    }
+
+    /*
+     * Adds another trigger to the chord.
+     */
+    pub fn and(mut self, trigger: Trigger) -> Binding {
+        self.triggers.push(trigger);
+        self
+    }
+
+    pub fn with(mut self, modifiers: Modifiers) -> Binding {
+        self.modifiers = modifiers;
+        self
+    }
+
+    pub fn parse(text: &str) -> Result<Binding, String> {
+        let mut triggers = Vec::new();
+        for name in text.split('+').map(|n| n.trim()) {
+            match Trigger::from_name(name) {
+                Some(trigger) => triggers.push(trigger),
+                None => return Err(format!("unknown trigger: {}", name))
+            }
+        }
+        // Modifier keys are modifiers unless they are all there is.
+        let mut modifiers = Modifiers::default();
+        if triggers.iter().any(|t| t.modifier().is_none()) {
+            triggers.retain(|t| match t.modifier() {
+                Some(m) => {
+                    modifiers.shift |= m.shift;
+                    modifiers.ctrl |= m.ctrl;
+                    modifiers.alt |= m.alt;
+                    false
+                },
+                None => true
+            });
+        }
+        Ok(Binding { triggers: triggers, modifiers: modifiers })
+    }
+
+    fn size(&self) -> usize {
+        self.triggers.len() + self.modifiers.shift as usize + self.modifiers.ctrl as usize + self.modifiers.alt as usize
+    }
+
+    /*
+     * Whether the modifiers held, less any the binding uses as triggers, are the ones it
+     * wants.
+     */
+    fn modifiers_match(&self, held: Modifiers) -> bool {
+        let mut held = held;
+        for m in self.triggers.iter().filter_map(|t| t.modifier()) {
+            held.shift &= !m.shift;
+            held.ctrl &= !m.ctrl;
+            held.alt &= !m.alt;
+        }
+        held == self.modifiers
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names = Vec::new();
+        if self.modifiers.ctrl {
+            names.push("ctrl".to_string());
+        }
+        if self.modifiers.alt {
+            names.push("alt".to_string());
+        }
+        if self.modifiers.shift {
+            names.push("shift".to_string());
+        }
+        names.extend(self.triggers.iter().map(|t| t.name()));
+        write!(f, "{}", names.join("+"))
+    }
+}
+
+/*
+ * Actions and their bindings, in the order they were first bound.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActionMap {
+    actions: Vec<(String, Vec<Binding>)>
+}
+
+impl ActionMap {
+    pub fn new() -> ActionMap {
+        ActionMap { actions: Vec::new() }
+    }
+
+    pub fn defaults() -> ActionMap {
+        ActionMap::new().merge(DEFAULT_BINDINGS).unwrap()
+    }
+
+    pub fn bind(&mut self, action: &str, binding: Binding) {
+        match self.actions.iter().position(|&(ref a, _)| a == action) {
+            Some(i) => self.actions[i].1.push(binding),
+            None => self.actions.push((action.to_string(), vec![binding]))
+        }
+    }
+
+    /*
+     * Removes every binding for the action, leaving it with none.
+     */
+    pub fn clear(&mut self, action: &str) {
+        match self.actions.iter().position(|&(ref a, _)| a == action) {
+            Some(i) => self.actions[i].1.clear(),
+            None => self.actions.push((action.to_string(), Vec::new()))
+        }
+    }
+
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        self.actions.iter().find(|&&(ref a, _)| a == action).map(|&(_, ref b)| &b[..]).unwrap_or(&[])
+    }
+
+    pub fn actions(&self) -> Vec<&str> {
+        self.actions.iter().map(|&(ref a, _)| &a[..]).collect()
+    }
+
+    /*
+     * Replaces the bindings of each action in the text, keeping the rest.
+     */
+    fn merge(mut self, text: &str) -> Result<ActionMap, String> {
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (action, value) = match line.find('=') {
+                Some(i) => (line[..i].trim(), line[i + 1..].trim()),
+                None => return Err(format!("line {}: expected action = bindings", n + 1))
+            };
+            self.clear(action);
+            for binding in value.split(',').map(|b| b.trim()).filter(|b| !b.is_empty()) {
+                let binding = try!(Binding::parse(binding).map_err(|e| format!("line {}: {}", n + 1, e)));
+                self.bind(action, binding);
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn parse(text: &str) -> Result<ActionMap, String> {
+        ActionMap::defaults().merge(text)
+    }
+
+    /*
+     * Loads the bindings. If there is no file yet the defaults are written to it, as a
+     * starting point for editing, and used.
+     */
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ActionMap, String> {
+        let mut text = String::new();
+        match File::open(path.as_ref()) {
+            Ok(mut f) => try!(f.read_to_string(&mut text).map_err(|e| format!("{}: {}", path.as_ref().display(), e))),
+            Err(_) => {
+                let defaults = ActionMap::defaults();
+                if let Err(e) = defaults.save(path.as_ref()) {
+                    println!("Could not save default key bindings: {}", e);
+                }
+                return Ok(defaults);
+            }
+        };
+        ActionMap::parse(&text).map_err(|e| format!("{}: {}", path.as_ref().display(), e))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        File::create(path.as_ref()).and_then(|mut f| f.write_all(self.to_string().as_bytes()))
+            .map_err(|e| format!("{}: {}", path.as_ref().display(), e))
+    }
+}
+
+impl fmt::Display for ActionMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(ref action, ref bindings) in &self.actions {
+            let bindings: Vec<String> = bindings.iter().map(|b| b.to_string()).collect();
+            try!(write!(f, "{} = {}\n", action, bindings.join(", ")));
+        }
+        Ok(())
+    }
+}
+
+/*
+ * Follows the input a ditty is given and turns it into actions.
+ */
+pub struct Actions {
+    map: ActionMap,
+    held: HashSet<Trigger>
+}
+
+impl Actions {
+    pub fn new(map: ActionMap) -> Actions {
+        Actions { map: map, held: HashSet::new() }
+    }
+
+    pub fn map(&self) -> &ActionMap {
+        &self.map
+    }
+
+    fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            shift: self.held.contains(&Trigger::Key(Key::Shift)),
+            ctrl: self.held.contains(&Trigger::Key(Key::Ctrl)),
+            alt: self.held.contains(&Trigger::Key(Key::Alt))
+        }
+    }
+
+    fn binding_held(&self, binding: &Binding, pressed: Option<Trigger>) -> bool {
+        binding.modifiers_match(self.modifiers())
+            && binding.triggers.iter().all(|t| Some(*t) == pressed || self.held.contains(t))
+    }
+
+    /*
+     * Takes note of an event, returning the actions it starts: those with a binding that
+     * includes the trigger just pressed and whose other triggers are all held.
+     */
+    pub fn apply(&mut self, event: &InputEvent) -> Vec<String> {
+        let pressed = match *event {
+            InputEvent::KeyDown { key, repeat: false, .. } => Some(Trigger::Key(key)),
+            InputEvent::MouseDown { button, .. } => Some(Trigger::Mouse(button)),
+            InputEvent::PadDown { button, .. } => Some(Trigger::Pad(button)),
+            InputEvent::Wheel { dy, .. } if dy > 0 => Some(Trigger::WheelUp),
+            InputEvent::Wheel { dy, .. } if dy < 0 => Some(Trigger::WheelDown),
+            InputEvent::KeyUp { key, .. } => {
+                self.held.remove(&Trigger::Key(key));
+                None
+            },
+            InputEvent::MouseUp { button, .. } => {
+                self.held.remove(&Trigger::Mouse(button));
+                None
+            },
+            InputEvent::PadUp { button, .. } => {
+                self.held.remove(&Trigger::Pad(button));
+                None
+            },
+            InputEvent::Focus(false) => {
+                self.held.clear();
+                None
+            },
+            InputEvent::PadRemoved(_) => {
+                self.held.retain(|t| match *t {
+                    Trigger::Pad(_) => false,
+                    _ => true
+                });
+                None
+            },
+            _ => None
+        };
+        let pressed = match pressed {
+            Some(trigger) => trigger,
+            None => return Vec::new()
+        };
+        // Where chords overlap, as space+mouse_left and mouse_left do, only the most
+        // specific of those that match counts.
+        let mut started = Vec::new();
+        let mut best = 0;
+        for &(ref action, ref bindings) in &self.map.actions {
+            let size = bindings.iter()
+                .filter(|b| b.triggers.contains(&pressed) && self.binding_held(b, Some(pressed)))
+                .map(|b| b.size())
+                .max();
+            match size {
+                Some(size) if size > best => {
+                    best = size;
+                    started = vec![action.clone()];
+                },
+                Some(size) if size == best => started.push(action.clone()),
+                _ => {}
+            }
+        }
+        if pressed != Trigger::WheelUp && pressed != Trigger::WheelDown {
+            self.held.insert(pressed);
+        }
+        started
+    }
+
+    /*
+     * Forgets everything held down, for when input stops arriving, as it does for a
+     * ditty covered by another.
+     */
+    pub fn release_all(&mut self) {
+        self.held.clear();
+    }
+
+    /*
+     * Whether a longer binding held right now, for any action, takes in all of this
+     * one's triggers, as space+mouse_left does mouse_left.
+     */
+    fn overridden(&self, binding: &Binding) -> bool {
+        self.map.actions.iter().flat_map(|&(_, ref bindings)| bindings.iter()).any(|other| {
+            other.size() > binding.size()
+                && binding.triggers.iter().all(|t| other.triggers.contains(t))
+                && self.binding_held(other, None)
+        })
+    }
+
+    /*
+     * Whether any of the action's bindings is held down right now. As when they start,
+     * only the most specific of overlapping chords counts.
+     */
+    pub fn is_down(&self, action: &str) -> bool {
+        self.map.bindings(action).iter().any(|b| self.binding_held(b, None) && !self.overridden(b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use input::Modifiers;
+
+    fn key(key: Key, down: bool) -> InputEvent {
+        if down {
+            InputEvent::KeyDown { key: key, modifiers: Modifiers::default(), repeat: false }
+        } else {
+            InputEvent::KeyUp { key: key, modifiers: Modifiers::default() }
+        }
+    }
+
+    #[test]
+    fn released_actions_stay_up() {
+        let mut actions = Actions::new(ActionMap::defaults());
+        assert_eq!(actions.apply(&key(Key::Right, true)), vec!["pan_right".to_string()]);
+        assert!(actions.is_down("pan_right"));
+        actions.release_all();
+        assert!(!actions.is_down("pan_right"));
+        // The key going up later, wherever it went, changes nothing.
+        assert!(actions.apply(&key(Key::Right, false)).is_empty());
+        assert!(!actions.is_down("pan_right"));
+    }
+
+    fn mouse(button: MouseButton, down: bool) -> InputEvent {
+        if down {
+            InputEvent::MouseDown { button: button, x: 0, y: 0 }
+        } else {
+            InputEvent::MouseUp { button: button, x: 0, y: 0 }
+        }
+    }
+
+    #[test]
+    fn modifiers_must_match_exactly() {
+        let mut map = ActionMap::new();
+        map.bind("undo", Binding::parse("ctrl+z").unwrap());
+        map.bind("zap", Binding::parse("z").unwrap());
+        let mut actions = Actions::new(map);
+        assert_eq!(actions.apply(&key(Key::Char('z'), true)), vec!["zap".to_string()]);
+        assert!(actions.is_down("zap") && !actions.is_down("undo"));
+        actions.apply(&key(Key::Char('z'), false));
+
+        actions.apply(&key(Key::Ctrl, true));
+        assert_eq!(actions.apply(&key(Key::Char('z'), true)), vec!["undo".to_string()]);
+        assert!(actions.is_down("undo") && !actions.is_down("zap"));
+    }
+
+    #[test]
+    fn chords_beat_the_bindings_inside_them() {
+        let mut actions = Actions::new(ActionMap::defaults());
+        assert!(actions.apply(&key(Key::Space, true)).is_empty());
+        assert_eq!(actions.apply(&mouse(MouseButton::Left, true)), vec!["drag".to_string()]);
+        assert!(actions.is_down("drag"));
+        assert!(!actions.is_down("build"));
+
+        // Letting go of space first leaves a plain left button held.
+        actions.apply(&key(Key::Space, false));
+        assert!(!actions.is_down("drag"));
+        assert!(actions.is_down("build"));
+        actions.apply(&mouse(MouseButton::Left, false));
+        assert!(!actions.is_down("build"));
+
+        // Letting go of the button first leaves nothing.
+        actions.apply(&key(Key::Space, true));
+        actions.apply(&mouse(MouseButton::Left, true));
+        actions.apply(&mouse(MouseButton::Left, false));
+        assert!(!actions.is_down("drag") && !actions.is_down("build"));
+
+        // Without space, the button builds.
+        actions.apply(&key(Key::Space, false));
+        assert_eq!(actions.apply(&mouse(MouseButton::Left, true)), vec!["build".to_string()]);
+        assert!(actions.is_down("build") && !actions.is_down("drag"));
+    }
+
+    #[test]
+    fn missing_bindings_are_written_out() {
+        let path = ::std::env::temp_dir().join("tycoon-bindings-test.cfg");
+        let _ = fs::remove_file(&path);
+        assert_eq!(ActionMap::load(&path), Ok(ActionMap::defaults()));
+        assert!(path.exists());
+        assert_eq!(ActionMap::load(&path), Ok(ActionMap::defaults()));
+        let _ = fs::remove_file(&path);
+    }
+}
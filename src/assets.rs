@@ -0,0 +1,689 @@
+/*
+ * Loads assets by name from under one root directory and shares them. Asking for the
+ * same name twice gives the same data, loaded once, behind a handle of its type:
+ * `Handle<SvgDocument>`, `Handle<Texture>` or `Handle<Sound>`.
+ *
+ * Handles are reference counted. Once the last handle to an asset is dropped the asset
+ * is unloaded, and the next request loads it afresh. Textures live in the backend, so
+ * theirs are freed by `update`, which the game loop calls once a frame, and not while
+ * anything still holds one through `Handle::get`.
+ *
+ * Names use forward slashes whatever the platform, e.g. "sounds/click.wav", and may not
+ * reach outside the root.
+ *
+ * While developing, the root can be watched so that documents and textures are reloaded
+ * when their files are saved. A reload swaps the data behind existing handles, so
+ * anything drawing from a handle sees the change on its next frame; anything built from
+ * the data can rebuild when the handle's version goes up. A file that fails to load, as
+ * one half-written might, leaves the old data in place. Each failure is reported once,
+ * not every time the same name fails again, until the name loads.
+ *
+ * Assets can also be loaded in the background with `preload`: files are read and parsed
+ * on worker threads, and only the upload of images to the backend is left for the main
+ * thread.
+ */
+
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::rc::Weak;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::thread;
+
+use audio::Sound;
+use backend::Backend;
+use backend::TextureId;
+use image::Image;
+use png;
+use svg;
+use svg::SvgDocument;
+use watch::Watcher;
+use wav;
+
+/*
+ * An image uploaded to the backend.
+ */
+pub struct Texture {
+    pub id: TextureId,
+    pub width: u32,
+    pub height: u32
+}
+
+struct Slot<T> {
+    name: String,
+    value: RefCell<Arc<T>>,
+    version: Cell<u64>
+}
+
+pub struct Handle<T> {
+    slot: Rc<Slot<T>>
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Handle<T> {
+        Handle { slot: self.slot.clone() }
+    }
+}
+
+impl<T> Handle<T> {
+    fn new(name: &str, value: T) -> Handle<T> {
+        Handle { slot: Rc::new(Slot { name: name.to_string(), value: RefCell::new(Arc::new(value)), version: Cell::new(0) }) }
+    }
+
+    /*
+     * The asset as it is now. Holding on to what this returns keeps it alive, but it
+     * won't see the asset change. A texture replaced by a reload stays in the backend
+     * until the last of these is dropped.
+     */
+    pub fn get(&self) -> Arc<T> {
+        self.slot.value.borrow().clone()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.slot.name
+    }
+
+    /*
+     * Goes up by one each time the asset is replaced, so things built from it can tell
+     * when to rebuild.
+     */
+    pub fn version(&self) -> u64 {
+        self.slot.version.get()
+    }
+
+    fn replace(&self, value: T) -> Arc<T> {
+        self.slot.version.set(self.slot.version.get() + 1);
+        ::std::mem::replace(&mut *self.slot.value.borrow_mut(), Arc::new(value))
+    }
+}
+
+/*
+ * Loaded assets of one type, by name. Entries whose handles have all gone are left
+ * until the next lookup or collection.
+ */
+struct Cache<T> {
+    entries: HashMap<String, Weak<Slot<T>>>
+}
+
+impl<T> Cache<T> {
+    fn new() -> Cache<T> {
+        Cache { entries: HashMap::new() }
+    }
+
+    fn get(&self, name: &str) -> Option<Handle<T>> {
+        self.entries.get(name).and_then(|w| w.upgrade()).map(|slot| Handle { slot: slot })
+    }
+
+    fn insert(&mut self, handle: &Handle<T>) {
+        self.entries.insert(handle.name().to_string(), Rc::downgrade(&handle.slot));
+    }
+
+    /*
+     * Forgets unreferenced entries, returning their names.
+     */
+    fn collect(&mut self) -> Vec<String> {
+        let dead: Vec<String> = self.entries.iter().filter(|&(_, w)| w.upgrade().is_none()).map(|(n, _)| n.clone()).collect();
+        for name in &dead {
+            self.entries.remove(name);
+        }
+        dead
+    }
+
+    fn len(&self) -> usize {
+        self.entries.values().filter(|w| w.upgrade().is_some()).count()
+    }
+}
+
+struct Store {
+    root: PathBuf,
+    documents: Cache<SvgDocument>,
+    textures: Cache<Texture>,
+    /*
+     * The current texture of each name, so it can be freed after its handles have gone.
+     */
+    uploaded: HashMap<String, Arc<Texture>>,
+    /*
+     * Textures replaced or forgotten, freed once nothing else holds them.
+     */
+    retired: Vec<Arc<Texture>>,
+    sounds: Cache<Sound>,
+    watcher: Option<Watcher>,
+    /*
+     * Names whose last load failed, and which have been reported as failing.
+     */
+    failing: HashSet<String>
+}
+
+impl Store {
+    /*
+     * Notes a texture as the current one for its name, retiring whatever was before,
+     * such as one dropped but not yet collected or one replaced by a reload.
+     */
+    fn track(&mut self, name: &str, texture: Arc<Texture>) {
+        if let Some(old) = self.uploaded.insert(name.to_string(), texture) {
+            self.retired.push(old);
+        }
+    }
+
+    /*
+     * Reports a failure to load, unless the name was already failing. Returns whether
+     * it was reported.
+     */
+    fn failed(&mut self, name: &str, error: &str) -> bool {
+        let new = self.failing.insert(name.to_string());
+        if new {
+            println!("Could not load {}", error);
+        }
+        new
+    }
+
+    fn succeeded(&mut self, name: &str) {
+        self.failing.remove(name);
+    }
+}
+
+/*
+ * The asset store, shared: clones all see the same assets.
+ */
+#[derive(Clone)]
+pub struct Assets {
+    store: Rc<RefCell<Store>>
+}
+
+/*
+ * Threads a preload reads and parses on.
+ */
+const WORKERS: usize = 4;
+
+fn load_image(path: &Path) -> Result<Image, String> {
+    let is_png = path.extension().map(|e| e.to_string_lossy().to_lowercase() == "png").unwrap_or(false);
+    if is_png { png::load(path) } else { Image::load_bmp(path) }
+}
+
+impl Assets {
+    pub fn new<P: AsRef<Path>>(root: P) -> Assets {
+        Assets {
+            store: Rc::new(RefCell::new(Store {
+                root: root.as_ref().to_path_buf(),
+                documents: Cache::new(),
+                textures: Cache::new(),
+                uploaded: HashMap::new(),
+                retired: Vec::new(),
+                sounds: Cache::new(),
+                watcher: None,
+                failing: HashSet::new()
+            }))
+        }
+    }
+
+    pub fn root(&self) -> PathBuf {
+        self.store.borrow().root.clone()
+    }
+
+    /*
+     * Moves the root. Assets already loaded stay as they are, and a watch stays on the
+     * old root.
+     */
+    pub fn set_root<P: AsRef<Path>>(&self, root: P) {
+        self.store.borrow_mut().root = root.as_ref().to_path_buf();
+    }
+
+    /*
+     * Starts reloading changed SVG documents and images, in `update`.
+     */
+    pub fn watch(&self) {
+        let watcher = Watcher::new(self.root());
+        println!("Watching {} for changes{}", self.root().display(),
+                 if watcher.is_polling() { ", by polling" } else { "" });
+        self.store.borrow_mut().watcher = Some(watcher);
+    }
+
+    /*
+     * Reloads with a particular watcher, such as one that polls.
+     */
+    pub fn set_watcher(&self, watcher: Watcher) {
+        self.store.borrow_mut().watcher = Some(watcher);
+    }
+
+    /*
+     * The file an asset name refers to. Names stay under the root: absolute names and
+     * ones that climb out with ".." are refused.
+     */
+    pub fn resolve(&self, name: &str) -> Result<PathBuf, String> {
+        let outside = || format!("{}: not an asset name under {}", name, self.root().display());
+        if name.starts_with('/') {
+            return Err(outside());
+        }
+        let mut path = self.root();
+        for part in name.split('/').filter(|p| !p.is_empty() && *p != ".") {
+            let mut components = Path::new(part).components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(_)), None) => path.push(part),
+                _ => return Err(outside())
+            }
+        }
+        Ok(path)
+    }
+
+    /*
+     * Starts loading assets on worker threads, the kind of each going by its extension:
+     * SVG documents, BMP or PNG textures and WAV sounds. Those already loaded are just
+     * kept. They are available as usual, and stay loaded, while the `Loading` is kept.
+     */
+    pub fn preload(&self, names: &[&str]) -> Loading {
+        let mut loading = Loading {
+            assets: self.clone(),
+            total: names.len(),
+            failed: Vec::new(),
+            documents: Vec::new(),
+            textures: Vec::new(),
+            sounds: Vec::new(),
+            results: None
+        };
+        let mut jobs = Vec::new();
+        for &name in names {
+            if loading.keep_loaded(name) {
+                continue;
+            }
+            match self.resolve(name) {
+                Ok(path) => jobs.push((name.to_string(), path)),
+                Err(e) => {
+                    self.store.borrow_mut().failed(name, &e);
+                    loading.failed.push(name.to_string());
+                }
+            }
+        }
+        if jobs.is_empty() {
+            return loading;
+        }
+        let (sender, receiver) = mpsc::channel();
+        let workers = WORKERS.min(jobs.len());
+        let jobs = Arc::new(Mutex::new(jobs));
+        for _ in 0..workers {
+            let jobs = jobs.clone();
+            let sender = sender.clone();
+            thread::spawn(move || {
+                loop {
+                    let job = jobs.lock().unwrap().pop();
+                    let (name, path) = match job {
+                        Some(job) => job,
+                        None => break
+                    };
+                    if sender.send((name, Decoded::load(&path))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        loading.results = Some(receiver);
+        loading
+    }
+
+    pub fn document(&self, name: &str) -> Result<Handle<SvgDocument>, String> {
+        if let Some(handle) = self.store.borrow().documents.get(name) {
+            return Ok(handle);
+        }
+        let path = try!(self.resolve(name));
+        let doc = try!(svg::get_document(&path).map_err(|e| format!("{}: {}", path.display(), e)));
+        let handle = Handle::new(name, doc);
+        self.store.borrow_mut().documents.insert(&handle);
+        Ok(handle)
+    }
+
+    /*
+     * Loads a BMP or PNG, by its extension, into a texture.
+     */
+    pub fn texture(&self, backend: &mut Backend, name: &str) -> Result<Handle<Texture>, String> {
+        if let Some(handle) = self.store.borrow().textures.get(name) {
+            return Ok(handle);
+        }
+        let image = try!(load_image(&try!(self.resolve(name))));
+        let id = try!(backend.create_texture(&image));
+        let handle = Handle::new(name, Texture { id: id, width: image.width, height: image.height });
+        let mut store = self.store.borrow_mut();
+        store.textures.insert(&handle);
+        store.track(name, handle.get());
+        Ok(handle)
+    }
+
+    pub fn sound(&self, name: &str) -> Result<Handle<Sound>, String> {
+        if let Some(handle) = self.store.borrow().sounds.get(name) {
+            return Ok(handle);
+        }
+        let handle = Handle::new(name, try!(wav::load(try!(self.resolve(name)))));
+        self.store.borrow_mut().sounds.insert(&handle);
+        Ok(handle)
+    }
+
+    /*
+     * How many assets are loaded and still referenced.
+     */
+    pub fn loaded(&self) -> usize {
+        let store = self.store.borrow();
+        store.documents.len() + store.textures.len() + store.sounds.len()
+    }
+
+    fn reload(&self, backend: &mut Backend, name: &str) -> Result<bool, String> {
+        let path = try!(self.resolve(name));
+        let (document, texture) = {
+            let store = self.store.borrow();
+            (store.documents.get(name), store.textures.get(name))
+        };
+        if let Some(handle) = document {
+            let doc = try!(svg::get_document(&path).map_err(|e| format!("{}: {}", path.display(), e)));
+            handle.replace(doc);
+        } else if let Some(handle) = texture {
+            let image = try!(load_image(&path));
+            let id = try!(backend.create_texture(&image));
+            handle.replace(Texture { id: id, width: image.width, height: image.height });
+            self.store.borrow_mut().track(name, handle.get());
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /*
+     * Reloads watched assets whose files have changed, then forgets assets nothing refers
+     * to any more, freeing textures no longer in use. Returns how many were forgotten.
+     */
+    pub fn update(&self, backend: &mut Backend) -> usize {
+        let changed = self.store.borrow_mut().watcher.as_mut().map(|w| w.changes()).unwrap_or(Vec::new());
+        for name in changed {
+            match self.reload(backend, &name) {
+                Ok(true) => {
+                    println!("Reloaded {}", name);
+                    self.store.borrow_mut().succeeded(&name);
+                },
+                Ok(false) => (),
+                Err(e) => {
+                    self.store.borrow_mut().failed(&name, &e);
+                }
+            }
+        }
+        let mut store = self.store.borrow_mut();
+        let dead_textures = store.textures.collect();
+        for name in &dead_textures {
+            if let Some(texture) = store.uploaded.remove(name) {
+                store.retired.push(texture);
+            }
+        }
+        let retired = ::std::mem::replace(&mut store.retired, Vec::new());
+        for texture in retired {
+            if Arc::strong_count(&texture) == 1 {
+                backend.destroy_texture(texture.id);
+            } else {
+                store.retired.push(texture);
+            }
+        }
+        dead_textures.len() + store.documents.collect().len() + store.sounds.collect().len()
+    }
+}
+
+/*
+ * An asset as the workers leave it, ready for the cache.
+ */
+enum Decoded {
+    Document(SvgDocument),
+    Image(Image),
+    Sound(Sound)
+}
+
+impl Decoded {
+    fn load(path: &Path) -> Result<Decoded, String> {
+        let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or(String::new());
+        match &extension[..] {
+            "svg" => svg::get_document(path).map(Decoded::Document).map_err(|e| format!("{}: {}", path.display(), e)),
+            "bmp" | "png" => load_image(path).map(Decoded::Image),
+            "wav" => wav::load(path).map(Decoded::Sound),
+            _ => Err(format!("{}: not a kind of asset that can be loaded", path.display()))
+        }
+    }
+}
+
+/*
+ * Assets being loaded by `Assets::preload`. Call `poll` once a frame, on the main thread,
+ * to take in what has finished.
+ */
+pub struct Loading {
+    assets: Assets,
+    total: usize,
+    failed: Vec<String>,
+    documents: Vec<Handle<SvgDocument>>,
+    textures: Vec<Handle<Texture>>,
+    sounds: Vec<Handle<Sound>>,
+    results: Option<Receiver<(String, Result<Decoded, String>)>>
+}
+
+impl Loading {
+    /*
+     * Holds on to an asset if it is loaded already.
+     */
+    fn keep_loaded(&mut self, name: &str) -> bool {
+        let store = self.assets.store.borrow();
+        if let Some(handle) = store.documents.get(name) {
+            self.documents.push(handle);
+        } else if let Some(handle) = store.textures.get(name) {
+            self.textures.push(handle);
+        } else if let Some(handle) = store.sounds.get(name) {
+            self.sounds.push(handle);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /*
+     * Caches what the workers have finished, uploading images as textures. Returns the
+     * progress.
+     */
+    pub fn poll(&mut self, backend: &mut Backend) -> f64 {
+        let mut finished = Vec::new();
+        if let Some(ref results) = self.results {
+            while let Ok(result) = results.try_recv() {
+                finished.push(result);
+            }
+        }
+        for (name, result) in finished {
+            self.take(backend, name, result);
+        }
+        if self.is_done() {
+            self.results = None;
+        }
+        self.progress()
+    }
+
+    fn take(&mut self, backend: &mut Backend, name: String, result: Result<Decoded, String>) {
+        match self.cache(backend, &name, result) {
+            Ok(()) => self.assets.store.borrow_mut().succeeded(&name),
+            Err(e) => {
+                self.assets.store.borrow_mut().failed(&name, &e);
+                self.failed.push(name);
+            }
+        }
+    }
+
+    fn cache(&mut self, backend: &mut Backend, name: &str, result: Result<Decoded, String>) -> Result<(), String> {
+        // Loaded some other way in the meantime.
+        if self.keep_loaded(name) {
+            return Ok(());
+        }
+        let mut store = self.assets.store.borrow_mut();
+        match try!(result) {
+            Decoded::Document(doc) => {
+                let handle = Handle::new(name, doc);
+                store.documents.insert(&handle);
+                self.documents.push(handle);
+            },
+            Decoded::Image(image) => {
+                let id = try!(backend.create_texture(&image));
+                let handle = Handle::new(name, Texture { id: id, width: image.width, height: image.height });
+                store.textures.insert(&handle);
+                store.track(name, handle.get());
+                self.textures.push(handle);
+            },
+            Decoded::Sound(sound) => {
+                let handle = Handle::new(name, sound);
+                store.sounds.insert(&handle);
+                self.sounds.push(handle);
+            }
+        }
+        Ok(())
+    }
+
+    /*
+     * Where the assets go.
+     */
+    pub fn assets(&self) -> &Assets {
+        &self.assets
+    }
+
+    fn loaded(&self) -> usize {
+        self.documents.len() + self.textures.len() + self.sounds.len()
+    }
+
+    /*
+     * How far through it is, from 0 to 1, counting failures as done.
+     */
+    pub fn progress(&self) -> f64 {
+        if self.total == 0 { 1.0 } else { (self.loaded() + self.failed.len()) as f64 / self.total as f64 }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.loaded() + self.failed.len() == self.total
+    }
+
+    /*
+     * Names of the assets that could not be loaded.
+     */
+    pub fn failed(&self) -> &[String] {
+        &self.failed
+    }
+
+    /*
+     * Waits for everything, for when it has to be loaded before going on.
+     */
+    pub fn finish(&mut self, backend: &mut Backend) {
+        while !self.is_done() {
+            let next = match self.results {
+                Some(ref results) => results.recv().ok(),
+                None => None
+            };
+            match next {
+                Some((name, result)) => self.take(backend, name, result),
+                None => break
+            }
+        }
+        self.results = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process;
+    use debug::CountingBackend;
+    use debug::DrawCounters;
+    use software::SoftwareBackend;
+
+    #[test]
+    fn replaced_textures_are_freed_once_unused() {
+        let root = ::std::env::temp_dir().join(format!("tycoon-assets-test-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        png::save(&Image::new(4, 3), root.join("pic.png")).unwrap();
+        let assets = Assets::new(&root);
+        let mut backend = SoftwareBackend::new(8, 8);
+        let handle = assets.texture(&mut backend, "pic.png").unwrap();
+        let held = handle.get();
+
+        png::save(&Image::new(5, 6), root.join("pic.png")).unwrap();
+        assert_eq!(assets.reload(&mut backend, "pic.png"), Ok(true));
+        assets.update(&mut backend);
+        assert_eq!((handle.get().width, handle.get().height), (5, 6));
+        // Still held, so still there to draw.
+        assert_eq!(backend.texture_size(held.id), Some((4, 3)));
+
+        drop(held);
+        assets.update(&mut backend);
+        assert_eq!(backend.texture_size(handle.get().id), Some((5, 6)));
+        let old = TextureId(0);
+        assert!(handle.get().id != old);
+        assert_eq!(backend.texture_size(old), None);
+
+        let current = handle.get().id;
+        drop(handle);
+        assets.update(&mut backend);
+        assert_eq!(backend.texture_size(current), None);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn names_cannot_leave_the_root() {
+        let root = ::std::env::temp_dir().join(format!("tycoon-assets-names-{}", process::id()));
+        let assets = Assets::new(&root);
+        assert_eq!(assets.resolve("sounds/click.wav"), Ok(root.join("sounds").join("click.wav")));
+        assert_eq!(assets.resolve("./sounds//click.wav"), Ok(root.join("sounds").join("click.wav")));
+        for name in &["../secret.wav", "sounds/../../secret.wav", "/etc/passwd", ".."] {
+            assert!(assets.resolve(name).is_err(), "{}", name);
+        }
+        assert!(assets.sound("../secret.wav").is_err());
+
+        let loading = assets.preload(&["../secret.wav"]);
+        assert!(loading.is_done());
+        assert_eq!(loading.failed(), &["../secret.wav".to_string()][..]);
+    }
+
+    #[test]
+    fn counted_textures_follow_reloads_and_frees() {
+        let root = ::std::env::temp_dir().join(format!("tycoon-assets-count-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        png::save(&Image::new(4, 3), root.join("pic.png")).unwrap();
+        let assets = Assets::new(&root);
+        let mut software = SoftwareBackend::new(8, 8);
+        let mut counters = DrawCounters::default();
+        let handle = assets.texture(&mut CountingBackend::new(&mut software, &mut counters), "pic.png").unwrap();
+        assert_eq!(counters.textures, 1);
+
+        assert_eq!(assets.reload(&mut CountingBackend::new(&mut software, &mut counters), "pic.png"), Ok(true));
+        assert_eq!(counters.textures, 2);
+        assets.update(&mut CountingBackend::new(&mut software, &mut counters));
+        assert_eq!(counters.textures, 1);
+
+        drop(handle);
+        assets.update(&mut CountingBackend::new(&mut software, &mut counters));
+        assert_eq!(counters.textures, 0);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn failures_are_reported_once_until_they_load() {
+        let root = ::std::env::temp_dir().join(format!("tycoon-assets-failing-{}", process::id()));
+        let assets = Assets::new(&root);
+        let mut backend = SoftwareBackend::new(8, 8);
+        for _ in 0..3 {
+            assets.preload(&["missing.png"]).finish(&mut backend);
+        }
+        assert!(assets.store.borrow().failing.contains("missing.png"));
+        assert!(!assets.store.borrow_mut().failed("missing.png", "again"));
+
+        fs::create_dir_all(&root).unwrap();
+        png::save(&Image::new(2, 2), root.join("missing.png")).unwrap();
+        let mut loading = assets.preload(&["missing.png"]);
+        loading.finish(&mut backend);
+        assert!(loading.failed().is_empty());
+        assert!(assets.store.borrow().failing.is_empty());
+        assert!(assets.store.borrow_mut().failed("missing.png", "once more"));
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,260 @@
+/*
+ * Texture atlases: many small images packed into a few large textures, so maps made of
+ * thousands of tiles can be drawn without switching textures for each one.
+ *
+ * Packing uses the MaxRects algorithm with the best short side fit heuristic. Every
+ * sprite is surrounded by padding filled with copies of its edge pixels, so filtering
+ * at the edge of a sub-rectangle never picks up a neighbour's pixels.
+ */
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use backend::Backend;
+use backend::Rect;
+use backend::TextureId;
+use image::Image;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Area {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32
+}
+
+impl Area {
+    fn contains(&self, o: &Area) -> bool {
+        o.x >= self.x && o.y >= self.y && o.x + o.w <= self.x + self.w && o.y + o.h <= self.y + self.h
+    }
+
+    fn overlaps(&self, o: &Area) -> bool {
+        o.x < self.x + self.w && o.x + o.w > self.x && o.y < self.y + self.h && o.y + o.h > self.y
+    }
+}
+
+/*
+ * Allocates rectangles within a fixed size bin.
+ */
+pub struct Packer {
+    width: u32,
+    height: u32,
+    free: Vec<Area>
+}
+
+impl Packer {
+    pub fn new(width: u32, height: u32) -> Packer {
+        Packer { width: width, height: height, free: vec![Area { x: 0, y: 0, w: width, h: height }] }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn reset(&mut self) {
+        self.free = vec![Area { x: 0, y: 0, w: self.width, h: self.height }];
+    }
+
+    /*
+     * Finds room for a w x h rectangle, returning its top-left corner. An empty
+     * rectangle has no place.
+     */
+    pub fn insert(&mut self, w: u32, h: u32) -> Option<(u32, u32)> {
+        if w == 0 || h == 0 {
+            return None;
+        }
+        let best = self.free.iter()
+            .filter(|f| f.w >= w && f.h >= h)
+            .min_by_key(|f| {
+                let (dw, dh) = (f.w - w, f.h - h);
+                (dw.min(dh), dw.max(dh))
+            })
+            .cloned();
+        best.map(|b| {
+            self.split(&Area { x: b.x, y: b.y, w: w, h: h });
+            (b.x, b.y)
+        })
+    }
+
+    fn split(&mut self, used: &Area) {
+        let mut next = Vec::<Area>::with_capacity(self.free.len() + 4);
+        for f in &self.free {
+            if !f.overlaps(used) {
+                next.push(*f);
+                continue;
+            }
+            if used.x > f.x {
+                next.push(Area { x: f.x, y: f.y, w: used.x - f.x, h: f.h });
+            }
+            if used.x + used.w < f.x + f.w {
+                next.push(Area { x: used.x + used.w, y: f.y, w: f.x + f.w - used.x - used.w, h: f.h });
+            }
+            if used.y > f.y {
+                next.push(Area { x: f.x, y: f.y, w: f.w, h: used.y - f.y });
+            }
+            if used.y + used.h < f.y + f.h {
+                next.push(Area { x: f.x, y: used.y + used.h, w: f.w, h: f.y + f.h - used.y - used.h });
+            }
+        }
+        // Drop free areas wholly inside another; they add nothing but search time.
+        let mut pruned = Vec::<Area>::with_capacity(next.len());
+        for (i, a) in next.iter().enumerate() {
+            let redundant = next.iter().enumerate().any(|(j, b)| {
+                i != j && b.contains(a) && (a != b || j < i)
+            });
+            if !redundant {
+                pruned.push(*a);
+            }
+        }
+        self.free = pruned;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasSprite {
+    pub texture: TextureId,
+    pub src: Rect
+}
+
+impl AtlasSprite {
+    pub fn draw(&self, backend: &mut Backend, x: f64, y: f64) {
+        backend.draw_texture(self.texture, Some(self.src), x, y, self.src.w as f64, self.src.h as f64);
+    }
+
+    pub fn draw_scaled(&self, backend: &mut Backend, x: f64, y: f64, w: f64, h: f64) {
+        backend.draw_texture(self.texture, Some(self.src), x, y, w, h);
+    }
+}
+
+/*
+ * Copies an image into a page with `pad` pixels of its edges repeated around it.
+ */
+fn blit_extruded(page: &mut Image, image: &Image, x: u32, y: u32, pad: u32) {
+    let (w, h) = (image.width, image.height);
+    for dy in 0..h + 2 * pad {
+        let sy = (dy as i64 - pad as i64).max(0).min(h as i64 - 1) as u32;
+        for dx in 0..w + 2 * pad {
+            let sx = (dx as i64 - pad as i64).max(0).min(w as i64 - 1) as u32;
+            page.put(x + dx, y + dy, image.get(sx, sy));
+        }
+    }
+}
+
+/*
+ * Collects named images and packs them into pages.
+ */
+pub struct AtlasBuilder {
+    page_size: u32,
+    padding: u32,
+    images: Vec<(String, Image)>
+}
+
+impl AtlasBuilder {
+    pub fn new(page_size: u32, padding: u32) -> AtlasBuilder {
+        AtlasBuilder { page_size: page_size, padding: padding, images: Vec::new() }
+    }
+
+    /*
+     * Adds an image to be packed. Empty images are refused, having nothing to draw.
+     */
+    pub fn add(&mut self, name: &str, image: Image) -> Result<(), String> {
+        if image.width == 0 || image.height == 0 {
+            return Err(format!("{} is empty ({}x{})", name, image.width, image.height));
+        }
+        self.images.push((name.to_string(), image));
+        Ok(())
+    }
+
+    pub fn add_bmp<P: AsRef<Path>>(&mut self, name: &str, path: P) -> Result<(), String> {
+        Image::load_bmp(path).and_then(|image| self.add(name, image))
+    }
+
+    /*
+     * Packs everything added so far and uploads the pages. Images are placed largest
+     * first, which packs noticeably tighter than insertion order.
+     */
+    pub fn build(mut self, backend: &mut Backend) -> Result<Atlas, String> {
+        let pad = self.padding;
+        let size = self.page_size;
+        self.images.sort_by(|a, b| (b.1.width * b.1.height).cmp(&(a.1.width * a.1.height)));
+
+        let mut pages = Vec::<(Packer, Image)>::new();
+        let mut placed = Vec::<(String, usize, u32, u32, u32, u32)>::new();
+        for &(ref name, ref image) in &self.images {
+            let (w, h) = (image.width + 2 * pad, image.height + 2 * pad);
+            if w > size || h > size {
+                return Err(format!("{} ({}x{}) does not fit in a {} atlas page",
+                                   name, image.width, image.height, size));
+            }
+            let mut spot = None;
+            for (i, page) in pages.iter_mut().enumerate() {
+                if let Some((x, y)) = page.0.insert(w, h) {
+                    spot = Some((i, x, y));
+                    break;
+                }
+            }
+            let (page, x, y) = match spot {
+                Some(s) => s,
+                None => {
+                    let mut packer = Packer::new(size, size);
+                    let (x, y) = packer.insert(w, h).unwrap();
+                    pages.push((packer, Image::new(size, size)));
+                    (pages.len() - 1, x, y)
+                }
+            };
+            blit_extruded(&mut pages[page].1, image, x, y, pad);
+            placed.push((name.clone(), page, x + pad, y + pad, image.width, image.height));
+        }
+
+        let mut textures = Vec::<TextureId>::new();
+        for &(_, ref image) in &pages {
+            textures.push(try!(backend.create_texture(image)));
+        }
+        let sprites = placed.into_iter().map(|(name, page, x, y, w, h)| {
+            (name, AtlasSprite { texture: textures[page], src: Rect::new(x as i32, y as i32, w, h) })
+        }).collect();
+        Ok(Atlas { textures: textures, sprites: sprites })
+    }
+}
+
+pub struct Atlas {
+    textures: Vec<TextureId>,
+    sprites: HashMap<String, AtlasSprite>
+}
+
+impl Atlas {
+    pub fn get(&self, name: &str) -> Option<AtlasSprite> {
+        self.sprites.get(name).cloned()
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn destroy(self, backend: &mut Backend) {
+        for tex in self.textures {
+            backend.destroy_texture(tex);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use software::SoftwareBackend;
+
+    #[test]
+    fn empty_images_are_refused() {
+        let mut packer = Packer::new(16, 16);
+        assert_eq!(packer.insert(0, 4), None);
+        assert_eq!(packer.insert(4, 0), None);
+        assert_eq!(packer.insert(4, 4), Some((0, 0)));
+
+        let mut builder = AtlasBuilder::new(16, 1);
+        assert!(builder.add("nothing", Image::new(0, 3)).is_err());
+        assert!(builder.add("dot", Image::new(1, 1)).is_ok());
+        let atlas = builder.build(&mut SoftwareBackend::new(1, 1)).unwrap();
+        assert!(atlas.get("nothing").is_none());
+        assert!(atlas.get("dot").is_some());
+    }
+}
@@ -0,0 +1,652 @@
+/*
+ * Sound effects and music, mixed in software and handed to an output a buffer at a
+ * time. The mixer runs on whatever thread the output calls it from, so the game talks to
+ * it through an `Audio` handle that can be cloned into any ditty that makes noise.
+ *
+ * Everything goes through a bus, each with its own volume under the master's: music,
+ * sound effects and ambience (looping background sounds). Effects can be placed in the
+ * world, and are then panned and faded by where they are relative to the listener,
+ * usually the camera. Music is streamed from disk, and changing track crossfades.
+ *
+ * The output's thread must never wait. It only mixes if it can take the mixer straight
+ * away, and plays a buffer of silence if the game happens to hold it, which it only does
+ * long enough to change a setting or start a sound. Music is read and decoded on a
+ * thread of its own, which keeps a ring buffer topped up for the mixer to take from.
+ */
+
+use std::f32::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::thread;
+use std::time::Duration;
+
+use camera::Camera;
+use wav::WavStream;
+
+/*
+ * A left and right sample, from -1 to 1.
+ */
+pub type Frame = [f32; 2];
+
+/*
+ * A sound decoded into memory, as stereo at its own sample rate.
+ */
+pub struct Sound {
+    rate: u32,
+    frames: Vec<Frame>
+}
+
+impl Sound {
+    pub fn new(rate: u32, frames: Vec<Frame>) -> Sound {
+        Sound { rate: rate, frames: frames }
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.frames.len() as f64 / self.rate as f64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bus {
+    Master,
+    Music,
+    Sfx,
+    Ambience
+}
+
+impl Bus {
+    fn index(&self) -> usize {
+        match *self {
+            Bus::Master => 0,
+            Bus::Music => 1,
+            Bus::Sfx => 2,
+            Bus::Ambience => 3
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VoiceId(u64);
+
+/*
+ * Voices beyond this cut off the oldest playing.
+ */
+const MAX_VOICES: usize = 32;
+
+/*
+ * Frames of music read from disk at a time.
+ */
+const STREAM_CHUNK: usize = 4096;
+
+/*
+ * Frames of music decoded ahead: a second and a half at 44.1kHz.
+ */
+const STREAM_AHEAD: usize = 65536;
+
+/*
+ * How far away, in multiples of the listener's range, a placed sound fades to nothing.
+ */
+const FALLOFF: f64 = 3.0;
+
+#[derive(Debug, Clone, Copy)]
+enum Placement {
+    Pan(f32),
+    At(f64, f64)
+}
+
+struct Voice {
+    id: VoiceId,
+    sound: Arc<Sound>,
+    bus: Bus,
+    volume: f32,
+    placement: Placement,
+    looping: bool,
+    pos: f64
+}
+
+/*
+ * Decoded music waiting to be played, filled by the decoding thread and emptied by the
+ * mixer. The storage is allocated once, up front.
+ */
+struct Ring {
+    frames: Vec<Frame>,
+    start: usize,
+    len: usize,
+    ended: bool
+}
+
+impl Ring {
+    fn new(capacity: usize) -> Ring {
+        Ring { frames: vec![[0.0, 0.0]; capacity], start: 0, len: 0, ended: false }
+    }
+
+    /*
+     * Adds as many frames as there is room for, returning how many.
+     */
+    fn push(&mut self, frames: &[Frame]) -> usize {
+        let capacity = self.frames.len();
+        let count = frames.len().min(capacity - self.len);
+        for (i, &frame) in frames[..count].iter().enumerate() {
+            self.frames[(self.start + self.len + i) % capacity] = frame;
+        }
+        self.len += count;
+        count
+    }
+
+    fn pop(&mut self) -> Option<Frame> {
+        if self.len == 0 {
+            return None;
+        }
+        let frame = self.frames[self.start];
+        self.start = (self.start + 1) % self.frames.len();
+        self.len -= 1;
+        Some(frame)
+    }
+}
+
+/*
+ * Reads a stream into the ring, looping it, until the track is dropped.
+ */
+fn decode_music(mut stream: WavStream, ring: Arc<Mutex<Ring>>) {
+    let mut chunk = Vec::with_capacity(STREAM_CHUNK);
+    let mut pushed = 0;
+    let mut since_rewind = 0;
+    // Only this thread holds the ring once the mixer has let the track go.
+    while Arc::strong_count(&ring) > 1 {
+        if pushed == chunk.len() {
+            chunk.clear();
+            pushed = 0;
+            match stream.read(STREAM_CHUNK, &mut chunk) {
+                Ok(0) => {
+                    // Music loops, unless it can't go back or is empty and would loop
+                    // for ever.
+                    if since_rewind == 0 || stream.rewind().is_err() {
+                        break;
+                    }
+                    since_rewind = 0;
+                },
+                Ok(n) => since_rewind += n,
+                Err(e) => {
+                    println!("Music stopped: {}", e);
+                    break;
+                }
+            }
+            continue;
+        }
+        pushed += ring.lock().unwrap().push(&chunk[pushed..]);
+        if pushed < chunk.len() {
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+    ring.lock().unwrap().ended = true;
+}
+
+/*
+ * A piece of music, opened and decoding ahead, ready to be given to a mixer.
+ */
+pub struct Music {
+    ring: Arc<Mutex<Ring>>,
+    rate: u32
+}
+
+impl Music {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Music, String> {
+        let stream = try!(WavStream::open(path));
+        let rate = stream.rate();
+        let ring = Arc::new(Mutex::new(Ring::new(STREAM_AHEAD)));
+        let decoding = ring.clone();
+        thread::spawn(move || decode_music(stream, decoding));
+        Ok(Music { ring: ring, rate: rate })
+    }
+}
+
+struct Track {
+    music: Music,
+    /*
+     * The frames either side of the play position, and how far it is between them.
+     */
+    current: Frame,
+    next: Frame,
+    frac: f64,
+    gain: f32,
+    /*
+     * Change in gain a second; negative while fading out.
+     */
+    fade: f32,
+    volume: f32
+}
+
+/*
+ * Where sounds are heard from, in world units.
+ */
+#[derive(Debug, Clone, Copy)]
+struct Listener {
+    x: f64,
+    y: f64,
+    range: f64,
+    rotation: f64
+}
+
+/*
+ * Left and right gains for a pan from -1 (left) to 1 (right). The centre is at full
+ * volume on both sides, and the far side falls away with a constant power curve.
+ */
+fn pan_gains(pan: f32) -> (f32, f32) {
+    let angle = (pan.max(-1.0).min(1.0) + 1.0) * PI / 4.0;
+    ((angle.cos() * 2f32.sqrt()).min(1.0), (angle.sin() * 2f32.sqrt()).min(1.0))
+}
+
+fn lerp(a: Frame, b: Frame, t: f32) -> Frame {
+    [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t]
+}
+
+pub struct Mixer {
+    rate: u32,
+    volumes: [f32; 4],
+    voices: Vec<Voice>,
+    music: Vec<Track>,
+    listener: Listener,
+    next_id: u64
+}
+
+impl Mixer {
+    pub fn new(rate: u32) -> Mixer {
+        Mixer {
+            rate: rate,
+            volumes: [1.0; 4],
+            voices: Vec::new(),
+            music: Vec::new(),
+            listener: Listener { x: 0.0, y: 0.0, range: 1.0, rotation: 0.0 },
+            next_id: 0
+        }
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /*
+     * For outputs that can't give the rate asked for.
+     */
+    pub fn set_rate(&mut self, rate: u32) {
+        self.rate = rate;
+    }
+
+    pub fn set_volume(&mut self, bus: Bus, volume: f32) {
+        self.volumes[bus.index()] = volume.max(0.0);
+    }
+
+    pub fn volume(&self, bus: Bus) -> f32 {
+        self.volumes[bus.index()]
+    }
+
+    /*
+     * Sounds within `range` of (x, y) play at full volume, fading out further away.
+     */
+    pub fn set_listener(&mut self, x: f64, y: f64, range: f64, rotation: f64) {
+        self.listener = Listener { x: x, y: y, range: range.max(1e-6), rotation: rotation };
+    }
+
+    /*
+     * Hears from the middle of the camera's view, so sounds at the sides of the screen
+     * pan fully and those offscreen fade.
+     */
+    pub fn listen_through(&mut self, camera: &Camera) {
+        let (x, y) = camera.position();
+        let range = camera.viewport().0 as f64 / 2.0 / camera.zoom();
+        self.set_listener(x, y, range, camera.rotation());
+    }
+
+    fn start(&mut self, sound: Arc<Sound>, bus: Bus, volume: f32, placement: Placement, looping: bool) -> VoiceId {
+        if self.voices.len() >= MAX_VOICES {
+            self.voices.remove(0);
+        }
+        let id = VoiceId(self.next_id);
+        self.next_id += 1;
+        self.voices.push(Voice {
+            id: id,
+            sound: sound,
+            bus: bus,
+            volume: volume,
+            placement: placement,
+            looping: looping,
+            pos: 0.0
+        });
+        id
+    }
+
+    /*
+     * Plays a sound once, panned from -1 (left) to 1 (right).
+     */
+    pub fn play(&mut self, sound: Arc<Sound>, bus: Bus, volume: f32, pan: f32) -> VoiceId {
+        self.start(sound, bus, volume, Placement::Pan(pan), false)
+    }
+
+    /*
+     * Plays a sound once from a place in the world.
+     */
+    pub fn play_at(&mut self, sound: Arc<Sound>, bus: Bus, volume: f32, x: f64, y: f64) -> VoiceId {
+        self.start(sound, bus, volume, Placement::At(x, y), false)
+    }
+
+    /*
+     * Plays a sound over and over until stopped.
+     */
+    pub fn play_looped(&mut self, sound: Arc<Sound>, bus: Bus, volume: f32) -> VoiceId {
+        self.start(sound, bus, volume, Placement::Pan(0.0), true)
+    }
+
+    pub fn stop(&mut self, voice: VoiceId) {
+        self.voices.retain(|v| v.id != voice);
+    }
+
+    pub fn is_playing(&self, voice: VoiceId) -> bool {
+        self.voices.iter().any(|v| v.id == voice)
+    }
+
+    pub fn voice_count(&self) -> usize {
+        self.voices.len()
+    }
+
+    /*
+     * Plays music on the music bus, looping, fading it in over `fade` seconds while
+     * whatever was playing fades out.
+     */
+    pub fn play_music(&mut self, music: Music, volume: f32, fade: f64) {
+        self.stop_music(fade);
+        let rate = if fade > 0.0 { 1.0 / fade as f32 } else { 0.0 };
+        self.music.push(Track {
+            music: music,
+            current: [0.0, 0.0],
+            next: [0.0, 0.0],
+            // Two frames are needed before the first can be played.
+            frac: 2.0,
+            gain: if fade > 0.0 { 0.0 } else { 1.0 },
+            fade: rate,
+            volume: volume
+        });
+    }
+
+    pub fn stop_music(&mut self, fade: f64) {
+        if fade <= 0.0 {
+            self.music.clear();
+            return;
+        }
+        for track in self.music.iter_mut() {
+            track.fade = -1.0 / fade as f32;
+        }
+    }
+
+    /*
+     * The gains for a voice's left and right, from its volume, bus and placement.
+     */
+    fn voice_gains(&self, voice: &Voice) -> (f32, f32) {
+        let bus = self.volumes[Bus::Master.index()] * self.volumes[voice.bus.index()] * voice.volume;
+        let (pan, distance) = match voice.placement {
+            Placement::Pan(pan) => (pan, 1.0),
+            Placement::At(x, y) => {
+                let l = &self.listener;
+                let (dx, dy) = ((x - l.x) / l.range, (y - l.y) / l.range);
+                let (s, c) = (-l.rotation).to_radians().sin_cos();
+                let across = c * dx - s * dy;
+                let d = (dx * dx + dy * dy).sqrt();
+                let fade = if d <= 1.0 { 1.0 } else { (1.0 - (d - 1.0) / (FALLOFF - 1.0)).max(0.0) };
+                (across as f32, fade as f32)
+            }
+        };
+        let (left, right) = pan_gains(pan);
+        (left * bus * distance, right * bus * distance)
+    }
+
+    /*
+     * Fills a buffer of interleaved left and right samples, moving everything on by
+     * that much time.
+     */
+    pub fn mix(&mut self, out: &mut [f32]) {
+        for s in out.iter_mut() {
+            *s = 0.0;
+        }
+        let frames = out.len() / 2;
+        let rate = self.rate as f64;
+
+        let mut voices = ::std::mem::replace(&mut self.voices, Vec::new());
+        for voice in voices.iter_mut() {
+            let (gl, gr) = self.voice_gains(voice);
+            let source = voice.sound.frames();
+            let len = source.len();
+            let step = voice.sound.rate() as f64 / rate;
+            for i in 0..frames {
+                let at = voice.pos as usize;
+                if at >= len {
+                    break;
+                }
+                let next = if at + 1 < len { source[at + 1] } else if voice.looping { source[0] } else { [0.0, 0.0] };
+                let frame = lerp(source[at], next, (voice.pos - at as f64) as f32);
+                out[i * 2] += frame[0] * gl;
+                out[i * 2 + 1] += frame[1] * gr;
+                voice.pos += step;
+                if voice.looping && voice.pos >= len as f64 {
+                    voice.pos -= len as f64;
+                }
+            }
+        }
+        voices.retain(|v| (v.pos as usize) < v.sound.frames().len());
+        self.voices = voices;
+
+        let bus = self.volumes[Bus::Master.index()] * self.volumes[Bus::Music.index()];
+        let dt = 1.0 / self.rate as f32;
+        for track in self.music.iter_mut() {
+            let step = track.music.rate as f64 / rate;
+            // The decoder only holds the lock to copy a chunk in; rather than wait for it,
+            // the track sits this buffer out.
+            let mut ring = match track.music.ring.try_lock() {
+                Ok(ring) => ring,
+                Err(_) => continue
+            };
+            for i in 0..frames {
+                while track.frac >= 1.0 {
+                    match ring.pop() {
+                        Some(frame) => {
+                            track.current = track.next;
+                            track.next = frame;
+                            track.frac -= 1.0;
+                        },
+                        None => break
+                    }
+                }
+                if track.frac >= 1.0 {
+                    if ring.ended {
+                        // Over, and not looping.
+                        track.gain = 0.0;
+                        track.fade = 0.0;
+                    }
+                    // Otherwise the decoder has fallen behind, and this is a gap.
+                    break;
+                }
+                let frame = lerp(track.current, track.next, track.frac as f32);
+                let gain = track.gain * track.volume * bus;
+                out[i * 2] += frame[0] * gain;
+                out[i * 2 + 1] += frame[1] * gain;
+                track.frac += step;
+                track.gain = (track.gain + track.fade * dt).max(0.0).min(1.0);
+            }
+        }
+        self.music.retain(|t| t.gain > 0.0 || t.fade > 0.0);
+
+        for s in out.iter_mut() {
+            *s = s.max(-1.0).min(1.0);
+        }
+    }
+}
+
+/*
+ * A mixer that can be shared with the thread that plays it.
+ */
+#[derive(Clone)]
+pub struct Audio {
+    mixer: Arc<Mutex<Mixer>>
+}
+
+impl Audio {
+    pub fn new(rate: u32) -> Audio {
+        Audio { mixer: Arc::new(Mutex::new(Mixer::new(rate))) }
+    }
+
+    pub fn mixer(&self) -> MutexGuard<Mixer> {
+        self.mixer.lock().unwrap()
+    }
+
+    /*
+     * Mixes into `out` for an output that can't wait, as one run by the sound hardware.
+     * If the mixer is in use it fills `out` with silence instead and returns false; the
+     * mixer then picks up where it left off next time.
+     */
+    pub fn try_mix(&self, out: &mut [f32]) -> bool {
+        match self.mixer.try_lock() {
+            Ok(mut mixer) => {
+                mixer.mix(out);
+                true
+            },
+            Err(_) => {
+                for s in out.iter_mut() {
+                    *s = 0.0;
+                }
+                false
+            }
+        }
+    }
+
+    /*
+     * Opens a WAV file and plays it as music, as `Mixer::play_music` does. The file is
+     * opened before the mixer is locked, so the output isn't kept waiting on the disk.
+     */
+    pub fn play_music<P: AsRef<Path>>(&self, path: P, volume: f32, fade: f64) -> Result<(), String> {
+        let music = try!(Music::open(path));
+        self.mixer().play_music(music, volume, fade);
+        Ok(())
+    }
+}
+
+/*
+ * Somewhere mixed sound goes.
+ */
+pub trait AudioOutput {
+    fn rate(&self) -> u32;
+
+    /*
+     * Called once a frame with the time since the last, for outputs that aren't driven
+     * by the sound hardware.
+     */
+    fn pump(&mut self, elapsed: f64) {
+    }
+}
+
+/*
+ * Mixes in step with the game and throws the result away, so sounds start and finish
+ * on time without a sound card, as in headless runs.
+ */
+pub struct NullOutput {
+    audio: Audio,
+    owed: f64,
+    buffer: Vec<f32>,
+    frames: u64,
+    peak: f32
+}
+
+impl NullOutput {
+    pub fn new(audio: Audio) -> NullOutput {
+        NullOutput { audio: audio, owed: 0.0, buffer: Vec::new(), frames: 0, peak: 0.0 }
+    }
+
+    /*
+     * Frames mixed so far.
+     */
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /*
+     * The loudest sample in the last pump.
+     */
+    pub fn peak(&self) -> f32 {
+        self.peak
+    }
+}
+
+impl AudioOutput for NullOutput {
+    fn rate(&self) -> u32 {
+        self.audio.mixer().rate()
+    }
+
+    fn pump(&mut self, elapsed: f64) {
+        let mut mixer = self.audio.mixer();
+        self.owed += elapsed * mixer.rate() as f64;
+        let frames = self.owed.floor();
+        self.owed -= frames;
+        self.buffer.resize(frames as usize * 2, 0.0);
+        mixer.mix(&mut self.buffer);
+        self.frames += frames as u64;
+        self.peak = self.buffer.iter().fold(0.0, |m: f32, s| m.max(s.abs()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use wav;
+
+    #[test]
+    fn the_ring_wraps_around() {
+        let mut ring = Ring::new(3);
+        assert_eq!(ring.push(&[[1.0, 1.0], [2.0, 2.0]]), 2);
+        assert_eq!(ring.pop(), Some([1.0, 1.0]));
+        assert_eq!(ring.push(&[[3.0, 3.0], [4.0, 4.0], [5.0, 5.0]]), 2);
+        assert_eq!(ring.pop(), Some([2.0, 2.0]));
+        assert_eq!(ring.pop(), Some([3.0, 3.0]));
+        assert_eq!(ring.pop(), Some([4.0, 4.0]));
+        assert_eq!(ring.pop(), None);
+    }
+
+    #[test]
+    fn music_is_decoded_ahead_and_loops() {
+        let path = ::std::env::temp_dir().join("tycoon-music-test.wav");
+        wav::save(&Sound::new(1000, vec![[0.5, -0.5]; 100]), &path).unwrap();
+        let audio = Audio::new(1000);
+        audio.play_music(&path, 1.0, 0.0).unwrap();
+        // Give the decoder time to get ahead, as the output's buffering would.
+        let started = Instant::now();
+        while audio.mixer().music[0].music.ring.lock().unwrap().len < 300
+              && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let mut out = vec![0.0; 500];
+        audio.mixer().mix(&mut out);
+        assert!(out.chunks(2).all(|f| f == &[0.5, -0.5][..]));
+        let _ = ::std::fs::remove_file(&path);
+    }
+    #[test]
+    fn the_output_never_waits_for_the_mixer() {
+        let audio = Audio::new(1000);
+        audio.mixer().play(Arc::new(Sound::new(1000, vec![[0.25, 0.25]; 10])), Bus::Sfx, 1.0, 0.0);
+        let mut out = vec![1.0; 8];
+        {
+            let _held = audio.mixer();
+            assert!(!audio.try_mix(&mut out));
+            assert!(out.iter().all(|&s| s == 0.0));
+        }
+        assert!(audio.try_mix(&mut out));
+        assert!(out.iter().all(|&s| s > 0.0));
+        assert_eq!(audio.mixer().voice_count(), 1);
+    }
+}
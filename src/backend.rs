@@ -0,0 +1,207 @@
+/*
+ * The drawing interface ditties render through. Textures are referred to by id so the
+ * trait stays usable as a trait object, and coordinates are floating point so that
+ * everything can pass through the current transform.
+ */
+
+use color::Rgba;
+use geom::Polyline;
+use geom::Transform;
+use image::Image;
+use raster::FillRule;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TextureId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, w: u32, h: u32) -> Rect {
+        Rect { x: x, y: y, w: w, h: h }
+    }
+
+    pub fn right(&self) -> i32 {
+        self.x + self.w as i32
+    }
+
+    pub fn bottom(&self) -> i32 {
+        self.y + self.h as i32
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    pub fn intersect(&self, o: &Rect) -> Option<Rect> {
+        let x0 = self.x.max(o.x);
+        let y0 = self.y.max(o.y);
+        let x1 = self.right().min(o.right());
+        let y1 = self.bottom().min(o.bottom());
+        if x1 > x0 && y1 > y0 {
+            Some(Rect::new(x0, y0, (x1 - x0) as u32, (y1 - y0) as u32))
+        } else {
+            None
+        }
+    }
+}
+
+/*
+ * One copy of (part of) a texture in a batch, with its own tint.
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct TexturedQuad {
+    pub src: Option<Rect>,
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+    pub tint: Rgba
+}
+
+pub trait Backend {
+    fn size(&self) -> (u32, u32);
+    fn clear(&mut self, colour: Rgba);
+    fn present(&mut self);
+    /*
+     * Reads back what has been drawn so far this frame.
+     */
+    fn read_pixels(&mut self) -> Result<Image, String>;
+    /*
+     * How many displays the window could be put on.
+     */
+    fn displays(&self) -> u32 {
+        1
+    }
+
+    fn set_color(&mut self, colour: Rgba);
+    fn draw_line(&mut self, x0: f64, y0: f64, x1: f64, y1: f64);
+    fn fill_rect(&mut self, x: f64, y: f64, w: f64, h: f64);
+    fn fill_path(&mut self, polys: &[Polyline], rule: FillRule);
+
+    fn create_texture(&mut self, image: &Image) -> Result<TextureId, String>;
+    /*
+     * Overwrites part of a texture with the given pixels, placing them at (x, y).
+     */
+    fn update_texture(&mut self, tex: TextureId, x: u32, y: u32, image: &Image) -> Result<(), String>;
+    fn destroy_texture(&mut self, tex: TextureId);
+    fn texture_size(&self, tex: TextureId) -> Option<(u32, u32)>;
+    /*
+     * Draws the src part of a texture (all of it if None) into the rectangle at (x, y)
+     * of size w x h, before the current transform is applied.
+     */
+    fn draw_texture(&mut self, tex: TextureId, src: Option<Rect>, x: f64, y: f64, w: f64, h: f64);
+    /*
+     * Texture pixels are multiplied by the tint, white by default.
+     */
+    fn set_tint(&mut self, tint: Rgba);
+    fn tint(&self) -> Rgba;
+
+    /*
+     * Clip rectangles are in screen pixels and are not affected by the transform.
+     */
+    fn set_clip(&mut self, clip: Option<Rect>);
+    fn clip(&self) -> Option<Rect>;
+
+    /*
+     * Transforms nest: a pushed transform is applied before the ones beneath it.
+     */
+    fn push_transform(&mut self, t: &Transform);
+    fn pop_transform(&mut self);
+    fn transform(&self) -> Transform;
+
+    fn draw_polyline(&mut self, points: &[(f64, f64)]) {
+        for w in points.windows(2) {
+            self.draw_line(w[0].0, w[0].1, w[1].0, w[1].1);
+        }
+    }
+
+    fn draw_rect(&mut self, x: f64, y: f64, w: f64, h: f64) {
+        self.draw_polyline(&[(x, y), (x + w, y), (x + w, y + h), (x, y + h), (x, y)]);
+    }
+
+    /*
+     * Draws many quads from one texture, leaving the tint as it was.
+     */
+    fn draw_texture_batch(&mut self, tex: TextureId, quads: &[TexturedQuad]) {
+        let tint = self.tint();
+        for q in quads {
+            self.set_tint(q.tint);
+            self.draw_texture(tex, q.src, q.x, q.y, q.w, q.h);
+        }
+        self.set_tint(tint);
+    }
+
+    /*
+     * Fills an indexed triangle list in the current colour. By default it is scan
+     * converted like any other path; backends may fill the triangles more directly.
+     */
+    fn fill_triangles(&mut self, vertices: &[(f64, f64)], indices: &[u32]) {
+        let tris: Vec<Polyline> = indices.chunks(3).filter(|t| t.len() == 3).map(|t| {
+            t.iter().map(|&i| vertices[i as usize]).collect()
+        }).collect();
+        self.fill_path(&tris, FillRule::NonZero);
+    }
+}
+
+/*
+ * Bookkeeping common to all backends: the current colour, tint, clip and transform
+ * stack.
+ */
+pub struct DrawState {
+    pub colour: Rgba,
+    pub tint: Rgba,
+    pub clip: Option<Rect>,
+    transforms: Vec<Transform>
+}
+
+impl DrawState {
+    pub fn new() -> DrawState {
+        DrawState {
+            colour: Rgba::rgb(255, 255, 255),
+            tint: Rgba::rgb(255, 255, 255),
+            clip: None,
+            transforms: vec![Transform::identity()]
+        }
+    }
+
+    pub fn transform(&self) -> Transform {
+        *self.transforms.last().unwrap()
+    }
+
+    pub fn push(&mut self, t: &Transform) {
+        let top = self.transform().then(t);
+        self.transforms.push(top);
+    }
+
+    pub fn pop(&mut self) {
+        if self.transforms.len() > 1 {
+            self.transforms.pop();
+        }
+    }
+
+    /*
+     * The clip box as (x0, y0, x1, y1), limited to a target of the given size.
+     */
+    pub fn clip_box(&self, width: u32, height: u32) -> (i32, i32, i32, i32) {
+        let screen = Rect::new(0, 0, width, height);
+        match self.clip.map(|c| c.intersect(&screen)) {
+            Some(Some(c)) => (c.x, c.y, c.right(), c.bottom()),
+            Some(None) => (0, 0, 0, 0),
+            None => (0, 0, width as i32, height as i32)
+        }
+    }
+
+    /*
+     * The axis-aligned rectangle (x, y, w, h) as a transformed quad.
+     */
+    pub fn quad(&self, x: f64, y: f64, w: f64, h: f64) -> Polyline {
+        let t = self.transform();
+        vec![t.apply(x, y), t.apply(x + w, y), t.apply(x + w, y + h), t.apply(x, y + h)]
+    }
+}
@@ -0,0 +1,274 @@
+/*
+ * A 2D camera looking at the world. The camera's position is the world point shown at
+ * the centre of the viewport; zoom is screen pixels per world unit.
+ *
+ * Pan, zoom and rotation each have a target, and update() eases the current values
+ * towards them, so input handlers only ever move targets.
+ */
+
+use geom::Transform;
+
+pub struct Camera {
+    x: f64,
+    y: f64,
+    zoom: f64,
+    rotation: f64,
+    target_x: f64,
+    target_y: f64,
+    target_zoom: f64,
+    target_rotation: f64,
+    pub min_zoom: f64,
+    pub max_zoom: f64,
+    // Fraction of the remaining distance covered per second is 1 - e^-ease.
+    pub ease: f64,
+    width: u32,
+    height: u32
+}
+
+fn approach(cur: f64, target: f64, k: f64) -> f64 {
+    if (target - cur).abs() < 1e-6 { target } else { cur + (target - cur) * k }
+}
+
+impl Camera {
+    pub fn new(width: u32, height: u32) -> Camera {
+        Camera {
+            x: 0.0,
+            y: 0.0,
+            zoom: 1.0,
+            rotation: 0.0,
+            target_x: 0.0,
+            target_y: 0.0,
+            target_zoom: 1.0,
+            target_rotation: 0.0,
+            min_zoom: 0.05,
+            max_zoom: 20.0,
+            ease: 10.0,
+            width: width,
+            height: height
+        }
+    }
+
+    pub fn set_viewport(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
+
+    pub fn viewport(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn position(&self) -> (f64, f64) {
+        (self.x, self.y)
+    }
+
+    pub fn zoom(&self) -> f64 {
+        self.zoom
+    }
+
+    pub fn rotation(&self) -> f64 {
+        self.rotation
+    }
+
+    fn clamp_zoom(&self, zoom: f64) -> f64 {
+        zoom.max(self.min_zoom).min(self.max_zoom)
+    }
+
+    /*
+     * World to screen.
+     */
+    pub fn transform(&self) -> Transform {
+        Transform::translate(self.width as f64 / 2.0, self.height as f64 / 2.0)
+            .then(&Transform::rotate(self.rotation))
+            .then(&Transform::scale(self.zoom, self.zoom))
+            .then(&Transform::translate(-self.x, -self.y))
+    }
+
+    pub fn world_to_screen(&self, x: f64, y: f64) -> (f64, f64) {
+        self.transform().apply(x, y)
+    }
+
+    pub fn screen_to_world(&self, sx: f64, sy: f64) -> (f64, f64) {
+        // Zoom is clamped above zero, so the camera transform always has an inverse.
+        self.transform().inverse().unwrap().apply(sx, sy)
+    }
+
+    /*
+     * The world-space bounding box of everything on screen, as (x0, y0, x1, y1).
+     */
+    pub fn visible_bounds(&self) -> (f64, f64, f64, f64) {
+        let (w, h) = (self.width as f64, self.height as f64);
+        let corners = [self.screen_to_world(0.0, 0.0), self.screen_to_world(w, 0.0),
+                       self.screen_to_world(w, h), self.screen_to_world(0.0, h)];
+        corners.iter().skip(1).fold(
+            (corners[0].0, corners[0].1, corners[0].0, corners[0].1),
+            |(x0, y0, x1, y1), &(x, y)| (x0.min(x), y0.min(y), x1.max(x), y1.max(y)))
+    }
+
+    /*
+     * Moves immediately, with no easing.
+     */
+    pub fn jump_to(&mut self, x: f64, y: f64, zoom: f64) {
+        let zoom = self.clamp_zoom(zoom);
+        self.x = x;
+        self.y = y;
+        self.zoom = zoom;
+        self.target_x = x;
+        self.target_y = y;
+        self.target_zoom = zoom;
+    }
+
+    pub fn look_at(&mut self, x: f64, y: f64) {
+        self.target_x = x;
+        self.target_y = y;
+    }
+
+    /*
+     * Pans by a distance given in screen pixels, e.g. a mouse drag.
+     */
+    pub fn pan_screen(&mut self, dx: f64, dy: f64) {
+        let (s, c) = (-self.target_rotation).to_radians().sin_cos();
+        self.target_x += (c * dx - s * dy) / self.target_zoom;
+        self.target_y += (s * dx + c * dy) / self.target_zoom;
+    }
+
+    pub fn zoom_to(&mut self, zoom: f64) {
+        self.target_zoom = self.clamp_zoom(zoom);
+    }
+
+    /*
+     * Zooms by a factor while keeping the world point under the screen position (sx, sy)
+     * in place, as for mouse wheel zooming.
+     */
+    pub fn zoom_at(&mut self, factor: f64, sx: f64, sy: f64) {
+        let new_zoom = self.clamp_zoom(self.target_zoom * factor);
+        let (wx, wy) = self.screen_to_world(sx, sy);
+        let (dx, dy) = (sx - self.width as f64 / 2.0, sy - self.height as f64 / 2.0);
+        let (s, c) = (-self.target_rotation).to_radians().sin_cos();
+        self.target_x = wx - (c * dx - s * dy) / new_zoom;
+        self.target_y = wy - (s * dx + c * dy) / new_zoom;
+        self.target_zoom = new_zoom;
+    }
+
+    pub fn rotate_to(&mut self, degrees: f64) {
+        self.target_rotation = degrees;
+    }
+
+    /*
+     * Fits a world rectangle inside the viewport, leaving a margin in screen pixels.
+     */
+    pub fn fit(&mut self, x0: f64, y0: f64, x1: f64, y1: f64, margin: f64) {
+        let w = (self.width as f64 - 2.0 * margin).max(1.0);
+        let h = (self.height as f64 - 2.0 * margin).max(1.0);
+        let zoom = (w / (x1 - x0).max(1e-6)).min(h / (y1 - y0).max(1e-6));
+        self.look_at((x0 + x1) / 2.0, (y0 + y1) / 2.0);
+        self.zoom_to(zoom);
+    }
+
+    /*
+     * Scrolls when the pointer is within `margin` pixels of a screen edge, faster the
+     * closer it gets. Speed is in screen pixels per second.
+     */
+    pub fn edge_scroll(&mut self, mx: f64, my: f64, margin: f64, speed: f64, dt: f64) {
+        let (w, h) = (self.width as f64, self.height as f64);
+        let push = |p: f64, size: f64| if p < margin {
+            -(margin - p) / margin
+        } else if p > size - margin {
+            (p - (size - margin)) / margin
+        } else {
+            0.0
+        };
+        let (px, py) = (push(mx, w), push(my, h));
+        if px != 0.0 || py != 0.0 {
+            self.pan_screen(px * speed * dt, py * speed * dt);
+        }
+    }
+
+    pub fn update(&mut self, dt: f64) {
+        let k = 1.0 - (-self.ease * dt).exp();
+        self.x = approach(self.x, self.target_x, k);
+        self.y = approach(self.y, self.target_y, k);
+        // Ease zoom in log space so zooming in and out feel the same speed.
+        self.zoom = approach(self.zoom.ln(), self.target_zoom.ln(), k).exp();
+        self.rotation = approach(self.rotation, self.target_rotation, k);
+    }
+
+    /*
+     * Skips any easing in progress.
+     */
+    pub fn settle(&mut self) {
+        self.x = self.target_x;
+        self.y = self.target_y;
+        self.zoom = self.target_zoom;
+        self.rotation = self.target_rotation;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn near(a: (f64, f64), b: (f64, f64)) -> bool {
+        (a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9
+    }
+
+    #[test]
+    fn screen_and_world_round_trip() {
+        let mut camera = Camera::new(640, 480);
+        for &(zoom, rotation) in &[(1.0, 0.0), (3.5, 0.0), (0.25, 30.0), (2.0, -135.0)] {
+            camera.jump_to(12.0, -7.5, zoom);
+            camera.rotate_to(rotation);
+            camera.settle();
+            assert!(near(camera.world_to_screen(12.0, -7.5), (320.0, 240.0)));
+            for &(sx, sy) in &[(0.0, 0.0), (640.0, 480.0), (17.0, 301.0)] {
+                let (wx, wy) = camera.screen_to_world(sx, sy);
+                assert!(near(camera.world_to_screen(wx, wy), (sx, sy)));
+            }
+            // A world unit is `zoom` pixels long whichever way it points.
+            let (ax, ay) = camera.world_to_screen(0.0, 0.0);
+            let (bx, by) = camera.world_to_screen(1.0, 0.0);
+            assert!(((bx - ax).hypot(by - ay) - camera.zoom()).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn panning_and_zooming_follow_the_screen() {
+        let mut camera = Camera::new(200, 100);
+        camera.jump_to(50.0, 50.0, 2.0);
+        camera.rotate_to(90.0);
+        camera.settle();
+        // Dragging the view moves the world under the pointer by as much on screen.
+        let world = camera.screen_to_world(120.0, 40.0);
+        camera.pan_screen(-30.0, 10.0);
+        camera.settle();
+        assert!(near(camera.world_to_screen(world.0, world.1), (150.0, 30.0)));
+
+        // The point under the pointer stays put while zooming.
+        let world = camera.screen_to_world(30.0, 80.0);
+        camera.zoom_at(1.5, 30.0, 80.0);
+        camera.settle();
+        assert!((camera.zoom() - 3.0).abs() < 1e-9);
+        assert!(near(camera.world_to_screen(world.0, world.1), (30.0, 80.0)));
+
+        camera.zoom_at(1e6, 0.0, 0.0);
+        camera.settle();
+        assert_eq!(camera.zoom(), camera.max_zoom);
+    }
+
+    #[test]
+    fn eased_pans_arrive() {
+        let mut camera = Camera::new(320, 240);
+        camera.look_at(100.0, -40.0);
+        camera.zoom_to(4.0);
+        let mut last = camera.position();
+        for _ in 0..600 {
+            camera.update(1.0 / 60.0);
+            let now = camera.position();
+            // Always closer, never past.
+            assert!(now.0 >= last.0 && now.0 <= 100.0);
+            assert!(now.1 <= last.1 && now.1 >= -40.0);
+            last = now;
+        }
+        assert_eq!(camera.position(), (100.0, -40.0));
+        assert!((camera.zoom() - 4.0).abs() < 1e-9);
+    }
+}
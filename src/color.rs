@@ -0,0 +1,95 @@
+/*
+ * Colours as they come out of SVG documents, independent of any renderer.
+ */
+
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8
+}
+
+impl Rgba {
+    pub fn new(r: u8, g: u8, b: u8, a: u8) -> Rgba {
+        Rgba { r: r, g: g, b: b, a: a }
+    }
+
+    pub fn rgb(r: u8, g: u8, b: u8) -> Rgba {
+        Rgba::new(r, g, b, 255)
+    }
+
+    pub fn with_alpha(&self, a: f64) -> Rgba {
+        Rgba::new(self.r, self.g, self.b, clamp_u8(self.a as f64 * a))
+    }
+
+    /*
+     * Multiplies channel by channel, as when tinting a texture.
+     */
+    pub fn modulate(&self, other: &Rgba) -> Rgba {
+        let mul = |a: u8, b: u8| ((a as u32 * b as u32 + 127) / 255) as u8;
+        Rgba::new(mul(self.r, other.r), mul(self.g, other.g),
+                  mul(self.b, other.b), mul(self.a, other.a))
+    }
+
+    pub fn lerp(&self, other: &Rgba, t: f64) -> Rgba {
+        let mix = |a: u8, b: u8| clamp_u8(a as f64 + (b as f64 - a as f64) * t);
+        Rgba::new(mix(self.r, other.r), mix(self.g, other.g),
+                  mix(self.b, other.b), mix(self.a, other.a))
+    }
+
+    /*
+     * Parses "#rgb", "#rrggbb", "rgb(r, g, b)" and the handful of named colours Inkscape
+     * writes out. "none" is not a colour, so callers should check for it first.
+     */
+    pub fn parse(s: &str) -> Option<Rgba> {
+        let s = s.trim();
+        if s.starts_with('#') {
+            let hex = &s[1..];
+            // Slicing below is by byte.
+            if !hex.is_ascii() {
+                return None;
+            }
+            let digit = |i: usize, n: usize| u8::from_str_radix(&hex[i..i + n], 16).ok();
+            match hex.len() {
+                3 => digit(0, 1).and_then(|r| digit(1, 1).and_then(|g| digit(2, 1)
+                    .map(|b| Rgba::rgb(r * 17, g * 17, b * 17)))),
+                6 => digit(0, 2).and_then(|r| digit(2, 2).and_then(|g| digit(4, 2)
+                    .map(|b| Rgba::rgb(r, g, b)))),
+                _ => None
+            }
+        } else if s.starts_with("rgb(") && s.ends_with(')') {
+            let parts: Vec<Option<u8>> = s[4..s.len() - 1].split(',').map(|p| {
+                let p = p.trim();
+                if p.ends_with('%') {
+                    f64::from_str(&p[..p.len() - 1]).ok().map(|v| clamp_u8(v * 2.55))
+                } else {
+                    f64::from_str(p).ok().map(clamp_u8)
+                }
+            }).collect();
+            match parts.as_slice() {
+                [Some(r), Some(g), Some(b)] => Some(Rgba::rgb(*r, *g, *b)),
+                _ => None
+            }
+        } else {
+            match s {
+                "black" => Some(Rgba::rgb(0, 0, 0)),
+                "white" => Some(Rgba::rgb(255, 255, 255)),
+                "red" => Some(Rgba::rgb(255, 0, 0)),
+                "green" => Some(Rgba::rgb(0, 128, 0)),
+                "blue" => Some(Rgba::rgb(0, 0, 255)),
+                "yellow" => Some(Rgba::rgb(255, 255, 0)),
+                "orange" => Some(Rgba::rgb(255, 165, 0)),
+                "gray" | "grey" => Some(Rgba::rgb(128, 128, 128)),
+                "transparent" => Some(Rgba::new(0, 0, 0, 0)),
+                _ => None
+            }
+        }
+    }
+}
+
+pub fn clamp_u8(v: f64) -> u8 {
+    if v <= 0.0 { 0 } else if v >= 255.0 { 255 } else { v.round() as u8 }
+}
@@ -0,0 +1,130 @@
+/*
+ * Game controllers through SDL's controller API, which maps whatever is plugged in onto
+ * an Xbox style layout. Controllers are opened as they are plugged in (SDL reports those
+ * already there at startup the same way) and given the lowest free slot, which is the
+ * number ditties see them by.
+ */
+
+extern crate sdl2;
+
+use self::sdl2::GameControllerSubsystem;
+use self::sdl2::controller::Axis;
+use self::sdl2::controller::Button;
+use self::sdl2::controller::GameController;
+use self::sdl2::event::Event;
+
+use input::InputEvent;
+use input::PadAxis;
+use input::PadButton;
+
+pub struct Controllers {
+    subsystem: GameControllerSubsystem,
+    slots: Vec<Option<GameController>>
+}
+
+impl Controllers {
+    pub fn new(subsystem: GameControllerSubsystem) -> Controllers {
+        Controllers { subsystem: subsystem, slots: Vec::new() }
+    }
+
+    /*
+     * Controllers plugged in right now.
+     */
+    pub fn count(&self) -> usize {
+        self.slots.iter().filter(|s| s.is_some()).count()
+    }
+
+    /*
+     * The slot of the controller with an SDL joystick instance id, as controller events
+     * give.
+     */
+    fn slot(&self, instance: i32) -> Option<u32> {
+        self.slots.iter().position(|s| s.as_ref().map(|c| c.instance_id() == instance).unwrap_or(false))
+            .map(|i| i as u32)
+    }
+
+    fn add(&mut self, index: u32) -> Option<InputEvent> {
+        let controller = match self.subsystem.open(index) {
+            Ok(controller) => controller,
+            Err(e) => {
+                println!("Could not open controller {}: {:?}", index, e);
+                return None;
+            }
+        };
+        // Devices can be reported twice, at startup.
+        if self.slot(controller.instance_id()).is_some() {
+            return None;
+        }
+        println!("Controller connected: {}", controller.name());
+        let slot = match self.slots.iter().position(|s| s.is_none()) {
+            Some(i) => i,
+            None => {
+                self.slots.push(None);
+                self.slots.len() - 1
+            }
+        };
+        self.slots[slot] = Some(controller);
+        Some(InputEvent::PadAdded(slot as u32))
+    }
+
+    fn remove(&mut self, instance: i32) -> Option<InputEvent> {
+        self.slot(instance).map(|slot| {
+            if let Some(controller) = self.slots[slot as usize].take() {
+                println!("Controller disconnected: {}", controller.name());
+            }
+            InputEvent::PadRemoved(slot)
+        })
+    }
+
+    /*
+     * Turns a controller event into input, opening and closing controllers as they come
+     * and go. Other events give None.
+     */
+    pub fn translate(&mut self, ev: &Event) -> Option<InputEvent> {
+        match *ev {
+            Event::ControllerDeviceAdded { which, .. } => self.add(which as u32),
+            Event::ControllerDeviceRemoved { which, .. } => self.remove(which),
+            Event::ControllerButtonDown { which, button, .. } => self.slot(which).map(|pad| {
+                InputEvent::PadDown { pad: pad, button: Controllers::button(button) }
+            }),
+            Event::ControllerButtonUp { which, button, .. } => self.slot(which).map(|pad| {
+                InputEvent::PadUp { pad: pad, button: Controllers::button(button) }
+            }),
+            Event::ControllerAxisMotion { which, axis, value, .. } => self.slot(which).map(|pad| {
+                InputEvent::PadAxis { pad: pad, axis: Controllers::axis(axis), value: (value as f64 / 32767.0).max(-1.0) }
+            }),
+            _ => None
+        }
+    }
+
+    fn button(button: Button) -> PadButton {
+        match button {
+            Button::A => PadButton::A,
+            Button::B => PadButton::B,
+            Button::X => PadButton::X,
+            Button::Y => PadButton::Y,
+            Button::Back => PadButton::Back,
+            Button::Guide => PadButton::Guide,
+            Button::Start => PadButton::Start,
+            Button::LeftStick => PadButton::LeftStick,
+            Button::RightStick => PadButton::RightStick,
+            Button::LeftShoulder => PadButton::LeftShoulder,
+            Button::RightShoulder => PadButton::RightShoulder,
+            Button::DPadUp => PadButton::DPadUp,
+            Button::DPadDown => PadButton::DPadDown,
+            Button::DPadLeft => PadButton::DPadLeft,
+            Button::DPadRight => PadButton::DPadRight
+        }
+    }
+
+    fn axis(axis: Axis) -> PadAxis {
+        match axis {
+            Axis::LeftX => PadAxis::LeftX,
+            Axis::LeftY => PadAxis::LeftY,
+            Axis::RightX => PadAxis::RightX,
+            Axis::RightY => PadAxis::RightY,
+            Axis::TriggerLeft => PadAxis::LeftTrigger,
+            Axis::TriggerRight => PadAxis::RightTrigger
+        }
+    }
+}
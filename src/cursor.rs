@@ -0,0 +1,218 @@
+/*
+ * A pointer steered with a controller stick, for playing without a mouse. Small pushes
+ * move it slowly for fine placement; keeping the stick pushed builds up speed, so it can
+ * still cross the screen quickly.
+ *
+ * It hides as soon as the mouse moves, jumping to wherever the mouse is, and shows again
+ * when the stick is pushed.
+ */
+
+use backend::Backend;
+use color::Rgba;
+use input::InputEvent;
+use input::PadAxis;
+use raster::FillRule;
+
+pub struct VirtualCursor {
+    x: f64,
+    y: f64,
+    stick: (f64, f64),
+    held: f64,
+    visible: bool,
+    /*
+     * How far the stick must move, out of 1, before the cursor does.
+     */
+    pub deadzone: f64,
+    /*
+     * Screen pixels a second at full tilt, when the stick is first pushed and after
+     * `acceleration` has had time to build up to `max_speed`.
+     */
+    pub speed: f64,
+    pub max_speed: f64,
+    pub acceleration: f64
+}
+
+impl VirtualCursor {
+    pub fn new(x: f64, y: f64) -> VirtualCursor {
+        VirtualCursor {
+            x: x,
+            y: y,
+            stick: (0.0, 0.0),
+            held: 0.0,
+            visible: false,
+            deadzone: 0.2,
+            speed: 200.0,
+            max_speed: 1400.0,
+            acceleration: 1600.0
+        }
+    }
+
+    pub fn position(&self) -> (f64, f64) {
+        (self.x, self.y)
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /*
+     * Moves the cursor without showing it.
+     */
+    pub fn warp(&mut self, x: f64, y: f64) {
+        self.x = x;
+        self.y = y;
+    }
+
+    /*
+     * Lets go of the stick, as if it had been centred.
+     */
+    pub fn release(&mut self) {
+        self.stick = (0.0, 0.0);
+    }
+
+    /*
+     * Follows the left stick of any controller, and the mouse. Returns whether the event
+     * was one it uses.
+     */
+    pub fn handle_input(&mut self, event: &InputEvent) -> bool {
+        match *event {
+            InputEvent::PadAxis { axis: PadAxis::LeftX, value, .. } => self.stick.0 = value,
+            InputEvent::PadAxis { axis: PadAxis::LeftY, value, .. } => self.stick.1 = value,
+            InputEvent::PadRemoved(_) => self.stick = (0.0, 0.0),
+            InputEvent::MouseMove { x, y, .. } => {
+                self.warp(x as f64, y as f64);
+                self.visible = false;
+                return false;
+            },
+            _ => return false
+        }
+        true
+    }
+
+    /*
+     * How far the stick is pushed past the deadzone, rescaled to run from 0 to 1 so the
+     * slowest movement is still slow. The deadzone is round, so diagonals aren't sticky.
+     */
+    fn tilt(&self) -> (f64, f64) {
+        let (sx, sy) = self.stick;
+        let length = (sx * sx + sy * sy).sqrt();
+        if length <= self.deadzone {
+            return (0.0, 0.0);
+        }
+        let scaled = ((length - self.deadzone) / (1.0 - self.deadzone)).min(1.0);
+        (sx / length * scaled, sy / length * scaled)
+    }
+
+    /*
+     * Moves the cursor, keeping it on a screen of the given size.
+     */
+    pub fn update(&mut self, dt: f64, width: u32, height: u32) {
+        let (tx, ty) = self.tilt();
+        let amount = (tx * tx + ty * ty).sqrt();
+        if amount == 0.0 {
+            self.held = 0.0;
+            return;
+        }
+        self.held += dt;
+        self.visible = true;
+        // Squared, for finer control near the centre.
+        let speed = (self.speed + self.acceleration * self.held).min(self.max_speed) * amount * amount;
+        self.x = (self.x + tx / amount * speed * dt).min(width as f64 - 1.0).max(0.0);
+        self.y = (self.y + ty / amount * speed * dt).min(height as f64 - 1.0).max(0.0);
+    }
+
+    /*
+     * Draws an arrow with its tip on the cursor, in screen pixels.
+     */
+    pub fn draw(&self, backend: &mut Backend) {
+        if !self.visible {
+            return;
+        }
+        let (x, y) = (self.x.floor(), self.y.floor());
+        let arrow = vec![(x, y), (x, y + 16.0), (x + 4.0, y + 12.0), (x + 11.0, y + 11.0)];
+        backend.set_color(Rgba::rgb(255, 255, 255));
+        backend.fill_path(&[arrow.clone()], FillRule::NonZero);
+        backend.set_color(Rgba::rgb(0, 0, 0));
+        let mut outline = arrow;
+        outline.push((x, y));
+        backend.draw_polyline(&outline);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push(cursor: &mut VirtualCursor, x: f64, y: f64) {
+        cursor.handle_input(&InputEvent::PadAxis { pad: 0, axis: PadAxis::LeftX, value: x });
+        cursor.handle_input(&InputEvent::PadAxis { pad: 0, axis: PadAxis::LeftY, value: y });
+    }
+
+    #[test]
+    fn the_deadzone_holds_still() {
+        let mut cursor = VirtualCursor::new(100.0, 100.0);
+        // Just inside the round deadzone, diagonally.
+        push(&mut cursor, 0.14, -0.14);
+        for _ in 0..60 {
+            cursor.update(1.0 / 60.0, 640, 480);
+        }
+        assert_eq!(cursor.position(), (100.0, 100.0));
+        assert!(!cursor.is_visible());
+
+        push(&mut cursor, 0.3, 0.0);
+        cursor.update(1.0 / 60.0, 640, 480);
+        assert!(cursor.position().0 > 100.0 && cursor.position().1 == 100.0);
+        assert!(cursor.is_visible());
+    }
+
+    #[test]
+    fn holding_the_stick_speeds_up() {
+        let mut cursor = VirtualCursor::new(0.0, 240.0);
+        push(&mut cursor, 1.0, 0.0);
+        let mut last_step = 0.0;
+        for _ in 0..60 {
+            let before = cursor.position().0;
+            cursor.update(1.0 / 60.0, 100000, 480);
+            let step = cursor.position().0 - before;
+            assert!(step >= last_step);
+            last_step = step;
+        }
+        // Full speed is reached within the second, and kept to.
+        assert!((last_step - cursor.max_speed / 60.0).abs() < 1e-9);
+
+        // A smaller push goes slower.
+        let mut gentle = VirtualCursor::new(0.0, 240.0);
+        push(&mut gentle, 0.5, 0.0);
+        gentle.update(1.0 / 60.0, 100000, 480);
+        let mut full = VirtualCursor::new(0.0, 240.0);
+        push(&mut full, 1.0, 0.0);
+        full.update(1.0 / 60.0, 100000, 480);
+        assert!(gentle.position().0 < full.position().0);
+
+        // Letting go starts again from the slow speed.
+        cursor.release();
+        cursor.update(1.0 / 60.0, 100000, 480);
+        push(&mut cursor, 1.0, 0.0);
+        let before = cursor.position().0;
+        cursor.update(1.0 / 60.0, 100000, 480);
+        assert!(cursor.position().0 - before < last_step);
+    }
+
+    #[test]
+    fn the_cursor_stays_on_screen() {
+        let mut cursor = VirtualCursor::new(10.0, 10.0);
+        push(&mut cursor, -1.0, -1.0);
+        cursor.update(1.0, 320, 200);
+        assert_eq!(cursor.position(), (0.0, 0.0));
+        push(&mut cursor, 1.0, 1.0);
+        for _ in 0..10 {
+            cursor.update(1.0, 320, 200);
+        }
+        assert_eq!(cursor.position(), (319.0, 199.0));
+
+        // The mouse takes over and hides it.
+        cursor.handle_input(&InputEvent::MouseMove { x: 5, y: 6, dx: 0, dy: 0 });
+        assert_eq!(cursor.position(), (5.0, 6.0));
+        assert!(!cursor.is_visible());
+    }
+}
@@ -0,0 +1,425 @@
+/*
+ * Frame timing and draw statistics, and an overlay to show them on screen.
+ *
+ * The game loop times each frame and counts what the ditty draws through a
+ * CountingBackend, and the ditty stack does the same for each of its ditties. The last
+ * few seconds of frames are kept in Stats, which tests can inspect directly; the overlay
+ * only reads from it.
+ */
+
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use backend::Backend;
+use backend::Rect;
+use backend::TexturedQuad;
+use backend::TextureId;
+use color::Rgba;
+use geom::Polyline;
+use geom::Transform;
+use image::Image;
+use raster::FillRule;
+
+const HISTORY: usize = 240;
+
+pub fn ms_since(start: Instant) -> f64 {
+    let e = start.elapsed();
+    e.as_secs() as f64 * 1000.0 + e.subsec_nanos() as f64 / 1e6
+}
+
+/*
+ * Running totals kept by CountingBackend. Draw calls are reset every frame; the texture
+ * count is the number currently alive.
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DrawCounters {
+    pub draw_calls: u32,
+    pub textures: usize
+}
+
+/*
+ * Passes everything through to another backend, counting as it goes.
+ */
+pub struct CountingBackend<'a> {
+    inner: &'a mut Backend,
+    counters: &'a mut DrawCounters
+}
+
+impl<'a> CountingBackend<'a> {
+    pub fn new(inner: &'a mut Backend, counters: &'a mut DrawCounters) -> CountingBackend<'a> {
+        CountingBackend { inner: inner, counters: counters }
+    }
+}
+
+impl<'a> Backend for CountingBackend<'a> {
+    fn size(&self) -> (u32, u32) {
+        self.inner.size()
+    }
+
+    fn clear(&mut self, colour: Rgba) {
+        self.inner.clear(colour)
+    }
+
+    fn present(&mut self) {
+        self.inner.present()
+    }
+
+    fn read_pixels(&mut self) -> Result<Image, String> {
+        self.inner.read_pixels()
+    }
+
+    fn displays(&self) -> u32 {
+        self.inner.displays()
+    }
+
+    fn set_color(&mut self, colour: Rgba) {
+        self.inner.set_color(colour)
+    }
+
+    fn draw_line(&mut self, x0: f64, y0: f64, x1: f64, y1: f64) {
+        self.counters.draw_calls += 1;
+        self.inner.draw_line(x0, y0, x1, y1)
+    }
+
+    fn fill_rect(&mut self, x: f64, y: f64, w: f64, h: f64) {
+        self.counters.draw_calls += 1;
+        self.inner.fill_rect(x, y, w, h)
+    }
+
+    fn fill_path(&mut self, polys: &[Polyline], rule: FillRule) {
+        self.counters.draw_calls += 1;
+        self.inner.fill_path(polys, rule)
+    }
+
+    fn create_texture(&mut self, image: &Image) -> Result<TextureId, String> {
+        let tex = self.inner.create_texture(image);
+        if tex.is_ok() {
+            self.counters.textures += 1;
+        }
+        tex
+    }
+
+    fn update_texture(&mut self, tex: TextureId, x: u32, y: u32, image: &Image) -> Result<(), String> {
+        self.inner.update_texture(tex, x, y, image)
+    }
+
+    fn destroy_texture(&mut self, tex: TextureId) {
+        if self.inner.texture_size(tex).is_some() {
+            self.counters.textures -= 1;
+        }
+        self.inner.destroy_texture(tex)
+    }
+
+    fn texture_size(&self, tex: TextureId) -> Option<(u32, u32)> {
+        self.inner.texture_size(tex)
+    }
+
+    fn draw_texture(&mut self, tex: TextureId, src: Option<Rect>, x: f64, y: f64, w: f64, h: f64) {
+        self.counters.draw_calls += 1;
+        self.inner.draw_texture(tex, src, x, y, w, h)
+    }
+
+    fn set_tint(&mut self, tint: Rgba) {
+        self.inner.set_tint(tint)
+    }
+
+    fn tint(&self) -> Rgba {
+        self.inner.tint()
+    }
+
+    fn set_clip(&mut self, clip: Option<Rect>) {
+        self.inner.set_clip(clip)
+    }
+
+    fn clip(&self) -> Option<Rect> {
+        self.inner.clip()
+    }
+
+    fn push_transform(&mut self, t: &Transform) {
+        self.inner.push_transform(t)
+    }
+
+    fn pop_transform(&mut self) {
+        self.inner.pop_transform()
+    }
+
+    fn transform(&self) -> Transform {
+        self.inner.transform()
+    }
+
+    // Forwarded whole, so backends that batch polylines still can.
+    fn draw_polyline(&mut self, points: &[(f64, f64)]) {
+        self.counters.draw_calls += 1;
+        self.inner.draw_polyline(points)
+    }
+
+    fn draw_rect(&mut self, x: f64, y: f64, w: f64, h: f64) {
+        self.counters.draw_calls += 1;
+        self.inner.draw_rect(x, y, w, h)
+    }
+
+    fn draw_texture_batch(&mut self, tex: TextureId, quads: &[TexturedQuad]) {
+        self.counters.draw_calls += 1;
+        self.inner.draw_texture_batch(tex, quads)
+    }
+
+    fn fill_triangles(&mut self, vertices: &[(f64, f64)], indices: &[u32]) {
+        self.counters.draw_calls += 1;
+        self.inner.fill_triangles(vertices, indices)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DittyStats {
+    pub name: String,
+    pub update_ms: f64,
+    pub render_ms: f64,
+    pub draw_calls: u32
+}
+
+#[derive(Debug, Clone)]
+pub struct FrameStats {
+    pub update_ms: f64,
+    /*
+     * Fixed updates run during the frame; more than one means the loop was catching up.
+     */
+    pub updates: u32,
+    pub render_ms: f64,
+    /*
+     * Everything from the start of the frame to presenting it, not counting the wait
+     * for the next one.
+     */
+    pub frame_ms: f64,
+    pub draw_calls: u32,
+    pub textures: usize,
+    pub ditties: Vec<DittyStats>
+}
+
+pub struct Stats {
+    frames: VecDeque<FrameStats>,
+    intervals: VecDeque<f64>,
+    total_frames: u64
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats { frames: VecDeque::new(), intervals: VecDeque::new(), total_frames: 0 }
+    }
+
+    /*
+     * Records a frame. `interval_ms` is the time since the previous frame started, wait
+     * included, which is what the frame rate is worked out from.
+     */
+    pub fn record(&mut self, frame: FrameStats, interval_ms: f64) {
+        if self.frames.len() == HISTORY {
+            self.frames.pop_front();
+            self.intervals.pop_front();
+        }
+        self.frames.push_back(frame);
+        self.intervals.push_back(interval_ms);
+        self.total_frames += 1;
+    }
+
+    pub fn total_frames(&self) -> u64 {
+        self.total_frames
+    }
+
+    pub fn last(&self) -> Option<&FrameStats> {
+        self.frames.back()
+    }
+
+    /*
+     * Recent frames, oldest first.
+     */
+    pub fn history(&self) -> &VecDeque<FrameStats> {
+        &self.frames
+    }
+
+    pub fn fps(&self) -> f64 {
+        let total: f64 = self.intervals.iter().sum();
+        if total > 0.0 { self.intervals.len() as f64 * 1000.0 / total } else { 0.0 }
+    }
+
+    pub fn mean_frame_ms(&self) -> f64 {
+        if self.frames.is_empty() {
+            return 0.0;
+        }
+        self.frames.iter().map(|f| f.frame_ms).sum::<f64>() / self.frames.len() as f64
+    }
+
+    /*
+     * The frame time that the given fraction of recent frames came in under, e.g. 0.95.
+     */
+    pub fn percentile_frame_ms(&self, p: f64) -> f64 {
+        let mut times: Vec<f64> = self.frames.iter().map(|f| f.frame_ms).collect();
+        if times.is_empty() {
+            return 0.0;
+        }
+        times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let i = ((times.len() - 1) as f64 * p.max(0.0).min(1.0)).round() as usize;
+        times[i]
+    }
+}
+
+/*
+ * Glyphs for the overlay, three pixels wide and five high, row by row from the top-left.
+ */
+fn small_glyph(c: char) -> u16 {
+    match c.to_ascii_uppercase() {
+        '0' => 0b111101101101111,
+        '1' => 0b010110010010111,
+        '2' => 0b111001111100111,
+        '3' => 0b111001111001111,
+        '4' => 0b101101111001001,
+        '5' => 0b111100111001111,
+        '6' => 0b111100111101111,
+        '7' => 0b111001010010010,
+        '8' => 0b111101111101111,
+        '9' => 0b111101111001111,
+        'A' => 0b010101111101101,
+        'B' => 0b110101110101110,
+        'C' => 0b011100100100011,
+        'D' => 0b110101101101110,
+        'E' => 0b111100110100111,
+        'F' => 0b111100110100100,
+        'G' => 0b011100101101011,
+        'H' => 0b101101111101101,
+        'I' => 0b111010010010111,
+        'J' => 0b001001001101010,
+        'K' => 0b101101110101101,
+        'L' => 0b100100100100111,
+        'M' => 0b101111111101101,
+        'N' => 0b110101101101101,
+        'O' => 0b010101101101010,
+        'P' => 0b110101110100100,
+        'Q' => 0b010101101110011,
+        'R' => 0b110101110101101,
+        'S' => 0b011100010001110,
+        'T' => 0b111010010010010,
+        'U' => 0b101101101101111,
+        'V' => 0b101101101101010,
+        'W' => 0b101101111111101,
+        'X' => 0b101101010101101,
+        'Y' => 0b101101010010010,
+        'Z' => 0b111001010100111,
+        '.' => 0b000000000000010,
+        ':' => 0b000010000010000,
+        '/' => 0b001001010100100,
+        '-' => 0b000000111000000,
+        '+' => 0b000010111010000,
+        '>' => 0b100010001010100,
+        '%' => 0b101001010100101,
+        '(' => 0b001010010010001,
+        ')' => 0b100010010010100,
+        _ => 0
+    }
+}
+
+/*
+ * Draws text in the built-in overlay font, so the overlay works before any font has
+ * been loaded. Each glyph pixel is `scale` screen pixels square.
+ */
+pub fn draw_small_text(backend: &mut Backend, text: &str, x: f64, y: f64, scale: f64) {
+    for (i, c) in text.chars().enumerate() {
+        let bits = small_glyph(c);
+        let gx = x + i as f64 * 4.0 * scale;
+        for row in 0..5 {
+            for col in 0..3 {
+                if bits & (1 << (14 - (row * 3 + col))) != 0 {
+                    backend.fill_rect(gx + col as f64 * scale, y + row as f64 * scale, scale, scale);
+                }
+            }
+        }
+    }
+}
+
+pub struct Overlay {
+    pub visible: bool,
+    pub scale: f64
+}
+
+const GRAPH_HEIGHT: f64 = 60.0;
+// The graph's full height, in milliseconds.
+const GRAPH_RANGE: f64 = 50.0;
+
+impl Overlay {
+    pub fn new() -> Overlay {
+        Overlay { visible: false, scale: 2.0 }
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    /*
+     * Draws in screen coordinates at the top-left, whatever transform is current.
+     */
+    pub fn draw(&self, backend: &mut Backend, stats: &Stats) {
+        if !self.visible {
+            return;
+        }
+        let frame = match stats.last() {
+            Some(f) => f,
+            None => return
+        };
+        let s = self.scale;
+        let line = 7.0 * s;
+        let mut lines = vec![
+            format!("FPS {:.1}  FRAME {:.2}MS  P95 {:.2}MS", stats.fps(), frame.frame_ms,
+                    stats.percentile_frame_ms(0.95)),
+            format!("UPDATE {:.2}MS X{}  RENDER {:.2}MS", frame.update_ms, frame.updates, frame.render_ms),
+            format!("DRAWS {}  TEXTURES {}", frame.draw_calls, frame.textures)
+        ];
+        for d in &frame.ditties {
+            lines.push(format!("  {} {:.2}+{:.2}MS {} DRAWS", d.name, d.update_ms, d.render_ms, d.draw_calls));
+        }
+        let width = (HISTORY as f64).max(lines.iter().map(|l| l.len()).max().unwrap_or(0) as f64 * 4.0 * s);
+        let height = lines.len() as f64 * line + GRAPH_HEIGHT + 3.0 * s;
+
+        let screen = backend.transform().inverse().unwrap_or(Transform::identity());
+        backend.push_transform(&screen);
+        backend.set_color(Rgba::new(0, 0, 0, 160));
+        backend.fill_rect(0.0, 0.0, width + 2.0 * s, height);
+        backend.set_color(Rgba::rgb(255, 255, 255));
+        for (i, text) in lines.iter().enumerate() {
+            draw_small_text(backend, text, s, s + i as f64 * line, s);
+        }
+
+        // Frame time graph, with a line at the 60fps budget.
+        let base = height - s;
+        for (i, f) in stats.history().iter().enumerate() {
+            let h = (f.frame_ms / GRAPH_RANGE).min(1.0) * GRAPH_HEIGHT;
+            let r = (f.render_ms / GRAPH_RANGE).min(1.0) * GRAPH_HEIGHT;
+            let x = s + i as f64;
+            backend.set_color(if f.frame_ms > 1000.0 / 60.0 { Rgba::rgb(220, 60, 60) } else { Rgba::rgb(60, 200, 60) });
+            backend.fill_rect(x, base - h, 1.0, (h - r).max(0.0));
+            backend.set_color(Rgba::rgb(60, 120, 220));
+            backend.fill_rect(x, base - r, 1.0, r);
+        }
+        let budget = base - (1000.0 / 60.0) / GRAPH_RANGE * GRAPH_HEIGHT;
+        backend.set_color(Rgba::rgb(255, 255, 0));
+        backend.draw_line(s, budget, s + HISTORY as f64, budget);
+        backend.pop_transform();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use software::SoftwareBackend;
+
+    #[test]
+    fn batches_count_as_one_draw() {
+        let mut software = SoftwareBackend::new(8, 8);
+        let mut counters = DrawCounters::default();
+        {
+            let mut backend = CountingBackend::new(&mut software, &mut counters);
+            let tex = backend.create_texture(&Image::new(2, 2)).unwrap();
+            let quad = TexturedQuad { src: None, x: 0.0, y: 0.0, w: 2.0, h: 2.0, tint: Rgba::rgb(255, 255, 255) };
+            backend.draw_texture_batch(tex, &[quad, quad, quad]);
+            backend.draw_texture(tex, None, 4.0, 4.0, 2.0, 2.0);
+        }
+        assert_eq!(counters.draw_calls, 2);
+        assert_eq!(counters.textures, 1);
+    }
+}
@@ -1,120 +1,1042 @@
-
 /*
  * A ditty in this context is a "screen" displayed in a particular way. This could be an intro
  * sequence or a cut-scene, or the main game, or the "new game, continue, etc" screen.
  */
-extern crate sdl2;
 
 use std::borrow::Borrow;
+use std::rc::Rc;
 
-use self::sdl2::pixels::Color;
-use self::sdl2::render::Renderer;
-use self::sdl2::render::Texture;
-use self::sdl2::rect::Point;
-use self::sdl2::rect::Rect;
-
+use actions::ActionMap;
+use actions::Actions;
+use assets::Assets;
+use assets::Handle;
+use assets::Loading;
+use assets::Texture;
+use audio::Audio;
+use audio::Bus;
+use audio::Sound;
+use backend::Backend;
+use backend::TextureId;
+use camera::Camera;
+use cursor::VirtualCursor;
+use color::Rgba;
+use easing;
+use easing::CubicBezier;
+use geom;
+use geom::PathMeasure;
+use geom::Polyline;
+use geom::Transform;
+use debug;
+use dittystack::Command;
+use dittystack::Direction;
+use dittystack::Transition;
+use input::InputEvent;
+use input::Key;
+use input::PadAxis;
+use input::PadButton;
+use smil;
 use spath::PathElem;
+use spritecache::SpriteCache;
+use tessellate::Mesh;
+use tessellate::MeshCache;
+use svg::SvgDocument;
 use rendererutils::RendererUtils;
+use replay::StateHasher;
+use scene::Drawable;
+use scene::NodeId;
+use scene::Scene;
+use settings;
+use settings::VideoSettings;
+use settings::WindowMode;
 use utils::FatalAction;
 
 pub trait Ditty {
-    fn init(&mut self, renderer: &mut Renderer);
-    fn render(&mut self, renderer: &mut Renderer, width: u32, height: u32);
+    fn init(&mut self, backend: &mut Backend);
+
+    /*
+     * Advances the ditty by one fixed step of `dt` seconds. All game logic belongs here
+     * so that it runs the same whatever the frame rate.
+     */
+    fn update(&mut self, dt: f64) -> Option<Command> {
+        None
+    }
+
+    /*
+     * Draws the current state. `alpha` is how far the frame falls between the last update
+     * and the next, for smoothing motion; it must not change the state.
+     */
+    fn render(&mut self, backend: &mut Backend, width: u32, height: u32, alpha: f64);
+
+    /*
+     * Offers the ditty an input event, returning whether it was used. Unused events fall
+     * through to the game loop's own keys.
+     */
+    fn handle_input(&mut self, event: &InputEvent) -> bool {
+        false
+    }
+
+    /*
+     * Called when another ditty is pushed over this one in a stack. It gets no more input
+     * until it is uncovered, so the releases of anything held down now will go elsewhere.
+     */
+    fn covered(&mut self) {
+    }
+
+    /*
+     * Overlays, such as a pause menu, are drawn over the ditty beneath them in a stack
+     * instead of hiding it.
+     */
+    fn is_overlay(&self) -> bool {
+        false
+    }
+
+    /*
+     * The game loop stops once its ditty is finished.
+     */
+    fn is_finished(&self) -> bool {
+        false
+    }
+
+    /*
+     * Feeds everything that decides what the ditty does next into the hasher, so replays
+     * can be checked against the recording. What is left out simply isn't checked.
+     */
+    fn hash_state(&self, hasher: &mut StateHasher) {
+    }
+
+    /*
+     * Identifies the ditty in the debug overlay and frame statistics.
+     */
+    fn name(&self) -> &str {
+        "ditty"
+    }
 }
 
 pub struct BackgroundDitty {
-    logo: Option<Texture>
+    assets: Assets,
+    logo: Option<Handle<Texture>>
 }
 
 impl BackgroundDitty {
-    pub fn new() -> BackgroundDitty {
+    pub fn new(assets: Assets) -> BackgroundDitty {
         BackgroundDitty {
+            assets: assets,
             logo: None
         }
     }
 
-    fn draw_tex(renderer: &mut Renderer, image: &Texture, screen_width: u32, screen_height: u32) {
-        let fgq = image.query();
-        let fg_x = (screen_width / 2 - fgq.width / 2) as i32;
-        let fg_y = (screen_height / 2 - fgq.height / 2) as i32;
-        renderer.render_texture(&image, fg_x, fg_y);
+    fn draw_tex(backend: &mut Backend, image: TextureId, screen_width: u32, screen_height: u32) {
+        let (width, height) = backend.texture_size(image).unwrap_or((0, 0));
+        let fg_x = (screen_width / 2 - width / 2) as i32;
+        let fg_y = (screen_height / 2 - height / 2) as i32;
+        backend.render_texture(image, fg_x, fg_y);
     }
 
 }
 
 impl Ditty for BackgroundDitty {
-    fn init(&mut self, renderer: &mut Renderer) {
-        renderer.load_bmp("logo.bmp").map(|logo| {
+    fn init(&mut self, backend: &mut Backend) {
+        self.assets.texture(backend, "logo.bmp").map(|logo| {
             self.logo = Some(logo)
         }).or_die("load bmp");
     }
 
-    fn render(&mut self, renderer: &mut Renderer, width: u32, height: u32) {
-        let logo = self.logo.as_ref().unwrap();
-        BackgroundDitty::draw_tex(renderer, &logo, width, height);
+    fn render(&mut self, backend: &mut Backend, width: u32, height: u32, alpha: f64) {
+        let logo = self.logo.as_ref().unwrap().get().id;
+        BackgroundDitty::draw_tex(backend, logo, width, height);
+    }
+
+    fn name(&self) -> &str {
+        "background"
     }
 }
 
+/*
+ * Shows a progress bar while assets load in the background, then makes the next ditty
+ * from them and replaces itself with it.
+ */
+pub struct LoadingDitty {
+    loading: Loading,
+    next: Option<(Box<FnMut(&Assets) -> Box<Ditty>>, Transition)>,
+    /*
+     * Progress as shown, which catches up with the real progress rather than jumping.
+     */
+    shown: f64,
+    wait: bool,
+    pub background: Rgba,
+    pub colour: Rgba
+}
+
+/*
+ * How fast the bar catches up, as a fraction of the gap closed each second.
+ */
+const BAR_CATCH_UP: f64 = 8.0;
+
+impl LoadingDitty {
+    pub fn new<F>(loading: Loading, next: F, transition: Transition) -> LoadingDitty
+        where F: FnMut(&Assets) -> Box<Ditty> + 'static {
+        LoadingDitty {
+            loading: loading,
+            next: Some((Box::new(next), transition)),
+            shown: 0.0,
+            wait: false,
+            background: Rgba::rgb(255, 255, 255),
+            colour: Rgba::rgb(64, 64, 64)
+        }
+    }
+
+    /*
+     * Finishes loading in the first frame instead, so the loading screen always takes the
+     * same number of updates, as recordings and their replays need.
+     */
+    pub fn waiting(mut self) -> LoadingDitty {
+        self.wait = true;
+        self
+    }
+}
+
+impl Ditty for LoadingDitty {
+    fn init(&mut self, backend: &mut Backend) {
+    }
+
+    fn update(&mut self, dt: f64) -> Option<Command> {
+        self.shown += (self.loading.progress() - self.shown) * (BAR_CATCH_UP * dt).min(1.0);
+        if !self.loading.is_done() {
+            return None;
+        }
+        self.next.take().map(|(mut make, transition)| {
+            Command::Replace(make(self.loading.assets()), transition)
+        })
+    }
+
+    fn render(&mut self, backend: &mut Backend, width: u32, height: u32, alpha: f64) {
+        // Textures can only be made here, on the main thread.
+        if self.wait {
+            self.loading.finish(backend);
+        } else {
+            self.loading.poll(backend);
+        }
+        let (w, h) = (width as f64, height as f64);
+        backend.set_color(self.background);
+        backend.fill_rect(0.0, 0.0, w, h);
+        let (bar_w, bar_h) = ((w * 0.4).floor(), 8.0);
+        let (x, y) = (((w - bar_w) / 2.0).floor(), (h / 2.0).floor());
+        backend.set_color(self.colour);
+        backend.draw_rect(x - 2.0, y - 2.0, bar_w + 4.0, bar_h + 4.0);
+        backend.fill_rect(x, y, (bar_w * self.shown).round(), bar_h);
+        let label = format!("LOADING {}%", (self.loading.progress() * 100.0).floor());
+        debug::draw_small_text(backend, &label, ((w - label.len() as f64 * 8.0) / 2.0).floor(), y - 24.0, 2.0);
+    }
+
+    fn name(&self) -> &str {
+        "loading"
+    }
+}
+
+/*
+ * Shows paths with their control points, to be looked around with the pan, zoom and drag
+ * actions. Wheel zooms are about the pointer. Pausing pushes a pause menu.
+ *
+ * On a controller the left stick moves a cursor, which scrolls the view at the screen's
+ * edges, and the right stick zooms about it.
+ */
 pub struct PathDitty {
-    paths: Vec<Vec<PathElem>>
+    paths: Vec<Vec<PathElem>>,
+    bounds: Option<(f64, f64, f64, f64)>,
+    /*
+     * The document the paths came from, if they should follow it as it is reloaded, and
+     * the version they are from.
+     */
+    asset: Option<(Handle<SvgDocument>, u64)>,
+    /*
+     * The document's elements as drawn, under the control-point overlay.
+     */
+    sprites: SpriteCache,
+    /*
+     * Where to play the sound of grabbing the view, heard from the camera.
+     */
+    sound: Option<(Audio, Handle<Sound>)>,
+    camera: Camera,
+    actions: Actions,
+    cursor: VirtualCursor,
+    zoom_stick: f64,
+    dragging: bool,
+    paused: bool
 }
 
+/*
+ * Screen pixels a second.
+ */
+const PAN_SPEED: f64 = 600.0;
+
+/*
+ * How near the edge the controller cursor starts scrolling, in screen pixels.
+ */
+const EDGE_MARGIN: f64 = 48.0;
+
+/*
+ * Bytes of texture the path viewer may keep rasterised elements in.
+ */
+const SPRITE_BUDGET: usize = 16 << 20;
+
 impl PathDitty {
     pub fn new(paths: Vec<Vec<PathElem>>) -> PathDitty {
-        PathDitty { paths: paths }
+        let outlines: Vec<Polyline> = paths.iter().flat_map(|p| geom::flatten(p, 1.0)).collect();
+        PathDitty {
+            paths: paths,
+            bounds: geom::bounds(&outlines),
+            asset: None,
+            sprites: SpriteCache::new(SPRITE_BUDGET),
+            sound: None,
+            camera: Camera::new(0, 0),
+            actions: Actions::new(ActionMap::defaults()),
+            cursor: VirtualCursor::new(0.0, 0.0),
+            zoom_stick: 0.0,
+            dragging: false,
+            paused: false
+        }
+    }
+
+    /*
+     * Shows a document's paths, picking up changes to it as it is reloaded. The view
+     * stays where it is when they change.
+     */
+    pub fn from_asset(doc: Handle<SvgDocument>) -> PathDitty {
+        let mut ditty = PathDitty::new(doc.get().paths());
+        ditty.asset = Some((doc.clone(), doc.version()));
+        ditty
+    }
+
+    pub fn with_actions(mut self, map: ActionMap) -> PathDitty {
+        self.actions = Actions::new(map);
+        self
+    }
+
+    /*
+     * Clicks where the view is grabbed to drag it, panned and faded by where that is on
+     * screen.
+     */
+    pub fn with_click(mut self, audio: Audio, click: Handle<Sound>) -> PathDitty {
+        self.sound = Some((audio, click));
+        self
+    }
+
+    fn follow_asset(&mut self) {
+        let doc = match self.asset {
+            Some((ref doc, version)) if doc.version() != version => doc.clone(),
+            _ => return
+        };
+        let reloaded = PathDitty::new(doc.get().paths());
+        self.sprites.invalidate(doc.name());
+        self.paths = reloaded.paths;
+        self.bounds = reloaded.bounds;
+        self.asset = Some((doc.clone(), doc.version()));
     }
 }
 
 impl Ditty for PathDitty {
-    fn init(&mut self, renderer: &mut Renderer) {
+    fn init(&mut self, backend: &mut Backend) {
     }
 
-    fn render(&mut self, renderer: &mut Renderer, width: u32, height: u32) {
+    fn update(&mut self, dt: f64) -> Option<Command> {
+        let axis = |neg: bool, pos: bool| (pos as i32 - neg as i32) as f64;
+        let dx = axis(self.actions.is_down("pan_left"), self.actions.is_down("pan_right"));
+        let dy = axis(self.actions.is_down("pan_up"), self.actions.is_down("pan_down"));
+        if dx != 0.0 || dy != 0.0 {
+            self.camera.pan_screen(dx * PAN_SPEED * dt, dy * PAN_SPEED * dt);
+        }
+        let (width, height) = self.camera.viewport();
+        self.cursor.update(dt, width, height);
+        if self.cursor.is_visible() {
+            let (x, y) = self.cursor.position();
+            self.camera.edge_scroll(x, y, EDGE_MARGIN, PAN_SPEED, dt);
+            // Pushing up zooms in, doubling the zoom each second at full tilt.
+            if self.zoom_stick.abs() > self.cursor.deadzone {
+                self.camera.zoom_at(2f64.powf(-self.zoom_stick * dt), x, y);
+            }
+        }
+        self.camera.update(dt);
+        if let Some((ref audio, _)) = self.sound {
+            audio.mixer().listen_through(&self.camera);
+        }
+        if self.paused {
+            self.paused = false;
+            let pause = PauseDitty::new(self.actions.map().clone());
+            return Some(Command::Push(Box::new(pause), Transition::slide(Direction::Down, 0.3)));
+        }
+        None
+    }
+
+    fn covered(&mut self) {
+        self.actions.release_all();
+        self.cursor.release();
+        self.dragging = false;
+        self.zoom_stick = 0.0;
+    }
+
+    fn handle_input(&mut self, event: &InputEvent) -> bool {
+        let mut used = false;
+        for action in self.actions.apply(event) {
+            match &action[..] {
+                "pause" => self.paused = true,
+                "drag" => {
+                    self.dragging = true;
+                    if let Some((ref audio, ref click)) = self.sound {
+                        if let InputEvent::MouseDown { x, y, .. } = *event {
+                            let (wx, wy) = self.camera.screen_to_world(x as f64, y as f64);
+                            audio.mixer().play_at(click.get(), Bus::Sfx, 1.0, wx, wy);
+                        }
+                    }
+                },
+                "zoom_in" | "zoom_out" => {
+                    let (x, y, clicks) = match *event {
+                        InputEvent::Wheel { dy, x, y, .. } => (x as f64, y as f64, dy.abs()),
+                        _ if self.cursor.is_visible() => {
+                            let (x, y) = self.cursor.position();
+                            (x, y, 1)
+                        },
+                        _ => {
+                            let (w, h) = self.camera.viewport();
+                            (w as f64 / 2.0, h as f64 / 2.0, 1)
+                        }
+                    };
+                    let clicks = if action == "zoom_in" { clicks } else { -clicks };
+                    self.camera.zoom_at(1.1f64.powi(clicks), x, y);
+                },
+                _ => continue
+            }
+            used = true;
+        }
+        if self.dragging && !self.actions.is_down("drag") {
+            self.dragging = false;
+        }
+        if self.cursor.handle_input(event) {
+            return true;
+        }
+        match *event {
+            InputEvent::MouseMove { dx, dy, .. } if self.dragging => {
+                self.camera.pan_screen(-dx as f64, -dy as f64);
+                true
+            },
+            InputEvent::PadAxis { axis: PadAxis::RightY, value, .. } => {
+                self.zoom_stick = value;
+                true
+            },
+            InputEvent::PadRemoved(_) => {
+                self.zoom_stick = 0.0;
+                used
+            },
+            _ => used
+        }
+    }
+
+    fn render(&mut self, backend: &mut Backend, width: u32, height: u32, alpha: f64) {
+        self.follow_asset();
+        if self.camera.viewport() != (width, height) {
+            if self.camera.viewport() == (0, 0) {
+                self.cursor.warp(width as f64 / 2.0, height as f64 / 2.0);
+            }
+            self.camera.set_viewport(width, height);
+            if let Some((x0, y0, x1, y1)) = self.bounds {
+                self.camera.fit(x0, y0, x1, y1, 20.0);
+                self.camera.settle();
+            }
+        }
+        backend.push_transform(&self.camera.transform());
+        if let Some((ref doc, _)) = self.asset {
+            let scale = self.camera.transform().scale_factor();
+            let svg = doc.get();
+            for node in (0..svg.nodes.len()).filter(|&i| svg.nodes[i].parent.is_none()) {
+                if let Some(sprite) = self.sprites.get(backend, doc.name(), &svg, node, scale) {
+                    sprite.draw(backend);
+                }
+            }
+        }
         for path in &self.paths {
-            let mut cp = Point::new(0, 0);
+            let mut cp = (0.0, 0.0);
             for elem in path {
                 match elem {
                     &PathElem::MoveTo { x, y } => {
-                        cp = Point::new(x as i32, y as i32);
+                        cp = (x, y);
                     },
                     &PathElem::LineTo { x, y } => {
-                        let np = Point::new(x as i32, y as i32);
-                        renderer.set_draw_color(Color::RGB(255, 0, 0));
-                        renderer.draw_line(cp, np);
-                        cp = np;
+                        backend.set_color(Rgba::rgb(255, 0, 0));
+                        backend.draw_line(cp.0, cp.1, x, y);
+                        cp = (x, y);
                     },
                     &PathElem::CurveTo { x1, y1, x2, y2, x, y } => {
-                        let np = Point::new(x as i32, y as i32);
-                        let cp1 = Point::new(x1 as i32, y1 as i32);
-                        let cp2 = Point::new(x2 as i32, y2 as i32);
-                        renderer.set_draw_color(Color::RGB(64, 64, 64));
-                        renderer.draw_line(cp, cp1);
-                        renderer.draw_line(np, cp2);
-
-                        let cpr1 = Rect::new_unwrap((x1 as i32) - 1, (y1 as i32) - 1, 2, 2);
-                        let cpr2 = Rect::new_unwrap((x2 as i32) - 1, (y2 as i32) - 1, 2, 2);
-                        renderer.set_draw_color(Color::RGB(255, 128, 0));
-                        renderer.draw_rect(cpr1);
-                        renderer.draw_rect(cpr2);
-
-                        renderer.draw_line(cp, np);
-                        cp = np;
+                        backend.set_color(Rgba::rgb(64, 64, 64));
+                        backend.draw_line(cp.0, cp.1, x1, y1);
+                        backend.draw_line(x, y, x2, y2);
+
+                        backend.set_color(Rgba::rgb(255, 128, 0));
+                        backend.draw_rect(x1 - 1.0, y1 - 1.0, 2.0, 2.0);
+                        backend.draw_rect(x2 - 1.0, y2 - 1.0, 2.0, 2.0);
+
+                        backend.draw_line(cp.0, cp.1, x, y);
+                        cp = (x, y);
                     },
                     &PathElem::QuadraticTo { x, y, .. } => {
-                        let np = Point::new(x as i32, y as i32);
-                        renderer.set_draw_color(Color::RGB(0, 255, 0));
-                        renderer.draw_line(cp, np);
-                        cp = np;
+                        backend.set_color(Rgba::rgb(0, 255, 0));
+                        backend.draw_line(cp.0, cp.1, x, y);
+                        cp = (x, y);
                     },
                     &PathElem::ArcTo { x, y, .. } => {
-                        let np = Point::new(x as i32, y as i32);
-                        renderer.set_draw_color(Color::RGB(255, 255, 0));
-                        renderer.draw_line(cp, np);
-                        cp = np;
+                        backend.set_color(Rgba::rgb(255, 255, 0));
+                        backend.draw_line(cp.0, cp.1, x, y);
+                        cp = (x, y);
                     }
                 }
             }
         }
-        renderer.set_draw_color(Color::RGB(0, 0, 0));
+        backend.pop_transform();
+        self.cursor.draw(backend);
+        backend.set_color(Rgba::rgb(0, 0, 0));
+    }
+
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        let (x, y) = self.camera.position();
+        hasher.write_f64(x);
+        hasher.write_f64(y);
+        hasher.write_f64(self.camera.zoom());
+        hasher.write_f64(self.camera.rotation());
+        hasher.write_bool(self.dragging);
+        let (x, y) = self.cursor.position();
+        hasher.write_f64(x);
+        hasher.write_f64(y);
+        hasher.write_bool(self.cursor.is_visible());
+    }
+
+    fn name(&self) -> &str {
+        "paths"
+    }
+}
+
+/*
+ * Plays back an SVG document with its SMIL animations, scaled to fit the screen. Paths
+ * are drawn as outlines in their stroke colour, or their fill colour if unstroked.
+ *
+ * Each shape becomes a scene node; animation only moves, recolours and hides them.
+ */
+pub struct SvgAnimDitty {
+    doc: SvgDocument,
+    scene: Scene,
+    shapes: Vec<Option<NodeId>>,
+    time: f64,
+    prev_time: f64
+}
+
+impl SvgAnimDitty {
+    pub fn new(doc: SvgDocument) -> SvgAnimDitty {
+        let mut scene = Scene::new();
+        let root = scene.root();
+        let shapes = doc.nodes.iter().map(|node| node.shape.as_ref().map(|shape| {
+            let outlines = geom::flatten(&shape.path, 0.5);
+            scene.add(root, Some(Drawable::path(outlines, None, None)))
+        })).collect();
+        SvgAnimDitty { doc: doc, scene: scene, shapes: shapes, time: 0.0, prev_time: 0.0 }
+    }
+
+    fn clock(&self, alpha: f64) -> f64 {
+        self.prev_time + (self.time - self.prev_time) * alpha
+    }
+
+    fn fit(&self, width: u32, height: u32) -> Transform {
+        if self.doc.width <= 0.0 || self.doc.height <= 0.0 {
+            return Transform::identity();
+        }
+        let s = (width as f64 / self.doc.width).min(height as f64 / self.doc.height);
+        Transform::translate((width as f64 - self.doc.width * s) / 2.0,
+                             (height as f64 - self.doc.height * s) / 2.0)
+            .then(&Transform::scale(s, s))
+    }
+}
+
+impl Ditty for SvgAnimDitty {
+    fn init(&mut self, backend: &mut Backend) {
+    }
+
+    fn update(&mut self, dt: f64) -> Option<Command> {
+        self.prev_time = self.time;
+        self.time += dt;
+        None
+    }
+
+    fn render(&mut self, backend: &mut Backend, width: u32, height: u32, alpha: f64) {
+        let frame = smil::evaluate(&self.doc, self.clock(alpha));
+        for (i, shape) in self.shapes.iter().enumerate() {
+            let id = match *shape {
+                Some(id) => id,
+                None => continue
+            };
+            let colour = frame.stroke[i].or(frame.fill[i]).map(|c| c.with_alpha(frame.opacity[i]));
+            self.scene.set_visible(id, colour.is_some());
+            self.scene.set_transform(id, frame.transforms[i]);
+            if let Some(&mut Drawable::Path { ref mut stroke, .. }) = self.scene.drawable_mut(id) {
+                *stroke = colour;
+            }
+        }
+        backend.push_transform(&self.fit(width, height));
+        self.scene.draw(backend);
+        backend.pop_transform();
+        backend.set_color(Rgba::rgb(0, 0, 0));
+    }
+
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_f64(self.time);
+    }
+
+    fn name(&self) -> &str {
+        "svg animation"
+    }
+}
+
+struct IntroPath {
+    outlines: Vec<Polyline>,
+    measure: PathMeasure,
+    ink: Rgba,
+    fill: Option<(Rgba, Rc<Mesh>)>
+}
+
+/*
+ * The logo intro. Each path is traced out along its length, starting a little after the
+ * one before, and once the last is done the strokes fade into the filled logo.
+ */
+pub struct IntroDitty {
+    paths: Vec<IntroPath>,
+    bounds: Option<(f64, f64, f64, f64)>,
+    asset: Option<(Handle<SvgDocument>, u64)>,
+    meshes: MeshCache,
+    camera: Camera,
+    pub background: Rgba,
+    /*
+     * Seconds to trace one path, and between starting one path and the next.
+     */
+    pub stroke_time: f64,
+    pub stagger: f64,
+    pub fade_time: f64,
+    pub curve: CubicBezier,
+    time: f64,
+    prev_time: f64,
+    next: Option<(Box<Ditty>, Transition)>
+}
+
+impl IntroDitty {
+    pub fn new(doc: &SvgDocument) -> IntroDitty {
+        IntroDitty::named("", doc)
+    }
+
+    fn named(asset: &str, doc: &SvgDocument) -> IntroDitty {
+        let mut meshes = MeshCache::new(0.5);
+        let (paths, bounds) = IntroDitty::build(asset, doc, &mut meshes);
+        IntroDitty {
+            paths: paths,
+            bounds: bounds,
+            asset: None,
+            meshes: meshes,
+            camera: Camera::new(0, 0),
+            background: Rgba::rgb(255, 255, 255),
+            stroke_time: 1.5,
+            stagger: 0.2,
+            fade_time: 1.0,
+            curve: easing::EASE_IN_OUT,
+            time: 0.0,
+            prev_time: 0.0,
+            next: None
+        }
+    }
+
+    /*
+     * Plays a document, picking up changes to it as it is reloaded. The timing carries
+     * on as it was.
+     */
+    pub fn from_asset(doc: Handle<SvgDocument>) -> IntroDitty {
+        let mut ditty = IntroDitty::named(doc.name(), &doc.get());
+        ditty.asset = Some((doc.clone(), doc.version()));
+        ditty
+    }
+
+    /*
+     * The paths to trace, with the fills they fade into tessellated once up front.
+     */
+    fn build(asset: &str, doc: &SvgDocument, meshes: &mut MeshCache)
+             -> (Vec<IntroPath>, Option<(f64, f64, f64, f64)>) {
+        let paths: Vec<IntroPath> = doc.nodes.iter().enumerate().filter_map(|(i, node)| {
+            node.shape.as_ref().map(|shape| {
+                let outlines = geom::transform_polylines(&geom::flatten(&shape.path, 0.5),
+                                                         &doc.world_transform(i));
+                IntroPath {
+                    measure: PathMeasure::new(outlines.clone()),
+                    outlines: outlines,
+                    ink: shape.stroke.or(shape.fill).unwrap_or(Rgba::rgb(0, 0, 0)).with_alpha(node.opacity),
+                    fill: shape.fill.and_then(|c| {
+                        meshes.get(asset, doc, i).map(|mesh| (c.with_alpha(node.opacity), mesh))
+                    })
+                }
+            })
+        }).collect();
+        let all: Vec<Polyline> = paths.iter().flat_map(|p| p.outlines.iter().cloned()).collect();
+        let bounds = geom::bounds(&all);
+        (paths, bounds)
+    }
+
+    /*
+     * Rebuilds from the document if it has been reloaded, refitting it to the screen.
+     */
+    fn follow_asset(&mut self) {
+        let doc = match self.asset {
+            Some((ref doc, version)) if doc.version() != version => doc.clone(),
+            _ => return
+        };
+        self.meshes.invalidate(doc.name());
+        let (paths, bounds) = IntroDitty::build(doc.name(), &doc.get(), &mut self.meshes);
+        self.paths = paths;
+        self.bounds = bounds;
+        self.asset = Some((doc.clone(), doc.version()));
+        self.camera.set_viewport(0, 0);
+    }
+
+    /*
+     * Has the intro replace itself with another ditty once it is over, when run in a
+     * ditty stack.
+     */
+    pub fn then(mut self, next: Box<Ditty>, transition: Transition) -> IntroDitty {
+        self.next = Some((next, transition));
+        self
+    }
+
+    fn clock(&self, alpha: f64) -> f64 {
+        self.prev_time + (self.time - self.prev_time) * alpha
+    }
+
+    fn strokes_done_at(&self) -> f64 {
+        self.paths.len().saturating_sub(1) as f64 * self.stagger + self.stroke_time
+    }
+
+    /*
+     * The length of the whole sequence in seconds.
+     */
+    pub fn duration(&self) -> f64 {
+        self.strokes_done_at() + self.fade_time
+    }
+
+    /*
+     * Draws the sequence as it is `t` seconds in.
+     */
+    pub fn draw_at(&mut self, backend: &mut Backend, width: u32, height: u32, t: f64) {
+        if self.camera.viewport() != (width, height) {
+            self.camera.set_viewport(width, height);
+            if let Some((x0, y0, x1, y1)) = self.bounds {
+                self.camera.fit(x0, y0, x1, y1, 40.0);
+                self.camera.settle();
+            }
+        }
+        backend.set_color(self.background);
+        backend.fill_rect(0.0, 0.0, width as f64, height as f64);
+
+        let fade = easing::smoothstep(easing::clamp01((t - self.strokes_done_at()) / self.fade_time));
+        backend.push_transform(&self.camera.transform());
+        if fade > 0.0 {
+            for path in &self.paths {
+                if let Some((fill, ref mesh)) = path.fill {
+                    backend.set_color(fill.with_alpha(fade));
+                    mesh.draw(backend);
+                }
+            }
+        }
+        if fade < 1.0 {
+            for (i, path) in self.paths.iter().enumerate() {
+                let p = easing::clamp01((t - i as f64 * self.stagger) / self.stroke_time);
+                if p <= 0.0 {
+                    continue;
+                }
+                backend.set_color(path.ink.with_alpha(1.0 - fade));
+                for poly in path.measure.segment(0.0, self.curve.eval(p) * path.measure.length()) {
+                    backend.draw_polyline(&poly);
+                }
+            }
+        }
+        backend.pop_transform();
+    }
+}
+
+impl Ditty for IntroDitty {
+    fn init(&mut self, backend: &mut Backend) {
+    }
+
+    fn update(&mut self, dt: f64) -> Option<Command> {
+        self.prev_time = self.time;
+        self.time += dt;
+        if self.is_finished() {
+            if let Some((next, transition)) = self.next.take() {
+                return Some(Command::Replace(next, transition));
+            }
+        }
+        None
+    }
+
+    /*
+     * Any key or click skips to the end.
+     */
+    fn handle_input(&mut self, event: &InputEvent) -> bool {
+        match *event {
+            InputEvent::KeyDown { repeat: false, .. } | InputEvent::MouseDown { .. } => {
+                let end = self.duration();
+                self.time = end;
+                self.prev_time = end;
+                true
+            },
+            _ => false
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.time >= self.duration()
+    }
+
+    fn render(&mut self, backend: &mut Backend, width: u32, height: u32, alpha: f64) {
+        self.follow_asset();
+        let t = self.clock(alpha);
+        self.draw_at(backend, width, height, t);
+    }
+
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_f64(self.time);
+    }
+
+    fn name(&self) -> &str {
+        "intro"
+    }
+}
+
+/*
+ * Pauses whatever is beneath it until the pause or cancel action. O (or Y on a
+ * controller) opens the options and Q (or back) quits.
+ */
+pub struct PauseDitty {
+    actions: Actions,
+    resume: bool,
+    options: bool,
+    quit: bool
+}
+
+impl PauseDitty {
+    pub fn new(actions: ActionMap) -> PauseDitty {
+        PauseDitty { actions: Actions::new(actions), resume: false, options: false, quit: false }
+    }
+}
+
+impl Ditty for PauseDitty {
+    fn init(&mut self, backend: &mut Backend) {
+    }
+
+    fn update(&mut self, dt: f64) -> Option<Command> {
+        if self.quit {
+            Some(Command::Quit)
+        } else if self.options {
+            self.options = false;
+            Some(Command::Push(Box::new(OptionsDitty::new()), Transition::wipe(Direction::Right, 0.25)))
+        } else if self.resume {
+            self.resume = false;
+            Some(Command::Pop(Transition::slide(Direction::Up, 0.3)))
+        } else {
+            None
+        }
+    }
+
+    fn render(&mut self, backend: &mut Backend, width: u32, height: u32, alpha: f64) {
+        let (w, h) = (width as f64, height as f64);
+        backend.set_color(Rgba::new(0, 0, 0, 160));
+        backend.fill_rect(0.0, 0.0, w, h);
+        backend.set_color(Rgba::rgb(255, 255, 255));
+        let title = "PAUSED";
+        let hint = "ESC TO RESUME  O FOR OPTIONS  Q TO QUIT";
+        debug::draw_small_text(backend, title, ((w - title.len() as f64 * 24.0) / 2.0).floor(), (h / 2.0 - 40.0).floor(), 6.0);
+        debug::draw_small_text(backend, hint, ((w - hint.len() as f64 * 8.0) / 2.0).floor(), (h / 2.0 + 10.0).floor(), 2.0);
+    }
+
+    /*
+     * Takes every event, so nothing leaks through to the game loop while paused.
+     */
+    fn handle_input(&mut self, event: &InputEvent) -> bool {
+        for action in self.actions.apply(event) {
+            if action == "pause" || action == "cancel" {
+                self.resume = true;
+            }
+        }
+        match *event {
+            InputEvent::KeyDown { key: Key::Char('o'), .. } |
+            InputEvent::PadDown { button: PadButton::Y, .. } => self.options = true,
+            InputEvent::KeyDown { key: Key::Char('q'), .. } |
+            InputEvent::PadDown { button: PadButton::Back, .. } => self.quit = true,
+            _ => {}
+        }
+        true
+    }
+
+    fn is_overlay(&self) -> bool {
+        true
+    }
+
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_bool(self.resume);
+        hasher.write_bool(self.options);
+        hasher.write_bool(self.quit);
+    }
+
+    fn name(&self) -> &str {
+        "pause"
+    }
+}
+
+const RESOLUTIONS: [(u32, u32); 6] = [(0, 0), (1280, 720), (1366, 768), (1600, 900), (1920, 1080), (2560, 1440)];
+const FPS_CAPS: [Option<u32>; 5] = [None, Some(30), Some(60), Some(120), Some(144)];
+
+/*
+ * The video options, read from and applied through the settings file. Up and down pick
+ * a line, left and right change it, and return on "apply" sends the settings to the game
+ * loop. Escape goes back without applying.
+ */
+pub struct OptionsDitty {
+    settings: VideoSettings,
+    displays: u32,
+    selected: usize,
+    apply: bool,
+    back: bool
+}
+
+impl OptionsDitty {
+    pub fn new() -> OptionsDitty {
+        let settings = VideoSettings::load(settings::VIDEO_CONFIG).unwrap_or_else(|e| {
+            println!("{}", e);
+            VideoSettings::new()
+        });
+        OptionsDitty { settings: settings, displays: 1, selected: 0, apply: false, back: false }
+    }
+
+    fn lines(&self) -> Vec<String> {
+        let s = &self.settings;
+        let resolution = if s.resolution == (0, 0) {
+            "DESKTOP".to_string()
+        } else {
+            format!("{}X{}", s.resolution.0, s.resolution.1)
+        };
+        vec![
+            format!("MODE        {}", s.mode.name()),
+            format!("RESOLUTION  {}", resolution),
+            format!("DISPLAY     {}", s.display),
+            format!("VSYNC       {}", if s.vsync { "ON  (NEXT START)" } else { "OFF  (NEXT START)" }),
+            format!("FPS CAP     {}", s.fps_cap.map(|f| f.to_string()).unwrap_or("NONE".to_string())),
+            "APPLY".to_string(),
+            "BACK".to_string()
+        ]
+    }
+
+    /*
+     * Steps the selected line's value forwards or backwards, wrapping around.
+     */
+    fn change(&mut self, forward: bool) {
+        fn step<T: PartialEq + Copy>(options: &[T], current: T, forward: bool) -> T {
+            let i = options.iter().position(|&o| o == current).unwrap_or(0);
+            let n = options.len();
+            options[if forward { (i + 1) % n } else { (i + n - 1) % n }]
+        }
+        // A backend that can't count its displays still has the one it is drawing on.
+        let n = self.displays.max(1);
+        let s = &mut self.settings;
+        match self.selected {
+            0 => s.mode = step(&[WindowMode::Windowed, WindowMode::Borderless, WindowMode::Fullscreen], s.mode, forward),
+            1 => s.resolution = step(&RESOLUTIONS, s.resolution, forward),
+            2 => s.display = if forward { (s.display + 1) % n } else { (s.display + n - 1) % n },
+            3 => s.vsync = !s.vsync,
+            4 => s.fps_cap = step(&FPS_CAPS, s.fps_cap, forward),
+            _ => {}
+        }
+    }
+}
+
+impl Ditty for OptionsDitty {
+    fn init(&mut self, backend: &mut Backend) {
+        self.displays = backend.displays();
+        self.settings.clamp_display(self.displays);
+    }
+
+    fn update(&mut self, dt: f64) -> Option<Command> {
+        if self.apply {
+            self.apply = false;
+            Some(Command::Video(self.settings))
+        } else if self.back {
+            self.back = false;
+            Some(Command::Pop(Transition::wipe(Direction::Left, 0.25)))
+        } else {
+            None
+        }
+    }
+
+    fn render(&mut self, backend: &mut Backend, width: u32, height: u32, alpha: f64) {
+        let lines = self.lines();
+        let scale = 3.0;
+        let line_height = 9.0 * scale;
+        let (w, h) = (width as f64, height as f64);
+        let box_w = 34.0 * 4.0 * scale;
+        let box_h = lines.len() as f64 * line_height + 2.0 * line_height;
+        let (x, y) = (((w - box_w) / 2.0).floor(), ((h - box_h) / 2.0).floor());
+        backend.set_color(Rgba::new(16, 16, 32, 230));
+        backend.fill_rect(x, y, box_w, box_h);
+        for (i, line) in lines.iter().enumerate() {
+            let ly = y + line_height * (i as f64 + 1.0);
+            if i == self.selected {
+                backend.set_color(Rgba::rgb(255, 200, 0));
+                debug::draw_small_text(backend, ">", x + 2.0 * scale, ly, scale);
+            } else {
+                backend.set_color(Rgba::rgb(255, 255, 255));
+            }
+            debug::draw_small_text(backend, line, x + 8.0 * scale, ly, scale);
+        }
+    }
+
+    fn handle_input(&mut self, event: &InputEvent) -> bool {
+        let count = self.lines().len();
+        // The d-pad, A and B work the menu as the arrow keys, return and escape do.
+        let key = match *event {
+            InputEvent::KeyDown { key, .. } => key,
+            InputEvent::PadDown { button, .. } => match button {
+                PadButton::DPadUp => Key::Up,
+                PadButton::DPadDown => Key::Down,
+                PadButton::DPadLeft => Key::Left,
+                PadButton::DPadRight => Key::Right,
+                PadButton::A => Key::Return,
+                PadButton::B => Key::Escape,
+                _ => return true
+            },
+            _ => return true
+        };
+        match key {
+            Key::Up => self.selected = (self.selected + count - 1) % count,
+            Key::Down => self.selected = (self.selected + 1) % count,
+            Key::Left => self.change(false),
+            Key::Right => self.change(true),
+            Key::Return => match self.selected {
+                5 => self.apply = true,
+                6 => self.back = true,
+                _ => self.change(true)
+            },
+            Key::Escape => self.back = true,
+            _ => {}
+        }
+        true
+    }
+
+    fn is_overlay(&self) -> bool {
+        true
+    }
+
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_u64(self.selected as u64);
+        hasher.write_str(&self.settings.to_string());
+    }
+
+    fn name(&self) -> &str {
+        "options"
     }
 }
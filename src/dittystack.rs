@@ -0,0 +1,580 @@
+/*
+ * Runs a stack of ditties as one: the intro replaces itself with the title screen, which
+ * pushes the game, which pushes a pause menu over itself, and so on. Ditties ask for
+ * these changes by returning a command from `update`.
+ *
+ * Only the top ditty is updated and offered input. Everything from the topmost ditty
+ * that isn't an overlay upwards is drawn, so an overlay shows the (frozen) screen under
+ * it. Ditties pushed on are initialised before they are first drawn. They may be updated
+ * before that, so that what the game does never depends on when frames are drawn.
+ */
+
+use std::time::Instant;
+
+use backend::Backend;
+use backend::Rect;
+use color::Rgba;
+use debug;
+use debug::CountingBackend;
+use debug::DittyStats;
+use debug::DrawCounters;
+use ditty::Ditty;
+use easing;
+use easing::CubicBezier;
+use geom::Transform;
+use input::InputEvent;
+use replay::StateHasher;
+use settings::VideoSettings;
+
+/*
+ * The direction things move in during a slide or wipe.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down
+}
+
+impl Direction {
+    fn vector(&self) -> (f64, f64) {
+        match *self {
+            Direction::Left => (-1.0, 0.0),
+            Direction::Right => (1.0, 0.0),
+            Direction::Up => (0.0, -1.0),
+            Direction::Down => (0.0, 1.0)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    Cut,
+    /*
+     * Fades out to a colour and back in to the new screen.
+     */
+    Fade(Rgba),
+    /*
+     * The new screen pushes the old one off. When an overlay comes or goes, only it moves.
+     */
+    Slide(Direction),
+    /*
+     * The new screen is uncovered by an edge sweeping across the old.
+     */
+    Wipe(Direction)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Transition {
+    pub effect: Effect,
+    pub duration: f64,
+    pub curve: CubicBezier
+}
+
+impl Transition {
+    pub fn new(effect: Effect, duration: f64) -> Transition {
+        Transition { effect: effect, duration: duration, curve: easing::EASE_IN_OUT }
+    }
+
+    pub fn cut() -> Transition {
+        Transition::new(Effect::Cut, 0.0)
+    }
+
+    pub fn fade(colour: Rgba, duration: f64) -> Transition {
+        Transition::new(Effect::Fade(colour), duration)
+    }
+
+    pub fn slide(direction: Direction, duration: f64) -> Transition {
+        Transition::new(Effect::Slide(direction), duration)
+    }
+
+    pub fn wipe(direction: Direction, duration: f64) -> Transition {
+        Transition::new(Effect::Wipe(direction), duration)
+    }
+}
+
+pub enum Command {
+    Push(Box<Ditty>, Transition),
+    Pop(Transition),
+    Replace(Box<Ditty>, Transition),
+    /*
+     * Empties the stack, which ends the game loop.
+     */
+    Quit,
+    /*
+     * Not for the stack: passed on to the game loop, which changes the window to suit.
+     */
+    Video(VideoSettings)
+}
+
+/*
+ * A ditty on the stack, with what it has cost since its statistics were last taken.
+ */
+struct Layer {
+    ditty: Box<Ditty>,
+    ready: bool,
+    update_ms: f64,
+    render_ms: f64,
+    draw_calls: u32
+}
+
+impl Layer {
+    fn new(ditty: Box<Ditty>) -> Layer {
+        Layer { ditty: ditty, ready: false, update_ms: 0.0, render_ms: 0.0, draw_calls: 0 }
+    }
+
+    fn update(&mut self, dt: f64) -> Option<Command> {
+        let start = Instant::now();
+        let command = self.ditty.update(dt);
+        self.update_ms += debug::ms_since(start);
+        command
+    }
+
+    fn draw(&mut self, backend: &mut Backend, width: u32, height: u32, alpha: f64) {
+        let start = Instant::now();
+        let mut counters = DrawCounters::default();
+        {
+            let mut backend = CountingBackend::new(backend, &mut counters);
+            if !self.ready {
+                self.ditty.init(&mut backend);
+                self.ready = true;
+            }
+            self.ditty.render(&mut backend, width, height, alpha);
+        }
+        self.render_ms += debug::ms_since(start);
+        self.draw_calls += counters.draw_calls;
+    }
+
+    fn take_stats(&mut self) -> DittyStats {
+        let stats = DittyStats {
+            name: self.ditty.name().to_string(),
+            update_ms: self.update_ms,
+            render_ms: self.render_ms,
+            draw_calls: self.draw_calls
+        };
+        self.update_ms = 0.0;
+        self.render_ms = 0.0;
+        self.draw_calls = 0;
+        stats
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Change {
+    Push,
+    Pop,
+    Replace
+}
+
+/*
+ * A change in progress. Popped and replaced ditties are kept until it is over.
+ */
+struct Active {
+    change: Change,
+    transition: Transition,
+    outgoing: Option<Layer>,
+    elapsed: f64,
+    prev_elapsed: f64
+}
+
+pub struct DittyStack {
+    layers: Vec<Layer>,
+    active: Option<Active>
+}
+
+impl DittyStack {
+    pub fn new(first: Box<Ditty>) -> DittyStack {
+        DittyStack { layers: vec![Layer::new(first)], active: None }
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    pub fn in_transition(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /*
+     * Carries out a command, cutting short any transition still running, or hands it
+     * back if it is for the game loop.
+     */
+    pub fn apply(&mut self, command: Command) -> Option<Command> {
+        if let Command::Video(_) = command {
+            return Some(command);
+        }
+        self.active = None;
+        let (change, transition, outgoing) = match command {
+            Command::Push(ditty, transition) => {
+                if let Some(top) = self.layers.last_mut() {
+                    top.ditty.covered();
+                }
+                self.layers.push(Layer::new(ditty));
+                (Change::Push, transition, None)
+            },
+            Command::Pop(transition) => {
+                let outgoing = self.layers.pop();
+                (Change::Pop, transition, outgoing)
+            },
+            Command::Replace(ditty, transition) => {
+                let outgoing = self.layers.pop();
+                self.layers.push(Layer::new(ditty));
+                (Change::Replace, transition, outgoing)
+            },
+            Command::Quit => {
+                self.layers.clear();
+                return None;
+            },
+            Command::Video(_) => unreachable!()
+        };
+        if transition.duration > 0.0 && transition.effect != Effect::Cut && !self.layers.is_empty() {
+            self.active = Some(Active {
+                change: change,
+                transition: transition,
+                outgoing: outgoing,
+                elapsed: 0.0,
+                prev_elapsed: 0.0
+            });
+        }
+        None
+    }
+
+    /*
+     * The ditties to draw for the stack as it was before the change in progress, or as it
+     * is now: the topmost opaque one and everything above it, bottom first. None stands
+     * for the ditty on its way out.
+     */
+    fn visible(&self, before: bool) -> Vec<Option<usize>> {
+        let mut slots: Vec<Option<usize>> = match self.active {
+            Some(ref active) if before => {
+                let kept = if active.change == Change::Pop { self.layers.len() } else { self.layers.len() - 1 };
+                let mut slots: Vec<Option<usize>> = (0..kept).map(Some).collect();
+                if active.outgoing.is_some() {
+                    slots.push(None);
+                }
+                slots
+            },
+            _ => (0..self.layers.len()).map(Some).collect()
+        };
+        let start = slots.iter().rposition(|&slot| !self.layer(slot).ditty.is_overlay()).unwrap_or(0);
+        slots.drain(..start);
+        slots
+    }
+
+    fn layer(&self, slot: Option<usize>) -> &Layer {
+        match slot {
+            Some(i) => &self.layers[i],
+            None => self.active.as_ref().and_then(|a| a.outgoing.as_ref()).expect("no ditty on its way out")
+        }
+    }
+
+    /*
+     * Draws the given ditties. The top one alone is drawn between updates; the rest are
+     * frozen and drawn as they stand.
+     */
+    fn draw_slots(&mut self, slots: &[Option<usize>], backend: &mut Backend, width: u32, height: u32, alpha: f64) {
+        let top = self.layers.len().saturating_sub(1);
+        for &slot in slots {
+            let layer = match slot {
+                Some(i) => &mut self.layers[i],
+                None => match self.active.as_mut().and_then(|a| a.outgoing.as_mut()) {
+                    Some(layer) => layer,
+                    None => continue
+                }
+            };
+            layer.draw(backend, width, height, if slot == Some(top) { alpha } else { 1.0 });
+        }
+    }
+
+    /*
+     * What each ditty has cost since this was last called, from the bottom of the stack
+     * up and then any on its way out, for the game loop to call once a frame.
+     */
+    pub fn take_stats(&mut self) -> Vec<DittyStats> {
+        let mut stats: Vec<DittyStats> = self.layers.iter_mut().map(|l| l.take_stats()).collect();
+        if let Some(layer) = self.active.as_mut().and_then(|a| a.outgoing.as_mut()) {
+            stats.push(layer.take_stats());
+        }
+        stats
+    }
+}
+
+/*
+ * The part of `area` inside the clip, or None if there is nothing to draw.
+ */
+fn within(clip: Option<Rect>, area: Rect) -> Option<Rect> {
+    match clip {
+        Some(c) => c.intersect(&area),
+        // An empty clip can mean no clip.
+        None if area.w > 0 && area.h > 0 => Some(area),
+        None => None
+    }
+}
+
+impl Ditty for DittyStack {
+    fn init(&mut self, backend: &mut Backend) {
+        for layer in self.layers.iter_mut().filter(|l| !l.ready) {
+            layer.ditty.init(backend);
+            layer.ready = true;
+        }
+    }
+
+    fn update(&mut self, dt: f64) -> Option<Command> {
+        let finished = match self.active {
+            Some(ref mut active) => {
+                active.prev_elapsed = active.elapsed;
+                active.elapsed += dt;
+                active.elapsed >= active.transition.duration
+            },
+            None => false
+        };
+        if finished {
+            self.active = None;
+        }
+        let command = match self.layers.last_mut() {
+            Some(top) => top.update(dt),
+            None => None
+        };
+        command.and_then(|c| self.apply(c))
+    }
+
+    fn render(&mut self, backend: &mut Backend, width: u32, height: u32, alpha: f64) {
+        if self.layers.is_empty() {
+            return;
+        }
+        let (transition, p) = match self.active {
+            Some(ref a) => {
+                let t = a.prev_elapsed + (a.elapsed - a.prev_elapsed) * alpha;
+                (a.transition, a.transition.curve.eval(easing::clamp01(t / a.transition.duration)))
+            },
+            None => {
+                let now = self.visible(false);
+                self.draw_slots(&now, backend, width, height, alpha);
+                return;
+            }
+        };
+        // What the stack had and has in common is drawn once, and only the rest moves.
+        let (old, new) = (self.visible(true), self.visible(false));
+        let shared = old.iter().zip(new.iter()).take_while(|&(a, b)| a == b).count();
+        let (w, h) = (width as f64, height as f64);
+        match transition.effect {
+            Effect::Cut => {
+                self.draw_slots(&new, backend, width, height, alpha);
+            },
+            Effect::Fade(colour) => {
+                let cover = if p < 0.5 {
+                    self.draw_slots(&old, backend, width, height, alpha);
+                    p * 2.0
+                } else {
+                    self.draw_slots(&new, backend, width, height, alpha);
+                    (1.0 - p) * 2.0
+                };
+                backend.set_color(colour.with_alpha(cover));
+                backend.fill_rect(0.0, 0.0, w, h);
+            },
+            Effect::Slide(direction) => {
+                let (ux, uy) = direction.vector();
+                let (ux, uy) = (ux * w, uy * h);
+                let entering = (-ux * (1.0 - p), -uy * (1.0 - p));
+                let leaving = (ux * p, uy * p);
+                self.draw_slots(&new[..shared], backend, width, height, alpha);
+                backend.push_transform(&Transform::translate(leaving.0, leaving.1));
+                self.draw_slots(&old[shared..], backend, width, height, alpha);
+                backend.pop_transform();
+                backend.push_transform(&Transform::translate(entering.0, entering.1));
+                self.draw_slots(&new[shared..], backend, width, height, alpha);
+                backend.pop_transform();
+            },
+            Effect::Wipe(direction) => {
+                // The edge splits the screen into the part revealed and the part still covered.
+                let (split_x, split_y) = ((w * p).round() as u32, (h * p).round() as u32);
+                let (reveal, covered) = match direction {
+                    Direction::Right => (Rect::new(0, 0, split_x, height),
+                                         Rect::new(split_x as i32, 0, width - split_x, height)),
+                    Direction::Left => (Rect::new((width - split_x) as i32, 0, split_x, height),
+                                        Rect::new(0, 0, width - split_x, height)),
+                    Direction::Down => (Rect::new(0, 0, width, split_y),
+                                        Rect::new(0, split_y as i32, width, height - split_y)),
+                    Direction::Up => (Rect::new(0, (height - split_y) as i32, width, split_y),
+                                      Rect::new(0, 0, width, height - split_y))
+                };
+                self.draw_slots(&new[..shared], backend, width, height, alpha);
+                let clip = backend.clip();
+                if let Some(area) = within(clip, covered) {
+                    backend.set_clip(Some(area));
+                    self.draw_slots(&old[shared..], backend, width, height, alpha);
+                }
+                if let Some(area) = within(clip, reveal) {
+                    backend.set_clip(Some(area));
+                    self.draw_slots(&new[shared..], backend, width, height, alpha);
+                }
+                backend.set_clip(clip);
+            }
+        }
+    }
+
+    fn handle_input(&mut self, event: &InputEvent) -> bool {
+        match self.layers.last_mut() {
+            Some(top) => top.ditty.handle_input(event),
+            None => false
+        }
+    }
+
+    fn covered(&mut self) {
+        if let Some(top) = self.layers.last_mut() {
+            top.ditty.covered();
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_u64(self.layers.len() as u64);
+        for layer in &self.layers {
+            hasher.write_str(layer.ditty.name());
+            layer.ditty.hash_state(hasher);
+        }
+        if let Some(ref active) = self.active {
+            hasher.write_f64(active.elapsed);
+        }
+    }
+
+    fn name(&self) -> &str {
+        self.layers.last().map(|l| l.ditty.name()).unwrap_or("empty")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use software::SoftwareBackend;
+
+    struct Probe {
+        covered: Rc<Cell<u32>>,
+        drawn: Rc<Cell<u32>>
+    }
+
+    impl Probe {
+        fn new(covered: &Rc<Cell<u32>>) -> Box<Probe> {
+            Box::new(Probe { covered: covered.clone(), drawn: Rc::new(Cell::new(0)) })
+        }
+    }
+
+    impl Ditty for Probe {
+        fn init(&mut self, backend: &mut Backend) {
+        }
+
+        fn render(&mut self, backend: &mut Backend, width: u32, height: u32, alpha: f64) {
+            self.drawn.set(self.drawn.get() + 1);
+        }
+
+        fn covered(&mut self) {
+            self.covered.set(self.covered.get() + 1);
+        }
+    }
+
+    #[test]
+    fn pushing_covers_the_top_ditty() {
+        let (bottom, top) = (Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+        let mut stack = DittyStack::new(Probe::new(&bottom));
+        stack.apply(Command::Push(Probe::new(&top), Transition::cut()));
+        assert_eq!((bottom.get(), top.get()), (1, 0));
+        stack.apply(Command::Pop(Transition::cut()));
+        stack.apply(Command::Replace(Probe::new(&top), Transition::cut()));
+        assert_eq!((bottom.get(), top.get()), (1, 0));
+    }
+
+    #[test]
+    fn wipes_draw_nothing_new_before_they_start() {
+        let covered = Rc::new(Cell::new(0));
+        let (old, new) = (Probe::new(&covered), Probe::new(&covered));
+        let (old_drawn, new_drawn) = (old.drawn.clone(), new.drawn.clone());
+        let mut stack = DittyStack::new(old);
+        stack.apply(Command::Push(new, Transition::wipe(Direction::Right, 0.25)));
+        let mut backend = SoftwareBackend::new(8, 8);
+        stack.render(&mut backend, 8, 8, 0.0);
+        assert_eq!((old_drawn.get(), new_drawn.get()), (1, 0));
+        stack.update(0.125);
+        stack.render(&mut backend, 8, 8, 1.0);
+        assert_eq!((old_drawn.get(), new_drawn.get()), (2, 1));
+    }
+
+    /*
+     * Fills a number of rects a frame.
+     */
+    struct Painter {
+        name: &'static str,
+        rects: u32,
+        overlay: bool
+    }
+
+    impl Ditty for Painter {
+        fn init(&mut self, backend: &mut Backend) {
+        }
+
+        fn render(&mut self, backend: &mut Backend, width: u32, height: u32, alpha: f64) {
+            for _ in 0..self.rects {
+                backend.fill_rect(0.0, 0.0, 1.0, 1.0);
+            }
+        }
+
+        fn is_overlay(&self) -> bool {
+            self.overlay
+        }
+
+        fn name(&self) -> &str {
+            self.name
+        }
+    }
+
+    #[test]
+    fn stats_are_kept_for_each_ditty() {
+        let mut stack = DittyStack::new(Box::new(Painter { name: "game", rects: 1, overlay: false }));
+        stack.apply(Command::Push(Box::new(Painter { name: "menu", rects: 3, overlay: true }), Transition::cut()));
+        let mut backend = SoftwareBackend::new(8, 8);
+        let mut counters = DrawCounters::default();
+        stack.update(0.125);
+        stack.render(&mut CountingBackend::new(&mut backend, &mut counters), 8, 8, 0.0);
+        let stats = stack.take_stats();
+        let names: Vec<&str> = stats.iter().map(|d| &d.name[..]).collect();
+        assert_eq!(names, vec!["game", "menu"]);
+        assert_eq!((stats[0].draw_calls, stats[1].draw_calls), (1, 3));
+        // Only the top ditty is updated.
+        assert_eq!(stats[0].update_ms, 0.0);
+        assert_eq!(counters.draw_calls, 4);
+
+        let stats = stack.take_stats();
+        assert!(stats.iter().all(|d| d.draw_calls == 0 && d.render_ms == 0.0));
+    }
+
+    #[test]
+    fn each_ditty_is_drawn_once_a_frame_in_transitions() {
+        let transitions = [Transition::slide(Direction::Down, 0.25), Transition::wipe(Direction::Left, 0.25)];
+        for transition in transitions.iter() {
+            let mut stack = DittyStack::new(Box::new(Painter { name: "game", rects: 1, overlay: false }));
+            let mut backend = SoftwareBackend::new(8, 8);
+            stack.apply(Command::Push(Box::new(Painter { name: "menu", rects: 3, overlay: true }), *transition));
+            stack.update(0.125);
+            stack.render(&mut backend, 8, 8, 1.0);
+            let stats = stack.take_stats();
+            assert_eq!((stats[0].draw_calls, stats[1].draw_calls), (1, 3));
+
+            stack.apply(Command::Pop(*transition));
+            stack.update(0.125);
+            stack.render(&mut backend, 8, 8, 1.0);
+            let stats = stack.take_stats();
+            let names: Vec<&str> = stats.iter().map(|d| &d.name[..]).collect();
+            assert_eq!(names, vec!["game", "menu"]);
+            assert_eq!((stats[0].draw_calls, stats[1].draw_calls), (1, 3));
+        }
+    }
+}
@@ -0,0 +1,78 @@
+/*
+ * Timing curves. Everything takes and returns a normalised time in [0, 1].
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CubicBezier {
+    pub x1: f64,
+    pub y1: f64,
+    pub x2: f64,
+    pub y2: f64
+}
+
+pub const LINEAR: CubicBezier = CubicBezier { x1: 0.0, y1: 0.0, x2: 1.0, y2: 1.0 };
+pub const EASE: CubicBezier = CubicBezier { x1: 0.25, y1: 0.1, x2: 0.25, y2: 1.0 };
+pub const EASE_IN: CubicBezier = CubicBezier { x1: 0.42, y1: 0.0, x2: 1.0, y2: 1.0 };
+pub const EASE_OUT: CubicBezier = CubicBezier { x1: 0.0, y1: 0.0, x2: 0.58, y2: 1.0 };
+pub const EASE_IN_OUT: CubicBezier = CubicBezier { x1: 0.42, y1: 0.0, x2: 0.58, y2: 1.0 };
+
+impl CubicBezier {
+    pub fn new(x1: f64, y1: f64, x2: f64, y2: f64) -> CubicBezier {
+        CubicBezier { x1: x1, y1: y1, x2: x2, y2: y2 }
+    }
+
+    fn sample(a1: f64, a2: f64, t: f64) -> f64 {
+        let u = 1.0 - t;
+        3.0 * u * u * t * a1 + 3.0 * u * t * t * a2 + t * t * t
+    }
+
+    fn slope(a1: f64, a2: f64, t: f64) -> f64 {
+        let u = 1.0 - t;
+        3.0 * u * u * a1 + 6.0 * u * t * (a2 - a1) + 3.0 * t * t * (1.0 - a2)
+    }
+
+    /*
+     * Finds the curve parameter for x with a few Newton steps, falling back to bisection
+     * where the slope is too flat to trust.
+     */
+    pub fn eval(&self, x: f64) -> f64 {
+        if x <= 0.0 {
+            return 0.0;
+        }
+        if x >= 1.0 {
+            return 1.0;
+        }
+        let mut t = x;
+        for _ in 0..8 {
+            let err = CubicBezier::sample(self.x1, self.x2, t) - x;
+            if err.abs() < 1e-7 {
+                return CubicBezier::sample(self.y1, self.y2, t);
+            }
+            let d = CubicBezier::slope(self.x1, self.x2, t);
+            if d.abs() < 1e-6 {
+                break;
+            }
+            t -= err / d;
+        }
+        let (mut lo, mut hi) = (0.0, 1.0);
+        t = x;
+        for _ in 0..32 {
+            let v = CubicBezier::sample(self.x1, self.x2, t);
+            if (v - x).abs() < 1e-7 {
+                break;
+            }
+            if v < x { lo = t; } else { hi = t; }
+            t = (lo + hi) / 2.0;
+        }
+        CubicBezier::sample(self.y1, self.y2, t)
+    }
+}
+
+pub fn smoothstep(t: f64) -> f64 {
+    let t = clamp01(t);
+    t * t * (3.0 - 2.0 * t)
+}
+
+pub fn clamp01(t: f64) -> f64 {
+    if t < 0.0 { 0.0 } else if t > 1.0 { 1.0 } else { t }
+}
@@ -0,0 +1,482 @@
+/*
+ * Bitmap fonts in the AngelCode BMFont format, as written by BMFont, Hiero and most
+ * other bitmap font tools. Both the text and the binary (version 3) descriptors are
+ * read; pages are loaded as BMP, or PNG if the descriptor names one.
+ *
+ * Glyph pages are expected to be white on transparent so that the tint gives the text
+ * its colour. Pages without an alpha channel are treated as white with coverage taken
+ * from their brightness.
+ */
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use backend::Backend;
+use backend::Rect;
+use backend::TextureId;
+use color::Rgba;
+use image::Image;
+use png;
+use rendererutils::RendererUtils;
+use scene::TextRenderer;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Centre,
+    Right
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Glyph {
+    pub page: usize,
+    pub src: Rect,
+    pub offset: (f64, f64),
+    pub advance: f64
+}
+
+/*
+ * Everything in a font descriptor. Pages are file names relative to the descriptor.
+ */
+pub struct FontDesc {
+    pub line_height: f64,
+    pub base: f64,
+    pub pages: Vec<String>,
+    pub glyphs: HashMap<u32, Glyph>,
+    pub kerning: HashMap<(u32, u32), f64>
+}
+
+/*
+ * One glyph placed by layout, relative to the top-left of the text.
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct PlacedGlyph {
+    pub texture: TextureId,
+    pub src: Rect,
+    pub x: f64,
+    pub y: f64
+}
+
+pub struct Font {
+    desc: FontDesc,
+    pages: Vec<TextureId>
+}
+
+/*
+ * Splits a descriptor line into its tag and key=value pairs, allowing quoted values
+ * with spaces in them.
+ */
+fn parse_tags(line: &str) -> (String, HashMap<String, String>) {
+    let mut tokens = Vec::<String>::new();
+    let mut token = String::new();
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ' ' | '\t' if !quoted => {
+                if !token.is_empty() {
+                    tokens.push(token.clone());
+                    token.clear();
+                }
+            },
+            _ => token.push(c)
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    let tag = if tokens.is_empty() { String::new() } else { tokens.remove(0) };
+    let pairs = tokens.iter().filter_map(|t| {
+        t.find('=').map(|i| (t[..i].to_string(), t[i + 1..].to_string()))
+    }).collect();
+    (tag, pairs)
+}
+
+fn parse_text(text: &str) -> Result<FontDesc, String> {
+    let mut desc = FontDesc {
+        line_height: 0.0,
+        base: 0.0,
+        pages: Vec::new(),
+        glyphs: HashMap::new(),
+        kerning: HashMap::new()
+    };
+    for (n, line) in text.lines().enumerate() {
+        let (tag, pairs) = parse_tags(line);
+        let num = |key: &str| -> Result<f64, String> {
+            pairs.get(key).and_then(|v| v.parse::<f64>().ok())
+                .ok_or(format!("line {}: {} has no number {}", n + 1, tag, key))
+        };
+        match &tag[..] {
+            "common" => {
+                desc.line_height = try!(num("lineHeight"));
+                desc.base = try!(num("base"));
+            },
+            "page" => {
+                let id = try!(num("id")) as usize;
+                let file = try!(pairs.get("file").ok_or(format!("line {}: page has no file", n + 1)));
+                if desc.pages.len() <= id {
+                    desc.pages.resize(id + 1, String::new());
+                }
+                desc.pages[id] = file.clone();
+            },
+            "char" => {
+                let glyph = Glyph {
+                    page: try!(num("page")) as usize,
+                    src: Rect::new(try!(num("x")) as i32, try!(num("y")) as i32,
+                                   try!(num("width")) as u32, try!(num("height")) as u32),
+                    offset: (try!(num("xoffset")), try!(num("yoffset"))),
+                    advance: try!(num("xadvance"))
+                };
+                desc.glyphs.insert(try!(num("id")) as u32, glyph);
+            },
+            "kerning" => {
+                desc.kerning.insert((try!(num("first")) as u32, try!(num("second")) as u32),
+                                    try!(num("amount")));
+            },
+            _ => {}
+        }
+    }
+    Ok(desc)
+}
+
+fn u16_at(data: &[u8], o: usize) -> u16 {
+    data[o] as u16 | (data[o + 1] as u16) << 8
+}
+
+fn i16_at(data: &[u8], o: usize) -> i16 {
+    u16_at(data, o) as i16
+}
+
+fn u32_at(data: &[u8], o: usize) -> u32 {
+    u16_at(data, o) as u32 | (u16_at(data, o + 2) as u32) << 16
+}
+
+fn parse_binary(data: &[u8]) -> Result<FontDesc, String> {
+    if data.len() < 4 || data[3] != 3 {
+        return Err(format!("unsupported binary font version {}", data.get(3).cloned().unwrap_or(0)));
+    }
+    let mut desc = FontDesc {
+        line_height: 0.0,
+        base: 0.0,
+        pages: Vec::new(),
+        glyphs: HashMap::new(),
+        kerning: HashMap::new()
+    };
+    let mut pos = 4;
+    while pos + 5 <= data.len() {
+        let kind = data[pos];
+        let size = u32_at(data, pos + 1) as usize;
+        let start = pos + 5;
+        if start + size > data.len() {
+            return Err(format!("font block {} is truncated", kind));
+        }
+        let block = &data[start..start + size];
+        match kind {
+            2 if size >= 4 => {
+                desc.line_height = u16_at(block, 0) as f64;
+                desc.base = u16_at(block, 2) as f64;
+            },
+            3 => {
+                desc.pages = block.split(|&b| b == 0)
+                    .filter(|name| !name.is_empty())
+                    .map(|name| String::from_utf8_lossy(name).into_owned())
+                    .collect();
+            },
+            4 => {
+                for c in block.chunks(20).filter(|c| c.len() == 20) {
+                    desc.glyphs.insert(u32_at(c, 0), Glyph {
+                        page: c[18] as usize,
+                        src: Rect::new(u16_at(c, 4) as i32, u16_at(c, 6) as i32,
+                                       u16_at(c, 8) as u32, u16_at(c, 10) as u32),
+                        offset: (i16_at(c, 12) as f64, i16_at(c, 14) as f64),
+                        advance: i16_at(c, 16) as f64
+                    });
+                }
+            },
+            5 => {
+                for k in block.chunks(10).filter(|k| k.len() == 10) {
+                    desc.kerning.insert((u32_at(k, 0), u32_at(k, 4)), i16_at(k, 8) as f64);
+                }
+            },
+            _ => {}
+        }
+        pos = start + size;
+    }
+    Ok(desc)
+}
+
+/*
+ * Parses a descriptor in either format.
+ */
+pub fn parse(data: &[u8]) -> Result<FontDesc, String> {
+    if data.starts_with(b"BMF") {
+        parse_binary(data)
+    } else {
+        String::from_utf8(data.to_vec()).map_err(|e| e.to_string()).and_then(|s| parse_text(&s))
+    }
+}
+
+pub fn read_desc<P: AsRef<Path>>(path: P) -> Result<FontDesc, String> {
+    let mut data = Vec::new();
+    try!(File::open(path.as_ref()).and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|e| format!("{}: {}", path.as_ref().display(), e)));
+    parse(&data)
+}
+
+/*
+ * Loads a glyph page, turning an opaque greyscale page into white with alpha coverage.
+ */
+pub fn load_page<P: AsRef<Path>>(path: P) -> Result<Image, String> {
+    let is_png = path.as_ref().extension().map(|e| e.eq_ignore_ascii_case("png")).unwrap_or(false);
+    let mut image = try!(if is_png { png::load(path) } else { Image::load_bmp(path) });
+    if image.pixels.chunks(4).all(|p| p[3] == 255) {
+        for p in image.pixels.chunks_mut(4) {
+            let level = ((p[0] as u32 + p[1] as u32 + p[2] as u32) / 3) as u8;
+            p[0] = 255;
+            p[1] = 255;
+            p[2] = 255;
+            p[3] = level;
+        }
+    }
+    Ok(image)
+}
+
+impl Font {
+    pub fn new(desc: FontDesc, pages: Vec<TextureId>) -> Font {
+        Font { desc: desc, pages: pages }
+    }
+
+    pub fn line_height(&self) -> f64 {
+        self.desc.line_height
+    }
+
+    pub fn base(&self) -> f64 {
+        self.desc.base
+    }
+
+    pub fn pages(&self) -> &[TextureId] {
+        &self.pages
+    }
+
+    fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.desc.glyphs.get(&(c as u32)).or_else(|| self.desc.glyphs.get(&('?' as u32)))
+    }
+
+    fn kerning(&self, prev: Option<char>, c: char) -> f64 {
+        prev.and_then(|p| self.desc.kerning.get(&(p as u32, c as u32)).cloned()).unwrap_or(0.0)
+    }
+
+    /*
+     * The advance width of a single line.
+     */
+    pub fn line_width(&self, line: &str) -> f64 {
+        let mut width = 0.0;
+        let mut prev = None;
+        for c in line.chars() {
+            width += self.kerning(prev, c) + self.glyph(c).map(|g| g.advance).unwrap_or(0.0);
+            prev = Some(c);
+        }
+        width
+    }
+
+    /*
+     * Breaks text into lines at newlines and, given a width, between words. A word wider
+     * than the width is left on a line of its own rather than split.
+     */
+    pub fn wrap(&self, text: &str, width: Option<f64>) -> Vec<String> {
+        let mut lines = Vec::new();
+        for para in text.split('\n') {
+            let max = match width {
+                Some(w) => w,
+                None => {
+                    lines.push(para.to_string());
+                    continue;
+                }
+            };
+            let mut line = String::new();
+            for word in para.split(' ') {
+                let candidate = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+                if line.is_empty() || self.line_width(&candidate) <= max {
+                    line = candidate;
+                } else {
+                    lines.push(line);
+                    line = word.to_string();
+                }
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    /*
+     * The size of the text as laid out by `layout` with the same width.
+     */
+    pub fn measure(&self, text: &str, width: Option<f64>) -> (f64, f64) {
+        let lines = self.wrap(text, width);
+        let widest = lines.iter().map(|l| self.line_width(l)).fold(0.0, f64::max);
+        (widest, lines.len() as f64 * self.desc.line_height)
+    }
+
+    /*
+     * Positions every glyph of the text. Lines are aligned within the width, or within
+     * the widest line if there is none.
+     */
+    pub fn layout(&self, text: &str, width: Option<f64>, align: Align) -> Vec<PlacedGlyph> {
+        let lines = self.wrap(text, width);
+        let widths: Vec<f64> = lines.iter().map(|l| self.line_width(l)).collect();
+        let box_width = width.unwrap_or(widths.iter().cloned().fold(0.0, f64::max));
+        let mut placed = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            let mut x = match align {
+                Align::Left => 0.0,
+                Align::Centre => ((box_width - widths[i]) / 2.0).floor(),
+                Align::Right => box_width - widths[i]
+            };
+            let y = i as f64 * self.desc.line_height;
+            let mut prev = None;
+            for c in line.chars() {
+                x += self.kerning(prev, c);
+                prev = Some(c);
+                let glyph = match self.glyph(c) {
+                    Some(g) => g,
+                    None => continue
+                };
+                if glyph.src.w > 0 && glyph.src.h > 0 && glyph.page < self.pages.len() {
+                    placed.push(PlacedGlyph {
+                        texture: self.pages[glyph.page],
+                        src: glyph.src,
+                        x: x + glyph.offset.0,
+                        y: y + glyph.offset.1
+                    });
+                }
+                x += glyph.advance;
+            }
+        }
+        placed
+    }
+}
+
+impl TextRenderer for Font {
+    fn measure(&self, text: &str) -> (f64, f64) {
+        Font::measure(self, text, None)
+    }
+
+    fn draw_text(&self, backend: &mut Backend, text: &str, x: f64, y: f64, colour: Rgba) {
+        RendererUtils::draw_text(backend, self, text, x, y, colour);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &'static str = "\
+info face=\"Some Font\" size=16 bold=0
+common lineHeight=18 base=14 scaleW=64 scaleH=64 pages=1 packed=0
+page id=0 file=\"glyphs 0.png\"
+chars count=4
+char id=65 x=0 y=0 width=8 height=10 xoffset=0 yoffset=2 xadvance=9 page=0 chnl=15
+char id=86 x=8 y=0 width=8 height=10 xoffset=-1 yoffset=2 xadvance=8 page=0 chnl=15
+char id=32 x=0 y=0 width=0 height=0 xoffset=0 yoffset=0 xadvance=4 page=0 chnl=15
+char id=63 x=16 y=0 width=6 height=10 xoffset=1 yoffset=2 xadvance=7 page=0 chnl=15
+kernings count=1
+kerning first=65 second=86 amount=-2
+";
+
+    fn font() -> Font {
+        Font::new(parse(TEXT.as_bytes()).unwrap(), vec![TextureId(7)])
+    }
+
+    fn block(kind: u8, body: &[u8], out: &mut Vec<u8>) {
+        out.push(kind);
+        let size = body.len() as u32;
+        out.extend(&[size as u8, (size >> 8) as u8, (size >> 16) as u8, (size >> 24) as u8]);
+        out.extend(body);
+    }
+
+    fn le16(v: i32) -> [u8; 2] {
+        [v as u8, (v >> 8) as u8]
+    }
+
+    fn le32(v: u32) -> [u8; 4] {
+        [v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]
+    }
+
+    #[test]
+    fn text_descriptors_are_read() {
+        let desc = parse(TEXT.as_bytes()).unwrap();
+        assert_eq!((desc.line_height, desc.base), (18.0, 14.0));
+        assert_eq!(desc.pages, vec!["glyphs 0.png".to_string()]);
+        assert_eq!(desc.glyphs.len(), 4);
+        let v = desc.glyphs[&86];
+        assert_eq!((v.src, v.offset, v.advance), (Rect::new(8, 0, 8, 10), (-1.0, 2.0), 8.0));
+        assert_eq!(desc.kerning.get(&(65, 86)), Some(&-2.0));
+
+        assert!(parse(b"char id=65 x=0").is_err());
+    }
+
+    #[test]
+    fn binary_descriptors_match_text_ones() {
+        let mut data = b"BMF\x03".to_vec();
+        block(2, &[18, 0, 14, 0, 64, 0, 64, 0, 1, 0, 0, 0, 0, 0, 0], &mut data);
+        block(3, b"glyphs 0.png\0", &mut data);
+        let mut chars = Vec::new();
+        for &(id, x, xoff, adv) in &[(65u32, 0, 0, 9), (86, 8, -1, 8)] {
+            chars.extend(&le32(id));
+            for &v in &[x, 0, 8, 10, xoff, 2, adv] {
+                chars.extend(&le16(v));
+            }
+            chars.extend(&[0, 15]);
+        }
+        block(4, &chars, &mut data);
+        let mut kerning = Vec::new();
+        kerning.extend(&le32(65));
+        kerning.extend(&le32(86));
+        kerning.extend(&le16(-2));
+        block(5, &kerning, &mut data);
+
+        let desc = parse(&data).unwrap();
+        let text = parse(TEXT.as_bytes()).unwrap();
+        assert_eq!((desc.line_height, desc.base), (text.line_height, text.base));
+        assert_eq!(desc.pages, text.pages);
+        for id in &[65, 86] {
+            let (a, b) = (desc.glyphs[id], text.glyphs[id]);
+            assert_eq!((a.page, a.src, a.offset, a.advance), (b.page, b.src, b.offset, b.advance));
+        }
+        assert_eq!(desc.kerning, text.kerning);
+
+        assert!(parse(b"BMF\x02").is_err());
+        let cut = data.len() - 3;
+        assert!(parse(&data[..cut]).is_err());
+    }
+
+    #[test]
+    fn widths_include_kerning() {
+        let font = font();
+        assert_eq!(font.line_width("A"), 9.0);
+        assert_eq!(font.line_width("AV"), 9.0 - 2.0 + 8.0);
+        assert_eq!(font.line_width("VA"), 17.0);
+        // Characters missing from the font are drawn as '?'.
+        assert_eq!(font.line_width("A~"), 16.0);
+        assert_eq!(font.measure("AV\nA", None), (15.0, 36.0));
+        // "AV A" is 24 wide, so it wraps to two lines at 20.
+        assert_eq!(font.wrap("AV A", Some(20.0)), vec!["AV".to_string(), "A".to_string()]);
+        assert_eq!(font.measure("AV A", Some(20.0)), (15.0, 36.0));
+    }
+
+    #[test]
+    fn layout_places_glyphs_by_offset_and_kerning() {
+        let font = font();
+        let placed = font.layout("AV A", None, Align::Left);
+        // The space has no pixels, so only three glyphs are placed.
+        let at: Vec<(f64, f64)> = placed.iter().map(|g| (g.x, g.y)).collect();
+        assert_eq!(at, vec![(0.0, 2.0), (6.0, 2.0), (19.0, 2.0)]);
+        assert!(placed.iter().all(|g| g.texture == TextureId(7)));
+
+        let right = font.layout("A\nAV", None, Align::Right);
+        assert_eq!((right[0].x, right[0].y), (6.0, 2.0));
+        assert_eq!((right[1].x, right[1].y), (0.0, 20.0));
+    }
+}
@@ -2,75 +2,413 @@ extern crate sdl2;
 
 use self::sdl2::Sdl;
 use self::sdl2::EventPump;
+use self::sdl2::GameControllerSubsystem;
 use self::sdl2::SdlResult;
 use self::sdl2::TimerSubsystem;
 use self::sdl2::VideoSubsystem;
 use self::sdl2::event::Event;
+use self::sdl2::event::WindowEventId;
+use self::sdl2::keyboard;
+use self::sdl2::keyboard::Mod;
+use self::sdl2::mouse::Mouse;
 use self::sdl2::render::Renderer;
+use self::sdl2::video::FullscreenType;
+use self::sdl2::video::WindowPos;
 
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use assets::Assets;
+use audio::Audio;
+use backend::Backend;
+use color::Rgba;
+use controllers::Controllers;
+use debug;
+use debug::CountingBackend;
+use debug::DrawCounters;
+use debug::FrameStats;
+use debug::Overlay;
+use debug::Stats;
 use ditty::Ditty;
+use dittystack::Command;
+use dittystack::DittyStack;
+use input::InputEvent;
+use input::Key;
+use input::Modifiers;
+use input::MouseButton;
+use png;
+use replay::Recorder;
+use replay::Recording;
+use replay::Replayer;
+use sdlaudio::SdlAudio;
+use sdlbackend::SdlBackend;
+use settings;
+use settings::VideoSettings;
+use settings::WindowMode;
+use timestep::FixedStep;
+
+const TITLE: &'static str = "Cretacious Island";
+
+/*
+ * Recordings hash the game state once a second or so.
+ */
+const HASH_EVERY: u64 = 60;
 
 pub struct GameLoop {
     context: Sdl,
     video: VideoSubsystem,
-    width: u32,
-    height: u32,
+    controllers: GameControllerSubsystem,
+    settings: VideoSettings,
+    update_rate: u32,
+    max_steps: u32,
+    record: Option<PathBuf>,
+    replay: Option<Recording>,
+    audio: Option<SdlAudio>,
+    assets: Option<Assets>,
 }
 
 impl GameLoop {
-    pub fn new() -> SdlResult<GameLoop> {
-        sdl2::init().and_then(|context| context.video()
-            .and_then(|video| video.current_display_mode(0)
-            .map(move |curmode| {
-                println!("Using mode: {}x{}", curmode.w, curmode.h);
-                GameLoop {
-                    context: context,
-                    video: video,
-                    width: curmode.w as u32,
-                    height: curmode.h as u32,
-                }
-            })))
+    pub fn new(settings: VideoSettings) -> SdlResult<GameLoop> {
+        let context = try!(sdl2::init());
+        let video = try!(context.video());
+        let controllers = try!(context.game_controller());
+        let mut settings = settings;
+        settings.clamp_display(GameLoop::displays(&video));
+        Ok(GameLoop {
+            context: context,
+            video: video,
+            controllers: controllers,
+            settings: settings,
+            update_rate: 60,
+            max_steps: 5,
+            record: None,
+            replay: None,
+            audio: None,
+            assets: None,
+        })
+    }
+
+    /*
+     * Sets how many fixed updates a second the ditty gets, and how many may be run in one
+     * frame to catch up after a slow one.
+     */
+    pub fn set_update_rate(&mut self, rate: u32, max_steps: u32) {
+        self.update_rate = rate;
+        self.max_steps = max_steps;
+    }
+
+    /*
+     * Starts playing the mixer through the sound card.
+     */
+    pub fn open_audio(&mut self, audio: Audio) -> SdlResult<()> {
+        let subsystem = try!(self.context.audio());
+        self.audio = Some(try!(SdlAudio::open(&subsystem, audio)));
+        Ok(())
+    }
+
+    /*
+     * Has the game loop free assets that are no longer used, once a frame.
+     */
+    pub fn use_assets(&mut self, assets: Assets) {
+        self.assets = Some(assets);
+    }
+
+    /*
+     * Records the session's input to a file, written when the game loop ends.
+     */
+    pub fn record_to<P: AsRef<Path>>(&mut self, path: P) {
+        self.record = Some(path.as_ref().to_path_buf());
+    }
+
+    /*
+     * Plays a recording instead of taking input, stopping when it ends or the game
+     * state stops matching it. The ditty should start as it did when recorded.
+     */
+    pub fn replay(&mut self, recording: Recording) {
+        self.replay = Some(recording);
     }
 
-    fn build_renderer(&self) -> SdlResult<Renderer> {
-        self.video.window("Cretacious Island", self.width, self.height).fullscreen().build()
-            .and_then(|builder| builder.renderer().accelerated().present_vsync().build())
+    fn displays(video: &VideoSubsystem) -> u32 {
+        video.num_video_displays().map(|n| n as u32).unwrap_or(1)
     }
 
-    fn do_run<T: Ditty>(&self,
-              mut renderer: Renderer,
+    /*
+     * SDL_WINDOWPOS_CENTERED_DISPLAY, which the bindings don't provide.
+     */
+    fn centred_on(display: u32) -> i32 {
+        (0x2FFF0000 | display) as i32
+    }
+
+    /*
+     * The size the window will be for the given settings. Borderless windows always
+     * cover the display at its current mode.
+     */
+    fn window_size(&self, settings: &VideoSettings) -> SdlResult<(u32, u32)> {
+        let desktop = try!(self.video.current_display_mode(settings.display as i32));
+        Ok(match (settings.mode, settings.resolution) {
+            (WindowMode::Borderless, _) | (_, (0, _)) | (_, (_, 0)) => (desktop.w as u32, desktop.h as u32),
+            (_, resolution) => resolution
+        })
+    }
+
+    fn build_renderer(&self, width: u32, height: u32) -> SdlResult<Renderer> {
+        let pos = GameLoop::centred_on(self.settings.display);
+        let mut builder = self.video.window(TITLE, width, height);
+        builder.position(pos, pos);
+        match self.settings.mode {
+            WindowMode::Windowed => builder.resizable(),
+            WindowMode::Borderless => builder.fullscreen_desktop(),
+            WindowMode::Fullscreen => builder.fullscreen()
+        };
+        let vsync = self.settings.vsync;
+        builder.build().and_then(|window| {
+            let renderer = window.renderer().accelerated();
+            if vsync { renderer.present_vsync() } else { renderer }.build()
+        })
+    }
+
+    /*
+     * Changes the window to match new settings, returning its new size. The renderer
+     * can't change vsync once made, so that waits for the next start.
+     */
+    fn apply_video(&self, backend: &mut SdlBackend, settings: &VideoSettings) -> SdlResult<(u32, u32)> {
+        let (width, height) = try!(self.window_size(settings));
+        let pos = WindowPos::Positioned(GameLoop::centred_on(settings.display));
+        let window = try!(backend.renderer().window_mut().ok_or("renderer has no window".to_string()));
+        try!(window.set_fullscreen(FullscreenType::Off));
+        window.set_size(width as i32, height as i32);
+        window.set_position(pos, pos);
+        match settings.mode {
+            WindowMode::Windowed => {},
+            WindowMode::Borderless => try!(window.set_fullscreen(FullscreenType::Desktop)),
+            WindowMode::Fullscreen => try!(window.set_fullscreen(FullscreenType::True))
+        }
+        Ok((width, height))
+    }
+
+    fn do_run(&self,
+              mut backend: SdlBackend,
               mut timer: TimerSubsystem,
               mut events: EventPump,
-              mut ditty: T) {
-        ditty.init(&mut renderer);
-        loop {
-            let start_ticks = timer.ticks();
-            // Events
+              mut ditty: DittyStack,
+              size: (u32, u32)) -> Stats {
+        let mut stats = Stats::new();
+        let mut overlay = Overlay::new();
+        let mut counters = DrawCounters::default();
+        let mut replayer = self.replay.clone().map(Replayer::new);
+        let mut recorder = self.record.as_ref().map(|_| Recorder::new(self.update_rate, HASH_EVERY));
+        let rate = replayer.as_ref().map(|r| r.update_rate()).unwrap_or(self.update_rate);
+        let mut clock = FixedStep::per_second(rate, self.max_steps);
+        backend.set_displays(GameLoop::displays(&self.video));
+        ditty.init(&mut CountingBackend::new(&mut backend, &mut counters));
+        let mut last_start = Instant::now();
+        let mut mouse = (0, 0);
+        let mut pads = Controllers::new(self.controllers.clone());
+        let (mut width, mut height) = size;
+        let mut settings = self.settings;
+        'frames: loop {
+            let frame_start = Instant::now();
+            let interval = debug::ms_since(last_start);
+            last_start = frame_start;
+            let mut screenshot = false;
+            // Events go to the ditty first; what it leaves is ours. While replaying the
+            // ditty only gets what was recorded.
             for ev in events.poll_iter() {
                 match ev {
-                    Event::Quit {timestamp: _} => {
+                    Event::Quit { .. } => {
                         println!("Quitting");
-                        return;
+                        break 'frames;
+                    },
+                    Event::Window { win_event_id: WindowEventId::SizeChanged, data1, data2, .. } => {
+                        width = data1 as u32;
+                        height = data2 as u32;
+                        backend.set_size(width, height);
+                    },
+                    _ => {}
+                }
+                let input = match GameLoop::translate(&ev, &mut mouse).or_else(|| pads.translate(&ev)) {
+                    Some(input) => input,
+                    None => continue
+                };
+                if replayer.is_none() {
+                    if let Some(ref mut recorder) = recorder {
+                        recorder.input(clock.total_steps(), &input);
+                    }
+                    if ditty.handle_input(&input) {
+                        continue;
+                    }
+                }
+                match input {
+                    InputEvent::KeyDown { key: Key::F(12), .. } => {
+                        screenshot = true;
+                    }
+                    InputEvent::KeyDown { key: Key::F(3), .. } => {
+                        overlay.toggle();
                     }
                     _ => {}
                 }
             }
-            // Logic
-            // Rendering
-            renderer.clear();
-            ditty.render(&mut renderer, self.width, self.height);
-            renderer.present();
-
-            while timer.ticks() - start_ticks < 1000 / 60 {
-                timer.delay(1);
+            // Logic, in as many fixed steps as the time since the last frame covers.
+            let update_start = Instant::now();
+            let updates = clock.advance(interval / 1000.0);
+            let first_tick = clock.total_steps() - updates as u64;
+            for tick in first_tick..first_tick + updates as u64 {
+                if let Some(ref mut replayer) = replayer {
+                    replayer.feed(tick, &mut ditty);
+                }
+                let command = ditty.update(clock.step());
+                if let Some(ref mut recorder) = recorder {
+                    recorder.updated(tick + 1, &ditty);
+                }
+                if let Some(ref mut replayer) = replayer {
+                    if let Err(e) = replayer.verify(tick + 1, &ditty) {
+                        println!("{}", e);
+                        break 'frames;
+                    }
+                    if replayer.is_finished(tick + 1) {
+                        println!("Replay finished, {} state hashes matched", replayer.verified());
+                        break 'frames;
+                    }
+                }
+                if let Some(Command::Video(video)) = command {
+                    match self.apply_video(&mut backend, &video) {
+                        Ok((w, h)) => {
+                            println!("Using mode: {}x{}", w, h);
+                            if video.vsync != settings.vsync {
+                                println!("Vsync will change on restart");
+                            }
+                            width = w;
+                            height = h;
+                            backend.set_size(w, h);
+                            settings = video;
+                            if let Err(e) = settings.save(settings::VIDEO_CONFIG) {
+                                println!("Could not save video settings: {}", e);
+                            }
+                        },
+                        Err(e) => println!("Could not change video mode: {}", e)
+                    }
+                }
+            }
+            let update_ms = debug::ms_since(update_start);
+            if ditty.is_finished() {
+                println!("Finished");
+                break 'frames;
+            }
+            // Rendering, as often as the display allows, in between the updates.
+            let render_start = Instant::now();
+            backend.clear(Rgba::rgb(0, 0, 0));
+            counters.draw_calls = 0;
+            ditty.render(&mut CountingBackend::new(&mut backend, &mut counters), width, height, clock.alpha());
+            let render_ms = debug::ms_since(render_start);
+            if screenshot {
+                GameLoop::save_screenshot(&mut backend, timer.ticks());
+            }
+            // The overlay is drawn last so it stays out of screenshots and its own counts.
+            overlay.draw(&mut backend, &stats);
+            stats.record(FrameStats {
+                update_ms: update_ms,
+                updates: updates,
+                render_ms: render_ms,
+                frame_ms: debug::ms_since(frame_start),
+                draw_calls: counters.draw_calls,
+                textures: counters.textures,
+                ditties: ditty.take_stats()
+            }, interval);
+            backend.present();
+            if let Some(ref assets) = self.assets {
+                assets.update(&mut CountingBackend::new(&mut backend, &mut counters));
+            }
+
+            if let Some(cap) = settings.fps_cap {
+                while debug::ms_since(frame_start) < 1000.0 / cap as f64 {
+                    timer.delay(1);
+                }
+            }
+        }
+        if let (Some(recorder), Some(path)) = (recorder, self.record.as_ref()) {
+            match recorder.finish().save(path) {
+                Ok(_) => println!("Saved recording {}", path.display()),
+                Err(e) => println!("Could not save recording: {}", e)
             }
         }
+        stats
+    }
+
+    fn modifiers(keymod: Mod) -> Modifiers {
+        Modifiers {
+            shift: keymod.intersects(keyboard::LSHIFTMOD | keyboard::RSHIFTMOD),
+            ctrl: keymod.intersects(keyboard::LCTRLMOD | keyboard::RCTRLMOD),
+            alt: keymod.intersects(keyboard::LALTMOD | keyboard::RALTMOD)
+        }
+    }
+
+    fn button(button: Mouse) -> MouseButton {
+        match button {
+            Mouse::Left => MouseButton::Left,
+            Mouse::Middle => MouseButton::Middle,
+            Mouse::Right => MouseButton::Right,
+            Mouse::X1 => MouseButton::Other(4),
+            Mouse::X2 => MouseButton::Other(5),
+            Mouse::Unknown(n) => MouseButton::Other(n)
+        }
+    }
+
+    /*
+     * Turns an SDL event into ours, or None for the ones ditties have no use for. SDL
+     * wheel events have no position, so the last known pointer position is kept here.
+     */
+    fn translate(ev: &Event, mouse: &mut (i32, i32)) -> Option<InputEvent> {
+        match *ev {
+            Event::KeyDown { keycode: Some(keycode), keymod, repeat, .. } => Some(InputEvent::KeyDown {
+                key: Key::from_sdl_code(keycode as i32),
+                modifiers: GameLoop::modifiers(keymod),
+                repeat: repeat
+            }),
+            Event::KeyUp { keycode: Some(keycode), keymod, .. } => Some(InputEvent::KeyUp {
+                key: Key::from_sdl_code(keycode as i32),
+                modifiers: GameLoop::modifiers(keymod)
+            }),
+            Event::TextInput { ref text, .. } => Some(InputEvent::Text(text.clone())),
+            Event::MouseButtonDown { mouse_btn, x, y, .. } => {
+                *mouse = (x, y);
+                Some(InputEvent::MouseDown { button: GameLoop::button(mouse_btn), x: x, y: y })
+            },
+            Event::MouseButtonUp { mouse_btn, x, y, .. } => {
+                *mouse = (x, y);
+                Some(InputEvent::MouseUp { button: GameLoop::button(mouse_btn), x: x, y: y })
+            },
+            Event::MouseMotion { x, y, xrel, yrel, .. } => {
+                *mouse = (x, y);
+                Some(InputEvent::MouseMove { x: x, y: y, dx: xrel, dy: yrel })
+            },
+            Event::MouseWheel { x, y, .. } => Some(InputEvent::Wheel { dx: x, dy: y, x: mouse.0, y: mouse.1 }),
+            Event::Window { win_event_id: WindowEventId::FocusGained, .. } => Some(InputEvent::Focus(true)),
+            Event::Window { win_event_id: WindowEventId::FocusLost, .. } => Some(InputEvent::Focus(false)),
+            _ => None
+        }
+    }
+
+    fn save_screenshot(backend: &mut Backend, ticks: u32) {
+        let name = format!("screenshot-{}.png", ticks);
+        match backend.read_pixels().and_then(|image| png::save(&image, &name)) {
+            Ok(_) => println!("Saved {}", name),
+            Err(e) => println!("Could not save {}: {}", name, e)
+        }
     }
 
-    pub fn run<T: Ditty>(&self, ditty: T) -> SdlResult<()> {
-        self.build_renderer().and_then(|renderer| self.context.timer()
+    /*
+     * Runs the ditty, at the bottom of a ditty stack, until the window is closed or the
+     * stack empties, returning the recent frame statistics.
+     */
+    pub fn run<T: Ditty + 'static>(&self, ditty: T) -> SdlResult<Stats> {
+        let ditty = DittyStack::new(Box::new(ditty));
+        let (width, height) = try!(self.window_size(&self.settings));
+        println!("Using mode: {}x{}", width, height);
+        self.build_renderer(width, height).and_then(|renderer| self.context.timer()
             .and_then(|timer| self.context.event_pump()
-            .map(|events| self.do_run(renderer, timer, events, ditty))))
+            .map(|events| self.do_run(SdlBackend::new(renderer, width, height),
+                                      timer, events, ditty, (width, height)))))
     }
 }
 
@@ -0,0 +1,414 @@
+/*
+ * Geometry shared by everything that draws paths: affine transforms, flattening of
+ * path elements into polylines, and arc-length measurement along those polylines.
+ */
+
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+use spath::PathElem;
+
+/*
+ * An SVG style affine matrix, mapping (x, y) to (a*x + c*y + e, b*x + d*y + f).
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    pub d: f64,
+    pub e: f64,
+    pub f: f64
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform { a: 1.0, b: 0.0, c: 0.0, d: 1.0, e: 0.0, f: 0.0 }
+    }
+
+    pub fn translate(tx: f64, ty: f64) -> Transform {
+        Transform { a: 1.0, b: 0.0, c: 0.0, d: 1.0, e: tx, f: ty }
+    }
+
+    pub fn scale(sx: f64, sy: f64) -> Transform {
+        Transform { a: sx, b: 0.0, c: 0.0, d: sy, e: 0.0, f: 0.0 }
+    }
+
+    pub fn rotate(degrees: f64) -> Transform {
+        let (s, c) = degrees.to_radians().sin_cos();
+        Transform { a: c, b: s, c: -s, d: c, e: 0.0, f: 0.0 }
+    }
+
+    pub fn rotate_about(degrees: f64, cx: f64, cy: f64) -> Transform {
+        Transform::translate(cx, cy)
+            .then(&Transform::rotate(degrees))
+            .then(&Transform::translate(-cx, -cy))
+    }
+
+    pub fn skew_x(degrees: f64) -> Transform {
+        Transform { a: 1.0, b: 0.0, c: degrees.to_radians().tan(), d: 1.0, e: 0.0, f: 0.0 }
+    }
+
+    pub fn skew_y(degrees: f64) -> Transform {
+        Transform { a: 1.0, b: degrees.to_radians().tan(), c: 0.0, d: 1.0, e: 0.0, f: 0.0 }
+    }
+
+    /*
+     * Matrix product self * other: other is applied to a point first. This is the order
+     * SVG uses when a child's transform is nested inside its parent's.
+     */
+    pub fn then(&self, o: &Transform) -> Transform {
+        Transform {
+            a: self.a * o.a + self.c * o.b,
+            b: self.b * o.a + self.d * o.b,
+            c: self.a * o.c + self.c * o.d,
+            d: self.b * o.c + self.d * o.d,
+            e: self.a * o.e + self.c * o.f + self.e,
+            f: self.b * o.e + self.d * o.f + self.f
+        }
+    }
+
+    pub fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        (self.a * x + self.c * y + self.e, self.b * x + self.d * y + self.f)
+    }
+
+    pub fn inverse(&self) -> Option<Transform> {
+        let det = self.a * self.d - self.b * self.c;
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        Some(Transform {
+            a: self.d / det,
+            b: -self.b / det,
+            c: -self.c / det,
+            d: self.a / det,
+            e: (self.c * self.f - self.d * self.e) / det,
+            f: (self.b * self.e - self.a * self.f) / det
+        })
+    }
+
+    /*
+     * Approximate uniform scale factor, used to pick flattening tolerances.
+     */
+    pub fn scale_factor(&self) -> f64 {
+        (self.a * self.d - self.b * self.c).abs().sqrt()
+    }
+
+    /*
+     * Parses an SVG transform attribute, e.g. "translate(0,27.6) rotate(45 10 10)".
+     * Unknown functions are skipped.
+     */
+    pub fn parse(s: &str) -> Transform {
+        let mut t = Transform::identity();
+        let mut rest = s.trim();
+        while let Some(open) = rest.find('(') {
+            let name = rest[..open].trim().trim_start_matches(',').trim();
+            let close = match rest[open..].find(')') {
+                Some(c) => open + c,
+                None => break
+            };
+            let args: Vec<f64> = parse_numbers(&rest[open + 1..close]);
+            let arg = |i: usize, def: f64| args.get(i).cloned().unwrap_or(def);
+            let next = match name {
+                "matrix" if args.len() == 6 => Some(Transform {
+                    a: args[0], b: args[1], c: args[2], d: args[3], e: args[4], f: args[5]
+                }),
+                "translate" => Some(Transform::translate(arg(0, 0.0), arg(1, 0.0))),
+                "scale" => Some(Transform::scale(arg(0, 1.0), arg(1, arg(0, 1.0)))),
+                "rotate" => Some(Transform::rotate_about(arg(0, 0.0), arg(1, 0.0), arg(2, 0.0))),
+                "skewX" => Some(Transform::skew_x(arg(0, 0.0))),
+                "skewY" => Some(Transform::skew_y(arg(0, 0.0))),
+                _ => None
+            };
+            if let Some(n) = next {
+                t = t.then(&n);
+            }
+            rest = rest[close + 1..].trim();
+        }
+        t
+    }
+}
+
+/*
+ * Splits a list of numbers separated by whitespace and/or commas.
+ */
+pub fn parse_numbers(s: &str) -> Vec<f64> {
+    s.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|p| !p.is_empty())
+        .filter_map(|p| f64::from_str(p).ok())
+        .collect()
+}
+
+pub type Polyline = Vec<(f64, f64)>;
+
+fn lerp(a: (f64, f64), b: (f64, f64), t: f64) -> (f64, f64) {
+    (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+}
+
+fn dist(a: (f64, f64), b: (f64, f64)) -> f64 {
+    ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt()
+}
+
+fn segments_for(len: f64, tolerance: f64) -> usize {
+    let n = (len / tolerance.max(0.01)).sqrt().ceil() as usize;
+    if n < 1 { 1 } else if n > 256 { 256 } else { n }
+}
+
+fn push_cubic(out: &mut Polyline, p0: (f64, f64), p1: (f64, f64), p2: (f64, f64),
+              p3: (f64, f64), tolerance: f64) {
+    let n = segments_for(dist(p0, p1) + dist(p1, p2) + dist(p2, p3), tolerance);
+    for i in 1..n + 1 {
+        let t = i as f64 / n as f64;
+        let a = lerp(lerp(p0, p1, t), lerp(p1, p2, t), t);
+        let b = lerp(lerp(p1, p2, t), lerp(p2, p3, t), t);
+        out.push(lerp(a, b, t));
+    }
+}
+
+fn push_quad(out: &mut Polyline, p0: (f64, f64), p1: (f64, f64), p2: (f64, f64),
+             tolerance: f64) {
+    let n = segments_for(dist(p0, p1) + dist(p1, p2), tolerance);
+    for i in 1..n + 1 {
+        let t = i as f64 / n as f64;
+        out.push(lerp(lerp(p0, p1, t), lerp(p1, p2, t), t));
+    }
+}
+
+fn vec_angle(ux: f64, uy: f64, vx: f64, vy: f64) -> f64 {
+    let dot = ux * vx + uy * vy;
+    let len = (ux * ux + uy * uy).sqrt() * (vx * vx + vy * vy).sqrt();
+    let a = (dot / len).max(-1.0).min(1.0).acos();
+    if ux * vy - uy * vx < 0.0 { -a } else { a }
+}
+
+/*
+ * Endpoint to centre parameterisation, as in the SVG implementation notes (F.6.5).
+ */
+fn push_arc(out: &mut Polyline, p0: (f64, f64), rx: f64, ry: f64, x_rotation: f64,
+            lrg_arc: bool, sweep: bool, p1: (f64, f64), tolerance: f64) {
+    let (mut rx, mut ry) = (rx.abs(), ry.abs());
+    if rx < 1e-9 || ry < 1e-9 || dist(p0, p1) < 1e-9 {
+        out.push(p1);
+        return;
+    }
+    let (sin_phi, cos_phi) = x_rotation.to_radians().sin_cos();
+    let dx = (p0.0 - p1.0) / 2.0;
+    let dy = (p0.1 - p1.1) / 2.0;
+    let x1p = cos_phi * dx + sin_phi * dy;
+    let y1p = -sin_phi * dx + cos_phi * dy;
+
+    let lambda = (x1p * x1p) / (rx * rx) + (y1p * y1p) / (ry * ry);
+    if lambda > 1.0 {
+        rx *= lambda.sqrt();
+        ry *= lambda.sqrt();
+    }
+    let num = rx * rx * ry * ry - rx * rx * y1p * y1p - ry * ry * x1p * x1p;
+    let den = rx * rx * y1p * y1p + ry * ry * x1p * x1p;
+    let mut coef = (num / den).max(0.0).sqrt();
+    if lrg_arc == sweep {
+        coef = -coef;
+    }
+    let cxp = coef * rx * y1p / ry;
+    let cyp = -coef * ry * x1p / rx;
+    let cx = cos_phi * cxp - sin_phi * cyp + (p0.0 + p1.0) / 2.0;
+    let cy = sin_phi * cxp + cos_phi * cyp + (p0.1 + p1.1) / 2.0;
+
+    let theta1 = vec_angle(1.0, 0.0, (x1p - cxp) / rx, (y1p - cyp) / ry);
+    let mut dtheta = vec_angle((x1p - cxp) / rx, (y1p - cyp) / ry,
+                               (-x1p - cxp) / rx, (-y1p - cyp) / ry);
+    if !sweep && dtheta > 0.0 {
+        dtheta -= 2.0 * PI;
+    } else if sweep && dtheta < 0.0 {
+        dtheta += 2.0 * PI;
+    }
+
+    let n = segments_for(dtheta.abs() * rx.max(ry), tolerance);
+    for i in 1..n + 1 {
+        let theta = theta1 + dtheta * (i as f64 / n as f64);
+        let (s, c) = theta.sin_cos();
+        out.push((cx + cos_phi * rx * c - sin_phi * ry * s,
+                  cy + sin_phi * rx * c + cos_phi * ry * s));
+    }
+    // Land exactly on the endpoint so rounding doesn't open gaps between segments.
+    if let Some(last) = out.last_mut() {
+        *last = p1;
+    }
+}
+
+/*
+ * Flattens a path into one polyline per subpath. The tolerance is roughly the largest
+ * allowed distance between the curve and its approximation, in path units.
+ */
+pub fn flatten(path: &[PathElem], tolerance: f64) -> Vec<Polyline> {
+    let mut out = Vec::<Polyline>::new();
+    let mut cur = Polyline::new();
+    let mut cp = (0.0, 0.0);
+    for elem in path {
+        match *elem {
+            PathElem::MoveTo { x, y } => {
+                if cur.len() > 1 {
+                    out.push(cur);
+                }
+                cur = vec![(x, y)];
+                cp = (x, y);
+                continue;
+            },
+            _ => if cur.is_empty() {
+                cur.push(cp);
+            }
+        }
+        match *elem {
+            PathElem::LineTo { x, y } => cur.push((x, y)),
+            PathElem::CurveTo { x1, y1, x2, y2, x, y } =>
+                push_cubic(&mut cur, cp, (x1, y1), (x2, y2), (x, y), tolerance),
+            PathElem::QuadraticTo { x1, y1, x, y } =>
+                push_quad(&mut cur, cp, (x1, y1), (x, y), tolerance),
+            PathElem::ArcTo { rx, ry, x_rotation, lrg_arc, sweep, x, y } =>
+                push_arc(&mut cur, cp, rx, ry, x_rotation, lrg_arc, sweep, (x, y), tolerance),
+            PathElem::MoveTo { .. } => ()
+        }
+        cp = *cur.last().unwrap();
+    }
+    if cur.len() > 1 {
+        out.push(cur);
+    }
+    out
+}
+
+pub fn transform_polylines(polys: &[Polyline], t: &Transform) -> Vec<Polyline> {
+    polys.iter().map(|p| p.iter().map(|&(x, y)| t.apply(x, y)).collect()).collect()
+}
+
+/*
+ * The area a stroke of the given width covers along polylines, as polygons to be filled
+ * with the non-zero rule: a quad per segment and a disc at every point, which gives round
+ * joins and caps. All are wound the same way so that where they overlap they add up
+ * rather than cancel out.
+ */
+pub fn stroke(polys: &[Polyline], width: f64) -> Vec<Polyline> {
+    const DISC_SIDES: usize = 16;
+    let r = width / 2.0;
+    let wound = |mut poly: Polyline| {
+        let n = poly.len();
+        let area: f64 = (0..n).map(|i| poly[i].0 * poly[(i + 1) % n].1 - poly[(i + 1) % n].0 * poly[i].1).sum();
+        if area < 0.0 {
+            poly.reverse();
+        }
+        poly
+    };
+    let mut out = Vec::new();
+    for poly in polys {
+        for (i, &(x, y)) in poly.iter().enumerate() {
+            out.push((0..DISC_SIDES).map(|k| {
+                let a = k as f64 * 2.0 * PI / DISC_SIDES as f64;
+                (x + r * a.cos(), y + r * a.sin())
+            }).collect());
+            if let Some(&(x1, y1)) = poly.get(i + 1) {
+                let len = ((x1 - x) * (x1 - x) + (y1 - y) * (y1 - y)).sqrt();
+                if len == 0.0 {
+                    continue;
+                }
+                let (nx, ny) = (-(y1 - y) / len * r, (x1 - x) / len * r);
+                out.push(wound(vec![(x + nx, y + ny), (x1 + nx, y1 + ny), (x1 - nx, y1 - ny), (x - nx, y - ny)]));
+            }
+        }
+    }
+    out
+}
+
+/*
+ * Bounding box of a set of polylines as (min_x, min_y, max_x, max_y).
+ */
+pub fn bounds(polys: &[Polyline]) -> Option<(f64, f64, f64, f64)> {
+    let mut it = polys.iter().flat_map(|p| p.iter());
+    it.next().map(|&(x, y)| it.fold((x, y, x, y), |(x0, y0, x1, y1), &(x, y)| {
+        (x0.min(x), y0.min(y), x1.max(x), y1.max(y))
+    }))
+}
+
+/*
+ * Cumulative arc lengths over flattened subpaths. Distances run continuously across
+ * subpaths, so the gap between one subpath's end and the next's start is not counted.
+ */
+pub struct PathMeasure {
+    contours: Vec<(Polyline, Vec<f64>)>,
+    total: f64
+}
+
+impl PathMeasure {
+    pub fn new(polys: Vec<Polyline>) -> PathMeasure {
+        let mut total = 0.0;
+        let contours = polys.into_iter().map(|poly| {
+            let mut lens = Vec::with_capacity(poly.len());
+            lens.push(total);
+            for i in 1..poly.len() {
+                total += dist(poly[i - 1], poly[i]);
+                lens.push(total);
+            }
+            (poly, lens)
+        }).collect();
+        PathMeasure { contours: contours, total: total }
+    }
+
+    pub fn from_path(path: &[PathElem], tolerance: f64) -> PathMeasure {
+        PathMeasure::new(flatten(path, tolerance))
+    }
+
+    pub fn length(&self) -> f64 {
+        self.total
+    }
+
+    /*
+     * Position and tangent angle (radians) at a distance along the path. Distances are
+     * clamped to the ends of the path.
+     */
+    pub fn point_at(&self, d: f64) -> Option<(f64, f64, f64)> {
+        let d = d.max(0.0).min(self.total);
+        for &(ref poly, ref lens) in &self.contours {
+            if poly.len() < 2 || d > *lens.last().unwrap() {
+                continue;
+            }
+            let i = match lens.iter().position(|&l| l >= d) {
+                Some(0) => 1,
+                Some(i) => i,
+                None => poly.len() - 1
+            };
+            let seg = lens[i] - lens[i - 1];
+            let t = if seg > 0.0 { (d - lens[i - 1]) / seg } else { 0.0 };
+            let (x, y) = lerp(poly[i - 1], poly[i], t);
+            let angle = (poly[i].1 - poly[i - 1].1).atan2(poly[i].0 - poly[i - 1].0);
+            return Some((x, y, angle));
+        }
+        None
+    }
+
+    /*
+     * The parts of the path between two distances along it, one polyline per contour
+     * they touch.
+     */
+    pub fn segment(&self, from: f64, to: f64) -> Vec<Polyline> {
+        let mut out = Vec::new();
+        for &(ref poly, ref lens) in &self.contours {
+            if poly.len() < 2 || lens[0] >= to || *lens.last().unwrap() <= from {
+                continue;
+            }
+            let mut part = Vec::new();
+            for i in 1..poly.len() {
+                let (l0, l1) = (lens[i - 1], lens[i]);
+                if l1 <= from || l0 >= to {
+                    continue;
+                }
+                let seg = l1 - l0;
+                let at = |d: f64| if seg > 0.0 { lerp(poly[i - 1], poly[i], (d - l0) / seg) } else { poly[i] };
+                if part.is_empty() {
+                    part.push(if l0 < from { at(from) } else { poly[i - 1] });
+                }
+                part.push(if l1 > to { at(to) } else { poly[i] });
+            }
+            if part.len() > 1 {
+                out.push(part);
+            }
+        }
+        out
+    }
+}
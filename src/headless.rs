@@ -0,0 +1,376 @@
+/*
+ * Runs a ditty without a window, for tests and build machines. Frames are drawn by the
+ * software backend and time comes from a virtual clock that moves on by a set amount each
+ * frame, so a run does exactly the same thing every time and as fast as the machine can
+ * go. Input is scripted by frame number.
+ *
+ * As in the game loop, the ditty sits at the bottom of a ditty stack, so the screens it
+ * pushes and replaces run too. Video settings sent up by an options screen resize the
+ * offscreen image.
+ *
+ * A run can be recorded, or driven by a recording instead of a script, in which case it
+ * stops when the recording ends or the game's state stops matching it.
+ */
+
+use std::time::Instant;
+
+use assets::Assets;
+use audio::Audio;
+use audio::AudioOutput;
+use audio::NullOutput;
+use backend::Backend;
+use color::Rgba;
+use debug;
+use debug::CountingBackend;
+use debug::DrawCounters;
+use debug::FrameStats;
+use debug::Stats;
+use ditty::Ditty;
+use dittystack::Command;
+use dittystack::DittyStack;
+use image::Image;
+use input::InputEvent;
+use replay::Recorder;
+use replay::Recording;
+use replay::Replayer;
+use software::SoftwareBackend;
+use timestep::FixedStep;
+
+pub struct Headless {
+    ditty: DittyStack,
+    backend: SoftwareBackend,
+    clock: FixedStep,
+    update_rate: u32,
+    frame_time: f64,
+    background: Rgba,
+    script: Vec<(u64, InputEvent)>,
+    frame: u64,
+    initialised: bool,
+    counters: DrawCounters,
+    stats: Stats,
+    recorder: Option<Recorder>,
+    replayer: Option<Replayer>,
+    replay_error: Option<String>,
+    audio: Option<NullOutput>,
+    assets: Option<Assets>
+}
+
+impl Headless {
+    /*
+     * Frames are a sixtieth of a second apart, with sixty updates a second, unless
+     * changed before the first frame.
+     */
+    pub fn new<T: Ditty + 'static>(ditty: T, width: u32, height: u32) -> Headless {
+        Headless {
+            ditty: DittyStack::new(Box::new(ditty)),
+            backend: SoftwareBackend::new(width, height),
+            clock: FixedStep::per_second(60, 5),
+            update_rate: 60,
+            frame_time: 1.0 / 60.0,
+            background: Rgba::rgb(0, 0, 0),
+            script: Vec::new(),
+            frame: 0,
+            initialised: false,
+            counters: DrawCounters::default(),
+            stats: Stats::new(),
+            recorder: None,
+            replayer: None,
+            replay_error: None,
+            audio: None,
+            assets: None
+        }
+    }
+
+    pub fn set_frame_time(&mut self, seconds: f64) {
+        self.frame_time = seconds;
+    }
+
+    pub fn set_update_rate(&mut self, rate: u32, max_steps: u32) {
+        self.clock = FixedStep::per_second(rate, max_steps);
+        self.update_rate = rate;
+    }
+
+    pub fn set_background(&mut self, colour: Rgba) {
+        self.background = colour;
+    }
+
+    /*
+     * Queues an event to be delivered at the start of the given frame, after any already
+     * queued for it.
+     */
+    pub fn schedule(&mut self, frame: u64, event: InputEvent) {
+        let at = self.script.iter().position(|&(f, _)| f > frame).unwrap_or(self.script.len());
+        self.script.insert(at, (frame, event));
+    }
+
+    /*
+     * Mixes the audio a frame's worth at a time, in virtual time, and discards it.
+     */
+    pub fn set_audio(&mut self, audio: Audio) {
+        self.audio = Some(NullOutput::new(audio));
+    }
+
+    pub fn audio(&self) -> Option<&NullOutput> {
+        self.audio.as_ref()
+    }
+
+    /*
+     * Frees assets that are no longer used after each frame, as the game loop does.
+     */
+    pub fn use_assets(&mut self, assets: Assets) {
+        self.assets = Some(assets);
+    }
+
+    /*
+     * Records the scripted input from here on, with the state hashed every `hash_every`
+     * updates. Start before the first frame to be able to replay it.
+     */
+    pub fn record(&mut self, hash_every: u64) {
+        self.recorder = Some(Recorder::new(self.update_rate, hash_every));
+    }
+
+    pub fn recording(&self) -> Option<&Recording> {
+        self.recorder.as_ref().map(|r| r.recording())
+    }
+
+    /*
+     * Drives the run from a recording, at its update rate, in place of the script.
+     */
+    pub fn replay(&mut self, recording: Recording) {
+        let max_steps = self.clock.max_steps();
+        self.set_update_rate(recording.update_rate, max_steps);
+        self.replayer = Some(Replayer::new(recording));
+    }
+
+    /*
+     * Why the replay stopped early, if it did.
+     */
+    pub fn replay_error(&self) -> Option<&str> {
+        self.replay_error.as_ref().map(|e| &e[..])
+    }
+
+    /*
+     * State hashes from the recording that have matched so far.
+     */
+    pub fn replay_verified(&self) -> usize {
+        self.replayer.as_ref().map(|r| r.verified()).unwrap_or(0)
+    }
+
+    /*
+     * The number of frames run so far, which is also the number of the next one.
+     */
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /*
+     * Virtual seconds since the start.
+     */
+    pub fn time(&self) -> f64 {
+        self.frame as f64 * self.frame_time
+    }
+
+    pub fn image(&self) -> &Image {
+        self.backend.image()
+    }
+
+    pub fn backend(&mut self) -> &mut SoftwareBackend {
+        &mut self.backend
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn ditty(&self) -> &DittyStack {
+        &self.ditty
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.ditty.is_finished()
+    }
+
+    /*
+     * Runs one frame: its scripted input, the updates due, then drawing. Returns false
+     * once the ditty has finished, without drawing.
+     */
+    pub fn step(&mut self) -> bool {
+        let frame_start = Instant::now();
+        if !self.initialised {
+            self.ditty.init(&mut CountingBackend::new(&mut self.backend, &mut self.counters));
+            self.initialised = true;
+        }
+        let replay_over = self.replayer.as_ref().map(|r| r.is_finished(self.clock.total_steps())).unwrap_or(false);
+        if self.replay_error.is_some() || replay_over {
+            return false;
+        }
+        while !self.script.is_empty() && self.script[0].0 <= self.frame {
+            let (_, event) = self.script.remove(0);
+            if self.replayer.is_some() {
+                continue;
+            }
+            if let Some(ref mut recorder) = self.recorder {
+                recorder.input(self.clock.total_steps(), &event);
+            }
+            self.ditty.handle_input(&event);
+        }
+
+        let update_start = Instant::now();
+        let updates = self.clock.advance(self.frame_time);
+        let first_tick = self.clock.total_steps() - updates as u64;
+        let mut replay_over = false;
+        for tick in first_tick..first_tick + updates as u64 {
+            if let Some(ref mut replayer) = self.replayer {
+                replayer.feed(tick, &mut self.ditty);
+            }
+            let command = self.ditty.update(self.clock.step());
+            if let Some(ref mut recorder) = self.recorder {
+                recorder.updated(tick + 1, &self.ditty);
+            }
+            if let Some(ref mut replayer) = self.replayer {
+                if let Err(e) = replayer.verify(tick + 1, &self.ditty) {
+                    self.replay_error = Some(e);
+                    return false;
+                }
+                if replayer.is_finished(tick + 1) {
+                    replay_over = true;
+                    break;
+                }
+            }
+            if let Some(Command::Video(video)) = command {
+                if video.resolution.0 > 0 && video.resolution.1 > 0 {
+                    self.backend.resize(video.resolution.0, video.resolution.1);
+                }
+            }
+        }
+        let update_ms = debug::ms_since(update_start);
+        if let Some(ref mut audio) = self.audio {
+            audio.pump(self.frame_time);
+        }
+        self.frame += 1;
+        if self.ditty.is_finished() || replay_over {
+            return false;
+        }
+
+        let render_start = Instant::now();
+        let (width, height) = self.backend.size();
+        self.backend.clear(self.background);
+        self.counters.draw_calls = 0;
+        self.ditty.render(&mut CountingBackend::new(&mut self.backend, &mut self.counters), width, height,
+                          self.clock.alpha());
+        let render_ms = debug::ms_since(render_start);
+        self.stats.record(FrameStats {
+            update_ms: update_ms,
+            updates: updates,
+            render_ms: render_ms,
+            frame_ms: debug::ms_since(frame_start),
+            draw_calls: self.counters.draw_calls,
+            textures: self.counters.textures,
+            ditties: self.ditty.take_stats()
+        }, self.frame_time * 1000.0);
+        if let Some(ref assets) = self.assets {
+            assets.update(&mut CountingBackend::new(&mut self.backend, &mut self.counters));
+        }
+        true
+    }
+
+    /*
+     * Runs up to `frames` frames, stopping early if the ditty finishes. Returns the number
+     * drawn.
+     */
+    pub fn run(&mut self, frames: u64) -> u64 {
+        let mut drawn = 0;
+        while drawn < frames && self.step() {
+            drawn += 1;
+        }
+        drawn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use ditty::PathDitty;
+    use input::Key;
+    use input::Modifiers;
+    use spath::PathElem;
+
+    /*
+     * Shows red once a key has gone down, and notes any text typed.
+     */
+    struct Switch {
+        on: bool,
+        typed: Rc<RefCell<String>>
+    }
+
+    impl Ditty for Switch {
+        fn init(&mut self, backend: &mut Backend) {
+        }
+
+        fn render(&mut self, backend: &mut Backend, width: u32, height: u32, alpha: f64) {
+            backend.set_color(if self.on { Rgba::rgb(255, 0, 0) } else { Rgba::rgb(0, 0, 255) });
+            backend.fill_rect(0.0, 0.0, width as f64, height as f64);
+        }
+
+        fn handle_input(&mut self, event: &InputEvent) -> bool {
+            match *event {
+                InputEvent::KeyDown { .. } => self.on = true,
+                InputEvent::Text(ref text) => self.typed.borrow_mut().push_str(text),
+                _ => return false
+            }
+            true
+        }
+    }
+
+    fn key(key: Key, down: bool) -> InputEvent {
+        if down {
+            InputEvent::KeyDown { key: key, modifiers: Modifiers::default(), repeat: false }
+        } else {
+            InputEvent::KeyUp { key: key, modifiers: Modifiers::default() }
+        }
+    }
+
+    #[test]
+    fn scheduled_input_arrives_on_its_frame_in_order() {
+        let typed = Rc::new(RefCell::new(String::new()));
+        let mut run = Headless::new(Switch { on: false, typed: typed.clone() }, 4, 4);
+        run.schedule(3, InputEvent::Text("a".to_string()));
+        run.schedule(3, InputEvent::Text("b".to_string()));
+        run.schedule(3, key(Key::Space, true));
+        run.schedule(1, InputEvent::Text("c".to_string()));
+
+        assert_eq!(run.run(3), 3);
+        assert_eq!(run.image().get(0, 0), Rgba::rgb(0, 0, 255));
+        assert_eq!(*typed.borrow(), "c");
+        run.step();
+        assert_eq!(run.image().get(0, 0), Rgba::rgb(255, 0, 0));
+        assert_eq!(*typed.borrow(), "cab");
+        assert_eq!(run.frame(), 4);
+        assert!((run.time() - 4.0 / 60.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn pausing_covers_the_view_and_quitting_ends_the_run() {
+        let line = vec![PathElem::MoveTo { x: 0.0, y: 0.0 }, PathElem::LineTo { x: 10.0, y: 10.0 }];
+        let mut run = Headless::new(PathDitty::new(vec![line]), 32, 32);
+        run.set_background(Rgba::rgb(255, 255, 255));
+        run.schedule(2, key(Key::Escape, true));
+        run.schedule(3, key(Key::Escape, false));
+        run.schedule(40, key(Key::Char('q'), true));
+
+        run.run(2);
+        assert_eq!(run.ditty().len(), 1);
+        assert_eq!(run.image().get(4, 16), Rgba::rgb(255, 255, 255));
+        // The pause menu slides in over a third of a second.
+        run.run(30);
+        assert_eq!(run.ditty().len(), 2);
+        assert_eq!(run.ditty().name(), "pause");
+        let dimmed = run.image().get(4, 16);
+        assert!(dimmed.r < 128 && dimmed.r == dimmed.g && dimmed.g == dimmed.b, "{:?}", dimmed);
+
+        assert_eq!(run.run(100), 8);
+        assert!(run.is_finished());
+    }
+}
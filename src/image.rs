@@ -0,0 +1,425 @@
+/*
+ * In-memory RGBA images, used as render targets for the software backend and as the
+ * source pixels when creating textures.
+ */
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use color::Rgba;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    // Row-major, four bytes per pixel in R, G, B, A order.
+    pub pixels: Vec<u8>
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32) -> Image {
+        Image { width: width, height: height, pixels: vec![0; (width * height * 4) as usize] }
+    }
+
+    pub fn filled(width: u32, height: u32, colour: Rgba) -> Image {
+        let mut image = Image::new(width, height);
+        image.fill(colour);
+        image
+    }
+
+    pub fn fill(&mut self, colour: Rgba) {
+        for px in self.pixels.chunks_mut(4) {
+            px[0] = colour.r;
+            px[1] = colour.g;
+            px[2] = colour.b;
+            px[3] = colour.a;
+        }
+    }
+
+    fn offset(&self, x: u32, y: u32) -> usize {
+        ((y * self.width + x) * 4) as usize
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Rgba {
+        let o = self.offset(x, y);
+        Rgba::new(self.pixels[o], self.pixels[o + 1], self.pixels[o + 2], self.pixels[o + 3])
+    }
+
+    pub fn put(&mut self, x: u32, y: u32, colour: Rgba) {
+        let o = self.offset(x, y);
+        self.pixels[o] = colour.r;
+        self.pixels[o + 1] = colour.g;
+        self.pixels[o + 2] = colour.b;
+        self.pixels[o + 3] = colour.a;
+    }
+
+    /*
+     * Source-over blend of a non-premultiplied colour onto the pixel at (x, y).
+     */
+    pub fn blend(&mut self, x: u32, y: u32, colour: Rgba) {
+        if colour.a == 255 {
+            return self.put(x, y, colour);
+        }
+        if colour.a == 0 {
+            return;
+        }
+        let o = self.offset(x, y);
+        let sa = colour.a as u32;
+        let da = self.pixels[o + 3] as u32 * (255 - sa) / 255;
+        let oa = sa + da;
+        let mix = |s: u8, d: u8| ((s as u32 * sa + d as u32 * da) / oa) as u8;
+        self.pixels[o] = mix(colour.r, self.pixels[o]);
+        self.pixels[o + 1] = mix(colour.g, self.pixels[o + 1]);
+        self.pixels[o + 2] = mix(colour.b, self.pixels[o + 2]);
+        self.pixels[o + 3] = oa as u8;
+    }
+
+    /*
+     * Copies a w x h block from src at (sx, sy) to (dx, dy), without blending.
+     */
+    pub fn blit(&mut self, src: &Image, sx: u32, sy: u32, w: u32, h: u32, dx: u32, dy: u32) {
+        for row in 0..h {
+            if sy + row >= src.height || dy + row >= self.height {
+                break;
+            }
+            let cols = w.min(src.width - sx).min(self.width - dx);
+            let so = src.offset(sx, sy + row);
+            let d = self.offset(dx, dy + row);
+            let n = (cols * 4) as usize;
+            self.pixels[d..d + n].copy_from_slice(&src.pixels[so..so + n]);
+        }
+    }
+
+    /*
+     * Shrinks the image by an integer factor, averaging each factor x factor block. Colours
+     * are weighted by alpha so transparent pixels don't darken the edges.
+     */
+    pub fn downsample(&self, factor: u32) -> Image {
+        if factor <= 1 {
+            return self.clone();
+        }
+        let mut out = Image::new(self.width / factor, self.height / factor);
+        for y in 0..out.height {
+            for x in 0..out.width {
+                let (mut r, mut g, mut b, mut a) = (0u32, 0u32, 0u32, 0u32);
+                for sy in 0..factor {
+                    for sx in 0..factor {
+                        let c = self.get(x * factor + sx, y * factor + sy);
+                        r += c.r as u32 * c.a as u32;
+                        g += c.g as u32 * c.a as u32;
+                        b += c.b as u32 * c.a as u32;
+                        a += c.a as u32;
+                    }
+                }
+                if a > 0 {
+                    out.put(x, y, Rgba::new((r / a) as u8, (g / a) as u8, (b / a) as u8,
+                                            (a / (factor * factor)) as u8));
+                }
+            }
+        }
+        out
+    }
+
+    /*
+     * Decodes a Windows bitmap: 1, 4 or 8-bit palettised, uncompressed or run-length
+     * encoded, or 16, 24 or 32-bit, with or without bit field masks. Only 32-bit images
+     * carry alpha, and even then many writers leave the channel zeroed, so an image with
+     * no non-zero alpha at all is taken to be opaque.
+     */
+    pub fn load_bmp<P: AsRef<Path>>(path: P) -> Result<Image, String> {
+        let mut data = Vec::new();
+        try!(File::open(path).and_then(|mut f| f.read_to_end(&mut data))
+            .map_err(|e| format!("{}", e)));
+        Image::decode_bmp(&data)
+    }
+
+    pub fn decode_bmp(data: &[u8]) -> Result<Image, String> {
+        let u16_at = |o: usize| data[o] as u32 | (data[o + 1] as u32) << 8;
+        let u32_at = |o: usize| u16_at(o) | u16_at(o + 2) << 16;
+        if data.len() < 26 || &data[0..2] != b"BM" {
+            return Err("not a BMP file".to_string());
+        }
+        let pixel_offset = u32_at(10) as usize;
+        let header_size = u32_at(14) as usize;
+        // OS/2 bitmaps have a shorter header, and three byte palette entries.
+        let core = header_size == 12;
+        if !core && (header_size < 40 || data.len() < 54) {
+            return Err("unsupported BMP header".to_string());
+        }
+        let (width, raw_height, bpp, compression, colours_used) = if core {
+            (u16_at(18) as i32, u16_at(20) as i32, u16_at(24), 0, 0)
+        } else {
+            (u32_at(18) as i32, u32_at(22) as i32, u16_at(28), u32_at(30), u32_at(46) as usize)
+        };
+        let (height, top_down) = if raw_height < 0 { (-(raw_height as i64), true) } else { (raw_height as i64, false) };
+        if width <= 0 || height <= 0 {
+            return Err("empty BMP".to_string());
+        }
+        let (width, height) = (width as usize, height as usize);
+        if width.checked_mul(height).and_then(|n| n.checked_mul(4)).map(|n| n > u32::max_value() as usize).unwrap_or(true) {
+            return Err("BMP too large".to_string());
+        }
+        let rle = match (compression, bpp) {
+            (0, 1) | (0, 4) | (0, 8) | (0, 16) | (0, 24) | (0, 32) | (3, 16) | (3, 32) => false,
+            (1, 8) | (2, 4) if !top_down => true,
+            _ => return Err(format!("unsupported BMP depth {} with compression {}", bpp, compression))
+        };
+
+        let palette = if bpp <= 8 {
+            let entry = if core { 3 } else { 4 };
+            let count = if colours_used == 0 || colours_used > 1 << bpp { 1 << bpp } else { colours_used };
+            let start = 14 + header_size;
+            let mut palette = Vec::with_capacity(count);
+            for i in 0..count {
+                let p = start + i * entry;
+                if p + 3 > data.len() {
+                    return Err("truncated BMP palette".to_string());
+                }
+                palette.push(Rgba::rgb(data[p + 2], data[p + 1], data[p]));
+            }
+            palette
+        } else {
+            Vec::new()
+        };
+        let colour_at = |index: u8| palette.get(index as usize).cloned()
+            .ok_or("BMP colour index out of range".to_string());
+        // Bit field masks follow a plain info header, and are part of longer ones, which
+        // also have room for alpha.
+        let masks = if compression == 3 {
+            let alpha = if header_size >= 56 { u32_at(66) } else { 0 };
+            [Mask::new(u32_at(54)), Mask::new(u32_at(58)), Mask::new(u32_at(62)), Mask::new(alpha)]
+        } else if bpp == 16 {
+            [Mask::new(0x7c00), Mask::new(0x03e0), Mask::new(0x001f), Mask::new(0)]
+        } else {
+            [Mask::new(0x00ff0000), Mask::new(0x0000ff00), Mask::new(0x000000ff), Mask::new(0xff000000)]
+        };
+
+        let mut image = Image::new(width as u32, height as u32);
+        if rle {
+            if pixel_offset > data.len() {
+                return Err("truncated BMP".to_string());
+            }
+            let indices = try!(decode_rle(&data[pixel_offset..], width, height, bpp == 4));
+            for (row, line) in indices.chunks(width).enumerate() {
+                for (x, &index) in line.iter().enumerate() {
+                    image.put(x as u32, (height - 1 - row) as u32, try!(colour_at(index)));
+                }
+            }
+            return Ok(image);
+        }
+
+        let stride = try!(width.checked_mul(bpp as usize).and_then(|bits| bits.checked_add(31))
+            .map(|bits| bits / 32 * 4).ok_or("BMP too large".to_string()));
+        let end = stride.checked_mul(height).and_then(|size| size.checked_add(pixel_offset));
+        if end.map(|end| end > data.len()).unwrap_or(true) {
+            return Err("truncated BMP".to_string());
+        }
+        for row in 0..height {
+            let y = if top_down { row } else { height - 1 - row };
+            let line = &data[pixel_offset + stride * row..];
+            for x in 0..width {
+                let colour = match bpp {
+                    1 => try!(colour_at(line[x / 8] >> (7 - x % 8) & 1)),
+                    4 => try!(colour_at(line[x / 2] >> (if x % 2 == 0 { 4 } else { 0 }) & 0xf)),
+                    8 => try!(colour_at(line[x])),
+                    24 => Rgba::rgb(line[x * 3 + 2], line[x * 3 + 1], line[x * 3]),
+                    _ => {
+                        let px = if bpp == 16 {
+                            line[x * 2] as u32 | (line[x * 2 + 1] as u32) << 8
+                        } else {
+                            line[x * 4] as u32 | (line[x * 4 + 1] as u32) << 8 |
+                                (line[x * 4 + 2] as u32) << 16 | (line[x * 4 + 3] as u32) << 24
+                        };
+                        Rgba::new(masks[0].get(px, 0), masks[1].get(px, 0), masks[2].get(px, 0), masks[3].get(px, 255))
+                    }
+                };
+                image.put(x as u32, y as u32, colour);
+            }
+        }
+        if bpp == 32 && image.pixels.chunks(4).all(|px| px[3] == 0) {
+            for px in image.pixels.chunks_mut(4) {
+                px[3] = 255;
+            }
+        }
+        Ok(image)
+    }
+}
+
+/*
+ * Where one colour channel sits in a 16 or 32-bit pixel.
+ */
+#[derive(Clone, Copy)]
+struct Mask {
+    mask: u32,
+    shift: u32,
+    bits: u32
+}
+
+impl Mask {
+    fn new(mask: u32) -> Mask {
+        let shift = if mask == 0 { 0 } else { mask.trailing_zeros() };
+        Mask { mask: mask, shift: shift, bits: (mask >> shift).count_ones() }
+    }
+
+    /*
+     * The channel scaled to eight bits, or `missing` if the pixel has no such channel.
+     */
+    fn get(&self, px: u32, missing: u8) -> u8 {
+        if self.bits == 0 {
+            return missing;
+        }
+        let max = (1u64 << self.bits) - 1;
+        ((((px & self.mask) >> self.shift) as u64 * 255 + max / 2) / max) as u8
+    }
+}
+
+/*
+ * Expands RLE8 or RLE4 data into one palette index a pixel, bottom row first. Pixels the
+ * data skips over are left as index 0.
+ */
+fn decode_rle(data: &[u8], width: usize, height: usize, four: bool) -> Result<Vec<u8>, String> {
+    let byte = |pos: usize| data.get(pos).cloned().ok_or("truncated BMP".to_string());
+    // The i-th index of a run whose bytes hold one or, for RLE4, two indices each.
+    let index = |b: u8, i: usize| if !four { b } else if i % 2 == 0 { b >> 4 } else { b & 0xf };
+    let mut out = vec![0u8; width * height];
+    let (mut x, mut y, mut pos) = (0, 0, 0);
+    while y < height {
+        let (count, value) = (try!(byte(pos)) as usize, try!(byte(pos + 1)));
+        pos += 2;
+        if count > 0 {
+            for i in 0..count {
+                if x < width {
+                    out[y * width + x] = index(value, i);
+                }
+                x += 1;
+            }
+            continue;
+        }
+        match value {
+            0 => {
+                x = 0;
+                y += 1;
+            },
+            1 => break,
+            2 => {
+                x += try!(byte(pos)) as usize;
+                y += try!(byte(pos + 1)) as usize;
+                pos += 2;
+            },
+            n => {
+                let n = n as usize;
+                for i in 0..n {
+                    let b = try!(byte(pos + if four { i / 2 } else { i }));
+                    if x < width {
+                        out[y * width + x] = index(b, i);
+                    }
+                    x += 1;
+                }
+                // Absolute runs are padded to a whole number of words.
+                let bytes = if four { (n + 1) / 2 } else { n };
+                pos += bytes + (bytes & 1);
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use color::Rgba;
+
+    /*
+     * A bitmap with a plain info header. Palette entries are B, G, R, 0.
+     */
+    fn bmp(bpp: u16, compression: u32, width: i32, height: i32, palette: &[[u8; 4]], pixels: &[u8]) -> Vec<u8> {
+        let le16 = |out: &mut Vec<u8>, v: u16| out.extend_from_slice(&[v as u8, (v >> 8) as u8]);
+        let le32 = |out: &mut Vec<u8>, v: u32| out.extend_from_slice(&[v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]);
+        let offset = 54 + palette.len() as u32 * 4;
+        let mut out = b"BM".to_vec();
+        le32(&mut out, offset + pixels.len() as u32);
+        le32(&mut out, 0);
+        le32(&mut out, offset);
+        le32(&mut out, 40);
+        le32(&mut out, width as u32);
+        le32(&mut out, height as u32);
+        le16(&mut out, 1);
+        le16(&mut out, bpp);
+        le32(&mut out, compression);
+        le32(&mut out, pixels.len() as u32);
+        le32(&mut out, 2835);
+        le32(&mut out, 2835);
+        le32(&mut out, palette.len() as u32);
+        le32(&mut out, 0);
+        for entry in palette {
+            out.extend_from_slice(entry);
+        }
+        out.extend_from_slice(pixels);
+        out
+    }
+
+    const PALETTE: [[u8; 4]; 3] = [[0, 0, 0, 0], [0, 0, 255, 0], [255, 0, 0, 0]];
+
+    fn red() -> Rgba { Rgba::rgb(255, 0, 0) }
+    fn blue() -> Rgba { Rgba::rgb(0, 0, 255) }
+    fn black() -> Rgba { Rgba::rgb(0, 0, 0) }
+
+    #[test]
+    fn decodes_24_bit_bottom_up() {
+        // Rows are padded to four bytes; the first row in the file is the bottom one.
+        let pixels = [0, 0, 255, 255, 0, 0, 0, 0,
+                      255, 255, 255, 0, 0, 0, 0, 0];
+        let image = Image::decode_bmp(&bmp(24, 0, 2, 2, &[], &pixels)).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(image.get(0, 1), red());
+        assert_eq!(image.get(1, 1), blue());
+        assert_eq!(image.get(0, 0), Rgba::rgb(255, 255, 255));
+    }
+
+    #[test]
+    fn decodes_palettised_depths() {
+        let one = Image::decode_bmp(&bmp(1, 0, 3, 1, &PALETTE[..2], &[0b0100_0000, 0, 0, 0])).unwrap();
+        assert_eq!((one.get(0, 0), one.get(1, 0), one.get(2, 0)), (black(), red(), black()));
+        let four = Image::decode_bmp(&bmp(4, 0, 3, 1, &PALETTE, &[0x12, 0x00, 0, 0])).unwrap();
+        assert_eq!((four.get(0, 0), four.get(1, 0), four.get(2, 0)), (red(), blue(), black()));
+        let eight = Image::decode_bmp(&bmp(8, 0, 2, 1, &PALETTE, &[2, 1, 0, 0])).unwrap();
+        assert_eq!((eight.get(0, 0), eight.get(1, 0)), (blue(), red()));
+    }
+
+    #[test]
+    fn decodes_16_bit_as_555() {
+        let image = Image::decode_bmp(&bmp(16, 0, 2, 1, &[], &[0x00, 0x7c, 0x1f, 0x00])).unwrap();
+        assert_eq!((image.get(0, 0), image.get(1, 0)), (red(), blue()));
+    }
+
+    #[test]
+    fn decodes_run_length_encoding() {
+        // Two rows of four: a run of three reds then an absolute pair, then end of bitmap.
+        let rle8 = [3, 1, 0, 0, 0, 3, 2, 1, 2, 0, 0, 1];
+        let image = Image::decode_bmp(&bmp(8, 1, 4, 2, &PALETTE, &rle8)).unwrap();
+        assert_eq!(image.get(2, 1), red());
+        assert_eq!(image.get(3, 1), black());
+        assert_eq!((image.get(0, 0), image.get(1, 0), image.get(2, 0)), (blue(), red(), blue()));
+        let rle4 = [4, 0x12, 0, 1];
+        let image = Image::decode_bmp(&bmp(4, 2, 4, 1, &PALETTE, &rle4)).unwrap();
+        assert_eq!((image.get(0, 0), image.get(1, 0), image.get(2, 0), image.get(3, 0)), (red(), blue(), red(), blue()));
+    }
+
+    #[test]
+    fn rejects_malformed_files_without_panicking() {
+        // Palette cut short.
+        let mut short = bmp(8, 0, 1, 1, &PALETTE, &[]);
+        short.truncate(60);
+        assert!(Image::decode_bmp(&short).is_err());
+        // Index past the end of the palette.
+        assert!(Image::decode_bmp(&bmp(8, 0, 1, 1, &PALETTE, &[7, 0, 0, 0])).is_err());
+        // Sizes whose arithmetic overflows.
+        assert!(Image::decode_bmp(&bmp(32, 0, 0x7fffffff, 0x7fffffff, &[], &[0; 16])).is_err());
+        assert!(Image::decode_bmp(&bmp(24, 0, 40000, 40000, &[], &[0; 16])).is_err());
+        // Run length data that stops early.
+        assert!(Image::decode_bmp(&bmp(8, 1, 4, 2, &PALETTE, &[3, 1])).is_err());
+        assert!(Image::decode_bmp(&bmp(24, 0, 2, 2, &[], &[0; 4])).is_err());
+        assert!(Image::decode_bmp(b"BM").is_err());
+    }
+}
@@ -0,0 +1,411 @@
+/*
+ * Input events as ditties see them. The game loop translates whatever the platform
+ * delivers into these, so ditties never touch SDL types and events can be made up by
+ * other sources.
+ */
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Key {
+    /*
+     * Letters, digits and punctuation, by the (lowercase) character they type unshifted.
+     */
+    Char(char),
+    Space,
+    Return,
+    Escape,
+    Tab,
+    Backspace,
+    Delete,
+    Insert,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Up,
+    Down,
+    Left,
+    Right,
+    F(u8),
+    Shift,
+    Ctrl,
+    Alt,
+    Other(i32)
+}
+
+impl Key {
+    /*
+     * Translates an SDL keycode, which is the character for printable keys and the
+     * scancode with bit 30 set for the rest.
+     */
+    pub fn from_sdl_code(code: i32) -> Key {
+        const SCANCODE: i32 = 1 << 30;
+        match code {
+            8 => Key::Backspace,
+            9 => Key::Tab,
+            13 => Key::Return,
+            27 => Key::Escape,
+            32 => Key::Space,
+            127 => Key::Delete,
+            33...126 => Key::Char((code as u8 as char).to_ascii_lowercase()),
+            _ if code & SCANCODE != 0 => match code & !SCANCODE {
+                58...69 => Key::F((code & !SCANCODE) as u8 - 57),
+                104...115 => Key::F((code & !SCANCODE) as u8 - 91),
+                73 => Key::Insert,
+                74 => Key::Home,
+                75 => Key::PageUp,
+                77 => Key::End,
+                78 => Key::PageDown,
+                79 => Key::Right,
+                80 => Key::Left,
+                81 => Key::Down,
+                82 => Key::Up,
+                224 | 228 => Key::Ctrl,
+                225 | 229 => Key::Shift,
+                226 | 230 => Key::Alt,
+                _ => Key::Other(code)
+            },
+            _ => Key::Other(code)
+        }
+    }
+
+    /*
+     * The name used for the key in config files: the character itself for most printable
+     * keys, and a word for the rest.
+     */
+    pub fn name(&self) -> String {
+        if let Some(&(name, _)) = NAMED_KEYS.iter().find(|&&(_, k)| k == *self) {
+            return name.to_string();
+        }
+        match *self {
+            Key::Char(c) => c.to_string(),
+            Key::F(n) => format!("f{}", n),
+            Key::Other(code) => format!("key{}", code),
+            _ => unreachable!()
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Key> {
+        if let Some(&(_, key)) = NAMED_KEYS.iter().find(|&&(n, _)| n == name) {
+            return Some(key);
+        }
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c > ' ' && c <= '~' => return Some(Key::Char(c.to_ascii_lowercase())),
+            _ => {}
+        }
+        if name.starts_with("key") {
+            name[3..].parse::<i32>().ok().map(Key::Other)
+        } else if name.starts_with('f') {
+            match name[1..].parse::<u8>() {
+                Ok(n) if n >= 1 && n <= 24 => Some(Key::F(n)),
+                _ => None
+            }
+        } else {
+            None
+        }
+    }
+}
+
+/*
+ * Keys with no character of their own, and characters that would confuse a config file.
+ */
+const NAMED_KEYS: [(&'static str, Key); 21] = [
+    ("space", Key::Space), ("return", Key::Return), ("escape", Key::Escape), ("tab", Key::Tab),
+    ("backspace", Key::Backspace), ("delete", Key::Delete), ("insert", Key::Insert), ("home", Key::Home),
+    ("end", Key::End), ("pageup", Key::PageUp), ("pagedown", Key::PageDown), ("up", Key::Up),
+    ("down", Key::Down), ("left", Key::Left), ("right", Key::Right), ("shift", Key::Shift),
+    ("ctrl", Key::Ctrl), ("alt", Key::Alt), ("comma", Key::Char(',')), ("plus", Key::Char('+')),
+    ("hash", Key::Char('#'))
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    Other(u8)
+}
+
+/*
+ * Game controller buttons, named after the Xbox layout as SDL's controller mappings are.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PadButton {
+    A,
+    B,
+    X,
+    Y,
+    Back,
+    Guide,
+    Start,
+    LeftStick,
+    RightStick,
+    LeftShoulder,
+    RightShoulder,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight
+}
+
+pub const PAD_BUTTONS: [PadButton; 15] = [
+    PadButton::A, PadButton::B, PadButton::X, PadButton::Y, PadButton::Back, PadButton::Guide,
+    PadButton::Start, PadButton::LeftStick, PadButton::RightStick, PadButton::LeftShoulder,
+    PadButton::RightShoulder, PadButton::DPadUp, PadButton::DPadDown, PadButton::DPadLeft,
+    PadButton::DPadRight
+];
+
+impl PadButton {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            PadButton::A => "a",
+            PadButton::B => "b",
+            PadButton::X => "x",
+            PadButton::Y => "y",
+            PadButton::Back => "back",
+            PadButton::Guide => "guide",
+            PadButton::Start => "start",
+            PadButton::LeftStick => "ls",
+            PadButton::RightStick => "rs",
+            PadButton::LeftShoulder => "lb",
+            PadButton::RightShoulder => "rb",
+            PadButton::DPadUp => "up",
+            PadButton::DPadDown => "down",
+            PadButton::DPadLeft => "left",
+            PadButton::DPadRight => "right"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<PadButton> {
+        PAD_BUTTONS.iter().cloned().find(|b| b.name() == name)
+    }
+}
+
+/*
+ * Stick axes run from -1 to 1, right and down being positive; triggers from 0 to 1.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PadAxis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    LeftTrigger,
+    RightTrigger
+}
+
+pub const PAD_AXES: [PadAxis; 6] = [
+    PadAxis::LeftX, PadAxis::LeftY, PadAxis::RightX, PadAxis::RightY, PadAxis::LeftTrigger,
+    PadAxis::RightTrigger
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool
+}
+
+/*
+ * Mouse positions are in window pixels. Wheel events carry the pointer position too, as
+ * most things that use the wheel zoom or scroll whatever is under it.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputEvent {
+    KeyDown { key: Key, modifiers: Modifiers, repeat: bool },
+    KeyUp { key: Key, modifiers: Modifiers },
+    Text(String),
+    MouseDown { button: MouseButton, x: i32, y: i32 },
+    MouseUp { button: MouseButton, x: i32, y: i32 },
+    MouseMove { x: i32, y: i32, dx: i32, dy: i32 },
+    Wheel { dx: i32, dy: i32, x: i32, y: i32 },
+    /*
+     * Controllers are numbered by slot, from 0. A controller plugged in takes the lowest
+     * free slot and keeps it until it is unplugged.
+     */
+    PadAdded(u32),
+    PadRemoved(u32),
+    PadDown { pad: u32, button: PadButton },
+    PadUp { pad: u32, button: PadButton },
+    PadAxis { pad: u32, axis: PadAxis, value: f64 },
+    Focus(bool)
+}
+
+/*
+ * What is held down right now, kept up to date from the event stream, for anything that
+ * would rather poll than react to events.
+ */
+pub struct InputState {
+    keys: HashSet<Key>,
+    buttons: HashSet<MouseButton>,
+    pad_buttons: HashSet<(u32, PadButton)>,
+    pad_axes: HashMap<(u32, PadAxis), f64>,
+    mouse: (i32, i32),
+    focused: bool
+}
+
+impl InputState {
+    pub fn new() -> InputState {
+        InputState {
+            keys: HashSet::new(),
+            buttons: HashSet::new(),
+            pad_buttons: HashSet::new(),
+            pad_axes: HashMap::new(),
+            mouse: (0, 0),
+            focused: true
+        }
+    }
+
+    pub fn apply(&mut self, event: &InputEvent) {
+        match *event {
+            InputEvent::KeyDown { key, .. } => {
+                self.keys.insert(key);
+            },
+            InputEvent::KeyUp { key, .. } => {
+                self.keys.remove(&key);
+            },
+            InputEvent::MouseDown { button, x, y } => {
+                self.buttons.insert(button);
+                self.mouse = (x, y);
+            },
+            InputEvent::MouseUp { button, x, y } => {
+                self.buttons.remove(&button);
+                self.mouse = (x, y);
+            },
+            InputEvent::MouseMove { x, y, .. } | InputEvent::Wheel { x, y, .. } => {
+                self.mouse = (x, y);
+            },
+            InputEvent::PadDown { pad, button } => {
+                self.pad_buttons.insert((pad, button));
+            },
+            InputEvent::PadUp { pad, button } => {
+                self.pad_buttons.remove(&(pad, button));
+            },
+            InputEvent::PadAxis { pad, axis, value } => {
+                self.pad_axes.insert((pad, axis), value);
+            },
+            InputEvent::PadRemoved(pad) => {
+                self.pad_buttons.retain(|&(p, _)| p != pad);
+                self.pad_axes.retain(|&(p, _), _| p != pad);
+            },
+            InputEvent::Focus(focused) => {
+                self.focused = focused;
+                // Releases that happen while unfocused never arrive.
+                if !focused {
+                    self.keys.clear();
+                    self.buttons.clear();
+                    self.pad_buttons.clear();
+                }
+            },
+            InputEvent::Text(_) | InputEvent::PadAdded(_) => {}
+        }
+    }
+
+    pub fn is_key_down(&self, key: Key) -> bool {
+        self.keys.contains(&key)
+    }
+
+    pub fn is_button_down(&self, button: MouseButton) -> bool {
+        self.buttons.contains(&button)
+    }
+
+    pub fn is_pad_button_down(&self, pad: u32, button: PadButton) -> bool {
+        self.pad_buttons.contains(&(pad, button))
+    }
+
+    pub fn pad_axis(&self, pad: u32, axis: PadAxis) -> f64 {
+        self.pad_axes.get(&(pad, axis)).cloned().unwrap_or(0.0)
+    }
+
+    pub fn mouse(&self) -> (i32, i32) {
+        self.mouse
+    }
+
+    pub fn is_focused(&self) -> bool {
+        self.focused
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCANCODE: i32 = 1 << 30;
+
+    #[test]
+    fn sdl_keycodes_become_keys() {
+        assert_eq!(Key::from_sdl_code('a' as i32), Key::Char('a'));
+        assert_eq!(Key::from_sdl_code('Q' as i32), Key::Char('q'));
+        assert_eq!(Key::from_sdl_code('=' as i32), Key::Char('='));
+        assert_eq!(Key::from_sdl_code(32), Key::Space);
+        assert_eq!(Key::from_sdl_code(13), Key::Return);
+        assert_eq!(Key::from_sdl_code(27), Key::Escape);
+        assert_eq!(Key::from_sdl_code(SCANCODE | 58), Key::F(1));
+        assert_eq!(Key::from_sdl_code(SCANCODE | 69), Key::F(12));
+        assert_eq!(Key::from_sdl_code(SCANCODE | 104), Key::F(13));
+        assert_eq!(Key::from_sdl_code(SCANCODE | 80), Key::Left);
+        assert_eq!(Key::from_sdl_code(SCANCODE | 82), Key::Up);
+        // Left and right modifiers are the same key.
+        assert_eq!(Key::from_sdl_code(SCANCODE | 224), Key::Ctrl);
+        assert_eq!(Key::from_sdl_code(SCANCODE | 228), Key::Ctrl);
+        assert_eq!(Key::from_sdl_code(SCANCODE | 229), Key::Shift);
+        assert_eq!(Key::from_sdl_code(SCANCODE | 300), Key::Other(SCANCODE | 300));
+        assert_eq!(Key::from_sdl_code(200), Key::Other(200));
+    }
+
+    #[test]
+    fn key_names_round_trip() {
+        let keys = [Key::Char('z'), Key::Char(','), Key::Char('#'), Key::Space, Key::F(7),
+                    Key::PageDown, Key::Alt, Key::Other(1234)];
+        for &key in keys.iter() {
+            assert_eq!(Key::from_name(&key.name()), Some(key), "{}", key.name());
+        }
+        assert_eq!(Key::from_name("Z"), Some(Key::Char('z')));
+        assert_eq!(Key::from_name("f25"), None);
+        assert_eq!(Key::from_name("nonsense"), None);
+    }
+
+    #[test]
+    fn state_follows_keys_buttons_and_the_wheel() {
+        let mut state = InputState::new();
+        state.apply(&InputEvent::KeyDown { key: Key::Char('w'), modifiers: Modifiers::default(), repeat: false });
+        state.apply(&InputEvent::MouseDown { button: MouseButton::Right, x: 10, y: 20 });
+        state.apply(&InputEvent::MouseDown { button: MouseButton::Other(4), x: 11, y: 21 });
+        assert!(state.is_key_down(Key::Char('w')));
+        assert!(state.is_button_down(MouseButton::Right) && state.is_button_down(MouseButton::Other(4)));
+        assert_eq!(state.mouse(), (11, 21));
+
+        state.apply(&InputEvent::MouseUp { button: MouseButton::Right, x: 12, y: 22 });
+        assert!(!state.is_button_down(MouseButton::Right));
+        // The wheel moves nothing but says where the pointer is.
+        state.apply(&InputEvent::Wheel { dx: 0, dy: -1, x: 30, y: 40 });
+        assert_eq!(state.mouse(), (30, 40));
+        assert!(state.is_button_down(MouseButton::Other(4)));
+
+        state.apply(&InputEvent::KeyUp { key: Key::Char('w'), modifiers: Modifiers::default() });
+        assert!(!state.is_key_down(Key::Char('w')));
+    }
+
+    #[test]
+    fn losing_focus_lets_go_of_everything() {
+        let mut state = InputState::new();
+        state.apply(&InputEvent::KeyDown { key: Key::Shift, modifiers: Modifiers::default(), repeat: false });
+        state.apply(&InputEvent::MouseDown { button: MouseButton::Left, x: 0, y: 0 });
+        state.apply(&InputEvent::PadDown { pad: 1, button: PadButton::A });
+        state.apply(&InputEvent::PadAxis { pad: 1, axis: PadAxis::LeftX, value: 0.5 });
+        state.apply(&InputEvent::Focus(false));
+        assert!(!state.is_focused());
+        assert!(!state.is_key_down(Key::Shift) && !state.is_button_down(MouseButton::Left));
+        assert!(!state.is_pad_button_down(1, PadButton::A));
+        // Sticks stay where they are; the next axis event will say otherwise.
+        assert_eq!(state.pad_axis(1, PadAxis::LeftX), 0.5);
+
+        state.apply(&InputEvent::PadRemoved(1));
+        assert_eq!(state.pad_axis(1, PadAxis::LeftX), 0.0);
+        state.apply(&InputEvent::Focus(true));
+        assert!(state.is_focused());
+    }
+}
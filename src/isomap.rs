@@ -0,0 +1,385 @@
+/*
+ * An isometric tile map drawn with 2:1 diamond tiles, e.g. 64x32 pixels.
+ *
+ * Tile (tx, ty) has its centre at world ((tx - ty) * w / 2, (tx + ty) * h / 2), raised by
+ * its elevation times `elevation_step`. x runs down and to the right on screen, y down
+ * and to the left, so tiles further down the screen have a larger tx + ty.
+ *
+ * The map is drawn back to front a diagonal of tiles at a time, so raised ground and
+ * tall objects hide whatever is behind them whichever layer that is on. Within each
+ * diagonal, flat layers (terrain, paths) are drawn first, in the order they were added,
+ * and sorted layers (buildings, trees) go over them tile by tile, so something wider
+ * than its tile isn't cut into by the ground beside it.
+ */
+
+use atlas::AtlasSprite;
+use backend::Backend;
+use camera::Camera;
+
+/*
+ * A sprite on the map. `anchor` is the pixel of the sprite placed on the tile's centre:
+ * the middle of a ground tile, or the foot of something standing on it.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub sprite: AtlasSprite,
+    pub anchor: (f64, f64)
+}
+
+impl Tile {
+    pub fn new(sprite: AtlasSprite, ax: f64, ay: f64) -> Tile {
+        Tile { sprite: sprite, anchor: (ax, ay) }
+    }
+
+    /*
+     * A tile whose sprite is centred on the diamond, as flat ground tiles are.
+     */
+    pub fn centred(sprite: AtlasSprite) -> Tile {
+        Tile::new(sprite, sprite.src.w as f64 / 2.0, sprite.src.h as f64 / 2.0)
+    }
+}
+
+pub struct Layer {
+    pub name: String,
+    pub sorted: bool,
+    tiles: Vec<Option<Tile>>
+}
+
+pub struct IsoMap {
+    width: u32,
+    height: u32,
+    tile_w: f64,
+    tile_h: f64,
+    pub elevation_step: f64,
+    // Extra rows drawn past the bottom of the screen, for sprites taller than a tile.
+    pub overdraw: u32,
+    elevation: Vec<i32>,
+    layers: Vec<Layer>
+}
+
+impl IsoMap {
+    pub fn new(width: u32, height: u32, tile_w: u32, tile_h: u32) -> IsoMap {
+        IsoMap {
+            width: width,
+            height: height,
+            tile_w: tile_w as f64,
+            tile_h: tile_h as f64,
+            elevation_step: tile_h as f64 / 2.0,
+            overdraw: 4,
+            elevation: vec![0; (width * height) as usize],
+            layers: Vec::new()
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn tile_size(&self) -> (f64, f64) {
+        (self.tile_w, self.tile_h)
+    }
+
+    fn index(&self, tx: u32, ty: u32) -> Option<usize> {
+        if tx < self.width && ty < self.height {
+            Some((ty * self.width + tx) as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn add_layer(&mut self, name: &str, sorted: bool) -> usize {
+        self.layers.push(Layer {
+            name: name.to_string(),
+            sorted: sorted,
+            tiles: vec![None; (self.width * self.height) as usize]
+        });
+        self.layers.len() - 1
+    }
+
+    pub fn layer_index(&self, name: &str) -> Option<usize> {
+        self.layers.iter().position(|l| l.name == name)
+    }
+
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    pub fn tile(&self, layer: usize, tx: u32, ty: u32) -> Option<Tile> {
+        self.index(tx, ty).and_then(|i| self.layers[layer].tiles[i])
+    }
+
+    pub fn set_tile(&mut self, layer: usize, tx: u32, ty: u32, tile: Option<Tile>) {
+        if let Some(i) = self.index(tx, ty) {
+            self.layers[layer].tiles[i] = tile;
+        }
+    }
+
+    pub fn fill_layer(&mut self, layer: usize, tile: Option<Tile>) {
+        for t in self.layers[layer].tiles.iter_mut() {
+            *t = tile;
+        }
+    }
+
+    pub fn elevation(&self, tx: u32, ty: u32) -> i32 {
+        self.index(tx, ty).map(|i| self.elevation[i]).unwrap_or(0)
+    }
+
+    pub fn set_elevation(&mut self, tx: u32, ty: u32, elevation: i32) {
+        if let Some(i) = self.index(tx, ty) {
+            self.elevation[i] = elevation;
+        }
+    }
+
+    fn max_elevation(&self) -> i32 {
+        self.elevation.iter().cloned().max().unwrap_or(0).max(0)
+    }
+
+    /*
+     * The world position of a tile's centre, including its elevation.
+     */
+    pub fn tile_centre(&self, tx: u32, ty: u32) -> (f64, f64) {
+        let (x, y) = self.ground_point(tx as f64, ty as f64);
+        (x, y - self.elevation(tx, ty) as f64 * self.elevation_step)
+    }
+
+    /*
+     * Tile coordinates to world coordinates at elevation zero.
+     */
+    pub fn ground_point(&self, tx: f64, ty: f64) -> (f64, f64) {
+        ((tx - ty) * self.tile_w / 2.0, (tx + ty) * self.tile_h / 2.0)
+    }
+
+    /*
+     * World coordinates to fractional tile coordinates at elevation zero, where tile
+     * centres fall on whole numbers.
+     */
+    pub fn ground_tile(&self, x: f64, y: f64) -> (f64, f64) {
+        let (u, v) = (x / (self.tile_w / 2.0), y / (self.tile_h / 2.0));
+        ((u + v) / 2.0, (v - u) / 2.0)
+    }
+
+    /*
+     * The world-space bounds of the whole map at elevation zero, for fitting a camera.
+     */
+    pub fn bounds(&self) -> (f64, f64, f64, f64) {
+        let (w, h) = (self.width as f64, self.height as f64);
+        let left = self.ground_point(-0.5, h - 0.5).0;
+        let right = self.ground_point(w - 0.5, -0.5).0;
+        let top = self.ground_point(-0.5, -0.5).1;
+        let bottom = self.ground_point(w - 0.5, h - 0.5).1;
+        (left, top, right, bottom)
+    }
+
+    fn diamond_contains(&self, tx: u32, ty: u32, x: f64, y: f64) -> bool {
+        let (cx, cy) = self.tile_centre(tx, ty);
+        (x - cx).abs() / (self.tile_w / 2.0) + (y - cy).abs() / (self.tile_h / 2.0) <= 1.0
+    }
+
+    /*
+     * The tile whose top surface is at a world position. Raised tiles can cover ones
+     * behind them, so the front-most diamond containing the point wins.
+     */
+    pub fn pick_world(&self, x: f64, y: f64) -> Option<(u32, u32)> {
+        let (fx, fy) = self.ground_tile(x, y);
+        let (gx, gy) = (fx.round() as i64, fy.round() as i64);
+        // A tile raised by e steps appears e * step pixels higher, i.e. in front of where
+        // the point lies on the ground.
+        let reach = (self.max_elevation() as f64 * self.elevation_step / self.tile_h).ceil() as i64 + 1;
+        let mut best: Option<(u32, u32)> = None;
+        for dy in -1..reach + 1 {
+            for dx in -1..reach + 1 {
+                let (tx, ty) = (gx + dx, gy + dy);
+                if tx < 0 || ty < 0 || tx >= self.width as i64 || ty >= self.height as i64 {
+                    continue;
+                }
+                let (tx, ty) = (tx as u32, ty as u32);
+                if !self.diamond_contains(tx, ty, x, y) {
+                    continue;
+                }
+                let in_front = match best {
+                    Some((bx, by)) => tx + ty > bx + by,
+                    None => true
+                };
+                if in_front {
+                    best = Some((tx, ty));
+                }
+            }
+        }
+        best
+    }
+
+    /*
+     * The tile under a screen position seen through a camera.
+     */
+    pub fn pick(&self, camera: &Camera, sx: f64, sy: f64) -> Option<(u32, u32)> {
+        let (x, y) = camera.screen_to_world(sx, sy);
+        self.pick_world(x, y)
+    }
+
+    /*
+     * The range of tiles (tx0, ty0, tx1, ty1), inclusive, that may show within the
+     * world rectangle, or None if it misses the map.
+     */
+    pub fn visible_range(&self, view: (f64, f64, f64, f64)) -> Option<(u32, u32, u32, u32)> {
+        let (x0, y0, x1, y1) = view;
+        // Raised tiles show above their ground position and tall sprites stick up above
+        // their tile, so look further down the screen than the view reaches.
+        let y1 = y1 + self.max_elevation() as f64 * self.elevation_step + self.overdraw as f64 * self.tile_h;
+        let corners = [self.ground_tile(x0, y0), self.ground_tile(x1, y0),
+                       self.ground_tile(x1, y1), self.ground_tile(x0, y1)];
+        let lo_x = corners.iter().map(|c| c.0).fold(::std::f64::INFINITY, f64::min).floor() - 1.0;
+        let hi_x = corners.iter().map(|c| c.0).fold(::std::f64::NEG_INFINITY, f64::max).ceil() + 1.0;
+        let lo_y = corners.iter().map(|c| c.1).fold(::std::f64::INFINITY, f64::min).floor() - 1.0;
+        let hi_y = corners.iter().map(|c| c.1).fold(::std::f64::NEG_INFINITY, f64::max).ceil() + 1.0;
+        if hi_x < 0.0 || hi_y < 0.0 || lo_x >= self.width as f64 || lo_y >= self.height as f64 {
+            return None;
+        }
+        Some((lo_x.max(0.0) as u32, lo_y.max(0.0) as u32,
+              hi_x.min(self.width as f64 - 1.0) as u32, hi_y.min(self.height as f64 - 1.0) as u32))
+    }
+
+    fn draw_tile(&self, backend: &mut Backend, tile: &Tile, tx: u32, ty: u32) {
+        let (cx, cy) = self.tile_centre(tx, ty);
+        tile.sprite.draw(backend, cx - tile.anchor.0, cy - tile.anchor.1);
+    }
+
+    /*
+     * Calls f for each tile in the range with tx + ty = d, left to right.
+     */
+    fn diagonal<F: FnMut(u32, u32)>(range: (u32, u32, u32, u32), d: u32, mut f: F) {
+        let (x0, y0, x1, y1) = range;
+        let start = if d > y1 { (d - y1).max(x0) } else { x0 };
+        let end = (d - y0).min(x1);
+        for tx in start..end + 1 {
+            f(tx, d - tx);
+        }
+    }
+
+    /*
+     * Draws the tiles visible to the camera. Returns the number of sprites drawn.
+     */
+    pub fn draw(&self, backend: &mut Backend, camera: &Camera) -> usize {
+        let range = match self.visible_range(camera.visible_bounds()) {
+            Some(r) => r,
+            None => return 0
+        };
+        let mut drawn = 0;
+        backend.push_transform(&camera.transform());
+        for d in (range.0 + range.1)..(range.2 + range.3 + 1) {
+            for layer in self.layers.iter().filter(|l| !l.sorted) {
+                IsoMap::diagonal(range, d, |tx, ty| {
+                    if let Some(ref tile) = layer.tiles[(ty * self.width + tx) as usize] {
+                        self.draw_tile(backend, tile, tx, ty);
+                        drawn += 1;
+                    }
+                });
+            }
+            IsoMap::diagonal(range, d, |tx, ty| {
+                for layer in self.layers.iter().filter(|l| l.sorted) {
+                    if let Some(ref tile) = layer.tiles[(ty * self.width + tx) as usize] {
+                        self.draw_tile(backend, tile, tx, ty);
+                        drawn += 1;
+                    }
+                }
+            });
+        }
+        backend.pop_transform();
+        drawn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::Rect;
+    use color::Rgba;
+    use image::Image;
+    use software::SoftwareBackend;
+
+    fn sprite(backend: &mut SoftwareBackend, w: u32, h: u32, colour: Rgba) -> AtlasSprite {
+        let texture = backend.create_texture(&Image::filled(w, h, colour)).unwrap();
+        AtlasSprite { texture: texture, src: Rect::new(0, 0, w, h) }
+    }
+
+    #[test]
+    fn raised_ground_hides_objects_behind_it() {
+        let mut backend = SoftwareBackend::new(64, 64);
+        let ground = sprite(&mut backend, 16, 8, Rgba::rgb(0, 255, 0));
+        let tree = sprite(&mut backend, 4, 12, Rgba::rgb(255, 0, 0));
+        let mut map = IsoMap::new(4, 4, 16, 8);
+        let terrain = map.add_layer("terrain", false);
+        let objects = map.add_layer("objects", true);
+        map.fill_layer(terrain, Some(Tile::centred(ground)));
+        map.set_tile(objects, 1, 1, Some(Tile::new(tree, 2.0, 12.0)));
+        // Lifts the tile in front so that it covers the foot of the tree.
+        map.set_elevation(2, 2, 2);
+
+        let mut camera = Camera::new(64, 64);
+        camera.jump_to(0.0, 8.0, 1.0);
+        map.draw(&mut backend, &camera);
+        let image = backend.read_pixels().unwrap();
+        let at = |x: f64, y: f64| {
+            let (sx, sy) = camera.world_to_screen(x, y);
+            image.get(sx as u32, sy as u32)
+        };
+        assert_eq!(at(0.0, 0.0), Rgba::rgb(255, 0, 0));
+        assert_eq!(at(0.0, 6.0), Rgba::rgb(0, 255, 0));
+    }
+
+    #[test]
+    fn picking_finds_the_raised_tile_in_front() {
+        let mut map = IsoMap::new(4, 4, 16, 8);
+        assert_eq!(map.pick_world(0.0, 8.0), Some((1, 1)));
+        // Two steps of 4 lift (2, 2) onto where (1, 1) lies on the ground.
+        map.set_elevation(2, 2, 2);
+        assert_eq!(map.tile_centre(2, 2), (0.0, 8.0));
+        assert_eq!(map.pick_world(0.0, 8.0), Some((2, 2)));
+        assert_eq!(map.pick_world(2.0, 6.0), Some((2, 2)));
+        // Where it stood is no longer its top.
+        assert!(map.pick_world(0.0, 16.0) != Some((2, 2)));
+        assert_eq!(map.pick_world(0.0, 1.0), Some((0, 0)));
+        assert_eq!(map.pick_world(-100.0, 0.0), None);
+
+        let mut camera = Camera::new(64, 64);
+        camera.jump_to(3.0, 10.0, 2.5);
+        camera.rotate_to(30.0);
+        camera.settle();
+        let (sx, sy) = camera.world_to_screen(0.5, 8.5);
+        assert_eq!(map.pick(&camera, sx, sy), Some((2, 2)));
+    }
+
+    #[test]
+    fn layers_interleave_by_diagonal() {
+        let mut backend = SoftwareBackend::new(64, 64);
+        let (green, red, blue) = (Rgba::rgb(0, 255, 0), Rgba::rgb(255, 0, 0), Rgba::rgb(0, 0, 255));
+        let ground = sprite(&mut backend, 16, 8, green);
+        let tree = sprite(&mut backend, 4, 12, red);
+        let wide = sprite(&mut backend, 40, 6, blue);
+        let mut map = IsoMap::new(3, 3, 16, 8);
+        let terrain = map.add_layer("terrain", false);
+        let objects = map.add_layer("objects", true);
+        map.fill_layer(terrain, Some(Tile::centred(ground)));
+        // A tree on the back tile reaching down into the next diagonal, and a building
+        // wider than its tile reaching over the ground beside it on its own.
+        map.set_tile(objects, 0, 0, Some(Tile::new(tree, 2.0, 6.0)));
+        map.set_tile(objects, 1, 1, Some(Tile::new(wide, 20.0, 3.0)));
+
+        let mut camera = Camera::new(64, 64);
+        camera.jump_to(0.0, 4.0, 1.0);
+        assert_eq!(map.draw(&mut backend, &camera), 11);
+        let image = backend.read_pixels().unwrap();
+        let at = |x: f64, y: f64| {
+            let (sx, sy) = camera.world_to_screen(x, y);
+            image.get(sx as u32, sy as u32)
+        };
+        // The tree is over its own tile's ground, but under the ground in front of it,
+        // which all of one layer then all of the next would get wrong.
+        assert_eq!(at(1.0, -3.0), red);
+        assert_eq!(at(1.0, 3.0), green);
+        // The building is over the ground of (2, 0), on the same diagonal, though that
+        // tile comes after it.
+        assert_eq!(at(17.0, 7.0), blue);
+        // But not over the ground of (2, 1), in front.
+        assert_eq!(at(10.0, 10.0), green);
+    }
+}
@@ -5,17 +5,137 @@ mod rendererutils;
 mod spath;
 mod ditty;
 mod svg;
+mod color;
+mod easing;
+mod geom;
+mod smil;
+mod image;
+mod raster;
+mod backend;
+mod software;
+mod sdlbackend;
+mod zlib;
+mod png;
+mod snapshot;
+mod spritecache;
+mod atlas;
+mod camera;
+mod scene;
+mod isomap;
+mod font;
+mod debug;
+mod particles;
+mod tessellate;
+mod timestep;
+mod input;
+mod dittystack;
+mod settings;
+mod headless;
+mod replay;
+mod actions;
+mod controllers;
+mod cursor;
+mod audio;
+mod wav;
+mod sdlaudio;
+mod assets;
+mod watch;
+
+use actions::ActionMap;
+use assets::Assets;
+use audio::Audio;
+use color::Rgba;
+use dittystack::Transition;
+use std::env;
 
 use gameloop::GameLoop;
-use spath::PathElem;
+use headless::Headless;
+use replay::Recording;
 use utils::FatalAction;
 
 fn main() {
+    // tycoon [--assets DIR] [--record FILE | --replay FILE] [--headless FRAMES [OUT.png]]
+    // --headless runs without a window and saves the last frame.
+    let mut args: Vec<String> = env::args().collect();
+    let assets = Assets::new(take_option(&mut args, "--assets").unwrap_or("assets".to_string()));
+
+    let record = take_option(&mut args, "--record");
+    let replay = take_option(&mut args, "--replay").map(|p| Recording::load(&p).or_die("load recording"));
+    let headless = args.len() > 2 && args[1] == "--headless";
 
-    let mut paths = svg::get_paths("assets/logo.svg").iter().map(|s| spath::read_path(s)).collect();
+    let audio = Audio::new(44100);
+    let bindings = ActionMap::load(actions::BINDINGS_CONFIG).or_die("load key bindings");
+    // The logo and sounds are loaded while the loading screen shows, then the intro
+    // plays the logo.
+    let sfx = audio.clone();
+    let mut loading = ditty::LoadingDitty::new(assets.preload(&["logo.svg", "sounds/click.wav"]), move |assets: &Assets| -> Box<ditty::Ditty> {
+        let logo = assets.document("logo.svg").or_die("load logo");
+        let mut viewer = ditty::PathDitty::from_asset(logo.clone()).with_actions(bindings.clone());
+        match assets.sound("sounds/click.wav") {
+            Ok(click) => viewer = viewer.with_click(sfx.clone(), click),
+            Err(e) => println!("Could not load {}", e)
+        }
+        Box::new(ditty::IntroDitty::from_asset(logo)
+            .then(Box::new(viewer), Transition::fade(Rgba::rgb(255, 255, 255), 0.6)))
+    }, Transition::cut());
+    if headless || record.is_some() || replay.is_some() {
+        loading = loading.waiting();
+    }
 
-    let ditty = ditty::PathDitty::new(paths);
+    if headless {
+        let frames = args[2].parse::<u64>().or_die("read frame count");
+        let out = args.get(3).cloned().unwrap_or("headless.png".to_string());
+        let mut runner = Headless::new(loading, 1280, 720);
+        runner.set_audio(audio);
+        runner.use_assets(assets);
+        if record.is_some() {
+            runner.record(60);
+        }
+        if let Some(recording) = replay {
+            runner.replay(recording);
+        }
+        let drawn = runner.run(frames);
+        println!("Drew {} frames, mean {:.2}ms", drawn, runner.stats().mean_frame_ms());
+        if let Some(e) = runner.replay_error() {
+            println!("{}", e);
+        }
+        if let (Some(path), Some(recording)) = (record, runner.recording()) {
+            recording.save(&path).or_die("save recording");
+        }
+        png::save(runner.image(), &out).or_die("save last frame");
+        return;
+    }
+
+    let video = settings::VideoSettings::load(settings::VIDEO_CONFIG).or_die("load video settings");
+    let mut mainloop = GameLoop::new(video).or_die("create Game Loop");
+    if let Err(e) = mainloop.open_audio(audio) {
+        println!("Playing without sound: {}", e);
+    }
+    // Debug builds reload the logo and other assets as they are saved, except when
+    // recording or replaying, where a file changing mid-run would make runs differ.
+    if cfg!(debug_assertions) && record.is_none() && replay.is_none() {
+        assets.watch();
+    }
+    mainloop.use_assets(assets);
+    if let Some(path) = record {
+        mainloop.record_to(path);
+    }
+    if let Some(recording) = replay {
+        mainloop.replay(recording);
+    }
+    mainloop.run(loading).or_die("run Game Loop");
+}
 
-    let mainloop = GameLoop::new().or_die("create Game Loop");
-    mainloop.run(ditty).or_die("run Game Loop");
+/*
+ * Removes `--name VALUE` from the arguments, returning the value.
+ */
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    match args.iter().position(|a| a == name) {
+        Some(i) if i + 1 < args.len() => {
+            let value = args.remove(i + 1);
+            args.remove(i);
+            Some(value)
+        },
+        _ => None
+    }
 }
@@ -0,0 +1,505 @@
+/*
+ * Particle effects: dust, fireworks, rain and the like.
+ *
+ * An emitter spawns particles from a point, a line, along a path or over an area, either
+ * continuously or in bursts. Each particle lives for a while, moving under its velocity,
+ * gravity and drag, with its colour and size following curves over its lifetime.
+ *
+ * Particles are drawn in batches: all of an emitter's textured particles in one call,
+ * and vector ones in one filled path per (coarsely quantised) colour.
+ *
+ * Random numbers come from a seeded generator per emitter, so an effect plays out the
+ * same way every time it is given the same time steps.
+ */
+
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+
+use backend::Backend;
+use backend::Rect;
+use backend::TexturedQuad;
+use backend::TextureId;
+use color::Rgba;
+use geom::PathMeasure;
+use geom::Polyline;
+use raster::FillRule;
+
+/*
+ * A small xorshift generator. Not for anything that matters, but fast and repeatable.
+ */
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // Zero is a fixed point of xorshift.
+        Rng { state: if seed == 0 { 0x9e3779b97f4a7c15 } else { seed } }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+
+    /*
+     * Uniform in [0, 1).
+     */
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn range(&mut self, (lo, hi): (f64, f64)) -> f64 {
+        lo + (hi - lo) * self.next_f64()
+    }
+}
+
+/*
+ * A piecewise linear curve over a particle's life, from 0 at birth to 1 at death.
+ */
+#[derive(Debug, Clone)]
+pub struct Curve {
+    pub keys: Vec<(f64, f64)>
+}
+
+impl Curve {
+    pub fn constant(v: f64) -> Curve {
+        Curve { keys: vec![(0.0, v)] }
+    }
+
+    pub fn linear(from: f64, to: f64) -> Curve {
+        Curve { keys: vec![(0.0, from), (1.0, to)] }
+    }
+
+    pub fn sample(&self, t: f64) -> f64 {
+        match self.keys.iter().position(|k| k.0 > t) {
+            Some(0) => self.keys[0].1,
+            Some(i) => {
+                let (a, b) = (self.keys[i - 1], self.keys[i]);
+                a.1 + (b.1 - a.1) * (t - a.0) / (b.0 - a.0)
+            },
+            None => self.keys.last().map(|k| k.1).unwrap_or(0.0)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ColourCurve {
+    pub keys: Vec<(f64, Rgba)>
+}
+
+impl ColourCurve {
+    pub fn constant(c: Rgba) -> ColourCurve {
+        ColourCurve { keys: vec![(0.0, c)] }
+    }
+
+    pub fn linear(from: Rgba, to: Rgba) -> ColourCurve {
+        ColourCurve { keys: vec![(0.0, from), (1.0, to)] }
+    }
+
+    pub fn sample(&self, t: f64) -> Rgba {
+        match self.keys.iter().position(|k| k.0 > t) {
+            Some(0) => self.keys[0].1,
+            Some(i) => {
+                let (a, b) = (self.keys[i - 1], self.keys[i]);
+                a.1.lerp(&b.1, (t - a.0) / (b.0 - a.0))
+            },
+            None => self.keys.last().map(|k| k.1).unwrap_or(Rgba::new(0, 0, 0, 0))
+        }
+    }
+}
+
+pub enum Shape {
+    Point(f64, f64),
+    Line(f64, f64, f64, f64),
+    /*
+     * Anywhere along a path, e.g. one flattened from an spath::PathElem list.
+     */
+    Path(PathMeasure),
+    /*
+     * Anywhere inside the rectangle (x, y, w, h).
+     */
+    Area(f64, f64, f64, f64)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sprite {
+    Square,
+    Circle,
+    Texture(TextureId, Option<Rect>)
+}
+
+pub struct EmitterConfig {
+    pub shape: Shape,
+    pub sprite: Sprite,
+    /*
+     * Particles per second while the emitter is running; zero for bursts only.
+     */
+    pub rate: f64,
+    /*
+     * How long the emitter runs in seconds, or None to run until stopped.
+     */
+    pub duration: Option<f64>,
+    pub life: (f64, f64),
+    pub speed: (f64, f64),
+    /*
+     * Launch direction in degrees clockwise from +x, and the spread either side of it.
+     */
+    pub direction: f64,
+    pub spread: f64,
+    pub gravity: (f64, f64),
+    /*
+     * Fraction of velocity lost per second.
+     */
+    pub drag: f64,
+    pub size: Curve,
+    pub colour: ColourCurve,
+    pub max_particles: usize
+}
+
+impl EmitterConfig {
+    pub fn new(shape: Shape) -> EmitterConfig {
+        EmitterConfig {
+            shape: shape,
+            sprite: Sprite::Square,
+            rate: 0.0,
+            duration: None,
+            life: (1.0, 1.0),
+            speed: (0.0, 0.0),
+            direction: 0.0,
+            spread: 180.0,
+            gravity: (0.0, 0.0),
+            drag: 0.0,
+            size: Curve::constant(2.0),
+            colour: ColourCurve::constant(Rgba::rgb(255, 255, 255)),
+            max_particles: 2000
+        }
+    }
+
+    /*
+     * Brown dust kicked up around (x, y), as by a stampede.
+     */
+    pub fn dust(x: f64, y: f64, radius: f64) -> EmitterConfig {
+        let mut c = EmitterConfig::new(Shape::Area(x - radius, y - radius / 2.0, radius * 2.0, radius));
+        c.sprite = Sprite::Circle;
+        c.rate = 60.0;
+        c.life = (0.8, 1.6);
+        c.speed = (5.0, 20.0);
+        c.direction = 270.0;
+        c.spread = 60.0;
+        c.gravity = (0.0, -4.0);
+        c.drag = 0.8;
+        c.size = Curve::linear(3.0, 10.0);
+        c.colour = ColourCurve::linear(Rgba::new(150, 120, 80, 160), Rgba::new(150, 120, 80, 0));
+        c
+    }
+
+    /*
+     * A single firework shell bursting at (x, y).
+     */
+    pub fn firework(x: f64, y: f64, colour: Rgba) -> EmitterConfig {
+        let mut c = EmitterConfig::new(Shape::Point(x, y));
+        c.duration = Some(0.0);
+        c.life = (1.0, 1.8);
+        c.speed = (60.0, 140.0);
+        c.gravity = (0.0, 60.0);
+        c.drag = 0.9;
+        c.size = Curve::linear(3.0, 1.0);
+        c.colour = ColourCurve {
+            keys: vec![(0.0, Rgba::rgb(255, 255, 255)), (0.15, colour), (1.0, colour.with_alpha(0.0))]
+        };
+        c
+    }
+
+    /*
+     * Rain falling over the rectangle (x, y, w, h), spawned along its top edge.
+     */
+    pub fn rain(x: f64, y: f64, w: f64, h: f64) -> EmitterConfig {
+        let mut c = EmitterConfig::new(Shape::Line(x, y, x + w, y));
+        c.rate = w * 0.5;
+        c.speed = (350.0, 400.0);
+        c.direction = 100.0;
+        c.spread = 2.0;
+        c.life = (h / 400.0, h / 350.0);
+        c.size = Curve::constant(2.0);
+        c.colour = ColourCurve::constant(Rgba::new(170, 190, 230, 140));
+        c
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Particle {
+    x: f64,
+    y: f64,
+    vx: f64,
+    vy: f64,
+    age: f64,
+    life: f64
+}
+
+pub struct Emitter {
+    pub config: EmitterConfig,
+    particles: Vec<Particle>,
+    rng: Rng,
+    time: f64,
+    pending: f64,
+    running: bool
+}
+
+impl Emitter {
+    pub fn new(config: EmitterConfig, seed: u64) -> Emitter {
+        Emitter {
+            config: config,
+            particles: Vec::new(),
+            rng: Rng::new(seed),
+            time: 0.0,
+            pending: 0.0,
+            running: true
+        }
+    }
+
+    pub fn particle_count(&self) -> usize {
+        self.particles.len()
+    }
+
+    /*
+     * Stops spawning; particles already alive carry on until they die.
+     */
+    pub fn stop(&mut self) {
+        self.running = false;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !self.running && self.particles.is_empty()
+    }
+
+    fn spawn_point(&mut self) -> (f64, f64) {
+        let t = self.rng.next_f64();
+        let u = self.rng.next_f64();
+        match self.config.shape {
+            Shape::Point(x, y) => (x, y),
+            Shape::Line(x0, y0, x1, y1) => (x0 + (x1 - x0) * t, y0 + (y1 - y0) * t),
+            Shape::Path(ref path) => path.point_at(path.length() * t).map(|p| (p.0, p.1)).unwrap_or((0.0, 0.0)),
+            Shape::Area(x, y, w, h) => (x + w * t, y + h * u)
+        }
+    }
+
+    /*
+     * Spawns n particles at once.
+     */
+    pub fn burst(&mut self, n: usize) {
+        for _ in 0..n {
+            if self.particles.len() >= self.config.max_particles {
+                break;
+            }
+            let (x, y) = self.spawn_point();
+            let angle = (self.config.direction + self.rng.range((-self.config.spread, self.config.spread))) * PI / 180.0;
+            let speed = self.rng.range(self.config.speed);
+            let life = self.rng.range(self.config.life).max(1e-3);
+            self.particles.push(Particle {
+                x: x,
+                y: y,
+                vx: angle.cos() * speed,
+                vy: angle.sin() * speed,
+                age: 0.0,
+                life: life
+            });
+        }
+    }
+
+    pub fn update(&mut self, dt: f64) {
+        let (gx, gy) = self.config.gravity;
+        let damping = (1.0 - self.config.drag).max(0.0).powf(dt);
+        for p in self.particles.iter_mut() {
+            p.age += dt;
+            p.vx = (p.vx + gx * dt) * damping;
+            p.vy = (p.vy + gy * dt) * damping;
+            p.x += p.vx * dt;
+            p.y += p.vy * dt;
+        }
+        self.particles.retain(|p| p.age < p.life);
+
+        if self.running {
+            self.pending += self.config.rate * dt;
+            let n = self.pending.floor();
+            self.pending -= n;
+            self.burst(n as usize);
+            self.time += dt;
+            if self.config.duration.map(|d| self.time >= d).unwrap_or(false) {
+                self.running = false;
+            }
+        }
+    }
+
+    pub fn draw(&self, backend: &mut Backend) {
+        if self.particles.is_empty() {
+            return;
+        }
+        match self.config.sprite {
+            Sprite::Texture(tex, src) => {
+                let quads: Vec<TexturedQuad> = self.particles.iter().map(|p| {
+                    let t = p.age / p.life;
+                    let size = self.config.size.sample(t);
+                    TexturedQuad {
+                        src: src,
+                        x: p.x - size / 2.0,
+                        y: p.y - size / 2.0,
+                        w: size,
+                        h: size,
+                        tint: self.config.colour.sample(t)
+                    }
+                }).collect();
+                backend.draw_texture_batch(tex, &quads);
+            },
+            Sprite::Square | Sprite::Circle => {
+                let circle = self.config.sprite == Sprite::Circle;
+                // Ordered, so overlapping groups are drawn the same way every frame.
+                let mut groups = BTreeMap::<(u8, u8, u8, u8), Vec<Polyline>>::new();
+                for p in &self.particles {
+                    let t = p.age / p.life;
+                    let c = quantise(self.config.colour.sample(t));
+                    if c.a == 0 {
+                        continue;
+                    }
+                    let r = self.config.size.sample(t) / 2.0;
+                    let poly = if circle {
+                        (0..8).map(|i| {
+                            let a = i as f64 * PI / 4.0;
+                            (p.x + r * a.cos(), p.y + r * a.sin())
+                        }).collect()
+                    } else {
+                        vec![(p.x - r, p.y - r), (p.x + r, p.y - r), (p.x + r, p.y + r), (p.x - r, p.y + r)]
+                    };
+                    groups.entry((c.r, c.g, c.b, c.a)).or_insert_with(Vec::new).push(poly);
+                }
+                for ((r, g, b, a), polys) in groups {
+                    backend.set_color(Rgba::new(r, g, b, a));
+                    backend.fill_path(&polys, FillRule::NonZero);
+                }
+            }
+        }
+    }
+}
+
+/*
+ * Rounds a colour to 16 levels per channel, so particles fading through similar colours
+ * share a draw call.
+ */
+fn quantise(c: Rgba) -> Rgba {
+    let q = |v: u8| (v >> 4) * 17;
+    Rgba::new(q(c.r), q(c.g), q(c.b), q(c.a))
+}
+
+/*
+ * All the effects currently playing. Emitters that have finished are dropped.
+ */
+pub struct ParticleSystem {
+    emitters: Vec<Emitter>,
+    seed: u64
+}
+
+impl ParticleSystem {
+    pub fn new(seed: u64) -> ParticleSystem {
+        ParticleSystem { emitters: Vec::new(), seed: seed }
+    }
+
+    /*
+     * Starts an effect, firing `burst` particles straight away on top of any that the
+     * emitter's rate produces later.
+     */
+    pub fn spawn(&mut self, config: EmitterConfig, burst: usize) -> &mut Emitter {
+        self.seed = self.seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let mut emitter = Emitter::new(config, self.seed);
+        emitter.burst(burst);
+        self.emitters.push(emitter);
+        self.emitters.last_mut().unwrap()
+    }
+
+    pub fn emitters(&self) -> &[Emitter] {
+        &self.emitters
+    }
+
+    pub fn particle_count(&self) -> usize {
+        self.emitters.iter().map(|e| e.particle_count()).sum()
+    }
+
+    pub fn update(&mut self, dt: f64) {
+        for e in self.emitters.iter_mut() {
+            e.update(dt);
+        }
+        self.emitters.retain(|e| !e.is_finished());
+    }
+
+    pub fn draw(&self, backend: &mut Backend) {
+        for e in &self.emitters {
+            e.draw(backend);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emitters_spawn_at_their_rate() {
+        let mut config = EmitterConfig::new(Shape::Point(0.0, 0.0));
+        config.rate = 20.0;
+        config.life = (10.0, 10.0);
+        let mut emitter = Emitter::new(config, 1);
+        // Two and a half a step: the half carries over to the next.
+        emitter.update(0.125);
+        assert_eq!(emitter.particle_count(), 2);
+        emitter.update(0.125);
+        assert_eq!(emitter.particle_count(), 5);
+        for _ in 0..6 {
+            emitter.update(0.125);
+        }
+        assert_eq!(emitter.particle_count(), 20);
+
+        emitter.config.max_particles = 22;
+        emitter.update(0.125);
+        assert_eq!(emitter.particle_count(), 22);
+    }
+
+    #[test]
+    fn particles_die_at_the_end_of_their_life() {
+        let mut config = EmitterConfig::new(Shape::Area(0.0, 0.0, 10.0, 10.0));
+        config.life = (0.5, 0.5);
+        config.duration = Some(0.0);
+        let mut system = ParticleSystem::new(7);
+        system.spawn(config, 5);
+        assert_eq!(system.particle_count(), 5);
+        system.update(0.25);
+        assert_eq!(system.particle_count(), 5);
+        assert!(!system.emitters()[0].is_running());
+        system.update(0.25);
+        assert_eq!(system.particle_count(), 0);
+        assert!(system.emitters().is_empty());
+    }
+
+    #[test]
+    fn curves_interpolate_between_keys_and_hold_at_the_ends() {
+        let curve = Curve { keys: vec![(0.0, 1.0), (0.5, 3.0), (1.0, 2.0)] };
+        assert_eq!(curve.sample(-1.0), 1.0);
+        assert_eq!(curve.sample(0.0), 1.0);
+        assert_eq!(curve.sample(0.25), 2.0);
+        assert_eq!(curve.sample(0.5), 3.0);
+        assert_eq!(curve.sample(0.75), 2.5);
+        assert_eq!(curve.sample(2.0), 2.0);
+        assert_eq!(Curve::constant(4.0).sample(0.9), 4.0);
+
+        let colours = ColourCurve::linear(Rgba::new(0, 0, 0, 255), Rgba::new(200, 100, 50, 55));
+        assert_eq!(colours.sample(0.5), Rgba::new(100, 50, 25, 155));
+        assert_eq!(colours.sample(1.5), Rgba::new(200, 100, 50, 55));
+    }
+}
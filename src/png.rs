@@ -0,0 +1,287 @@
+/*
+ * PNG reading and writing for Images.
+ *
+ * Images are always written as 8-bit RGBA with the Sub filter. Reading accepts any
+ * non-interlaced 8-bit greyscale, RGB, palette, grey+alpha or RGBA file, which covers
+ * what Inkscape and the usual image editors produce.
+ */
+
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+use color::Rgba;
+use image::Image;
+use zlib;
+
+const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+/*
+ * The largest width or height read, well past any texture a GPU will take.
+ */
+const MAX_SIDE: u32 = 1 << 14;
+
+fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    for n in 0..256 {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+        }
+        table[n] = c;
+    }
+    table
+}
+
+// Built once for each thread that needs it, as preloading decodes on several.
+thread_local!(static CRC_TABLE: [u32; 256] = crc_table());
+
+pub fn crc32(parts: &[&[u8]]) -> u32 {
+    CRC_TABLE.with(|table| {
+        let mut c = 0xffffffffu32;
+        for part in parts {
+            for &b in part.iter() {
+                c = table[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8);
+            }
+        }
+        c ^ 0xffffffff
+    })
+}
+
+fn be32(v: u32) -> [u8; 4] {
+    [(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8]
+}
+
+fn read_be32(b: &[u8]) -> u32 {
+    (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+    out.extend_from_slice(&be32(data.len() as u32));
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    out.extend_from_slice(&be32(crc32(&[kind, data])));
+}
+
+pub fn encode(image: &Image) -> Vec<u8> {
+    let mut out = SIGNATURE.to_vec();
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&be32(image.width));
+    ihdr.extend_from_slice(&be32(image.height));
+    ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+    write_chunk(&mut out, b"IHDR", &ihdr);
+
+    let stride = (image.width * 4) as usize;
+    let mut raw = Vec::with_capacity((stride + 1) * image.height as usize);
+    for row in image.pixels.chunks(stride) {
+        raw.push(1);
+        for i in 0..stride {
+            let left = if i >= 4 { row[i - 4] } else { 0 };
+            raw.push(row[i].wrapping_sub(left));
+        }
+    }
+    write_chunk(&mut out, b"IDAT", &zlib::compress(&raw));
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+fn unfilter(raw: &[u8], stride: usize, bpp: usize, height: usize) -> Result<Vec<u8>, String> {
+    let size = try!(stride.checked_mul(height).ok_or("image too large".to_string()));
+    if raw.len() < size.saturating_add(height) {
+        return Err("image data too short".to_string());
+    }
+    let mut out = vec![0u8; size];
+    for y in 0..height {
+        let filter = raw[y * (stride + 1)];
+        let line = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        for i in 0..stride {
+            let a = if i >= bpp { out[y * stride + i - bpp] } else { 0 };
+            let b = if y > 0 { out[(y - 1) * stride + i] } else { 0 };
+            let c = if y > 0 && i >= bpp { out[(y - 1) * stride + i - bpp] } else { 0 };
+            let pred = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(format!("unknown filter type {}", filter))
+            };
+            out[y * stride + i] = line[i].wrapping_add(pred);
+        }
+    }
+    Ok(out)
+}
+
+pub fn decode(data: &[u8]) -> Result<Image, String> {
+    if data.len() < 8 || data[..8] != SIGNATURE {
+        return Err("not a PNG file".to_string());
+    }
+    let mut pos = 8;
+    let mut header = None;
+    let mut palette = Vec::<Rgba>::new();
+    let mut idat = Vec::<u8>::new();
+    while pos + 12 <= data.len() {
+        let len = read_be32(&data[pos..]) as usize;
+        let kind = &data[pos + 4..pos + 8];
+        if pos + 12 + len > data.len() {
+            return Err("truncated chunk".to_string());
+        }
+        let body = &data[pos + 8..pos + 8 + len];
+        if crc32(&[kind, body]) != read_be32(&data[pos + 8 + len..]) {
+            return Err(format!("bad CRC in {} chunk", String::from_utf8_lossy(kind)));
+        }
+        match kind {
+            b"IHDR" if len == 13 => header = Some((read_be32(body), read_be32(&body[4..]),
+                                                   body[8], body[9], body[12])),
+            b"PLTE" => {
+                if len % 3 != 0 || len > 256 * 3 {
+                    return Err("bad palette".to_string());
+                }
+                palette = body.chunks(3).map(|c| Rgba::rgb(c[0], c[1], c[2])).collect();
+            },
+            b"tRNS" => for (i, &a) in body.iter().enumerate() {
+                if let Some(p) = palette.get_mut(i) {
+                    p.a = a;
+                }
+            },
+            b"IDAT" => idat.extend_from_slice(body),
+            b"IEND" => break,
+            _ => ()
+        }
+        pos += 12 + len;
+    }
+
+    let (width, height, depth, colour_type, interlace) = match header {
+        Some(h) => h,
+        None => return Err("missing IHDR".to_string())
+    };
+    if width == 0 || height == 0 || width > MAX_SIDE || height > MAX_SIDE {
+        return Err(format!("unsupported size {}x{}", width, height));
+    }
+    if depth != 8 {
+        return Err(format!("unsupported bit depth {}", depth));
+    }
+    if interlace != 0 {
+        return Err("interlaced PNGs are not supported".to_string());
+    }
+    let channels = match colour_type {
+        0 => 1,
+        2 => 3,
+        3 => 1,
+        4 => 2,
+        6 => 4,
+        _ => return Err(format!("unknown colour type {}", colour_type))
+    };
+    let raw = try!(zlib::decompress(&idat));
+    let stride = width as usize * channels;
+    let px = try!(unfilter(&raw, stride, channels, height as usize));
+
+    let mut image = Image::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let o = y as usize * stride + x as usize * channels;
+            let colour = match colour_type {
+                0 => Rgba::rgb(px[o], px[o], px[o]),
+                2 => Rgba::rgb(px[o], px[o + 1], px[o + 2]),
+                3 => match palette.get(px[o] as usize) {
+                    Some(&c) => c,
+                    None => return Err("palette index out of range".to_string())
+                },
+                4 => Rgba::new(px[o], px[o], px[o], px[o + 1]),
+                _ => Rgba::new(px[o], px[o + 1], px[o + 2], px[o + 3])
+            };
+            image.put(x, y, colour);
+        }
+    }
+    Ok(image)
+}
+
+pub fn save<P: AsRef<Path>>(image: &Image, path: P) -> Result<(), String> {
+    File::create(path).and_then(|mut f| f.write_all(&encode(image)))
+        .map_err(|e| format!("{}", e))
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Image, String> {
+    let mut data = Vec::new();
+    try!(File::open(path).and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|e| format!("{}", e)));
+    decode(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Written with zlib outside this crate: a 3x2 palette image with transparency, the
+    // second row Up filtered.
+    const PALETTE: [u8; 108] = [
+        0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+        0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x02, 0x08, 0x03, 0x00, 0x00, 0x00, 0xaa, 0xaa, 0x96,
+        0x28, 0x00, 0x00, 0x00, 0x09, 0x50, 0x4c, 0x54, 0x45, 0xff, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00,
+        0x00, 0xff, 0x2d, 0x4a, 0xcd, 0x8a, 0x00, 0x00, 0x00, 0x02, 0x74, 0x52, 0x4e, 0x53, 0xff, 0x80,
+        0x08, 0x0f, 0xb3, 0x6a, 0x00, 0x00, 0x00, 0x10, 0x49, 0x44, 0x41, 0x54, 0x78, 0xda, 0x63, 0x60,
+        0x60, 0x64, 0x62, 0x62, 0x62, 0xfc, 0x07, 0x00, 0x01, 0x26, 0x01, 0x07, 0xbf, 0xd4, 0xd5, 0xdc,
+        0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82];
+    // A 2x2 grey+alpha image, Sub then Paeth filtered.
+    const GREY_ALPHA: [u8; 75] = [
+        0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+        0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x08, 0x04, 0x00, 0x00, 0x00, 0xd8, 0xbf, 0xc5,
+        0xaf, 0x00, 0x00, 0x00, 0x12, 0x49, 0x44, 0x41, 0x54, 0x78, 0xda, 0x63, 0xe4, 0x3a, 0x21, 0x32,
+        0x87, 0x85, 0x8b, 0x8b, 0xeb, 0x1b, 0x00, 0x0c, 0x46, 0x02, 0x9c, 0x81, 0x2f, 0x51, 0x1e, 0x00,
+        0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82];
+
+    fn png(ihdr: &[u8], plte: &[u8], raw: &[u8]) -> Vec<u8> {
+        let mut out = SIGNATURE.to_vec();
+        write_chunk(&mut out, b"IHDR", ihdr);
+        write_chunk(&mut out, b"PLTE", plte);
+        write_chunk(&mut out, b"IDAT", &zlib::compress(raw));
+        write_chunk(&mut out, b"IEND", &[]);
+        out
+    }
+
+    #[test]
+    fn known_files_decode() {
+        let image = decode(&PALETTE).unwrap();
+        let (red, green, blue) = (Rgba::rgb(255, 0, 0), Rgba::new(0, 255, 0, 128), Rgba::rgb(0, 0, 255));
+        assert_eq!((image.width, image.height), (3, 2));
+        let pixels: Vec<Rgba> = (0..6).map(|i| image.get(i % 3, i / 3)).collect();
+        assert_eq!(pixels, vec![red, green, blue, blue, blue, red]);
+
+        let image = decode(&GREY_ALPHA).unwrap();
+        assert_eq!(image.get(0, 0), Rgba::new(10, 10, 10, 200));
+        assert_eq!(image.get(1, 0), Rgba::new(30, 30, 30, 100));
+        assert_eq!(image.get(0, 1), Rgba::new(20, 20, 20, 210));
+        assert_eq!(image.get(1, 1), Rgba::new(40, 40, 40, 90));
+        assert_eq!(crc32(&[b"IEND"]), 0xae426082);
+    }
+
+    #[test]
+    fn malformed_files_are_refused() {
+        for len in 0..PALETTE.len() - 12 {
+            assert!(decode(&PALETTE[..len]).is_err(), "{} bytes", len);
+        }
+        let ihdr = [0, 0, 0, 1, 0, 0, 0, 1, 8, 3, 0, 0, 0];
+        assert!(decode(&png(&ihdr, &[1, 2, 3], &[0, 0])).is_ok());
+        assert!(decode(&png(&ihdr, &[1, 2, 3, 4], &[0, 0])).is_err());
+        assert!(decode(&png(&ihdr, &[0; 257 * 3], &[0, 0])).is_err());
+        assert!(decode(&png(&ihdr, &[1, 2, 3], &[0, 1])).is_err());
+        let huge = [0x7f, 0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff, 8, 6, 0, 0, 0];
+        assert!(decode(&png(&huge, &[], &[0])).is_err());
+    }
+
+    #[test]
+    fn written_images_read_back() {
+        let mut image = Image::new(5, 3);
+        for i in 0..15 {
+            image.put(i % 5, i / 5, Rgba::new(i as u8 * 17, 255 - i as u8, 3, 255 - i as u8 * 9));
+        }
+        assert_eq!(decode(&encode(&image)).map(|i| i.pixels), Ok(image.pixels));
+    }
+}
@@ -0,0 +1,189 @@
+/*
+ * Scanline conversion of polygons into horizontal pixel spans. Both backends fill paths
+ * this way; they differ only in how a span reaches the screen.
+ */
+
+use geom::Polyline;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FillRule {
+    NonZero,
+    EvenOdd
+}
+
+impl FillRule {
+    fn inside(&self, winding: i32) -> bool {
+        match *self {
+            FillRule::NonZero => winding != 0,
+            FillRule::EvenOdd => winding % 2 != 0
+        }
+    }
+}
+
+struct Edge {
+    x0: f64,
+    y0: f64,
+    x1: f64,
+    y1: f64,
+    dir: i32
+}
+
+fn edges(polys: &[Polyline]) -> Vec<Edge> {
+    let mut v = Vec::<Edge>::new();
+    for poly in polys {
+        if poly.len() < 2 {
+            continue;
+        }
+        // Every subpath is implicitly closed for filling.
+        for i in 0..poly.len() {
+            let (a, b) = (poly[i], poly[(i + 1) % poly.len()]);
+            if a.1 == b.1 {
+                continue;
+            }
+            v.push(if a.1 < b.1 {
+                Edge { x0: a.0, y0: a.1, x1: b.0, y1: b.1, dir: 1 }
+            } else {
+                Edge { x0: b.0, y0: b.1, x1: a.0, y1: a.1, dir: -1 }
+            });
+        }
+    }
+    v
+}
+
+/*
+ * Calls span(y, x0, x1) for every run of pixels [x0, x1) on row y whose centre lies
+ * inside the polygons. Rows and columns are limited to the clip box (x0, y0, x1, y1).
+ */
+pub fn fill_spans<F>(polys: &[Polyline], rule: FillRule, clip: (i32, i32, i32, i32), mut span: F)
+    where F: FnMut(i32, i32, i32) {
+    let edges = edges(polys);
+    if edges.is_empty() {
+        return;
+    }
+    let min_y = edges.iter().fold(::std::f64::MAX, |m, e| m.min(e.y0));
+    let max_y = edges.iter().fold(::std::f64::MIN, |m, e| m.max(e.y1));
+    let row0 = ((min_y - 0.5).ceil() as i32).max(clip.1);
+    let row1 = ((max_y - 0.5).ceil() as i32).min(clip.3);
+
+    let mut crossings = Vec::<(f64, i32)>::new();
+    for y in row0..row1 {
+        let yc = y as f64 + 0.5;
+        crossings.clear();
+        for e in &edges {
+            if yc >= e.y0 && yc < e.y1 {
+                let x = e.x0 + (yc - e.y0) * (e.x1 - e.x0) / (e.y1 - e.y0);
+                crossings.push((x, e.dir));
+            }
+        }
+        crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(::std::cmp::Ordering::Equal));
+
+        let mut winding = 0;
+        let mut start = 0.0;
+        for &(x, dir) in &crossings {
+            let was_inside = rule.inside(winding);
+            winding += dir;
+            let is_inside = rule.inside(winding);
+            if !was_inside && is_inside {
+                start = x;
+            } else if was_inside && !is_inside {
+                let px0 = ((start - 0.5).ceil() as i32).max(clip.0);
+                let px1 = ((x - 0.5).ceil() as i32).min(clip.2);
+                if px1 > px0 {
+                    span(y, px0, px1);
+                }
+            }
+        }
+    }
+}
+
+/*
+ * Calls span(y, x0, x1) for the pixels whose centres lie in a triangle, the same ones
+ * fill_spans would give, without building an edge list. Triangles sharing an edge never
+ * both cover a pixel on it, so a mesh can be filled one triangle at a time.
+ */
+pub fn triangle_spans<F>(a: (f64, f64), b: (f64, f64), c: (f64, f64), clip: (i32, i32, i32, i32),
+                         mut span: F) where F: FnMut(i32, i32, i32) {
+    if [a, b, c].iter().any(|p| !p.0.is_finite() || !p.1.is_finite()) {
+        return;
+    }
+    let mut v = [a, b, c];
+    v.sort_by(|p, q| p.1.partial_cmp(&q.1).unwrap_or(::std::cmp::Ordering::Equal));
+    let (top, mid, bottom) = (v[0], v[1], v[2]);
+    if top.1 == bottom.1 {
+        return;
+    }
+    let x_at = |p: (f64, f64), q: (f64, f64), y: f64| p.0 + (y - p.1) * (q.0 - p.0) / (q.1 - p.1);
+    let row0 = ((top.1 - 0.5).ceil() as i32).max(clip.1);
+    let row1 = ((bottom.1 - 0.5).ceil() as i32).min(clip.3);
+    for y in row0..row1 {
+        let yc = y as f64 + 0.5;
+        let long = x_at(top, bottom, yc);
+        let short = if yc < mid.1 { x_at(top, mid, yc) } else { x_at(mid, bottom, yc) };
+        let (left, right) = if long < short { (long, short) } else { (short, long) };
+        let px0 = ((left - 0.5).ceil() as i32).max(clip.0);
+        let px1 = ((right - 0.5).ceil() as i32).min(clip.2);
+        if px1 > px0 {
+            span(y, px0, px1);
+        }
+    }
+}
+
+/*
+ * Integer points along a line, inclusive of both ends.
+ */
+pub fn line_points<F>(x0: f64, y0: f64, x1: f64, y1: f64, mut point: F) where F: FnMut(i32, i32) {
+    let (x0, y0, x1, y1) = (x0.floor() as i32, y0.floor() as i32, x1.floor() as i32, y1.floor() as i32);
+    let dx = (x1 - x0).abs();
+    let dy = -(y1 - y0).abs();
+    let sx = if x0 < x1 { 1 } else { -1 };
+    let sy = if y0 < y1 { 1 } else { -1 };
+    let (mut x, mut y, mut err) = (x0, y0, dx + dy);
+    loop {
+        point(x, y);
+        if x == x1 && y == y1 {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn triangles_sharing_an_edge_cover_each_pixel_once() {
+        let mut coverage = [[0; 8]; 8];
+        let (a, b, c, d) = ((0.3, 0.2), (7.6, 0.9), (7.1, 7.8), (0.5, 7.4));
+        for &(p, q, r) in &[(a, b, c), (a, c, d)] {
+            triangle_spans(p, q, r, (0, 0, 8, 8), |y, x0, x1| {
+                for x in x0..x1 {
+                    coverage[y as usize][x as usize] += 1;
+                }
+            });
+        }
+        let mut expected = [[0; 8]; 8];
+        fill_spans(&[vec![a, b, c, d]], FillRule::NonZero, (0, 0, 8, 8), |y, x0, x1| {
+            for x in x0..x1 {
+                expected[y as usize][x as usize] += 1;
+            }
+        });
+        assert_eq!(coverage, expected);
+    }
+
+    #[test]
+    fn degenerate_triangles_cover_nothing() {
+        let mut spans = 0;
+        triangle_spans((0.0, 0.0), (4.0, 4.0), (8.0, 8.0), (0, 0, 8, 8), |_, _, _| spans += 1);
+        triangle_spans((0.0, ::std::f64::NAN), (4.0, 0.0), (8.0, 8.0), (0, 0, 8, 8), |_, _, _| spans += 1);
+        assert_eq!(spans, 0);
+    }
+}
@@ -1,28 +1,75 @@
-extern crate sdl2;
-
 use std::path::Path;
 
-use self::sdl2::surface::Surface;
-use self::sdl2::SdlResult;
-use self::sdl2::rect::Rect;
-use self::sdl2::render::Renderer;
-use self::sdl2::render::Texture;
+use backend::Backend;
+use backend::Rect;
+use backend::TextureId;
+use color::Rgba;
+use font;
+use font::Align;
+use font::Font;
+use image::Image;
 
 pub trait RendererUtils {
-    fn load_bmp<P: AsRef<Path>>(&self, name: P) -> SdlResult<Texture>;
-    fn render_texture(&mut self, tex: &Texture, x: i32, y: i32);
+    fn load_bmp<P: AsRef<Path>>(&mut self, name: P) -> Result<TextureId, String>;
+    fn render_texture(&mut self, tex: TextureId, x: i32, y: i32);
+    fn render_texture_rect(&mut self, tex: TextureId, src: Rect, x: i32, y: i32);
+    /*
+     * Loads a BMFont descriptor and its glyph pages, which are looked for next to it.
+     */
+    fn load_font<P: AsRef<Path>>(&mut self, name: P) -> Result<Font, String>;
+    fn draw_text(&mut self, font: &Font, text: &str, x: f64, y: f64, colour: Rgba);
+    /*
+     * Draws text wrapped to a width and aligned within it.
+     */
+    fn draw_text_box(&mut self, font: &Font, text: &str, x: f64, y: f64, width: f64, align: Align,
+                     colour: Rgba);
 }
 
-impl<'a> RendererUtils for Renderer<'a> {
-    fn load_bmp<P: AsRef<Path>>(&self, name: P) -> SdlResult<Texture> {
-        Surface::load_bmp(name).and_then(|surface| self.create_texture_from_surface(surface))
+impl<B: Backend + ?Sized> RendererUtils for B {
+    fn load_bmp<P: AsRef<Path>>(&mut self, name: P) -> Result<TextureId, String> {
+        Image::load_bmp(name).and_then(|image| self.create_texture(&image))
+    }
+
+    fn render_texture(&mut self, tex: TextureId, x: i32, y: i32) {
+        if let Some((w, h)) = self.texture_size(tex) {
+            self.draw_texture(tex, None, x as f64, y as f64, w as f64, h as f64);
+        }
     }
 
-    fn render_texture(&mut self, tex: &Texture, x: i32, y: i32) {
-        let tq = tex.query();
-        let rect = Rect::new(x, y, tq.width, tq.height).unwrap();
-        self.copy(tex, None, rect);
+    fn render_texture_rect(&mut self, tex: TextureId, src: Rect, x: i32, y: i32) {
+        self.draw_texture(tex, Some(src), x as f64, y as f64, src.w as f64, src.h as f64);
+    }
+
+    fn load_font<P: AsRef<Path>>(&mut self, name: P) -> Result<Font, String> {
+        let name = name.as_ref();
+        let desc = try!(font::read_desc(name));
+        let mut pages = Vec::new();
+        for file in &desc.pages {
+            let image = try!(font::load_page(name.with_file_name(file)));
+            pages.push(try!(self.create_texture(&image)));
+        }
+        Ok(Font::new(desc, pages))
+    }
+
+    fn draw_text(&mut self, font: &Font, text: &str, x: f64, y: f64, colour: Rgba) {
+        let glyphs = font.layout(text, None, Align::Left);
+        draw_glyphs(self, &glyphs, x, y, colour);
+    }
+
+    fn draw_text_box(&mut self, font: &Font, text: &str, x: f64, y: f64, width: f64, align: Align,
+                     colour: Rgba) {
+        let glyphs = font.layout(text, Some(width), align);
+        draw_glyphs(self, &glyphs, x, y, colour);
     }
 
 }
 
+fn draw_glyphs<B: Backend + ?Sized>(backend: &mut B, glyphs: &[font::PlacedGlyph], x: f64, y: f64,
+                                    colour: Rgba) {
+    let tint = backend.tint();
+    backend.set_tint(colour);
+    for g in glyphs {
+        backend.draw_texture(g.texture, Some(g.src), x + g.x, y + g.y, g.src.w as f64, g.src.h as f64);
+    }
+    backend.set_tint(tint);
+}
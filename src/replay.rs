@@ -0,0 +1,617 @@
+/*
+ * Input recordings, for reproducing a session exactly. Every input event is stored with
+ * the update tick it arrived before, along with a hash of the ditty's state every so
+ * often. Played back on a fixed timestep, the same events reach the same updates, and the
+ * hashes show whether the game really did the same thing.
+ *
+ * "Tick n" means after n updates, so events at tick 0 arrive before the first update
+ * and the hash at tick n is taken after the n-th.
+ *
+ * Only updates are reproduced, so a replay should run in a window of the size it was
+ * recorded at: some ditties fit their view to the window, and mouse positions are in
+ * window pixels.
+ *
+ * Files are a header followed by entries, with numbers as LEB128 varints and ticks stored
+ * as the difference from the entry before:
+ *
+ *     "TYRP" version:u8 update_rate hash_every
+ *     entry*: 0 dtick event | 1 dtick hash:u64le | 2 dtick (end)
+ */
+
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+use ditty::Ditty;
+use input::InputEvent;
+use input::Key;
+use input::Modifiers;
+use input::MouseButton;
+use input::PAD_AXES;
+use input::PAD_BUTTONS;
+
+const MAGIC: &'static [u8] = b"TYRP";
+const VERSION: u8 = 1;
+
+/*
+ * FNV-1a over whatever a ditty feeds it. Unlike the standard library's hashers its
+ * output is fixed, so hashes stay comparable across builds and machines.
+ */
+pub struct StateHasher {
+    hash: u64
+}
+
+impl StateHasher {
+    pub fn new() -> StateHasher {
+        StateHasher { hash: 0xcbf29ce484222325 }
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.hash ^= b as u64;
+            self.hash = self.hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub fn write_u64(&mut self, v: u64) {
+        let mut bytes = [0u8; 8];
+        for i in 0..8 {
+            bytes[i] = (v >> (i * 8)) as u8;
+        }
+        self.write(&bytes);
+    }
+
+    pub fn write_i64(&mut self, v: i64) {
+        self.write_u64(v as u64);
+    }
+
+    pub fn write_f64(&mut self, v: f64) {
+        self.write_u64(v.to_bits());
+    }
+
+    pub fn write_bool(&mut self, v: bool) {
+        self.write(&[v as u8]);
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        self.write_u64(s.len() as u64);
+        self.write(s.as_bytes());
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+pub fn hash_ditty(ditty: &Ditty) -> u64 {
+    let mut hasher = StateHasher::new();
+    ditty.hash_state(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    Input(u64, InputEvent),
+    Hash(u64, u64)
+}
+
+#[derive(Debug, Clone)]
+pub struct Recording {
+    pub update_rate: u32,
+    /*
+     * Ticks between state hashes.
+     */
+    pub hash_every: u64,
+    pub entries: Vec<Entry>,
+    /*
+     * How many updates the session ran for.
+     */
+    pub ticks: u64
+}
+
+fn put_varint(out: &mut Vec<u8>, mut v: u64) {
+    loop {
+        let b = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            out.push(b);
+            return;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+fn put_signed(out: &mut Vec<u8>, v: i64) {
+    put_varint(out, ((v << 1) ^ (v >> 63)) as u64);
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, String> {
+        let b = try!(self.data.get(self.pos).cloned().ok_or(format!("recording ends early at byte {}", self.pos)));
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut v = 0u64;
+        let mut shift = 0;
+        loop {
+            let b = try!(self.byte());
+            if shift > 63 {
+                return Err(format!("bad number at byte {}", self.pos));
+            }
+            v |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+            shift += 7;
+        }
+    }
+
+    fn signed(&mut self) -> Result<i64, String> {
+        let v = try!(self.varint());
+        Ok((v >> 1) as i64 ^ -((v & 1) as i64))
+    }
+
+    fn u64le(&mut self) -> Result<u64, String> {
+        let mut v = 0u64;
+        for i in 0..8 {
+            v |= (try!(self.byte()) as u64) << (i * 8);
+        }
+        Ok(v)
+    }
+}
+
+const NAMED_KEYS: [Key; 18] = [
+    Key::Space, Key::Return, Key::Escape, Key::Tab, Key::Backspace, Key::Delete, Key::Insert,
+    Key::Home, Key::End, Key::PageUp, Key::PageDown, Key::Up, Key::Down, Key::Left, Key::Right,
+    Key::Shift, Key::Ctrl, Key::Alt
+];
+
+fn put_key(out: &mut Vec<u8>, key: Key) {
+    match key {
+        Key::Char(c) => {
+            out.push(0);
+            put_varint(out, c as u64);
+        },
+        Key::F(n) => {
+            out.push(1);
+            out.push(n);
+        },
+        Key::Other(code) => {
+            out.push(2);
+            put_signed(out, code as i64);
+        },
+        _ => out.push(3 + NAMED_KEYS.iter().position(|&k| k == key).unwrap() as u8)
+    }
+}
+
+fn read_key(r: &mut Reader) -> Result<Key, String> {
+    Ok(match try!(r.byte()) {
+        0 => Key::Char(try!(::std::char::from_u32(try!(r.varint()) as u32).ok_or("bad character".to_string()))),
+        1 => Key::F(try!(r.byte())),
+        2 => Key::Other(try!(r.signed()) as i32),
+        n if n >= 3 && ((n - 3) as usize) < NAMED_KEYS.len() => NAMED_KEYS[(n - 3) as usize],
+        n => return Err(format!("bad key {}", n))
+    })
+}
+
+fn put_button(out: &mut Vec<u8>, button: MouseButton) {
+    out.push(match button {
+        MouseButton::Left => 0,
+        MouseButton::Middle => 1,
+        MouseButton::Right => 2,
+        MouseButton::Other(n) => 3u8.saturating_add(n)
+    });
+}
+
+fn read_button(r: &mut Reader) -> Result<MouseButton, String> {
+    Ok(match try!(r.byte()) {
+        0 => MouseButton::Left,
+        1 => MouseButton::Middle,
+        2 => MouseButton::Right,
+        n => MouseButton::Other(n - 3)
+    })
+}
+
+fn put_modifiers(out: &mut Vec<u8>, m: Modifiers, repeat: bool) {
+    out.push(m.shift as u8 | (m.ctrl as u8) << 1 | (m.alt as u8) << 2 | (repeat as u8) << 3);
+}
+
+fn put_event(out: &mut Vec<u8>, event: &InputEvent) {
+    match *event {
+        InputEvent::KeyDown { key, modifiers, repeat } => {
+            out.push(0);
+            put_key(out, key);
+            put_modifiers(out, modifiers, repeat);
+        },
+        InputEvent::KeyUp { key, modifiers } => {
+            out.push(1);
+            put_key(out, key);
+            put_modifiers(out, modifiers, false);
+        },
+        InputEvent::Text(ref text) => {
+            out.push(2);
+            put_varint(out, text.len() as u64);
+            out.extend_from_slice(text.as_bytes());
+        },
+        InputEvent::MouseDown { button, x, y } | InputEvent::MouseUp { button, x, y } => {
+            out.push(if let InputEvent::MouseDown { .. } = *event { 3 } else { 4 });
+            put_button(out, button);
+            put_signed(out, x as i64);
+            put_signed(out, y as i64);
+        },
+        InputEvent::MouseMove { x, y, dx, dy } => {
+            out.push(5);
+            for &v in &[x, y, dx, dy] {
+                put_signed(out, v as i64);
+            }
+        },
+        InputEvent::Wheel { dx, dy, x, y } => {
+            out.push(6);
+            for &v in &[dx, dy, x, y] {
+                put_signed(out, v as i64);
+            }
+        },
+        InputEvent::Focus(focused) => {
+            out.push(7);
+            out.push(focused as u8);
+        },
+        InputEvent::PadDown { pad, button } | InputEvent::PadUp { pad, button } => {
+            out.push(if let InputEvent::PadDown { .. } = *event { 8 } else { 9 });
+            put_varint(out, pad as u64);
+            out.push(PAD_BUTTONS.iter().position(|&b| b == button).unwrap() as u8);
+        },
+        InputEvent::PadAdded(pad) | InputEvent::PadRemoved(pad) => {
+            out.push(if let InputEvent::PadAdded(_) = *event { 10 } else { 11 });
+            put_varint(out, pad as u64);
+        },
+        InputEvent::PadAxis { pad, axis, value } => {
+            // Axis values come from 16 bit readings, which this gets back exactly.
+            out.push(12);
+            put_varint(out, pad as u64);
+            out.push(PAD_AXES.iter().position(|&a| a == axis).unwrap() as u8);
+            put_signed(out, (value * 32767.0).round() as i64);
+        }
+    }
+}
+
+fn read_event(r: &mut Reader) -> Result<InputEvent, String> {
+    let kind = try!(r.byte());
+    let int = |r: &mut Reader| r.signed().map(|v| v as i32);
+    Ok(match kind {
+        0 | 1 => {
+            let key = try!(read_key(r));
+            let bits = try!(r.byte());
+            let modifiers = Modifiers { shift: bits & 1 != 0, ctrl: bits & 2 != 0, alt: bits & 4 != 0 };
+            if kind == 0 {
+                InputEvent::KeyDown { key: key, modifiers: modifiers, repeat: bits & 8 != 0 }
+            } else {
+                InputEvent::KeyUp { key: key, modifiers: modifiers }
+            }
+        },
+        2 => {
+            let len = try!(r.varint()) as usize;
+            if r.pos + len > r.data.len() {
+                return Err("recording ends inside text".to_string());
+            }
+            let text = String::from_utf8_lossy(&r.data[r.pos..r.pos + len]).into_owned();
+            r.pos += len;
+            InputEvent::Text(text)
+        },
+        3 | 4 => {
+            let button = try!(read_button(r));
+            let (x, y) = (try!(int(r)), try!(int(r)));
+            if kind == 3 {
+                InputEvent::MouseDown { button: button, x: x, y: y }
+            } else {
+                InputEvent::MouseUp { button: button, x: x, y: y }
+            }
+        },
+        5 => InputEvent::MouseMove { x: try!(int(r)), y: try!(int(r)), dx: try!(int(r)), dy: try!(int(r)) },
+        6 => InputEvent::Wheel { dx: try!(int(r)), dy: try!(int(r)), x: try!(int(r)), y: try!(int(r)) },
+        7 => InputEvent::Focus(try!(r.byte()) != 0),
+        8 | 9 => {
+            let pad = try!(r.varint()) as u32;
+            let button = match PAD_BUTTONS.get(try!(r.byte()) as usize) {
+                Some(&b) => b,
+                None => return Err("bad controller button".to_string())
+            };
+            if kind == 8 {
+                InputEvent::PadDown { pad: pad, button: button }
+            } else {
+                InputEvent::PadUp { pad: pad, button: button }
+            }
+        },
+        10 => InputEvent::PadAdded(try!(r.varint()) as u32),
+        11 => InputEvent::PadRemoved(try!(r.varint()) as u32),
+        12 => {
+            let pad = try!(r.varint()) as u32;
+            let axis = match PAD_AXES.get(try!(r.byte()) as usize) {
+                Some(&a) => a,
+                None => return Err("bad controller axis".to_string())
+            };
+            InputEvent::PadAxis { pad: pad, axis: axis, value: try!(r.signed()) as f64 / 32767.0 }
+        },
+        n => return Err(format!("bad event type {}", n))
+    })
+}
+
+impl Recording {
+    pub fn new(update_rate: u32, hash_every: u64) -> Recording {
+        Recording { update_rate: update_rate, hash_every: hash_every.max(1), entries: Vec::new(), ticks: 0 }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        put_varint(&mut out, self.update_rate as u64);
+        put_varint(&mut out, self.hash_every);
+        let mut last = 0;
+        for entry in &self.entries {
+            match *entry {
+                Entry::Input(tick, ref event) => {
+                    out.push(0);
+                    put_varint(&mut out, tick - last);
+                    put_event(&mut out, event);
+                    last = tick;
+                },
+                Entry::Hash(tick, hash) => {
+                    out.push(1);
+                    put_varint(&mut out, tick - last);
+                    for i in 0..8 {
+                        out.push((hash >> (i * 8)) as u8);
+                    }
+                    last = tick;
+                }
+            }
+        }
+        out.push(2);
+        put_varint(&mut out, self.ticks.saturating_sub(last));
+        out
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Recording, String> {
+        if !data.starts_with(MAGIC) || data.len() < 5 {
+            return Err("not a recording".to_string());
+        }
+        if data[4] != VERSION {
+            return Err(format!("unsupported recording version {}", data[4]));
+        }
+        let mut r = Reader { data: data, pos: 5 };
+        let mut recording = Recording::new(try!(r.varint()) as u32, try!(r.varint()));
+        let mut tick = 0;
+        loop {
+            let kind = try!(r.byte());
+            tick += try!(r.varint());
+            match kind {
+                0 => recording.entries.push(Entry::Input(tick, try!(read_event(&mut r)))),
+                1 => recording.entries.push(Entry::Hash(tick, try!(r.u64le()))),
+                2 => {
+                    recording.ticks = tick;
+                    return Ok(recording);
+                },
+                n => return Err(format!("bad entry type {} at byte {}", n, r.pos))
+            }
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Recording, String> {
+        let mut data = Vec::new();
+        try!(File::open(path.as_ref()).and_then(|mut f| f.read_to_end(&mut data))
+            .map_err(|e| format!("{}: {}", path.as_ref().display(), e)));
+        Recording::decode(&data).map_err(|e| format!("{}: {}", path.as_ref().display(), e))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        File::create(path.as_ref()).and_then(|mut f| f.write_all(&self.encode()))
+            .map_err(|e| format!("{}: {}", path.as_ref().display(), e))
+    }
+}
+
+/*
+ * Builds a recording as a session runs.
+ */
+pub struct Recorder {
+    recording: Recording
+}
+
+impl Recorder {
+    pub fn new(update_rate: u32, hash_every: u64) -> Recorder {
+        Recorder { recording: Recording::new(update_rate, hash_every) }
+    }
+
+    /*
+     * Notes an event given to the ditty after `tick` updates.
+     */
+    pub fn input(&mut self, tick: u64, event: &InputEvent) {
+        self.recording.entries.push(Entry::Input(tick, event.clone()));
+    }
+
+    /*
+     * Call after each update, with the number of updates so far.
+     */
+    pub fn updated(&mut self, tick: u64, ditty: &Ditty) {
+        self.recording.ticks = tick;
+        if tick % self.recording.hash_every == 0 {
+            self.recording.entries.push(Entry::Hash(tick, hash_ditty(ditty)));
+        }
+    }
+
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    pub fn finish(self) -> Recording {
+        self.recording
+    }
+}
+
+/*
+ * Feeds a recording back in and checks the hashes as it goes.
+ */
+pub struct Replayer {
+    recording: Recording,
+    next: usize,
+    verified: usize
+}
+
+impl Replayer {
+    pub fn new(recording: Recording) -> Replayer {
+        Replayer { recording: recording, next: 0, verified: 0 }
+    }
+
+    pub fn update_rate(&self) -> u32 {
+        self.recording.update_rate
+    }
+
+    /*
+     * Gives the ditty every event recorded for after `tick` updates. Call before each
+     * update.
+     */
+    pub fn feed(&mut self, tick: u64, ditty: &mut Ditty) {
+        while self.next < self.recording.entries.len() {
+            match self.recording.entries[self.next] {
+                Entry::Input(t, ref event) if t <= tick => {
+                    ditty.handle_input(event);
+                },
+                Entry::Hash(t, _) if t <= tick => {},
+                _ => return
+            }
+            self.next += 1;
+        }
+    }
+
+    /*
+     * Checks the hash, if one was recorded, after the `tick`-th update.
+     */
+    pub fn verify(&mut self, tick: u64, ditty: &Ditty) -> Result<(), String> {
+        for entry in &self.recording.entries[self.next..] {
+            match *entry {
+                Entry::Hash(t, expected) if t == tick => {
+                    let actual = hash_ditty(ditty);
+                    if actual != expected {
+                        return Err(format!("replay diverged at tick {}: state hash {:016x}, recorded {:016x}",
+                                           tick, actual, expected));
+                    }
+                    self.verified += 1;
+                    return Ok(());
+                },
+                Entry::Hash(t, _) | Entry::Input(t, _) if t > tick => return Ok(()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /*
+     * Hashes that have matched so far.
+     */
+    pub fn verified(&self) -> usize {
+        self.verified
+    }
+
+    pub fn is_finished(&self, tick: u64) -> bool {
+        tick >= self.recording.ticks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ditty::PathDitty;
+    use headless::Headless;
+    use input::PadAxis;
+    use input::PadButton;
+    use spath::PathElem;
+
+    fn every_kind_of_event() -> Vec<InputEvent> {
+        let shift = Modifiers { shift: true, ctrl: false, alt: true };
+        vec![
+            InputEvent::KeyDown { key: Key::Char('q'), modifiers: shift, repeat: true },
+            InputEvent::KeyUp { key: Key::F(12), modifiers: Modifiers::default() },
+            InputEvent::KeyDown { key: Key::Other(-7), modifiers: Modifiers::default(), repeat: false },
+            InputEvent::Text("héllo".to_string()),
+            InputEvent::MouseDown { button: MouseButton::Left, x: -3, y: 70000 },
+            InputEvent::MouseUp { button: MouseButton::Other(9), x: 0, y: 1 },
+            InputEvent::MouseMove { x: 10, y: 20, dx: -1, dy: 2 },
+            InputEvent::Wheel { dx: 0, dy: -2, x: 5, y: 6 },
+            InputEvent::Focus(false),
+            InputEvent::PadDown { pad: 1, button: PadButton::Start },
+            InputEvent::PadUp { pad: 0, button: PadButton::A },
+            InputEvent::PadAdded(3),
+            InputEvent::PadRemoved(3),
+            InputEvent::PadAxis { pad: 2, axis: PadAxis::RightTrigger, value: -1.0 }
+        ]
+    }
+
+    #[test]
+    fn recordings_survive_encoding() {
+        let mut recording = Recording::new(120, 30);
+        for (i, event) in every_kind_of_event().into_iter().enumerate() {
+            recording.entries.push(Entry::Input(i as u64 * 3, event));
+        }
+        recording.entries.push(Entry::Hash(60, 0xdead_beef_0123_4567));
+        recording.entries.push(Entry::Input(60, InputEvent::Focus(true)));
+        recording.ticks = 75;
+
+        let decoded = Recording::decode(&recording.encode()).unwrap();
+        assert_eq!(decoded.update_rate, 120);
+        assert_eq!(decoded.hash_every, 30);
+        assert_eq!(decoded.entries, recording.entries);
+        assert_eq!(decoded.ticks, 75);
+    }
+
+    #[test]
+    fn damaged_recordings_are_refused() {
+        let mut recording = Recording::new(60, 10);
+        recording.entries = every_kind_of_event().into_iter().map(|e| Entry::Input(1, e)).collect();
+        let data = recording.encode();
+        for len in 0..data.len() {
+            assert!(Recording::decode(&data[..len]).is_err(), "decoded the first {} bytes", len);
+        }
+        let mut future = data.clone();
+        future[4] += 1;
+        assert!(Recording::decode(&future).unwrap_err().contains("version"));
+    }
+
+    fn viewer(size: f64) -> PathDitty {
+        PathDitty::new(vec![vec![PathElem::MoveTo { x: 0.0, y: 0.0 }, PathElem::LineTo { x: size, y: size }]])
+    }
+
+    fn record() -> Recording {
+        let mut run = Headless::new(viewer(100.0), 64, 64);
+        run.record(5);
+        let none = Modifiers::default();
+        run.schedule(2, InputEvent::KeyDown { key: Key::Right, modifiers: none, repeat: false });
+        run.schedule(15, InputEvent::KeyUp { key: Key::Right, modifiers: none });
+        run.schedule(20, InputEvent::Wheel { dx: 0, dy: 1, x: 10, y: 10 });
+        run.run(40);
+        run.recording().unwrap().clone()
+    }
+
+    #[test]
+    fn headless_runs_replay_as_recorded() {
+        let recording = Recording::decode(&record().encode()).unwrap();
+        let mut replay = Headless::new(viewer(100.0), 64, 64);
+        replay.replay(recording);
+        replay.run(100);
+        assert_eq!(replay.replay_error(), None);
+        assert_eq!(replay.replay_verified(), 8);
+        assert_eq!(replay.frame(), 40);
+    }
+
+    #[test]
+    fn replays_that_diverge_are_stopped() {
+        let mut replay = Headless::new(viewer(200.0), 64, 64);
+        replay.replay(record());
+        replay.run(100);
+        assert!(replay.replay_error().unwrap().starts_with("replay diverged at tick 5"));
+    }
+}
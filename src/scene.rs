@@ -0,0 +1,390 @@
+/*
+ * A retained scene graph. Ditties build a tree of nodes once and then move, hide and
+ * restack them, rather than issuing every draw call themselves each frame.
+ *
+ * Each node has a transform relative to its parent and an optional drawable in its own
+ * coordinate space. Siblings are drawn in ascending z, ties in the order they were added,
+ * and a node is drawn before its children. Drawables wholly outside the screen (or the
+ * clip rectangle) are skipped, but their children are still visited since they may be
+ * somewhere else entirely.
+ */
+
+use std::rc::Rc;
+
+use atlas::AtlasSprite;
+use backend::Backend;
+use backend::Rect;
+use backend::TextureId;
+use color::Rgba;
+use geom;
+use geom::Polyline;
+use geom::Transform;
+use raster::FillRule;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+/*
+ * Something that can lay out and draw a line of text. Fonts implement this so the scene
+ * graph does not depend on any particular one.
+ */
+pub trait TextRenderer {
+    fn measure(&self, text: &str) -> (f64, f64);
+    fn draw_text(&self, backend: &mut Backend, text: &str, x: f64, y: f64, colour: Rgba);
+}
+
+pub enum Drawable {
+    /*
+     * The src part of a texture (all of it if None), stretched over (0, 0, w, h).
+     */
+    Texture { texture: TextureId, src: Option<Rect>, w: f64, h: f64 },
+    Sprite(AtlasSprite),
+    Path { outlines: Vec<Polyline>, fill: Option<Rgba>, stroke: Option<Rgba> },
+    /*
+     * Text with its top-left corner at the node's origin.
+     */
+    Text { text: String, colour: Rgba, font: Rc<TextRenderer> }
+}
+
+impl Drawable {
+    pub fn path(outlines: Vec<Polyline>, fill: Option<Rgba>, stroke: Option<Rgba>) -> Drawable {
+        Drawable::Path { outlines: outlines, fill: fill, stroke: stroke }
+    }
+
+    /*
+     * The local bounding box as (x0, y0, x1, y1).
+     */
+    pub fn bounds(&self) -> Option<(f64, f64, f64, f64)> {
+        match *self {
+            Drawable::Texture { w, h, .. } => Some((0.0, 0.0, w, h)),
+            Drawable::Sprite(ref s) => Some((0.0, 0.0, s.src.w as f64, s.src.h as f64)),
+            Drawable::Path { ref outlines, .. } => geom::bounds(outlines),
+            Drawable::Text { ref text, ref font, .. } => {
+                let (w, h) = font.measure(text);
+                Some((0.0, 0.0, w, h))
+            }
+        }
+    }
+
+    pub fn draw(&self, backend: &mut Backend) {
+        match *self {
+            Drawable::Texture { texture, src, w, h } => backend.draw_texture(texture, src, 0.0, 0.0, w, h),
+            Drawable::Sprite(ref s) => s.draw(backend, 0.0, 0.0),
+            Drawable::Path { ref outlines, fill, stroke } => {
+                if let Some(c) = fill {
+                    backend.set_color(c);
+                    backend.fill_path(outlines, FillRule::NonZero);
+                }
+                if let Some(c) = stroke {
+                    backend.set_color(c);
+                    for poly in outlines {
+                        backend.draw_polyline(poly);
+                    }
+                }
+            },
+            Drawable::Text { ref text, colour, ref font } => font.draw_text(backend, text, 0.0, 0.0, colour)
+        }
+    }
+}
+
+struct Node {
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    transform: Transform,
+    visible: bool,
+    z: i32,
+    drawable: Option<Drawable>
+}
+
+pub struct Scene {
+    nodes: Vec<Option<Node>>,
+    free: Vec<usize>
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        let root = Node {
+            parent: None,
+            children: Vec::new(),
+            transform: Transform::identity(),
+            visible: true,
+            z: 0,
+            drawable: None
+        };
+        Scene { nodes: vec![Some(root)], free: Vec::new() }
+    }
+
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    fn node(&self, id: NodeId) -> &Node {
+        self.nodes[id.0].as_ref().expect("node has been removed")
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        self.nodes[id.0].as_mut().expect("node has been removed")
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.nodes.get(id.0).map(|n| n.is_some()).unwrap_or(false)
+    }
+
+    pub fn add(&mut self, parent: NodeId, drawable: Option<Drawable>) -> NodeId {
+        let node = Node {
+            parent: Some(parent),
+            children: Vec::new(),
+            transform: Transform::identity(),
+            visible: true,
+            z: 0,
+            drawable: drawable
+        };
+        let id = match self.free.pop() {
+            Some(i) => {
+                self.nodes[i] = Some(node);
+                NodeId(i)
+            },
+            None => {
+                self.nodes.push(Some(node));
+                NodeId(self.nodes.len() - 1)
+            }
+        };
+        self.node_mut(parent).children.push(id);
+        id
+    }
+
+    /*
+     * Removes a node and everything beneath it. The root cannot be removed.
+     */
+    pub fn remove(&mut self, id: NodeId) {
+        if id == self.root() {
+            return;
+        }
+        if let Some(parent) = self.node(id).parent {
+            self.node_mut(parent).children.retain(|&c| c != id);
+        }
+        let mut doomed = vec![id];
+        while let Some(n) = doomed.pop() {
+            if let Some(node) = self.nodes[n.0].take() {
+                doomed.extend(node.children);
+                self.free.push(n.0);
+            }
+        }
+    }
+
+    /*
+     * Moves a node under a new parent, keeping its local transform. Attaching a node
+     * beneath itself is refused.
+     */
+    pub fn set_parent(&mut self, id: NodeId, parent: NodeId) -> Result<(), String> {
+        let mut p = Some(parent);
+        while let Some(n) = p {
+            if n == id {
+                return Err(format!("{:?} cannot be attached beneath itself", id));
+            }
+            p = self.node(n).parent;
+        }
+        if let Some(old) = self.node(id).parent {
+            self.node_mut(old).children.retain(|&c| c != id);
+        }
+        self.node_mut(id).parent = Some(parent);
+        self.node_mut(parent).children.push(id);
+        Ok(())
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).parent
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        &self.node(id).children
+    }
+
+    pub fn transform(&self, id: NodeId) -> Transform {
+        self.node(id).transform
+    }
+
+    pub fn set_transform(&mut self, id: NodeId, t: Transform) {
+        self.node_mut(id).transform = t;
+    }
+
+    pub fn set_position(&mut self, id: NodeId, x: f64, y: f64) {
+        self.set_transform(id, Transform::translate(x, y));
+    }
+
+    /*
+     * Maps the node's local coordinates to the scene's.
+     */
+    pub fn world_transform(&self, id: NodeId) -> Transform {
+        let node = self.node(id);
+        match node.parent {
+            Some(p) => self.world_transform(p).then(&node.transform),
+            None => node.transform
+        }
+    }
+
+    pub fn is_visible(&self, id: NodeId) -> bool {
+        self.node(id).visible
+    }
+
+    /*
+     * Hiding a node hides everything beneath it too.
+     */
+    pub fn set_visible(&mut self, id: NodeId, visible: bool) {
+        self.node_mut(id).visible = visible;
+    }
+
+    pub fn z(&self, id: NodeId) -> i32 {
+        self.node(id).z
+    }
+
+    pub fn set_z(&mut self, id: NodeId, z: i32) {
+        self.node_mut(id).z = z;
+    }
+
+    pub fn drawable(&self, id: NodeId) -> Option<&Drawable> {
+        self.node(id).drawable.as_ref()
+    }
+
+    pub fn drawable_mut(&mut self, id: NodeId) -> Option<&mut Drawable> {
+        self.node_mut(id).drawable.as_mut()
+    }
+
+    pub fn set_drawable(&mut self, id: NodeId, drawable: Option<Drawable>) {
+        self.node_mut(id).drawable = drawable;
+    }
+
+    /*
+     * Every visible node in the order it is drawn, whether or not it is on screen.
+     */
+    pub fn draw_order(&self) -> Vec<NodeId> {
+        let mut order = Vec::new();
+        self.collect(self.root(), &mut order);
+        order
+    }
+
+    fn collect(&self, id: NodeId, order: &mut Vec<NodeId>) {
+        if !self.node(id).visible {
+            return;
+        }
+        order.push(id);
+        for c in self.sorted_children(id) {
+            self.collect(c, order);
+        }
+    }
+
+    fn sorted_children(&self, id: NodeId) -> Vec<NodeId> {
+        let mut children = self.node(id).children.clone();
+        // A stable sort, so equal z keeps insertion order.
+        children.sort_by_key(|&c| self.node(c).z);
+        children
+    }
+
+    /*
+     * Draws the scene under the backend's current transform, so a camera transform can
+     * be pushed beforehand. Returns the number of drawables actually drawn.
+     */
+    pub fn draw(&self, backend: &mut Backend) -> usize {
+        let (w, h) = backend.size();
+        let screen = match backend.clip() {
+            Some(c) => (c.x as f64, c.y as f64, c.right() as f64, c.bottom() as f64),
+            None => (0.0, 0.0, w as f64, h as f64)
+        };
+        self.draw_node(backend, self.root(), screen)
+    }
+
+    fn draw_node(&self, backend: &mut Backend, id: NodeId, screen: (f64, f64, f64, f64)) -> usize {
+        let node = self.node(id);
+        if !node.visible {
+            return 0;
+        }
+        let mut drawn = 0;
+        backend.push_transform(&node.transform);
+        if let Some(ref d) = node.drawable {
+            if on_screen(d, &backend.transform(), screen) {
+                d.draw(backend);
+                drawn += 1;
+            }
+        }
+        for c in self.sorted_children(id) {
+            drawn += self.draw_node(backend, c, screen);
+        }
+        backend.pop_transform();
+        drawn
+    }
+}
+
+fn on_screen(d: &Drawable, t: &Transform, screen: (f64, f64, f64, f64)) -> bool {
+    let (x0, y0, x1, y1) = match d.bounds() {
+        Some(b) => b,
+        None => return false
+    };
+    let corners = vec![vec![t.apply(x0, y0), t.apply(x1, y0), t.apply(x1, y1), t.apply(x0, y1)]];
+    match geom::bounds(&corners) {
+        // Strokes reach half a pixel past the outline, so allow a little slack.
+        Some((ax, ay, bx, by)) => bx >= screen.0 - 1.0 && ax <= screen.2 + 1.0 &&
+                                  by >= screen.1 - 1.0 && ay <= screen.3 + 1.0,
+        None => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use software::SoftwareBackend;
+
+    fn square(size: f64) -> Option<Drawable> {
+        let outline = vec![(0.0, 0.0), (size, 0.0), (size, size), (0.0, size)];
+        Some(Drawable::path(vec![outline], Some(Rgba::rgb(255, 255, 255)), None))
+    }
+
+    #[test]
+    fn siblings_draw_by_z_then_by_when_they_were_added() {
+        let mut scene = Scene::new();
+        let root = scene.root();
+        let a = scene.add(root, None);
+        let b = scene.add(root, None);
+        let c = scene.add(root, None);
+        let d = scene.add(root, None);
+        scene.set_z(a, 1);
+        scene.set_z(c, -1);
+        let inner = scene.add(b, None);
+        assert_eq!(scene.draw_order(), vec![root, c, b, inner, d, a]);
+
+        // Restacking moves only the node restacked; equals keep their order.
+        scene.set_z(a, 0);
+        assert_eq!(scene.draw_order(), vec![root, c, a, b, inner, d]);
+        scene.set_z(c, 0);
+        assert_eq!(scene.draw_order(), vec![root, a, b, inner, c, d]);
+
+        scene.set_visible(b, false);
+        assert_eq!(scene.draw_order(), vec![root, a, c, d]);
+    }
+
+    #[test]
+    fn drawables_off_screen_are_skipped() {
+        let mut scene = Scene::new();
+        let root = scene.root();
+        let visible = scene.add(root, square(10.0));
+        let away = scene.add(root, square(10.0));
+        scene.set_position(away, 500.0, 20.0);
+        // A child of something off screen may itself be on it.
+        let back = scene.add(away, square(10.0));
+        scene.set_position(back, -480.0, 0.0);
+        scene.set_position(visible, 5.0, 5.0);
+
+        let mut backend = SoftwareBackend::new(100, 100);
+        assert_eq!(scene.draw(&mut backend), 2);
+
+        // Under a camera looking elsewhere, only what is there now is drawn.
+        backend.push_transform(&Transform::translate(-450.0, 0.0));
+        assert_eq!(scene.draw(&mut backend), 1);
+        backend.pop_transform();
+
+        // The clip narrows the view further.
+        backend.set_clip(Some(Rect::new(0, 0, 18, 18)));
+        assert_eq!(scene.draw(&mut backend), 1);
+        backend.set_clip(Some(Rect::new(50, 50, 50, 50)));
+        assert_eq!(scene.draw(&mut backend), 0);
+    }
+}
@@ -0,0 +1,73 @@
+/*
+ * Plays the mixer through SDL's audio device, which calls it for more sound on its own
+ * thread whenever it runs low. That thread never waits for the mixer; see `Audio::try_mix`.
+ */
+
+extern crate sdl2;
+
+use self::sdl2::AudioSubsystem;
+use self::sdl2::SdlResult;
+use self::sdl2::audio::AudioCallback;
+use self::sdl2::audio::AudioDevice;
+use self::sdl2::audio::AudioSpecDesired;
+
+use audio::Audio;
+use audio::AudioOutput;
+
+/*
+ * Frames a buffer: about 23ms at 44.1kHz, short enough that sounds start with the action.
+ */
+const BUFFER_FRAMES: u16 = 1024;
+
+struct Callback {
+    audio: Audio
+}
+
+impl AudioCallback for Callback {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.audio.try_mix(out);
+    }
+}
+
+pub struct SdlAudio {
+    device: AudioDevice<Callback>,
+    rate: u32
+}
+
+impl SdlAudio {
+    /*
+     * Opens the default output in stereo, at the mixer's rate if it can, and starts it
+     * playing.
+     */
+    pub fn open(subsystem: &AudioSubsystem, audio: Audio) -> SdlResult<SdlAudio> {
+        let desired = AudioSpecDesired {
+            freq: Some(audio.mixer().rate() as i32),
+            channels: Some(2),
+            samples: Some(BUFFER_FRAMES)
+        };
+        let mut rate = 0;
+        let device = try!(subsystem.open_playback(None, &desired, |spec| {
+            rate = spec.freq as u32;
+            audio.mixer().set_rate(rate);
+            Callback { audio: audio }
+        }));
+        device.resume();
+        Ok(SdlAudio { device: device, rate: rate })
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        if paused {
+            self.device.pause();
+        } else {
+            self.device.resume();
+        }
+    }
+}
+
+impl AudioOutput for SdlAudio {
+    fn rate(&self) -> u32 {
+        self.rate
+    }
+}
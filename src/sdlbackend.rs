@@ -0,0 +1,329 @@
+extern crate sdl2;
+
+use self::sdl2::SdlResult;
+use self::sdl2::pixels::Color;
+use self::sdl2::pixels::PixelFormatEnum;
+use self::sdl2::rect::Point;
+use self::sdl2::rect::Rect as SdlRect;
+use self::sdl2::render::BlendMode;
+use self::sdl2::render::Renderer;
+use self::sdl2::render::Texture;
+
+use backend::Backend;
+use backend::DrawState;
+use backend::Rect;
+use backend::TextureId;
+use backend::TexturedQuad;
+use color::Rgba;
+use geom::Polyline;
+use geom::Transform;
+use image::Image;
+use raster;
+use raster::FillRule;
+
+/*
+ * Draws through an accelerated SDL renderer. SDL has no polygon primitive, so filled
+ * paths are scan converted here and sent as a batch of one pixel high rectangles.
+ */
+pub struct SdlBackend<'a> {
+    renderer: Renderer<'a>,
+    textures: Vec<Option<Texture>>,
+    texture_sizes: Vec<(u32, u32)>,
+    state: DrawState,
+    width: u32,
+    height: u32,
+    displays: u32
+}
+
+fn sdl_color(c: Rgba) -> Color {
+    Color::RGBA(c.r, c.g, c.b, c.a)
+}
+
+fn sdl_rect(r: Rect) -> SdlRect {
+    SdlRect::new_unwrap(r.x, r.y, r.w, r.h)
+}
+
+/*
+ * How a texture is placed on screen by copy_ex.
+ */
+struct Placement {
+    dst: SdlRect,
+    angle: f64,
+    flip_h: bool,
+    flip_v: bool
+}
+
+/*
+ * Where a texture drawn at (x, y, w, h) lands on screen, or None if it would be too
+ * small to see.
+ *
+ * SDL can only flip a texture, then scale and rotate it about its centre. Transforms
+ * that mirror are turned into a flip. Skew has no equivalent and is dropped: the
+ * texture keeps the rotation of its x axis and the length of each axis, so a skewed
+ * sprite is drawn as an unskewed one of about the same size.
+ */
+fn texture_placement(t: &Transform, x: f64, y: f64, w: f64, h: f64) -> Option<Placement> {
+    let sx = (t.a * t.a + t.b * t.b).sqrt();
+    let sy = (t.c * t.c + t.d * t.d).sqrt();
+    let mut angle = t.b.atan2(t.a).to_degrees();
+    // A mirrored transform is a rotation of a vertically flipped texture, or of a
+    // horizontally flipped one turned half a turn, whichever turns it less.
+    let mirrored = t.a * t.d - t.b * t.c < 0.0;
+    let flip_h = mirrored && angle.abs() > 90.0;
+    let flip_v = mirrored && !flip_h;
+    if flip_h {
+        angle += if angle > 0.0 { -180.0 } else { 180.0 };
+    }
+    let (cx, cy) = t.apply(x + w / 2.0, y + h / 2.0);
+    let (dw, dh) = (w * sx, h * sy);
+    if dw < 0.5 || dh < 0.5 {
+        return None;
+    }
+    Some(Placement {
+        dst: SdlRect::new_unwrap((cx - dw / 2.0).round() as i32, (cy - dh / 2.0).round() as i32,
+                                 dw.round() as u32, dh.round() as u32),
+        angle: angle,
+        flip_h: flip_h,
+        flip_v: flip_v
+    })
+}
+
+impl<'a> SdlBackend<'a> {
+    pub fn new(mut renderer: Renderer<'a>, width: u32, height: u32) -> SdlBackend<'a> {
+        renderer.set_blend_mode(BlendMode::Blend);
+        SdlBackend {
+            renderer: renderer,
+            textures: Vec::new(),
+            texture_sizes: Vec::new(),
+            state: DrawState::new(),
+            width: width,
+            height: height,
+            displays: 1
+        }
+    }
+
+    pub fn renderer(&mut self) -> &mut Renderer<'a> {
+        &mut self.renderer
+    }
+
+    /*
+     * Follows the window when it is resized.
+     */
+    pub fn set_size(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
+
+    pub fn set_displays(&mut self, displays: u32) {
+        self.displays = displays;
+    }
+
+    fn point(&self, x: f64, y: f64) -> Point {
+        let (sx, sy) = self.state.transform().apply(x, y);
+        Point::new(sx.floor() as i32, sy.floor() as i32)
+    }
+}
+
+impl<'a> Backend for SdlBackend<'a> {
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn clear(&mut self, colour: Rgba) {
+        self.renderer.set_draw_color(sdl_color(colour));
+        self.renderer.clear();
+        self.renderer.set_draw_color(sdl_color(self.state.colour));
+    }
+
+    fn present(&mut self) {
+        self.renderer.present();
+    }
+
+    fn read_pixels(&mut self) -> Result<Image, String> {
+        let (width, height) = (self.width, self.height);
+        self.renderer.read_pixels(None, PixelFormatEnum::ABGR8888).map(|pixels| Image {
+            width: width,
+            height: height,
+            pixels: pixels
+        })
+    }
+
+    fn displays(&self) -> u32 {
+        self.displays
+    }
+
+    fn set_color(&mut self, colour: Rgba) {
+        self.state.colour = colour;
+        self.renderer.set_draw_color(sdl_color(colour));
+    }
+
+    fn draw_line(&mut self, x0: f64, y0: f64, x1: f64, y1: f64) {
+        let (a, b) = (self.point(x0, y0), self.point(x1, y1));
+        self.renderer.draw_line(a, b);
+    }
+
+    fn draw_polyline(&mut self, points: &[(f64, f64)]) {
+        let points: Vec<Point> = points.iter().map(|&(x, y)| self.point(x, y)).collect();
+        self.renderer.draw_lines(&points);
+    }
+
+    fn fill_rect(&mut self, x: f64, y: f64, w: f64, h: f64) {
+        self.fill_path(&[vec![(x, y), (x + w, y), (x + w, y + h), (x, y + h)]], FillRule::NonZero);
+    }
+
+    fn fill_path(&mut self, polys: &[Polyline], rule: FillRule) {
+        let t = self.state.transform();
+        let polys: Vec<Polyline> = polys.iter()
+            .map(|p| p.iter().map(|&(x, y)| t.apply(x, y)).collect())
+            .collect();
+        let mut rects = Vec::<SdlRect>::new();
+        raster::fill_spans(&polys, rule, self.state.clip_box(self.width, self.height),
+                           |y, x0, x1| rects.push(SdlRect::new_unwrap(x0, y, (x1 - x0) as u32, 1)));
+        if !rects.is_empty() {
+            self.renderer.fill_rects(&rects);
+        }
+    }
+
+    /*
+     * SDL 2.0 has no call for drawing triangles, so meshes are still scan converted on
+     * the CPU. What this saves over the default is work, not the rasteriser: vertices are
+     * transformed once each rather than once per corner, each triangle is filled without
+     * building polylines or sorting edges for a whole path, and every span goes out in
+     * one fill_rects call.
+     */
+    fn fill_triangles(&mut self, vertices: &[(f64, f64)], indices: &[u32]) {
+        let t = self.state.transform();
+        let points: Vec<(f64, f64)> = vertices.iter().map(|&(x, y)| t.apply(x, y)).collect();
+        let clip = self.state.clip_box(self.width, self.height);
+        let mut rects = Vec::<SdlRect>::new();
+        for tri in indices.chunks(3).filter(|t| t.len() == 3) {
+            match (points.get(tri[0] as usize), points.get(tri[1] as usize), points.get(tri[2] as usize)) {
+                (Some(&a), Some(&b), Some(&c)) => raster::triangle_spans(a, b, c, clip, |y, x0, x1| {
+                    rects.push(SdlRect::new_unwrap(x0, y, (x1 - x0) as u32, 1))
+                }),
+                _ => {}
+            }
+        }
+        if !rects.is_empty() {
+            self.renderer.fill_rects(&rects);
+        }
+    }
+
+    fn create_texture(&mut self, image: &Image) -> Result<TextureId, String> {
+        let tex: SdlResult<Texture> = self.renderer
+            .create_texture_streaming(PixelFormatEnum::ABGR8888, (image.width, image.height))
+            .and_then(|mut tex| tex.update(None, &image.pixels, (image.width * 4) as usize)
+            .map(|_| {
+                tex.set_blend_mode(BlendMode::Blend);
+                tex
+            }));
+        tex.map(|t| {
+            self.textures.push(Some(t));
+            self.texture_sizes.push((image.width, image.height));
+            TextureId(self.textures.len() - 1)
+        })
+    }
+
+    fn update_texture(&mut self, tex: TextureId, x: u32, y: u32, image: &Image) -> Result<(), String> {
+        match self.textures.get_mut(tex.0) {
+            Some(&mut Some(ref mut texture)) => texture.update(
+                Some(SdlRect::new_unwrap(x as i32, y as i32, image.width, image.height)),
+                &image.pixels, (image.width * 4) as usize),
+            _ => Err(format!("no texture {}", tex.0))
+        }
+    }
+
+    fn destroy_texture(&mut self, tex: TextureId) {
+        if let Some(slot) = self.textures.get_mut(tex.0) {
+            *slot = None;
+        }
+    }
+
+    fn texture_size(&self, tex: TextureId) -> Option<(u32, u32)> {
+        match self.textures.get(tex.0) {
+            Some(&Some(_)) => Some(self.texture_sizes[tex.0]),
+            _ => None
+        }
+    }
+
+    fn draw_texture(&mut self, tex: TextureId, src: Option<Rect>, x: f64, y: f64, w: f64, h: f64) {
+        let place = match texture_placement(&self.state.transform(), x, y, w, h) {
+            Some(place) => place,
+            None => return
+        };
+        let texture = match self.textures.get_mut(tex.0) {
+            Some(&mut Some(ref mut texture)) => texture,
+            _ => return
+        };
+        let tint = self.state.tint;
+        texture.set_color_mod(tint.r, tint.g, tint.b);
+        texture.set_alpha_mod(tint.a);
+        if place.angle.abs() < 1e-6 && !place.flip_h && !place.flip_v {
+            self.renderer.copy(texture, src.map(sdl_rect), Some(place.dst));
+        } else {
+            self.renderer.copy_ex(texture, src.map(sdl_rect), Some(place.dst), place.angle, None,
+                                  place.flip_h, place.flip_v);
+        }
+    }
+
+    /*
+     * The texture is looked up and the transform taken apart once for the whole batch,
+     * and the colour modulation only changes where the tint does. SDL's renderer has no
+     * way to take many quads at once, so each is still its own copy, in order, and
+     * overlaps come out as they would one at a time.
+     */
+    fn draw_texture_batch(&mut self, tex: TextureId, quads: &[TexturedQuad]) {
+        let t = self.state.transform();
+        let texture = match self.textures.get_mut(tex.0) {
+            Some(&mut Some(ref mut texture)) => texture,
+            _ => return
+        };
+        let mut tint = None;
+        for q in quads {
+            let place = match texture_placement(&t, q.x, q.y, q.w, q.h) {
+                Some(place) => place,
+                None => continue
+            };
+            if tint != Some(q.tint) {
+                texture.set_color_mod(q.tint.r, q.tint.g, q.tint.b);
+                texture.set_alpha_mod(q.tint.a);
+                tint = Some(q.tint);
+            }
+            if place.angle.abs() < 1e-6 && !place.flip_h && !place.flip_v {
+                self.renderer.copy(texture, q.src.map(sdl_rect), Some(place.dst));
+            } else {
+                self.renderer.copy_ex(texture, q.src.map(sdl_rect), Some(place.dst), place.angle, None,
+                                      place.flip_h, place.flip_v);
+            }
+        }
+    }
+
+    fn set_tint(&mut self, tint: Rgba) {
+        self.state.tint = tint;
+    }
+
+    fn tint(&self) -> Rgba {
+        self.state.tint
+    }
+
+    fn set_clip(&mut self, clip: Option<Rect>) {
+        self.state.clip = clip;
+        self.renderer.set_clip_rect(clip.map(sdl_rect));
+    }
+
+    fn clip(&self) -> Option<Rect> {
+        self.state.clip
+    }
+
+    fn push_transform(&mut self, t: &Transform) {
+        self.state.push(t);
+    }
+
+    fn pop_transform(&mut self) {
+        self.state.pop();
+    }
+
+    fn transform(&self) -> Transform {
+        self.state.transform()
+    }
+}
@@ -0,0 +1,178 @@
+/*
+ * Video settings, kept in a small text file of `key = value` lines:
+ *
+ *     mode = windowed        # windowed, borderless or fullscreen
+ *     width = 1280           # 0 for the desktop resolution
+ *     height = 720
+ *     display = 0
+ *     vsync = true
+ *     fps_cap = 0            # 0 for no cap
+ *
+ * Missing keys keep their defaults, which match the desktop: fullscreen at its own
+ * resolution with vsync.
+ */
+
+use std::cmp;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+pub const VIDEO_CONFIG: &'static str = "video.cfg";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowMode {
+    Windowed,
+    /*
+     * A window without decorations covering the whole display, at the desktop mode.
+     */
+    Borderless,
+    /*
+     * Exclusive fullscreen, changing the display mode to the chosen resolution.
+     */
+    Fullscreen
+}
+
+impl WindowMode {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            WindowMode::Windowed => "windowed",
+            WindowMode::Borderless => "borderless",
+            WindowMode::Fullscreen => "fullscreen"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<WindowMode> {
+        match name {
+            "windowed" => Some(WindowMode::Windowed),
+            "borderless" => Some(WindowMode::Borderless),
+            "fullscreen" => Some(WindowMode::Fullscreen),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VideoSettings {
+    pub mode: WindowMode,
+    /*
+     * The window or fullscreen size; (0, 0) means the display's current mode.
+     */
+    pub resolution: (u32, u32),
+    pub display: u32,
+    pub vsync: bool,
+    pub fps_cap: Option<u32>
+}
+
+impl VideoSettings {
+    pub fn new() -> VideoSettings {
+        VideoSettings {
+            mode: WindowMode::Fullscreen,
+            resolution: (0, 0),
+            display: 0,
+            vsync: true,
+            fps_cap: None
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<VideoSettings, String> {
+        let mut settings = VideoSettings::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(i) => (line[..i].trim(), line[i + 1..].trim()),
+                None => return Err(format!("line {}: expected key = value", n + 1))
+            };
+            let bad = || format!("line {}: bad value for {}: {}", n + 1, key, value);
+            let number = || value.parse::<u32>().map_err(|_| bad());
+            match key {
+                "mode" => settings.mode = try!(WindowMode::from_name(value).ok_or(bad())),
+                "width" => settings.resolution.0 = try!(number()),
+                "height" => settings.resolution.1 = try!(number()),
+                "display" => settings.display = try!(number()),
+                "vsync" => settings.vsync = try!(value.parse::<bool>().map_err(|_| bad())),
+                "fps_cap" => {
+                    let cap = try!(number());
+                    settings.fps_cap = if cap > 0 { Some(cap) } else { None };
+                },
+                _ => {}
+            }
+        }
+        // A width without a height, or the other way round, can't make a window.
+        if settings.resolution.0 == 0 || settings.resolution.1 == 0 {
+            settings.resolution = (0, 0);
+        }
+        Ok(settings)
+    }
+
+    /*
+     * Keeps the display to one that exists; there may be fewer than when the settings
+     * were saved.
+     */
+    pub fn clamp_display(&mut self, displays: u32) {
+        self.display = cmp::min(self.display, displays.saturating_sub(1));
+    }
+
+    /*
+     * Loads the settings, falling back to the defaults if there is no file yet.
+     */
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<VideoSettings, String> {
+        let mut text = String::new();
+        match File::open(path.as_ref()) {
+            Ok(mut f) => try!(f.read_to_string(&mut text).map_err(|e| format!("{}: {}", path.as_ref().display(), e))),
+            Err(_) => return Ok(VideoSettings::new())
+        };
+        VideoSettings::parse(&text).map_err(|e| format!("{}: {}", path.as_ref().display(), e))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        File::create(path.as_ref()).and_then(|mut f| f.write_all(self.to_string().as_bytes()))
+            .map_err(|e| format!("{}: {}", path.as_ref().display(), e))
+    }
+}
+
+impl fmt::Display for VideoSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "mode = {}\nwidth = {}\nheight = {}\ndisplay = {}\nvsync = {}\nfps_cap = {}\n",
+               self.mode.name(), self.resolution.0, self.resolution.1, self.display, self.vsync,
+               self.fps_cap.unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_survive_saving() {
+        let settings = VideoSettings {
+            mode: WindowMode::Borderless,
+            resolution: (1600, 900),
+            display: 1,
+            vsync: false,
+            fps_cap: Some(144)
+        };
+        assert_eq!(VideoSettings::parse(&settings.to_string()), Ok(settings));
+    }
+
+    #[test]
+    fn half_a_resolution_means_the_desktop() {
+        let settings = VideoSettings::parse("mode = windowed\nwidth = 1280\n").unwrap();
+        assert_eq!(settings.resolution, (0, 0));
+        let settings = VideoSettings::parse("height = 720\n").unwrap();
+        assert_eq!(settings.resolution, (0, 0));
+    }
+
+    #[test]
+    fn missing_displays_fall_back_to_the_last() {
+        let mut settings = VideoSettings::parse("display = 3\n").unwrap();
+        settings.clamp_display(2);
+        assert_eq!(settings.display, 1);
+        settings.clamp_display(0);
+        assert_eq!(settings.display, 0);
+    }
+}
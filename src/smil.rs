@@ -0,0 +1,739 @@
+/*
+ * SMIL animation for SVG documents: <animate>, <animateTransform> and <animateMotion>.
+ *
+ * Only offset based timing is supported, so begin="2s" works but begin="foo.click" does
+ * not. Animations are pure functions of the document clock; evaluate() takes the time in
+ * seconds since the document started and returns the animated state of every node.
+ *
+ * accumulate="sum" is honoured for numbers and transforms; colours and motion start each
+ * repeat afresh. Paint animated on a group reaches the shapes in it that don't set their
+ * own, as the paint it sets statically does.
+ */
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+use color::Rgba;
+use easing::CubicBezier;
+use geom;
+use geom::PathMeasure;
+use geom::Transform;
+use spath;
+use spath::PathElem;
+use svg::Style;
+use svg::SvgDocument;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fill {
+    Remove,
+    Freeze
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RepeatCount {
+    Count(f64),
+    Indefinite
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalcMode {
+    Discrete,
+    Linear,
+    Paced,
+    Spline
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnimValue {
+    Number(f64),
+    Color(Rgba)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransformType {
+    Translate,
+    Scale,
+    Rotate,
+    SkewX,
+    SkewY
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MotionRotate {
+    Fixed(f64),
+    Auto,
+    AutoReverse
+}
+
+pub enum AnimKind {
+    Attribute { name: String, values: Vec<AnimValue> },
+    Transform { ttype: TransformType, values: Vec<Vec<f64>> },
+    /*
+     * `stops` are the distances along the path of each of its values, which keyTimes and
+     * keySplines go between: every point given in `values`, or just the two ends of a
+     * path.
+     */
+    Motion { path: PathMeasure, rotate: MotionRotate, stops: Vec<f64> }
+}
+
+pub struct Timing {
+    pub begin: f64,
+    pub dur: f64,
+    pub repeat_count: Option<RepeatCount>,
+    pub repeat_dur: Option<f64>,
+    pub fill: Fill
+}
+
+pub struct Animation {
+    pub target: usize,
+    pub kind: AnimKind,
+    pub timing: Timing,
+    pub calc_mode: CalcMode,
+    pub key_times: Vec<f64>,
+    pub key_splines: Vec<CubicBezier>,
+    pub additive: bool,
+    /*
+     * Whether each repeat carries on from where the last one ended.
+     */
+    pub accumulate: bool
+}
+
+/*
+ * Parses a SMIL clock value: "2s", "150ms", "1.5min", "0.5h", "01:02.5" or a bare number
+ * of seconds.
+ */
+pub fn parse_clock(s: &str) -> Option<f64> {
+    let s = s.trim();
+    if s.contains(':') {
+        return s.split(':').fold(Some(0.0), |acc, part| acc.and_then(|a| {
+            f64::from_str(part).ok().map(|v| a * 60.0 + v)
+        }));
+    }
+    let units = [("ms", 0.001), ("min", 60.0), ("h", 3600.0), ("s", 1.0)];
+    for &(suffix, scale) in units.iter() {
+        if s.ends_with(suffix) {
+            return f64::from_str(&s[..s.len() - suffix.len()]).ok().map(|v| v * scale);
+        }
+    }
+    f64::from_str(s).ok()
+}
+
+impl Timing {
+    /*
+     * The active duration, or None if the animation repeats forever.
+     */
+    fn active_duration(&self) -> Option<f64> {
+        let by_count = match self.repeat_count {
+            Some(RepeatCount::Count(n)) => Some(self.dur * n),
+            Some(RepeatCount::Indefinite) => None,
+            None => if self.repeat_dur.is_some() { None } else { Some(self.dur) }
+        };
+        match (by_count, self.repeat_dur) {
+            (Some(c), Some(r)) => Some(c.min(r)),
+            (None, Some(r)) => Some(r),
+            (c, None) => c
+        }
+    }
+
+    /*
+     * Progress through the simple duration in [0, 1] at document time t, or None when
+     * the animation has no effect.
+     */
+    pub fn progress(&self, t: f64) -> Option<f64> {
+        if t < self.begin || self.dur <= 0.0 {
+            return None;
+        }
+        let local = t - self.begin;
+        match self.active_duration() {
+            Some(active) if local >= active => match self.fill {
+                Fill::Remove => None,
+                Fill::Freeze => {
+                    let reps = active / self.dur;
+                    let frac = reps - reps.floor();
+                    Some(if frac == 0.0 && reps > 0.0 { 1.0 } else { frac })
+                }
+            },
+            _ => Some((local % self.dur) / self.dur)
+        }
+    }
+
+    /*
+     * How many repeats have been completed at document time t. Frozen at the very end of
+     * a repeat, that repeat is still the current one.
+     */
+    pub fn iteration(&self, t: f64) -> f64 {
+        if t < self.begin || self.dur <= 0.0 {
+            return 0.0;
+        }
+        let local = t - self.begin;
+        match self.active_duration() {
+            Some(active) if local >= active => ((active / self.dur).ceil() - 1.0).max(0.0),
+            _ => (local / self.dur).floor()
+        }
+    }
+}
+
+fn parse_list<T, F: Fn(&str) -> Option<T>>(s: &str, f: F) -> Option<Vec<T>> {
+    s.split(';').map(|p| p.trim()).filter(|p| !p.is_empty()).map(f).collect()
+}
+
+fn parse_value(s: &str) -> Option<AnimValue> {
+    f64::from_str(s.trim()).ok().map(AnimValue::Number)
+        .or_else(|| Rgba::parse(s).map(AnimValue::Color))
+}
+
+fn parse_motion_rotate(s: Option<&String>) -> MotionRotate {
+    match s.map(|s| s.trim()) {
+        Some("auto") => MotionRotate::Auto,
+        Some("auto-reverse") => MotionRotate::AutoReverse,
+        Some(v) => MotionRotate::Fixed(f64::from_str(v).unwrap_or(0.0)),
+        None => MotionRotate::Fixed(0.0)
+    }
+}
+
+/*
+ * Collapses values / from / to / by into a single list of keyframes. "to" and "by"
+ * animations without a "from" start from `base`, the value underneath the animation.
+ */
+fn keyframes<T: Clone, F>(attrs: &HashMap<String, String>, base: Option<T>, parse: F,
+                          add: &Fn(&T, &T) -> Option<T>) -> Option<Vec<T>>
+    where F: Fn(&str) -> Option<T> {
+    if let Some(values) = attrs.get("values") {
+        return parse_list(values, |v| parse(v));
+    }
+    let from = attrs.get("from").and_then(|f| parse(f)).or(base);
+    match (from, attrs.get("to"), attrs.get("by")) {
+        (Some(f), Some(to), _) => parse(to).map(|t| vec![f, t]),
+        (Some(f), None, Some(by)) => parse(by).and_then(|b| add(&f, &b)).map(|t| vec![f, t]),
+        _ => None
+    }
+}
+
+/*
+ * The arguments of one kind of transform that make up as much of `t` as it can, as
+ * the starting point of a "to" or "by" <animateTransform>.
+ */
+fn transform_base(ttype: TransformType, t: &Transform) -> Vec<f64> {
+    match ttype {
+        TransformType::Translate => vec![t.e, t.f],
+        TransformType::Scale => vec![(t.a * t.a + t.b * t.b).sqrt(), (t.c * t.c + t.d * t.d).sqrt()],
+        TransformType::Rotate => vec![t.b.atan2(t.a) * 180.0 / PI],
+        TransformType::SkewX => vec![t.c.atan2(t.d) * 180.0 / PI],
+        TransformType::SkewY => vec![t.b.atan2(t.a) * 180.0 / PI]
+    }
+}
+
+impl Animation {
+    /*
+     * Builds an animation from the attributes of an <animate>, <animateTransform> or
+     * <animateMotion> element. `motion_path` is the path of an <mpath> child, if any.
+     */
+    pub fn from_element(element: &str, attrs: &HashMap<String, String>, target: usize,
+                        target_transform: &Transform, motion_path: Option<&[PathElem]>) -> Option<Animation> {
+        let kind = match element {
+            "animate" => {
+                let name = match attrs.get("attributeName") {
+                    Some(n) => n.clone(),
+                    None => return None
+                };
+                let base = if name.ends_with("opacity") { Some(AnimValue::Number(1.0)) } else { None };
+                let values = keyframes(attrs, base, parse_value, &|a, b| match (*a, *b) {
+                    (AnimValue::Number(x), AnimValue::Number(y)) => Some(AnimValue::Number(x + y)),
+                    _ => None
+                });
+                values.map(|v| AnimKind::Attribute { name: name, values: v })
+            },
+            "animateTransform" => {
+                let ttype = match attrs.get("type").map(|s| s.as_str()).unwrap_or("translate") {
+                    "translate" => TransformType::Translate,
+                    "scale" => TransformType::Scale,
+                    "rotate" => TransformType::Rotate,
+                    "skewX" => TransformType::SkewX,
+                    "skewY" => TransformType::SkewY,
+                    _ => return None
+                };
+                let base = Some(transform_base(ttype, target_transform));
+                let values = keyframes(attrs, base, |v| Some(geom::parse_numbers(v)),
+                    &|a: &Vec<f64>, b: &Vec<f64>| Some(a.iter().zip(b.iter())
+                        .map(|(x, y)| x + y).collect()));
+                // A rotation's centre isn't part of the base, so it comes from the end value.
+                let values = values.map(|mut v| {
+                    if v.len() == 2 && v[0].len() < v[1].len() {
+                        let rest = v[1][v[0].len()..].to_vec();
+                        v[0].extend(rest);
+                    }
+                    v
+                });
+                values.map(|v| AnimKind::Transform { ttype: ttype, values: v })
+            },
+            "animateMotion" => {
+                let path = match (motion_path, attrs.get("path")) {
+                    (Some(p), _) => Some(PathMeasure::from_path(p, 0.5)).map(|m| {
+                        let end = m.length();
+                        (m, vec![0.0, end])
+                    }),
+                    (None, Some(d)) => Some(PathMeasure::from_path(&spath::read_path(d), 0.5)).map(|m| {
+                        let end = m.length();
+                        (m, vec![0.0, end])
+                    }),
+                    (None, None) => keyframes(attrs, None, |v| {
+                        let n = geom::parse_numbers(v);
+                        if n.len() == 2 { Some((n[0], n[1])) } else { None }
+                    }, &|a: &(f64, f64), b: &(f64, f64)| Some((a.0 + b.0, a.1 + b.1)))
+                        .map(|pts| {
+                            let mut stops = vec![0.0];
+                            for w in pts.windows(2) {
+                                let last = stops[stops.len() - 1];
+                                stops.push(last + (w[1].0 - w[0].0).hypot(w[1].1 - w[0].1));
+                            }
+                            (PathMeasure::new(vec![pts]), stops)
+                        })
+                };
+                path.map(|(p, stops)| AnimKind::Motion {
+                    path: p,
+                    rotate: parse_motion_rotate(attrs.get("rotate")),
+                    stops: stops
+                })
+            },
+            _ => None
+        };
+
+        let dur = attrs.get("dur").and_then(|d| parse_clock(d));
+        let timing = Timing {
+            begin: attrs.get("begin").and_then(|b| b.split(';').filter_map(parse_clock).next())
+                .unwrap_or(0.0),
+            dur: dur.unwrap_or(0.0),
+            repeat_count: attrs.get("repeatCount").and_then(|r| match r.trim() {
+                "indefinite" => Some(RepeatCount::Indefinite),
+                n => f64::from_str(n).ok().map(RepeatCount::Count)
+            }),
+            repeat_dur: attrs.get("repeatDur").and_then(|r| parse_clock(r)),
+            fill: match attrs.get("fill").map(|f| f.trim()) {
+                Some("freeze") => Fill::Freeze,
+                _ => Fill::Remove
+            }
+        };
+        let default_mode = if element == "animateMotion" { CalcMode::Paced } else { CalcMode::Linear };
+        let calc_mode = match attrs.get("calcMode").map(|c| c.trim()) {
+            Some("discrete") => CalcMode::Discrete,
+            Some("linear") => CalcMode::Linear,
+            Some("paced") => CalcMode::Paced,
+            Some("spline") => CalcMode::Spline,
+            _ => default_mode
+        };
+        let key_times = attrs.get("keyTimes")
+            .and_then(|k| parse_list(k, |v| f64::from_str(v).ok()))
+            .unwrap_or(Vec::new());
+        let key_splines = attrs.get("keySplines")
+            .and_then(|k| parse_list(k, |v| {
+                let n = geom::parse_numbers(v);
+                if n.len() == 4 { Some(CubicBezier::new(n[0], n[1], n[2], n[3])) } else { None }
+            }))
+            .unwrap_or(Vec::new());
+
+        kind.map(|k| Animation {
+            target: target,
+            kind: k,
+            timing: timing,
+            calc_mode: calc_mode,
+            key_times: key_times,
+            key_splines: key_splines,
+            additive: attrs.get("additive").map(|a| a.trim() == "sum").unwrap_or(false),
+            accumulate: attrs.get("accumulate").map(|a| a.trim() == "sum").unwrap_or(false)
+        })
+    }
+
+    fn value_count(&self) -> usize {
+        match self.kind {
+            AnimKind::Attribute { ref values, .. } => values.len(),
+            AnimKind::Transform { ref values, .. } => values.len(),
+            AnimKind::Motion { ref stops, .. } => stops.len()
+        }
+    }
+
+    /*
+     * Key times for paced animations, spaced by the distance between successive values.
+     */
+    fn paced_times(&self) -> Option<Vec<f64>> {
+        let dists: Vec<f64> = match self.kind {
+            AnimKind::Attribute { ref values, .. } => values.windows(2).map(|w| match (w[0], w[1]) {
+                (AnimValue::Number(a), AnimValue::Number(b)) => (b - a).abs(),
+                (AnimValue::Color(a), AnimValue::Color(b)) => {
+                    let d = |x: u8, y: u8| (x as f64 - y as f64).powi(2);
+                    (d(a.r, b.r) + d(a.g, b.g) + d(a.b, b.b)).sqrt()
+                },
+                _ => 0.0
+            }).collect(),
+            AnimKind::Transform { ref values, .. } => values.windows(2).map(|w| {
+                w[0].iter().zip(w[1].iter()).map(|(a, b)| (b - a).powi(2)).sum::<f64>().sqrt()
+            }).collect(),
+            AnimKind::Motion { ref stops, .. } => stops.windows(2).map(|w| w[1] - w[0]).collect()
+        };
+        let total: f64 = dists.iter().sum();
+        if total <= 0.0 {
+            return None;
+        }
+        let mut acc = 0.0;
+        let mut times = vec![0.0];
+        for d in dists {
+            acc += d;
+            times.push(acc / total);
+        }
+        Some(times)
+    }
+
+    /*
+     * Maps simple duration progress onto a keyframe interval, returning the index of the
+     * interval's first value and the (eased) position within it.
+     */
+    fn interval(&self, p: f64) -> (usize, f64) {
+        let n = self.value_count();
+        if n < 2 {
+            return (0, 0.0);
+        }
+        let times = if self.key_times.len() == n {
+            self.key_times.clone()
+        } else if self.calc_mode == CalcMode::Paced {
+            self.paced_times().unwrap_or((0..n).map(|i| i as f64 / (n - 1) as f64).collect())
+        } else if self.calc_mode == CalcMode::Discrete {
+            (0..n).map(|i| i as f64 / n as f64).collect()
+        } else {
+            (0..n).map(|i| i as f64 / (n - 1) as f64).collect()
+        };
+
+        if self.calc_mode == CalcMode::Discrete {
+            let i = times.iter().rposition(|&k| k <= p).unwrap_or(0);
+            return (i, 0.0);
+        }
+
+        let i = match times.windows(2).position(|w| p >= w[0] && p <= w[1]) {
+            Some(i) => i,
+            None => return if p <= times[0] { (0, 0.0) } else { (n - 2, 1.0) }
+        };
+        let span = times[i + 1] - times[i];
+        let local = if span > 0.0 { (p - times[i]) / span } else { 1.0 };
+        let eased = if self.calc_mode == CalcMode::Spline {
+            self.key_splines.get(i).map(|s| s.eval(local)).unwrap_or(local)
+        } else {
+            local
+        };
+        (i, eased)
+    }
+
+    /*
+     * Value of an attribute animation at document time t.
+     */
+    pub fn sample_value(&self, t: f64) -> Option<AnimValue> {
+        let values = match self.kind {
+            AnimKind::Attribute { ref values, .. } => values,
+            _ => return None
+        };
+        let value = self.timing.progress(t).and_then(|p| {
+            let (i, u) = self.interval(p);
+            if self.calc_mode == CalcMode::Discrete || i + 1 >= values.len() {
+                return values.get(i).cloned();
+            }
+            Some(match (values[i], values[i + 1]) {
+                (AnimValue::Number(a), AnimValue::Number(b)) => AnimValue::Number(a + (b - a) * u),
+                (AnimValue::Color(a), AnimValue::Color(b)) => AnimValue::Color(a.lerp(&b, u)),
+                (a, _) => a
+            })
+        });
+        match (value, values.last()) {
+            (Some(AnimValue::Number(v)), Some(&AnimValue::Number(last))) if self.accumulate =>
+                Some(AnimValue::Number(v + last * self.timing.iteration(t))),
+            _ => value
+        }
+    }
+
+    /*
+     * The transform contributed by an <animateTransform> or <animateMotion> at time t.
+     */
+    pub fn sample_transform(&self, t: f64) -> Option<Transform> {
+        let p = match self.timing.progress(t) {
+            Some(p) => p,
+            None => return None
+        };
+        match self.kind {
+            AnimKind::Transform { ttype, ref values } => {
+                let (i, u) = self.interval(p);
+                let mut v: Vec<f64> = if self.calc_mode == CalcMode::Discrete || i + 1 >= values.len() {
+                    values[i].clone()
+                } else {
+                    values[i].iter().zip(values[i + 1].iter()).map(|(a, b)| a + (b - a) * u).collect()
+                };
+                if self.accumulate {
+                    let n = self.timing.iteration(t);
+                    for (x, last) in v.iter_mut().zip(values[values.len() - 1].iter()) {
+                        *x += last * n;
+                    }
+                }
+                let arg = |i: usize, def: f64| v.get(i).cloned().unwrap_or(def);
+                Some(match ttype {
+                    TransformType::Translate => Transform::translate(arg(0, 0.0), arg(1, 0.0)),
+                    TransformType::Scale => Transform::scale(arg(0, 1.0), arg(1, arg(0, 1.0))),
+                    TransformType::Rotate => Transform::rotate_about(arg(0, 0.0), arg(1, 0.0), arg(2, 0.0)),
+                    TransformType::SkewX => Transform::skew_x(arg(0, 0.0)),
+                    TransformType::SkewY => Transform::skew_y(arg(0, 0.0))
+                })
+            },
+            AnimKind::Motion { ref path, rotate, ref stops } => {
+                let d = match self.calc_mode {
+                    CalcMode::Paced if self.key_times.len() != stops.len() => p * path.length(),
+                    _ => {
+                        let (i, u) = self.interval(p);
+                        match (stops.get(i), stops.get(i + 1)) {
+                            (Some(&a), Some(&b)) if self.calc_mode != CalcMode::Discrete => a + (b - a) * u,
+                            (Some(&a), _) => a,
+                            _ => 0.0
+                        }
+                    }
+                };
+                path.point_at(d).map(|(x, y, angle)| {
+                    let degrees = match rotate {
+                        MotionRotate::Fixed(d) => d,
+                        MotionRotate::Auto => angle * 180.0 / PI,
+                        MotionRotate::AutoReverse => angle * 180.0 / PI + 180.0
+                    };
+                    Transform::translate(x, y).then(&Transform::rotate(degrees))
+                })
+            },
+            AnimKind::Attribute { .. } => None
+        }
+    }
+}
+
+/*
+ * The animated state of every node in a document at one instant. Transforms and
+ * opacities already include the contributions of the node's ancestors.
+ */
+pub struct FrameState {
+    pub transforms: Vec<Transform>,
+    pub opacity: Vec<f64>,
+    pub fill: Vec<Option<Rgba>>,
+    pub stroke: Vec<Option<Rgba>>,
+    pub stroke_width: Vec<f64>
+}
+
+pub fn evaluate(doc: &SvgDocument, t: f64) -> FrameState {
+    let n = doc.nodes.len();
+    let mut local: Vec<Transform> = doc.nodes.iter().map(|node| node.transform).collect();
+    let mut motion = vec![Transform::identity(); n];
+    let mut opacity: Vec<f64> = doc.nodes.iter().map(|node| node.opacity).collect();
+    let mut style: Vec<Style> = doc.nodes.iter().map(|node| node.style).collect();
+
+    for anim in &doc.animations {
+        let i = anim.target;
+        if i >= n {
+            continue;
+        }
+        match anim.kind {
+            AnimKind::Attribute { ref name, .. } => {
+                // additive="sum" adds to the element's own value, or to the default.
+                let add = |base: Option<f64>, def: f64, v: f64| {
+                    if anim.additive { base.unwrap_or(def) + v } else { v }
+                };
+                let s = &mut style[i];
+                match (name.as_str(), anim.sample_value(t)) {
+                    ("opacity", Some(AnimValue::Number(v))) => opacity[i] = add(Some(opacity[i]), 1.0, v),
+                    ("fill-opacity", Some(AnimValue::Number(v))) =>
+                        s.fill_opacity = Some(add(s.fill_opacity, 1.0, v)),
+                    ("stroke-opacity", Some(AnimValue::Number(v))) =>
+                        s.stroke_opacity = Some(add(s.stroke_opacity, 1.0, v)),
+                    ("stroke-width", Some(AnimValue::Number(v))) =>
+                        s.stroke_width = Some(add(s.stroke_width, 1.0, v)),
+                    ("fill", Some(AnimValue::Color(c))) => s.fill = Some(Some(c)),
+                    ("stroke", Some(AnimValue::Color(c))) => s.stroke = Some(Some(c)),
+                    _ => ()
+                }
+            },
+            AnimKind::Transform { .. } => if let Some(at) = anim.sample_transform(t) {
+                local[i] = if anim.additive { local[i].then(&at) } else { at };
+            },
+            AnimKind::Motion { .. } => if let Some(mt) = anim.sample_transform(t) {
+                motion[i] = motion[i].then(&mt);
+            }
+        }
+    }
+
+    // Parents always precede their children, so a single forward pass is enough.
+    let mut transforms = Vec::<Transform>::with_capacity(n);
+    for (i, node) in doc.nodes.iter().enumerate() {
+        let own = motion[i].then(&local[i]);
+        let world = match node.parent {
+            Some(p) => {
+                opacity[i] *= opacity[p];
+                style[i] = style[i].inherit(&style[p]);
+                transforms[p].then(&own)
+            },
+            None => own
+        };
+        transforms.push(world);
+    }
+
+    let fill = doc.nodes.iter().zip(style.iter())
+        .map(|(node, s)| node.shape.as_ref().and_then(|_| s.fill())).collect();
+    let stroke = doc.nodes.iter().zip(style.iter())
+        .map(|(node, s)| node.shape.as_ref().and_then(|_| s.stroke())).collect();
+    let stroke_width = style.iter().map(|s| s.stroke_width()).collect();
+
+    FrameState {
+        transforms: transforms,
+        opacity: opacity,
+        fill: fill,
+        stroke: stroke,
+        stroke_width: stroke_width
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+    use std::process;
+    use svg;
+
+    fn animation(element: &str, attrs: &[(&str, &str)]) -> Animation {
+        let attrs: HashMap<String, String> = attrs.iter()
+            .map(|&(k, v)| (k.to_string(), v.to_string())).collect();
+        Animation::from_element(element, &attrs, 0, &Transform::identity(), None).unwrap()
+    }
+
+    fn number(anim: &Animation, t: f64) -> Option<f64> {
+        match anim.sample_value(t) {
+            Some(AnimValue::Number(v)) => Some(v),
+            _ => None
+        }
+    }
+
+    fn close(a: Option<f64>, b: f64) -> bool {
+        a.map(|a| (a - b).abs() < 1e-6).unwrap_or(false)
+    }
+
+    fn document(name: &str, text: &str) -> SvgDocument {
+        let path = env::temp_dir().join(format!("tycoon-smil-{}-{}.svg", name, process::id()));
+        File::create(&path).unwrap().write_all(text.as_bytes()).unwrap();
+        let doc = svg::get_document(&path).unwrap();
+        let _ = ::std::fs::remove_file(&path);
+        doc
+    }
+
+    #[test]
+    fn key_times_and_splines_shape_the_interpolation() {
+        let timed = animation("animate", &[("attributeName", "x"), ("values", "0;10;20"),
+                                           ("keyTimes", "0;0.8;1"), ("dur", "1s")]);
+        assert!(close(number(&timed, 0.4), 5.0));
+        assert!(close(number(&timed, 0.9), 15.0));
+
+        let eased = animation("animate", &[("attributeName", "x"), ("values", "0;10"),
+                                           ("calcMode", "spline"), ("keyTimes", "0;1"),
+                                           ("keySplines", "0.5 0 1 0.5"), ("dur", "1s")]);
+        let expected = CubicBezier::new(0.5, 0.0, 1.0, 0.5).eval(0.5) * 10.0;
+        assert!(close(number(&eased, 0.5), expected));
+        assert!(number(&eased, 0.5).unwrap() < 5.0);
+    }
+
+    #[test]
+    fn discrete_holds_and_paced_goes_by_distance() {
+        let discrete = animation("animate", &[("attributeName", "x"), ("values", "0;10;30"),
+                                              ("calcMode", "discrete"), ("dur", "3s")]);
+        assert!(close(number(&discrete, 0.5), 0.0));
+        assert!(close(number(&discrete, 1.5), 10.0));
+        assert!(close(number(&discrete, 2.9), 30.0));
+
+        // 10 then 30 units: a quarter of the way through is reached at a quarter of the time.
+        let paced = animation("animate", &[("attributeName", "x"), ("values", "0;10;40"),
+                                           ("calcMode", "paced"), ("dur", "1s")]);
+        assert!(close(number(&paced, 0.25), 10.0));
+        assert!(close(number(&paced, 0.5), 20.0));
+    }
+
+    #[test]
+    fn timing_follows_begin_dur_repeats_and_fill() {
+        let removed = animation("animate", &[("attributeName", "x"), ("from", "0"), ("to", "10"),
+                                             ("begin", "1s"), ("dur", "2s"), ("repeatCount", "2")]);
+        assert_eq!(number(&removed, 0.5), None);
+        assert!(close(number(&removed, 2.0), 5.0));
+        assert!(close(number(&removed, 4.0), 5.0));
+        assert_eq!(number(&removed, 5.5), None);
+
+        let frozen = animation("animate", &[("attributeName", "x"), ("from", "0"), ("to", "10"),
+                                            ("dur", "2s"), ("repeatCount", "1.5"), ("fill", "freeze")]);
+        assert!(close(number(&frozen, 10.0), 5.0));
+
+        let by_dur = animation("animate", &[("attributeName", "x"), ("from", "0"), ("to", "10"),
+                                            ("dur", "1s"), ("repeatDur", "2.5s"), ("fill", "freeze")]);
+        assert!(close(number(&by_dur, 9.0), 5.0));
+    }
+
+    #[test]
+    fn accumulate_carries_on_from_each_repeat() {
+        let anim = animation("animate", &[("attributeName", "x"), ("from", "0"), ("to", "10"),
+                                          ("dur", "1s"), ("repeatCount", "3"), ("accumulate", "sum"),
+                                          ("fill", "freeze")]);
+        assert!(close(number(&anim, 0.5), 5.0));
+        assert!(close(number(&anim, 1.5), 15.0));
+        assert!(close(number(&anim, 2.5), 25.0));
+        assert!(close(number(&anim, 9.0), 30.0));
+
+        let spin = animation("animateTransform", &[("type", "rotate"), ("from", "0"), ("to", "90"),
+                                                   ("dur", "1s"), ("repeatCount", "indefinite"),
+                                                   ("accumulate", "sum")]);
+        let (x, y) = spin.sample_transform(1.5).unwrap().apply(1.0, 0.0);
+        let expected = 135.0f64.to_radians();
+        assert!((x - expected.cos()).abs() < 1e-6 && (y - expected.sin()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn motion_values_follow_key_times() {
+        let anim = animation("animateMotion", &[("values", "0,0; 10,0; 10,30"), ("calcMode", "linear"),
+                                                ("keyTimes", "0;0.5;1"), ("dur", "1s")]);
+        assert_eq!(anim.value_count(), 3);
+        let at = |t: f64| anim.sample_transform(t).unwrap().apply(0.0, 0.0);
+        let (x, y) = at(0.25);
+        assert!((x - 5.0).abs() < 1e-6 && y.abs() < 1e-6);
+        let (x, y) = at(0.75);
+        assert!((x - 10.0).abs() < 1e-6 && (y - 15.0).abs() < 1e-6);
+
+        let discrete = animation("animateMotion", &[("values", "0,0; 10,0; 10,30"),
+                                                    ("calcMode", "discrete"), ("dur", "3s")]);
+        let (x, y) = discrete.sample_transform(1.5).unwrap().apply(0.0, 0.0);
+        assert!((x - 10.0).abs() < 1e-6 && y.abs() < 1e-6);
+    }
+
+    #[test]
+    fn additive_attributes_add_to_the_base_value() {
+        let doc = document("additive", r##"<svg width="10" height="10">
+            <path d="M0 0 L1 0 L1 1 Z" stroke="#000" stroke-width="2">
+                <animate attributeName="stroke-width" from="0" to="4" dur="1s" additive="sum"/>
+            </path>
+        </svg>"##);
+        let state = evaluate(&doc, 0.5);
+        assert!((state.stroke_width[0] - 4.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn shapes_inherit_paint_from_their_groups() {
+        let doc = document("inherit", r##"<svg width="10" height="10">
+            <g fill="#ff0000" stroke="#0000ff" fill-opacity="0.5">
+                <path d="M0 0 L1 0 L1 1 Z"/>
+                <path d="M0 0 L1 0 L1 1 Z" fill="#00ff00"/>
+                <animate attributeName="fill" from="#ff0000" to="#ffffff" dur="1s" fill="freeze"/>
+            </g>
+        </svg>"##);
+        let shape = doc.nodes[1].shape.as_ref().unwrap();
+        assert_eq!(shape.fill, Some(Rgba::rgb(255, 0, 0).with_alpha(0.5)));
+        assert_eq!(shape.stroke, Some(Rgba::rgb(0, 0, 255)));
+        let own = doc.nodes[2].shape.as_ref().unwrap();
+        assert_eq!(own.fill, Some(Rgba::rgb(0, 255, 0).with_alpha(0.5)));
+
+        let state = evaluate(&doc, 2.0);
+        assert_eq!(state.fill[0], None);
+        assert_eq!(state.fill[1], Some(Rgba::rgb(255, 255, 255).with_alpha(0.5)));
+        assert_eq!(state.fill[2], Some(Rgba::rgb(0, 255, 0).with_alpha(0.5)));
+        assert_eq!(state.stroke[1], Some(Rgba::rgb(0, 0, 255)));
+    }
+}
@@ -0,0 +1,191 @@
+/*
+ * Golden image comparisons for render regression checks. A ditty is rendered with the
+ * software backend and compared against a reference PNG, allowing each channel of each
+ * pixel to differ by a tolerance.
+ *
+ * When a comparison fails the rendered image is written next to the reference as
+ * NAME.actual.png, along with NAME.diff.png highlighting the differing pixels in red.
+ * Setting TYCOON_BLESS=1 in the environment rewrites the reference instead.
+ */
+
+use std::env;
+use std::path::Path;
+use std::path::PathBuf;
+
+use backend::Backend;
+use color::Rgba;
+use ditty::Ditty;
+use image::Image;
+use png;
+use software::SoftwareBackend;
+
+pub struct Comparison {
+    pub mismatched: usize,
+    pub max_delta: u8,
+    pub diff: Image
+}
+
+pub fn compare(actual: &Image, expected: &Image, tolerance: u8) -> Result<Comparison, String> {
+    if actual.width != expected.width || actual.height != expected.height {
+        return Err(format!("size {}x{} does not match reference {}x{}",
+                           actual.width, actual.height, expected.width, expected.height));
+    }
+    let mut diff = Image::new(actual.width, actual.height);
+    let mut mismatched = 0;
+    let mut max_delta = 0;
+    for y in 0..actual.height {
+        for x in 0..actual.width {
+            let (a, e) = (actual.get(x, y), expected.get(x, y));
+            let d = |p: u8, q: u8| if p > q { p - q } else { q - p };
+            let delta = d(a.r, e.r).max(d(a.g, e.g)).max(d(a.b, e.b)).max(d(a.a, e.a));
+            max_delta = max_delta.max(delta);
+            if delta > tolerance {
+                mismatched += 1;
+                diff.put(x, y, Rgba::rgb(255, 0, 0));
+            } else {
+                let grey = ((a.r as u32 + a.g as u32 + a.b as u32) / 12) as u8;
+                diff.put(x, y, Rgba::rgb(grey, grey, grey));
+            }
+        }
+    }
+    Ok(Comparison { mismatched: mismatched, max_delta: max_delta, diff: diff })
+}
+
+fn sibling(reference: &Path, suffix: &str) -> PathBuf {
+    let stem = reference.file_stem().map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or(String::new());
+    reference.with_file_name(format!("{}.{}.png", stem, suffix))
+}
+
+/*
+ * Checks an image against the reference PNG at `reference`, failing if more than
+ * `max_mismatched` pixels differ by more than `tolerance`.
+ */
+pub fn check<P: AsRef<Path>>(actual: &Image, reference: P, tolerance: u8,
+                             max_mismatched: usize) -> Result<(), String> {
+    let bless = env::var("TYCOON_BLESS").map(|v| v == "1").unwrap_or(false);
+    check_against(actual, reference.as_ref(), tolerance, max_mismatched, bless)
+}
+
+fn check_against(actual: &Image, reference: &Path, tolerance: u8, max_mismatched: usize,
+                 bless: bool) -> Result<(), String> {
+    if bless {
+        return png::save(actual, reference);
+    }
+    let expected = try!(png::load(reference)
+        .map_err(|e| format!("{}: {}", reference.display(), e)));
+    let result = match compare(actual, &expected, tolerance) {
+        Ok(ref c) if c.mismatched <= max_mismatched => return Ok(()),
+        Ok(c) => {
+            try!(png::save(&c.diff, sibling(reference, "diff")));
+            Err(format!("{}: {} pixels differ (largest difference {})",
+                        reference.display(), c.mismatched, c.max_delta))
+        },
+        Err(e) => Err(format!("{}: {}", reference.display(), e))
+    };
+    try!(png::save(actual, sibling(reference, "actual")));
+    result
+}
+
+/*
+ * Renders a single frame of a ditty into a fresh software backend.
+ */
+pub fn render<T: Ditty>(ditty: &mut T, width: u32, height: u32, background: Rgba) -> Image {
+    let mut backend = SoftwareBackend::new(width, height);
+    ditty.init(&mut backend);
+    backend.clear(background);
+    ditty.render(&mut backend, width, height, 0.0);
+    backend.image().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    use super::*;
+    use actions::ActionMap;
+    use color::Rgba;
+    use ditty::Ditty;
+    use ditty::IntroDitty;
+    use ditty::PathDitty;
+    use ditty::PauseDitty;
+    use image::Image;
+    use svg;
+    use svg::SvgDocument;
+
+    /*
+     * Reference images live in snapshots/ at the top of the crate.
+     */
+    fn reference(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("snapshots").join(name)
+    }
+
+    fn logo() -> SvgDocument {
+        svg::get_document(Path::new(env!("CARGO_MANIFEST_DIR")).join("assets").join("logo.svg")).unwrap()
+    }
+
+    fn white() -> Rgba {
+        Rgba::rgb(255, 255, 255)
+    }
+
+    #[test]
+    fn path_viewer() {
+        let mut ditty = PathDitty::new(logo().paths());
+        let image = render(&mut ditty, 320, 240, Rgba::rgb(0, 0, 0));
+        check(&image, reference("path_ditty.png"), 2, 0).unwrap();
+    }
+
+    #[test]
+    fn intro_part_way_through_tracing() {
+        let mut ditty = IntroDitty::new(&logo());
+        // `render` draws as of the start of the last update, so finish with an empty one.
+        ditty.update(1.0);
+        ditty.update(0.0);
+        let image = render(&mut ditty, 320, 240, white());
+        check(&image, reference("intro_tracing.png"), 2, 0).unwrap();
+    }
+
+    #[test]
+    fn intro_finished() {
+        let mut ditty = IntroDitty::new(&logo());
+        let end = ditty.duration();
+        ditty.update(end);
+        ditty.update(0.0);
+        let image = render(&mut ditty, 320, 240, white());
+        check(&image, reference("intro_finished.png"), 2, 0).unwrap();
+    }
+
+    #[test]
+    fn pause_menu() {
+        let mut ditty = PauseDitty::new(ActionMap::defaults());
+        let image = render(&mut ditty, 320, 240, Rgba::rgb(40, 80, 120));
+        check(&image, reference("pause_menu.png"), 2, 0).unwrap();
+    }
+
+    #[test]
+    fn compare_allows_the_tolerance() {
+        let expected = Image::filled(4, 4, Rgba::rgb(100, 100, 100));
+        let mut actual = expected.clone();
+        actual.put(0, 0, Rgba::rgb(103, 100, 100));
+        actual.put(1, 0, Rgba::rgb(100, 90, 100));
+        let c = compare(&actual, &expected, 3).unwrap();
+        assert_eq!((c.mismatched, c.max_delta), (1, 10));
+        assert_eq!(c.diff.get(1, 0), Rgba::rgb(255, 0, 0));
+        assert!(compare(&Image::new(4, 3), &expected, 255).is_err());
+    }
+
+    #[test]
+    fn failed_check_writes_actual_and_diff() {
+        let dir = env::temp_dir().join(format!("tycoon-snapshot-{}", ::std::process::id()));
+        ::std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("square.png");
+        png::save(&Image::filled(2, 2, white()), &path).unwrap();
+        assert!(check_against(&Image::filled(2, 2, white()), &path, 0, 0, false).is_ok());
+        assert!(check_against(&Image::filled(2, 2, Rgba::rgb(0, 0, 0)), &path, 0, 0, false).is_err());
+        assert!(dir.join("square.actual.png").exists());
+        assert!(dir.join("square.diff.png").exists());
+        let _ = ::std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,249 @@
+/*
+ * A backend that renders into an in-memory RGBA image, so ditties can be exercised
+ * without a window. Nothing is anti-aliased and textures are sampled nearest-neighbour.
+ */
+
+use backend::Backend;
+use backend::DrawState;
+use backend::Rect;
+use backend::TextureId;
+use color::Rgba;
+use geom::Polyline;
+use geom::Transform;
+use image::Image;
+use raster;
+use raster::FillRule;
+
+pub struct SoftwareBackend {
+    target: Image,
+    textures: Vec<Option<Image>>,
+    state: DrawState
+}
+
+impl SoftwareBackend {
+    pub fn new(width: u32, height: u32) -> SoftwareBackend {
+        SoftwareBackend {
+            target: Image::new(width, height),
+            textures: Vec::new(),
+            state: DrawState::new()
+        }
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.target
+    }
+
+    /*
+     * Replaces the target with a blank one of a new size. Textures are kept.
+     */
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.target = Image::new(width, height);
+    }
+
+    fn clip_box(&self) -> (i32, i32, i32, i32) {
+        self.state.clip_box(self.target.width, self.target.height)
+    }
+
+    fn plot(&mut self, x: i32, y: i32, colour: Rgba) {
+        let (x0, y0, x1, y1) = self.clip_box();
+        if x >= x0 && x < x1 && y >= y0 && y < y1 {
+            self.target.blend(x as u32, y as u32, colour);
+        }
+    }
+}
+
+impl Backend for SoftwareBackend {
+    fn size(&self) -> (u32, u32) {
+        (self.target.width, self.target.height)
+    }
+
+    fn clear(&mut self, colour: Rgba) {
+        self.target.fill(colour);
+    }
+
+    fn present(&mut self) {
+    }
+
+    fn read_pixels(&mut self) -> Result<Image, String> {
+        Ok(self.target.clone())
+    }
+
+    fn set_color(&mut self, colour: Rgba) {
+        self.state.colour = colour;
+    }
+
+    fn draw_line(&mut self, x0: f64, y0: f64, x1: f64, y1: f64) {
+        let t = self.state.transform();
+        let (ax, ay) = t.apply(x0, y0);
+        let (bx, by) = t.apply(x1, y1);
+        let colour = self.state.colour;
+        let mut points = Vec::new();
+        raster::line_points(ax, ay, bx, by, |x, y| points.push((x, y)));
+        for (x, y) in points {
+            self.plot(x, y, colour);
+        }
+    }
+
+    fn fill_rect(&mut self, x: f64, y: f64, w: f64, h: f64) {
+        self.fill_path(&[vec![(x, y), (x + w, y), (x + w, y + h), (x, y + h)]], FillRule::NonZero);
+    }
+
+    fn fill_path(&mut self, polys: &[Polyline], rule: FillRule) {
+        let t = self.state.transform();
+        let polys: Vec<Polyline> = polys.iter()
+            .map(|p| p.iter().map(|&(x, y)| t.apply(x, y)).collect())
+            .collect();
+        let colour = self.state.colour;
+        let target = &mut self.target;
+        raster::fill_spans(&polys, rule, self.state.clip_box(target.width, target.height),
+                           |y, x0, x1| {
+            for x in x0..x1 {
+                target.blend(x as u32, y as u32, colour);
+            }
+        });
+    }
+
+    fn create_texture(&mut self, image: &Image) -> Result<TextureId, String> {
+        self.textures.push(Some(image.clone()));
+        Ok(TextureId(self.textures.len() - 1))
+    }
+
+    fn update_texture(&mut self, tex: TextureId, x: u32, y: u32, image: &Image) -> Result<(), String> {
+        match self.textures.get_mut(tex.0) {
+            Some(&mut Some(ref mut target)) => {
+                target.blit(image, 0, 0, image.width, image.height, x, y);
+                Ok(())
+            },
+            _ => Err(format!("no texture {}", tex.0))
+        }
+    }
+
+    fn destroy_texture(&mut self, tex: TextureId) {
+        if let Some(slot) = self.textures.get_mut(tex.0) {
+            *slot = None;
+        }
+    }
+
+    fn texture_size(&self, tex: TextureId) -> Option<(u32, u32)> {
+        self.textures.get(tex.0).and_then(|t| t.as_ref()).map(|t| (t.width, t.height))
+    }
+
+    fn draw_texture(&mut self, tex: TextureId, src: Option<Rect>, x: f64, y: f64, w: f64, h: f64) {
+        let image = match self.textures.get(tex.0).and_then(|t| t.as_ref()) {
+            Some(image) => image,
+            None => return
+        };
+        let src = src.unwrap_or(Rect::new(0, 0, image.width, image.height));
+        if w <= 0.0 || h <= 0.0 || src.w == 0 || src.h == 0 {
+            return;
+        }
+        // Map every covered screen pixel back into the source rectangle.
+        let to_dst = self.state.transform()
+            .then(&Transform::translate(x, y))
+            .then(&Transform::scale(w / src.w as f64, h / src.h as f64));
+        let to_src = match to_dst.inverse() {
+            Some(inv) => inv,
+            None => return
+        };
+        let quad = self.state.quad(x, y, w, h);
+        let tint = self.state.tint;
+        let target = &mut self.target;
+        raster::fill_spans(&[quad], FillRule::NonZero,
+                           self.state.clip_box(target.width, target.height), |py, px0, px1| {
+            for px in px0..px1 {
+                let (u, v) = to_src.apply(px as f64 + 0.5, py as f64 + 0.5);
+                let (u, v) = (u.floor() as i32, v.floor() as i32);
+                if u >= 0 && v >= 0 && u < src.w as i32 && v < src.h as i32 {
+                    let sx = (src.x + u) as u32;
+                    let sy = (src.y + v) as u32;
+                    if sx < image.width && sy < image.height {
+                        target.blend(px as u32, py as u32, image.get(sx, sy).modulate(&tint));
+                    }
+                }
+            }
+        });
+    }
+
+    fn set_tint(&mut self, tint: Rgba) {
+        self.state.tint = tint;
+    }
+
+    fn tint(&self) -> Rgba {
+        self.state.tint
+    }
+
+    fn set_clip(&mut self, clip: Option<Rect>) {
+        self.state.clip = clip;
+    }
+
+    fn clip(&self) -> Option<Rect> {
+        self.state.clip
+    }
+
+    fn push_transform(&mut self, t: &Transform) {
+        self.state.push(t);
+    }
+
+    fn pop_transform(&mut self) {
+        self.state.pop();
+    }
+
+    fn transform(&self) -> Transform {
+        self.state.transform()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::Backend;
+    use backend::Rect;
+    use color::Rgba;
+    use geom::Transform;
+    use image::Image;
+
+    #[test]
+    fn draws_textures_scaled_tinted_and_clipped() {
+        let mut backend = SoftwareBackend::new(8, 8);
+        backend.clear(Rgba::rgb(0, 0, 0));
+        let mut image = Image::filled(2, 2, Rgba::rgb(255, 255, 255));
+        image.put(1, 1, Rgba::rgb(0, 0, 255));
+        let tex = backend.create_texture(&image).unwrap();
+        assert_eq!(backend.texture_size(tex), Some((2, 2)));
+
+        // Each texel becomes a 2x2 block, from (2, 2).
+        backend.draw_texture(tex, None, 2.0, 2.0, 4.0, 4.0);
+        let out = backend.read_pixels().unwrap();
+        assert_eq!(out.get(1, 1), Rgba::rgb(0, 0, 0));
+        assert_eq!(out.get(2, 2), Rgba::rgb(255, 255, 255));
+        assert_eq!(out.get(3, 3), Rgba::rgb(255, 255, 255));
+        assert_eq!(out.get(4, 4), Rgba::rgb(0, 0, 255));
+        assert_eq!(out.get(5, 5), Rgba::rgb(0, 0, 255));
+        assert_eq!(out.get(6, 6), Rgba::rgb(0, 0, 0));
+
+        backend.clear(Rgba::rgb(0, 0, 0));
+        backend.set_tint(Rgba::rgb(255, 0, 0));
+        backend.set_clip(Some(Rect::new(0, 0, 4, 8)));
+        backend.draw_texture(tex, None, 2.0, 2.0, 4.0, 4.0);
+        let out = backend.read_pixels().unwrap();
+        assert_eq!(out.get(3, 3), Rgba::rgb(255, 0, 0));
+        assert_eq!(out.get(4, 3), Rgba::rgb(0, 0, 0));
+    }
+
+    #[test]
+    fn fills_through_the_transform_stack() {
+        let mut backend = SoftwareBackend::new(8, 8);
+        backend.clear(Rgba::rgb(0, 0, 0));
+        backend.set_color(Rgba::rgb(0, 255, 0));
+        backend.push_transform(&Transform::translate(4.0, 0.0));
+        backend.push_transform(&Transform::scale(2.0, 2.0));
+        backend.fill_rect(0.0, 0.0, 1.0, 1.0);
+        backend.pop_transform();
+        backend.pop_transform();
+        backend.fill_rect(0.0, 6.0, 1.0, 1.0);
+        let out = backend.image().clone();
+        let green: Vec<(u32, u32)> = (0..64).map(|i| (i % 8, i / 8))
+            .filter(|&(x, y)| out.get(x, y) == Rgba::rgb(0, 255, 0)).collect();
+        assert_eq!(green, vec![(4, 0), (5, 0), (4, 1), (5, 1), (0, 6)]);
+    }
+}
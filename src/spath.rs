@@ -13,7 +13,7 @@
 use std::str::FromStr;
 use std::borrow::Borrow;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum PathElem {
     MoveTo { x: f64, y: f64 },
     LineTo { x: f64, y: f64 },
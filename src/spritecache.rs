@@ -0,0 +1,317 @@
+/*
+ * Caches SVG elements rasterised into textures, so vector art is flattened and filled
+ * once per zoom level instead of every frame.
+ *
+ * Requested scales are rounded up to the next half power of two, so a sprite is never
+ * magnified by more than about 1.4x and zooming only occasionally needs a re-raster.
+ * Rasters are packed into shared atlas pages by the atlas packer. Once the pages exceed
+ * the memory budget, the least recently used page is emptied and reused.
+ */
+
+use std::collections::HashMap;
+
+use atlas::Packer;
+use backend::Backend;
+use backend::Rect;
+use backend::TextureId;
+use color::Rgba;
+use geom;
+use image::Image;
+use raster::FillRule;
+use software::SoftwareBackend;
+use svg::SvgDocument;
+
+const PAGE_SIZE: u32 = 1024;
+const PADDING: u32 = 1;
+const SUPERSAMPLE: u32 = 2;
+
+/*
+ * A rasterised element. `origin` is the document position of the raster's top-left
+ * corner and `scale` the number of raster pixels per document unit.
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct Sprite {
+    pub texture: TextureId,
+    pub src: Rect,
+    pub origin: (f64, f64),
+    pub scale: f64
+}
+
+impl Sprite {
+    /*
+     * Draws the sprite in document coordinates under the backend's current transform.
+     */
+    pub fn draw(&self, backend: &mut Backend) {
+        backend.draw_texture(self.texture, Some(self.src), self.origin.0, self.origin.1,
+                             self.src.w as f64 / self.scale, self.src.h as f64 / self.scale);
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Key {
+    asset: String,
+    node: usize,
+    bucket: i32
+}
+
+struct Page {
+    texture: TextureId,
+    packer: Packer,
+    last_used: u64
+}
+
+impl Page {
+    fn alloc(&mut self, w: u32, h: u32) -> Option<(u32, u32)> {
+        self.packer.insert(w + PADDING, h + PADDING)
+    }
+
+    fn bytes(&self) -> usize {
+        let (w, h) = self.packer.size();
+        (w * h * 4) as usize
+    }
+}
+
+struct Entry {
+    sprite: Sprite,
+    page: usize
+}
+
+pub struct SpriteCache {
+    pages: Vec<Page>,
+    entries: HashMap<Key, Entry>,
+    budget: usize,
+    clock: u64
+}
+
+/*
+ * The bucket for a scale, counted in half powers of two.
+ */
+fn bucket_for(scale: f64) -> i32 {
+    (scale.max(1e-3).log2() * 2.0).ceil() as i32
+}
+
+fn bucket_scale(bucket: i32) -> f64 {
+    (bucket as f64 / 2.0).exp2()
+}
+
+/*
+ * Renders a node and its descendants at the given scale. Returns None for elements
+ * with nothing to draw.
+ */
+pub fn rasterize(doc: &SvgDocument, node: usize, scale: f64) -> Option<(Image, (f64, f64))> {
+    let mut shapes = Vec::new();
+    for i in node..doc.nodes.len() {
+        if !doc.is_within(i, node) {
+            continue;
+        }
+        if let Some(ref shape) = doc.nodes[i].shape {
+            let transform = doc.world_transform(i);
+            let polys = geom::transform_polylines(&geom::flatten(&shape.path, 0.25 / scale), &transform);
+            // Strokes are outlined at their width in document units, but never thinner
+            // than a pixel.
+            let outline = shape.stroke.map(|_| {
+                geom::stroke(&polys, (shape.stroke_width * transform.scale_factor()).max(1.0 / scale))
+            });
+            shapes.push((polys, shape.fill, shape.stroke.and_then(|c| outline.map(|o| (c, o))),
+                         doc.world_opacity(i)));
+        }
+    }
+    let all: Vec<geom::Polyline> = shapes.iter()
+        .flat_map(|s| s.2.as_ref().map(|o| &o.1).unwrap_or(&s.0).iter().cloned()).collect();
+    let (x0, y0, x1, y1) = match geom::bounds(&all) {
+        Some(b) => b,
+        None => return None
+    };
+    let origin = ((x0 - 1.0 / scale).floor(), (y0 - 1.0 / scale).floor());
+    let width = ((x1 - origin.0) * scale).ceil() as u32 + 1;
+    let height = ((y1 - origin.1) * scale).ceil() as u32 + 1;
+
+    let ss = scale * SUPERSAMPLE as f64;
+    let mut backend = SoftwareBackend::new(width * SUPERSAMPLE, height * SUPERSAMPLE);
+    backend.push_transform(&geom::Transform::scale(ss, ss)
+        .then(&geom::Transform::translate(-origin.0, -origin.1)));
+    for &(ref polys, fill, ref stroke, opacity) in &shapes {
+        if let Some(c) = fill {
+            backend.set_color(c.with_alpha(opacity));
+            backend.fill_path(polys, FillRule::NonZero);
+        }
+        if let Some((c, ref outline)) = *stroke {
+            backend.set_color(c.with_alpha(opacity));
+            backend.fill_path(outline, FillRule::NonZero);
+        }
+    }
+    Some((backend.image().downsample(SUPERSAMPLE), origin))
+}
+
+impl SpriteCache {
+    pub fn new(budget_bytes: usize) -> SpriteCache {
+        SpriteCache {
+            pages: Vec::new(),
+            entries: HashMap::new(),
+            budget: budget_bytes,
+            clock: 0
+        }
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.pages.iter().map(|p| p.bytes()).sum()
+    }
+
+    /*
+     * Returns the sprite for a node of the document loaded as `asset`, rasterising it if
+     * there is no cached copy at a suitable scale.
+     */
+    pub fn get(&mut self, backend: &mut Backend, asset: &str, doc: &SvgDocument, node: usize,
+               scale: f64) -> Option<Sprite> {
+        self.clock += 1;
+        let key = Key { asset: asset.to_string(), node: node, bucket: bucket_for(scale) };
+        if let Some(entry) = self.entries.get(&key) {
+            self.pages[entry.page].last_used = self.clock;
+            return Some(entry.sprite);
+        }
+
+        let scale = bucket_scale(key.bucket);
+        let (image, origin) = match rasterize(doc, node, scale) {
+            Some(r) => r,
+            None => return None
+        };
+        let (page, x, y) = match self.place(backend, image.width, image.height) {
+            Some(p) => p,
+            None => return None
+        };
+        if let Err(e) = backend.update_texture(self.pages[page].texture, x, y, &image) {
+            println!("Could not upload sprite for {}: {}", asset, e);
+            return None;
+        }
+        let sprite = Sprite {
+            texture: self.pages[page].texture,
+            src: Rect::new(x as i32, y as i32, image.width, image.height),
+            origin: origin,
+            scale: scale
+        };
+        self.pages[page].last_used = self.clock;
+        self.entries.insert(key, Entry { sprite: sprite, page: page });
+        Some(sprite)
+    }
+
+    fn place(&mut self, backend: &mut Backend, w: u32, h: u32) -> Option<(usize, u32, u32)> {
+        for (i, page) in self.pages.iter_mut().enumerate() {
+            if let Some((x, y)) = page.alloc(w, h) {
+                return Some((i, x, y));
+            }
+        }
+
+        let size = (PAGE_SIZE.max(w + PADDING), PAGE_SIZE.max(h + PADDING));
+        let new_bytes = (size.0 * size.1 * 4) as usize;
+        if self.memory_used() + new_bytes > self.budget {
+            // Recycle the least recently used page. A sprite too big for it still gets a
+            // page of its own below, going over budget until something else is evicted.
+            let lru = (0..self.pages.len()).min_by_key(|&i| self.pages[i].last_used);
+            if let Some(i) = lru {
+                self.evict_page(i);
+                if let Some((x, y)) = self.pages[i].alloc(w, h) {
+                    return Some((i, x, y));
+                }
+            }
+        }
+
+        let blank = Image::filled(size.0, size.1, Rgba::new(0, 0, 0, 0));
+        match backend.create_texture(&blank) {
+            Ok(texture) => {
+                self.pages.push(Page {
+                    texture: texture,
+                    packer: Packer::new(size.0, size.1),
+                    last_used: self.clock
+                });
+                let i = self.pages.len() - 1;
+                self.pages[i].alloc(w, h).map(|(x, y)| (i, x, y))
+            },
+            Err(e) => {
+                println!("Could not create sprite page: {}", e);
+                None
+            }
+        }
+    }
+
+    /*
+     * Forgets every sprite on a page and resets its allocator. The texture is kept, so
+     * stale pixels stay behind until overwritten; nothing refers to them any more.
+     */
+    fn evict_page(&mut self, page: usize) {
+        self.entries.retain(|_, e| e.page != page);
+        self.pages[page].packer.reset();
+    }
+
+    /*
+     * Drops every cached raster of an asset, e.g. after the file has changed on disk.
+     */
+    pub fn invalidate(&mut self, asset: &str) {
+        self.entries.retain(|k, _| k.asset != asset);
+        // Space on partly used pages is only reclaimed when the whole page empties.
+        for i in 0..self.pages.len() {
+            if !self.entries.values().any(|e| e.page == i) {
+                self.evict_page(i);
+            }
+        }
+    }
+
+    pub fn clear(&mut self, backend: &mut Backend) {
+        for page in &self.pages {
+            backend.destroy_texture(page.texture);
+        }
+        self.pages.clear();
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geom::Transform;
+    use spath::PathElem;
+    use svg::Shape;
+    use svg::Style;
+    use svg::SvgNode;
+
+    fn node(parent: Option<usize>, opacity: f64, shape: Option<Shape>) -> SvgNode {
+        SvgNode {
+            id: None,
+            parent: parent,
+            transform: Transform::identity(),
+            opacity: opacity,
+            style: Style::default(),
+            shape: shape
+        }
+    }
+
+    fn line(stroke_width: f64) -> Shape {
+        Shape {
+            path: vec![PathElem::MoveTo { x: 0.0, y: 10.0 }, PathElem::LineTo { x: 20.0, y: 10.0 }],
+            fill: None,
+            stroke: Some(Rgba::rgb(255, 255, 255)),
+            stroke_width: stroke_width
+        }
+    }
+
+    fn document(nodes: Vec<SvgNode>) -> SvgDocument {
+        SvgDocument { width: 20.0, height: 20.0, nodes: nodes, animations: Vec::new() }
+    }
+
+    #[test]
+    fn strokes_are_as_wide_as_their_stroke_width() {
+        let doc = document(vec![node(None, 1.0, Some(line(4.0)))]);
+        let (image, origin) = rasterize(&doc, 0, 1.0).unwrap();
+        // Count the covered pixels down the middle of the line.
+        let x = (10.0 - origin.0) as u32;
+        let covered = (0..image.height).filter(|&y| image.get(x, y).a > 128).count();
+        assert_eq!(covered, 4);
+    }
+
+    #[test]
+    fn opacity_is_inherited() {
+        let doc = document(vec![node(None, 0.5, None), node(Some(0), 0.5, Some(line(4.0)))]);
+        let (image, origin) = rasterize(&doc, 0, 1.0).unwrap();
+        let alpha = image.get((10.0 - origin.0) as u32, (10.0 - origin.1) as u32).a;
+        assert!((alpha as i32 - 64).abs() <= 2, "alpha {}", alpha);
+    }
+}
@@ -4,12 +4,20 @@
 
 extern crate xml;
 
+use std::collections::HashMap;
 use std::path::Path;
 use std::fs::File;
+use std::str::FromStr;
 
 use self::xml::reader::EventReader;
 use self::xml::reader::XmlEvent;
 
+use color::Rgba;
+use geom::Transform;
+use smil::Animation;
+use spath;
+use spath::PathElem;
+
 pub fn get_paths(path: &str) -> Vec<String> {
     let mut f = File::open(path).unwrap();
 
@@ -34,3 +42,282 @@ pub fn get_paths(path: &str) -> Vec<String> {
 
     v
 }
+
+pub struct Shape {
+    pub path: Vec<PathElem>,
+    pub fill: Option<Rgba>,
+    pub stroke: Option<Rgba>,
+    pub stroke_width: f64
+}
+
+/*
+ * The paint an element sets on itself. Whatever it leaves unset comes from its parent,
+ * and failing that from the defaults: a black fill, no stroke, one unit wide.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Style {
+    /*
+     * Some(None) for "none".
+     */
+    pub fill: Option<Option<Rgba>>,
+    pub stroke: Option<Option<Rgba>>,
+    pub fill_opacity: Option<f64>,
+    pub stroke_opacity: Option<f64>,
+    pub stroke_width: Option<f64>
+}
+
+impl Style {
+    fn parse(props: &HashMap<String, String>) -> Style {
+        let paint = |name: &str| props.get(name).and_then(|s| match s.trim() {
+            "none" => Some(None),
+            c => Rgba::parse(c).map(Some)
+        });
+        let number = |name: &str| props.get(name).and_then(|o| f64::from_str(o.trim()).ok());
+        Style {
+            fill: paint("fill"),
+            stroke: paint("stroke"),
+            fill_opacity: number("fill-opacity"),
+            stroke_opacity: number("stroke-opacity"),
+            stroke_width: props.get("stroke-width").and_then(|w| parse_length(w))
+        }
+    }
+
+    /*
+     * This style with anything it leaves unset taken from its parent's.
+     */
+    pub fn inherit(&self, parent: &Style) -> Style {
+        Style {
+            fill: self.fill.or(parent.fill),
+            stroke: self.stroke.or(parent.stroke),
+            fill_opacity: self.fill_opacity.or(parent.fill_opacity),
+            stroke_opacity: self.stroke_opacity.or(parent.stroke_opacity),
+            stroke_width: self.stroke_width.or(parent.stroke_width)
+        }
+    }
+
+    pub fn fill(&self) -> Option<Rgba> {
+        self.fill.unwrap_or(Some(Rgba::rgb(0, 0, 0))).map(|c| c.with_alpha(self.fill_opacity.unwrap_or(1.0)))
+    }
+
+    pub fn stroke(&self) -> Option<Rgba> {
+        self.stroke.unwrap_or(None).map(|c| c.with_alpha(self.stroke_opacity.unwrap_or(1.0)))
+    }
+
+    pub fn stroke_width(&self) -> f64 {
+        self.stroke_width.unwrap_or(1.0)
+    }
+}
+
+/*
+ * A <g> or <path> element. Nodes are stored in document order, so a node's parent
+ * always comes before it. A shape's paint is resolved against its ancestors' styles;
+ * `style` is only what the element sets itself, which animation starts from.
+ */
+pub struct SvgNode {
+    pub id: Option<String>,
+    pub parent: Option<usize>,
+    pub transform: Transform,
+    pub opacity: f64,
+    pub style: Style,
+    pub shape: Option<Shape>
+}
+
+pub struct SvgDocument {
+    pub width: f64,
+    pub height: f64,
+    pub nodes: Vec<SvgNode>,
+    pub animations: Vec<Animation>
+}
+
+impl SvgDocument {
+    pub fn find(&self, id: &str) -> Option<usize> {
+        self.nodes.iter().position(|n| n.id.as_ref().map(|i| i == id).unwrap_or(false))
+    }
+
+    /*
+     * The transform from a node's coordinates to document coordinates, ignoring animation.
+     */
+    pub fn world_transform(&self, node: usize) -> Transform {
+        let own = self.nodes[node].transform;
+        match self.nodes[node].parent {
+            Some(p) => self.world_transform(p).then(&own),
+            None => own
+        }
+    }
+
+    /*
+     * A node's opacity combined with its ancestors', ignoring animation.
+     */
+    pub fn world_opacity(&self, node: usize) -> f64 {
+        let own = self.nodes[node].opacity;
+        match self.nodes[node].parent {
+            Some(p) => self.world_opacity(p) * own,
+            None => own
+        }
+    }
+
+    /*
+     * Every path in document order, untransformed, as `get_paths` gives them.
+     */
+    pub fn paths(&self) -> Vec<Vec<PathElem>> {
+        self.nodes.iter().filter_map(|n| n.shape.as_ref().map(|s| s.path.clone())).collect()
+    }
+
+    /*
+     * Whether node is the given ancestor or one of its descendants.
+     */
+    pub fn is_within(&self, node: usize, ancestor: usize) -> bool {
+        let mut cur = Some(node);
+        while let Some(i) = cur {
+            if i == ancestor {
+                return true;
+            }
+            cur = self.nodes[i].parent;
+        }
+        false
+    }
+}
+
+/*
+ * Presentation attributes may appear directly or inside the style attribute; style wins.
+ */
+fn presentation(attrs: &HashMap<String, String>) -> HashMap<String, String> {
+    let mut p = attrs.clone();
+    if let Some(style) = attrs.get("style") {
+        for decl in style.split(';') {
+            let mut kv = decl.splitn(2, ':');
+            if let (Some(k), Some(v)) = (kv.next(), kv.next()) {
+                p.insert(k.trim().to_string(), v.trim().to_string());
+            }
+        }
+    }
+    p
+}
+
+fn parse_length(s: &str) -> Option<f64> {
+    f64::from_str(s.trim().trim_end_matches("px")).ok()
+}
+
+fn length(s: Option<&String>, default: f64) -> f64 {
+    s.and_then(|v| parse_length(v)).unwrap_or(default)
+}
+
+enum Open {
+    Node(usize),
+    Anim(usize),
+    Other
+}
+
+struct PendingAnim {
+    element: String,
+    attrs: HashMap<String, String>,
+    parent: Option<usize>,
+    mpath: Option<String>
+}
+
+fn href(attrs: &HashMap<String, String>) -> Option<String> {
+    attrs.get("href").map(|h| h.trim_start_matches('#').to_string())
+}
+
+/*
+ * Reads the drawable structure of an SVG file: groups, paths, their styles and
+ * transforms, and any SMIL animations attached to them.
+ */
+pub fn get_document<P: AsRef<Path>>(path: P) -> Result<SvgDocument, String> {
+    let f = try!(File::open(path).map_err(|e| format!("{}", e)));
+    let reader = EventReader::new(f);
+
+    let mut doc = SvgDocument { width: 0.0, height: 0.0, nodes: Vec::new(), animations: Vec::new() };
+    let mut stack = Vec::<Open>::new();
+    let mut pending = Vec::<PendingAnim>::new();
+    // Each node's style with its ancestors' filled in.
+    let mut styles = Vec::<Style>::new();
+
+    for event in reader {
+        let edata = try!(event.map_err(|e| format!("{:?}", e)));
+        match edata {
+            XmlEvent::StartElement { name, attributes, .. } => {
+                let attrs: HashMap<String, String> = attributes.into_iter()
+                    .map(|a| (a.name.local_name, a.value)).collect();
+                let parent = stack.iter().rev().filter_map(|o| match *o {
+                    Open::Node(i) => Some(i),
+                    _ => None
+                }).next();
+                let opened = match name.local_name.as_str() {
+                    "svg" => {
+                        doc.width = length(attrs.get("width"), 0.0);
+                        doc.height = length(attrs.get("height"), 0.0);
+                        Open::Other
+                    },
+                    "g" | "path" => {
+                        let props = presentation(&attrs);
+                        let style = Style::parse(&props);
+                        let resolved = match parent {
+                            Some(p) => style.inherit(&styles[p]),
+                            None => style
+                        };
+                        styles.push(resolved);
+                        let shape = attrs.get("d").map(|d| Shape {
+                            path: spath::read_path(d),
+                            fill: resolved.fill(),
+                            stroke: resolved.stroke(),
+                            stroke_width: resolved.stroke_width()
+                        });
+                        doc.nodes.push(SvgNode {
+                            id: attrs.get("id").cloned(),
+                            parent: parent,
+                            transform: attrs.get("transform")
+                                .map(|t| Transform::parse(t))
+                                .unwrap_or(Transform::identity()),
+                            opacity: props.get("opacity")
+                                .and_then(|o| f64::from_str(o).ok())
+                                .unwrap_or(1.0),
+                            style: style,
+                            shape: shape
+                        });
+                        Open::Node(doc.nodes.len() - 1)
+                    },
+                    "animate" | "animateTransform" | "animateMotion" => {
+                        pending.push(PendingAnim {
+                            element: name.local_name.clone(),
+                            attrs: attrs,
+                            parent: parent,
+                            mpath: None
+                        });
+                        Open::Anim(pending.len() - 1)
+                    },
+                    "mpath" => {
+                        if let Some(&Open::Anim(a)) = stack.last() {
+                            pending[a].mpath = href(&attrs);
+                        }
+                        Open::Other
+                    },
+                    _ => Open::Other
+                };
+                stack.push(opened);
+            },
+            XmlEvent::EndElement { .. } => {
+                stack.pop();
+            },
+            _ => ()
+        }
+    }
+
+    // Animations can refer to elements further down the file, so resolve them last.
+    for p in pending {
+        let target = match href(&p.attrs) {
+            Some(id) => doc.find(&id),
+            None => p.parent
+        };
+        let motion_path = p.mpath.as_ref().and_then(|id| doc.find(id))
+            .and_then(|i| doc.nodes[i].shape.as_ref().map(|s| s.path.clone()));
+        let anim = target.and_then(|t| Animation::from_element(&p.element, &p.attrs, t, &doc.nodes[t].transform,
+            motion_path.as_ref().map(|m| m.as_slice())));
+        match anim {
+            Some(a) => doc.animations.push(a),
+            None => println!("Ignoring unusable <{}>", p.element)
+        }
+    }
+
+    Ok(doc)
+}
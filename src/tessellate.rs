@@ -0,0 +1,396 @@
+/*
+ * Turns filled paths into triangle meshes, so vector art can be drawn at any zoom as
+ * geometry instead of being rasterised again for each scale.
+ *
+ * Contours are first sorted into outlines and holes according to the fill rule. Each
+ * hole is then joined to the outline around it by a pair of coincident "bridge" edges,
+ * leaving one simple polygon per outline, which is cut into triangles by ear clipping.
+ *
+ * Contours may nest to any depth and may overlap as long as they don't cross: glyph
+ * outlines and the like are fine, but a self-intersecting star is not.
+ */
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use backend::Backend;
+use geom;
+use geom::Polyline;
+use raster::FillRule;
+use svg::SvgDocument;
+
+/*
+ * A triangle list. Triangles are wound anticlockwise in a y-up frame, which is clockwise
+ * on screen.
+ */
+#[derive(Debug, Clone)]
+pub struct Mesh {
+    pub vertices: Vec<(f64, f64)>,
+    pub indices: Vec<u32>
+}
+
+impl Mesh {
+    pub fn new() -> Mesh {
+        Mesh { vertices: Vec::new(), indices: Vec::new() }
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn area(&self) -> f64 {
+        self.indices.chunks(3).map(|t| {
+            let (a, b, c) = (self.vertices[t[0] as usize], self.vertices[t[1] as usize],
+                             self.vertices[t[2] as usize]);
+            cross(a, b, c) / 2.0
+        }).sum()
+    }
+
+    /*
+     * Fills the mesh in the backend's current colour and transform.
+     */
+    pub fn draw(&self, backend: &mut Backend) {
+        backend.fill_triangles(&self.vertices, &self.indices);
+    }
+
+    fn append(&mut self, points: &[(f64, f64)], triangles: &[(usize, usize, usize)]) {
+        let base = self.vertices.len() as u32;
+        self.vertices.extend_from_slice(points);
+        for &(a, b, c) in triangles {
+            self.indices.push(base + a as u32);
+            self.indices.push(base + b as u32);
+            self.indices.push(base + c as u32);
+        }
+    }
+}
+
+/*
+ * Twice the signed area of triangle abc; positive when it turns left.
+ */
+fn cross(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> f64 {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+/*
+ * Orders numbers that may be NaN, taking NaN as equal to anything so sorts still finish.
+ */
+fn order(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+}
+
+fn signed_area(poly: &[(f64, f64)]) -> f64 {
+    let n = poly.len();
+    (0..n).map(|i| {
+        let (a, b) = (poly[i], poly[(i + 1) % n]);
+        a.0 * b.1 - b.0 * a.1
+    }).sum::<f64>() / 2.0
+}
+
+/*
+ * The winding number of a contour around a point.
+ */
+fn winding(poly: &[(f64, f64)], p: (f64, f64)) -> i32 {
+    let n = poly.len();
+    let mut w = 0;
+    for i in 0..n {
+        let (a, b) = (poly[i], poly[(i + 1) % n]);
+        if a.1 <= p.1 {
+            if b.1 > p.1 && cross(a, b, p) > 0.0 {
+                w += 1;
+            }
+        } else if b.1 <= p.1 && cross(a, b, p) < 0.0 {
+            w -= 1;
+        }
+    }
+    w
+}
+
+/*
+ * Drops points that aren't finite, repeated points, the closing point, and points in the
+ * middle of straight runs.
+ */
+fn clean(poly: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    let mut pts: Vec<(f64, f64)> = Vec::with_capacity(poly.len());
+    for &p in poly.iter().filter(|p| p.0.is_finite() && p.1.is_finite()) {
+        if pts.last().map(|&q| q != p).unwrap_or(true) {
+            pts.push(p);
+        }
+    }
+    while pts.len() > 1 && pts[0] == pts[pts.len() - 1] {
+        pts.pop();
+    }
+    let mut changed = true;
+    while changed && pts.len() >= 3 {
+        changed = false;
+        let n = pts.len();
+        for i in 0..n {
+            let (a, b, c) = (pts[(i + n - 1) % n], pts[i], pts[(i + 1) % n]);
+            if cross(a, b, c).abs() < 1e-12 {
+                pts.remove(i);
+                changed = true;
+                break;
+            }
+        }
+    }
+    pts
+}
+
+fn filled(w: i32, rule: FillRule) -> bool {
+    match rule {
+        FillRule::NonZero => w != 0,
+        FillRule::EvenOdd => w % 2 != 0
+    }
+}
+
+fn in_triangle(p: (f64, f64), a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> bool {
+    cross(a, b, p) >= 0.0 && cross(b, c, p) >= 0.0 && cross(c, a, p) >= 0.0
+}
+
+/*
+ * Splices a hole (wound clockwise) into an outline (anticlockwise), joining the hole's
+ * rightmost point to a point of the outline it can see.
+ */
+fn bridge(outer: &[(f64, f64)], hole: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    let m = (0..hole.len()).max_by(|&a, &b| order(hole[a].0, hole[b].0)).unwrap();
+    let mp = hole[m];
+
+    // The nearest outline edge hit by a ray from the hole's rightmost point going right.
+    let n = outer.len();
+    let mut hit: Option<(f64, usize)> = None;
+    for i in 0..n {
+        let (a, b) = (outer[i], outer[(i + 1) % n]);
+        if (a.1 > mp.1) == (b.1 > mp.1) {
+            continue;
+        }
+        let x = a.0 + (mp.1 - a.1) * (b.0 - a.0) / (b.1 - a.1);
+        if x >= mp.0 && hit.map(|h| x < h.0).unwrap_or(true) {
+            hit = Some((x, i));
+        }
+    }
+    let (hx, edge) = match hit {
+        Some(h) => h,
+        // Not actually inside; leave the hole out rather than make a mess.
+        None => return outer.to_vec()
+    };
+    let (i0, i1) = (edge, (edge + 1) % n);
+    let nearer = |i: usize| (outer[i].0, -(outer[i].1 - mp.1).abs());
+    let mut best = if nearer(i0) > nearer(i1) { i0 } else { i1 };
+
+    // Any reflex outline point inside the triangle between the hit and the candidate
+    // could block the view; the one closest in angle to the ray is visible.
+    let ip = (hx, mp.1);
+    let bp = outer[best];
+    let (ta, tb, tc) = if bp.1 < mp.1 { (mp, bp, ip) } else { (mp, ip, bp) };
+    let mut best_key = (::std::f64::INFINITY, ::std::f64::INFINITY);
+    for j in 0..n {
+        let p = outer[j];
+        if p == outer[best] || p == mp {
+            continue;
+        }
+        let reflex = cross(outer[(j + n - 1) % n], p, outer[(j + 1) % n]) <= 0.0;
+        if reflex && in_triangle(p, ta, tb, tc) {
+            let key = (((p.1 - mp.1) / (p.0 - mp.0)).abs(), (p.0 - mp.0).abs());
+            if key < best_key {
+                best_key = key;
+                best = j;
+            }
+        }
+    }
+
+    // Earlier bridges leave points that appear twice; join at the copy whose corner
+    // faces the hole.
+    let target = outer[best];
+    best = (0..n).filter(|&j| outer[j] == target).find(|&j| {
+        let (prev, next) = (outer[(j + n - 1) % n], outer[(j + 1) % n]);
+        let (left_in, left_out) = (cross(prev, target, mp) > 0.0, cross(target, next, mp) > 0.0);
+        if cross(prev, target, next) >= 0.0 { left_in && left_out } else { left_in || left_out }
+    }).unwrap_or(best);
+
+    let mut out = Vec::with_capacity(n + hole.len() + 2);
+    out.extend_from_slice(&outer[..best + 1]);
+    for k in 0..hole.len() + 1 {
+        out.push(hole[(m + k) % hole.len()]);
+    }
+    out.push(outer[best]);
+    out.extend_from_slice(&outer[best + 1..]);
+    out
+}
+
+/*
+ * Ear clipping of a simple anticlockwise polygon, possibly with bridge edges.
+ */
+fn ear_clip(poly: &[(f64, f64)]) -> Vec<(usize, usize, usize)> {
+    let mut idx: Vec<usize> = (0..poly.len()).collect();
+    let mut tris = Vec::with_capacity(poly.len().saturating_sub(2));
+    let mut misses = 0;
+    let mut i = 0;
+    while idx.len() > 3 {
+        let n = idx.len();
+        let (ia, ib, ic) = (idx[(i + n - 1) % n], idx[i % n], idx[(i + 1) % n]);
+        let (a, b, c) = (poly[ia], poly[ib], poly[ic]);
+        let convex = cross(a, b, c) > 0.0;
+        let is_ear = convex && !idx.iter().any(|&j| {
+            let p = poly[j];
+            j != ia && j != ib && j != ic && p != a && p != b && p != c && in_triangle(p, a, b, c)
+        });
+        if is_ear {
+            tris.push((ia, ib, ic));
+            idx.remove(i % n);
+            misses = 0;
+        } else {
+            i += 1;
+            misses += 1;
+            if misses > n {
+                // Nothing clips cleanly, which only happens with bad input; drop a
+                // degenerate or the least bad vertex so as to always finish.
+                let k = (0..n).min_by(|&p, &q| {
+                    let area = |k: usize| cross(poly[idx[(k + n - 1) % n]], poly[idx[k]], poly[idx[(k + 1) % n]]).abs();
+                    order(area(p), area(q))
+                }).unwrap();
+                idx.remove(k);
+                misses = 0;
+            }
+        }
+        if i >= idx.len() {
+            i = 0;
+        }
+    }
+    if idx.len() == 3 && cross(poly[idx[0]], poly[idx[1]], poly[idx[2]]) > 0.0 {
+        tris.push((idx[0], idx[1], idx[2]));
+    }
+    tris
+}
+
+/*
+ * Triangulates the area filled by a set of closed contours under the given rule.
+ */
+pub fn tessellate(contours: &[Polyline], rule: FillRule) -> Mesh {
+    let polys: Vec<Vec<(f64, f64)>> = contours.iter().map(|c| clean(c))
+        .filter(|c| c.len() >= 3 && signed_area(c).abs() > 1e-12)
+        .collect();
+
+    // A contour bounds the fill if the fill differs either side of it. The winding just
+    // outside it is whatever the other contours give; inside, its own adds one.
+    let mut outers = Vec::<Vec<(f64, f64)>>::new();
+    let mut holes = Vec::<Vec<(f64, f64)>>::new();
+    for (i, poly) in polys.iter().enumerate() {
+        let probe = ((poly[0].0 + poly[1].0) / 2.0, (poly[0].1 + poly[1].1) / 2.0);
+        let outside: i32 = polys.iter().enumerate().filter(|&(j, _)| j != i)
+            .map(|(_, other)| winding(other, probe)).sum();
+        let inside = outside + if signed_area(poly) > 0.0 { 1 } else { -1 };
+        let mut p = poly.clone();
+        match (filled(inside, rule), filled(outside, rule)) {
+            (true, false) => {
+                if signed_area(&p) < 0.0 {
+                    p.reverse();
+                }
+                outers.push(p);
+            },
+            (false, true) => {
+                if signed_area(&p) > 0.0 {
+                    p.reverse();
+                }
+                holes.push(p);
+            },
+            _ => {}
+        }
+    }
+
+    // Each hole belongs to the smallest outline around it.
+    let mut owned: Vec<Vec<Vec<(f64, f64)>>> = vec![Vec::new(); outers.len()];
+    for hole in holes {
+        let owner = (0..outers.len())
+            .filter(|&o| winding(&outers[o], hole[0]) != 0)
+            .min_by(|&a, &b| order(signed_area(&outers[a]), signed_area(&outers[b])));
+        if let Some(o) = owner {
+            owned[o].push(hole);
+        }
+    }
+
+    let mut mesh = Mesh::new();
+    for (mut outer, mut hs) in outers.into_iter().zip(owned.into_iter()) {
+        // Bridging right to left keeps earlier bridges out of later holes' way.
+        hs.sort_by(|a, b| {
+            let ra = a.iter().map(|p| p.0).fold(::std::f64::NEG_INFINITY, f64::max);
+            let rb = b.iter().map(|p| p.0).fold(::std::f64::NEG_INFINITY, f64::max);
+            order(rb, ra)
+        });
+        for hole in &hs {
+            outer = bridge(&outer, hole);
+        }
+        let tris = ear_clip(&outer);
+        mesh.append(&outer, &tris);
+    }
+    mesh
+}
+
+/*
+ * Meshes for the shapes of SVG documents, kept per asset and node. Curves are flattened
+ * finely enough to hold up under a good deal of zoom.
+ */
+pub struct MeshCache {
+    tolerance: f64,
+    meshes: HashMap<(String, usize), Rc<Mesh>>
+}
+
+impl MeshCache {
+    pub fn new(tolerance: f64) -> MeshCache {
+        MeshCache { tolerance: tolerance, meshes: HashMap::new() }
+    }
+
+    /*
+     * The mesh for a node's own shape in document coordinates, or None if it has none.
+     */
+    pub fn get(&mut self, asset: &str, doc: &SvgDocument, node: usize) -> Option<Rc<Mesh>> {
+        let key = (asset.to_string(), node);
+        if let Some(mesh) = self.meshes.get(&key) {
+            return Some(mesh.clone());
+        }
+        let mesh = match doc.nodes.get(node).and_then(|n| n.shape.as_ref()) {
+            Some(shape) => {
+                let outlines = geom::transform_polylines(&geom::flatten(&shape.path, self.tolerance),
+                                                         &doc.world_transform(node));
+                Rc::new(tessellate(&outlines, FillRule::NonZero))
+            },
+            None => return None
+        };
+        self.meshes.insert(key, mesh.clone());
+        Some(mesh)
+    }
+
+    pub fn invalidate(&mut self, asset: &str) {
+        self.meshes.retain(|k, _| k.0 != asset);
+    }
+
+    pub fn len(&self) -> usize {
+        self.meshes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(x: f64, y: f64, size: f64) -> Polyline {
+        vec![(x, y), (x + size, y), (x + size, y + size), (x, y + size)]
+    }
+
+    #[test]
+    fn holes_are_left_out() {
+        let mut hole = square(3.0, 3.0, 4.0);
+        hole.reverse();
+        let mesh = tessellate(&[square(0.0, 0.0, 10.0), hole], FillRule::NonZero);
+        assert!((mesh.area().abs() - 84.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn points_that_are_not_finite_are_dropped() {
+        let nan = ::std::f64::NAN;
+        let mut outline = square(0.0, 0.0, 10.0);
+        outline.insert(2, (nan, 5.0));
+        let mesh = tessellate(&[outline, vec![(nan, nan), (1.0, nan), (::std::f64::INFINITY, 2.0)]],
+                              FillRule::NonZero);
+        assert!((mesh.area().abs() - 100.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,143 @@
+/*
+ * Fixed-timestep bookkeeping. Real time is fed in as it passes and handed back as a whole
+ * number of equal steps, so the simulation sees the same sequence of updates however fast
+ * or unevenly frames are drawn. What is left over gives how far between the last step
+ * and the next the frame falls, for rendering to interpolate with.
+ */
+
+pub struct FixedStep {
+    step: f64,
+    max_steps: u32,
+    accumulator: f64,
+    steps: u64,
+    dropped: f64
+}
+
+impl FixedStep {
+    /*
+     * `step` is in seconds. At most `max_steps` updates are run for one frame; time
+     * beyond that is dropped, so that a slow stretch slows the game down instead of
+     * leaving it ever further behind.
+     */
+    pub fn new(step: f64, max_steps: u32) -> FixedStep {
+        FixedStep {
+            step: step,
+            max_steps: max_steps.max(1),
+            accumulator: 0.0,
+            steps: 0,
+            dropped: 0.0
+        }
+    }
+
+    pub fn per_second(rate: u32, max_steps: u32) -> FixedStep {
+        FixedStep::new(1.0 / rate.max(1) as f64, max_steps)
+    }
+
+    pub fn step(&self) -> f64 {
+        self.step
+    }
+
+    pub fn max_steps(&self) -> u32 {
+        self.max_steps
+    }
+
+    /*
+     * Adds `elapsed` seconds and returns the number of updates due.
+     */
+    pub fn advance(&mut self, elapsed: f64) -> u32 {
+        self.accumulator += elapsed.max(0.0);
+        // Sums of frame times drift by rounding; a hair short of a step counts as one.
+        let due = (self.accumulator / self.step + 1e-6).floor();
+        let steps = if due > self.max_steps as f64 {
+            let excess = self.accumulator - self.max_steps as f64 * self.step;
+            // Keep the fraction of a step so the phase doesn't jump.
+            let kept = (excess + 1e-6 * self.step) % self.step;
+            self.dropped += (excess - kept).max(0.0);
+            self.accumulator = self.max_steps as f64 * self.step + kept;
+            self.max_steps
+        } else {
+            due as u32
+        };
+        self.accumulator -= steps as f64 * self.step;
+        self.steps += steps as u64;
+        steps
+    }
+
+    /*
+     * How far the current frame is from the last update towards the next, in [0, 1).
+     */
+    pub fn alpha(&self) -> f64 {
+        (self.accumulator / self.step).max(0.0).min(1.0)
+    }
+
+    /*
+     * Total updates run, and so simulated time, since the start.
+     */
+    pub fn total_steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn simulated_time(&self) -> f64 {
+        self.steps as f64 * self.step
+    }
+
+    /*
+     * Real time thrown away by the catch-up limit.
+     */
+    pub fn dropped_time(&self) -> f64 {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leftover_time_carries_over() {
+        let mut clock = FixedStep::per_second(60, 5);
+        // A little over a step and a half at a time: 1, then 2, then 1...
+        let frame = 1.5 / 60.0;
+        let steps: Vec<u32> = (0..4).map(|_| clock.advance(frame)).collect();
+        assert_eq!(steps, vec![1, 2, 1, 2]);
+        assert_eq!(clock.total_steps(), 6);
+        assert!((clock.simulated_time() - 0.1).abs() < 1e-9);
+
+        // Sixty frames of a sixtieth come to sixty steps, despite rounding.
+        let mut clock = FixedStep::per_second(60, 5);
+        let total: u32 = (0..60).map(|_| clock.advance(1.0 / 60.0)).sum();
+        assert_eq!(total, 60);
+        assert_eq!(clock.dropped_time(), 0.0);
+    }
+
+    #[test]
+    fn slow_frames_are_cut_to_max_steps() {
+        let mut clock = FixedStep::new(0.01, 4);
+        assert_eq!(clock.advance(0.1025), 4);
+        // Six whole steps go, and the quarter step stays.
+        assert!((clock.dropped_time() - 0.06).abs() < 1e-6);
+        assert!((clock.alpha() - 0.25).abs() < 1e-6);
+        assert_eq!(clock.advance(0.0075), 1);
+        assert_eq!(clock.total_steps(), 5);
+
+        assert_eq!(FixedStep::new(0.01, 0).max_steps(), 1);
+        assert_eq!(clock.advance(-1.0), 0);
+    }
+
+    #[test]
+    fn alpha_stays_below_one() {
+        let mut clock = FixedStep::per_second(60, 8);
+        assert_eq!(clock.alpha(), 0.0);
+        let mut elapsed = 0.0;
+        for i in 0..500 {
+            let frame = 0.001 + (i % 17) as f64 * 0.0023;
+            elapsed += frame;
+            clock.advance(frame);
+            let alpha = clock.alpha();
+            assert!(alpha >= 0.0 && alpha < 1.0, "alpha {} at frame {}", alpha, i);
+        }
+        // Nothing was slow enough to drop, so simulated time trails real time by alpha.
+        let behind = elapsed - clock.simulated_time();
+        assert!((behind - clock.alpha() * clock.step()).abs() < 1e-6);
+    }
+}
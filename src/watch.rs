@@ -0,0 +1,336 @@
+/*
+ * Notices files changing under a directory, for reloading assets while the game runs.
+ *
+ * On Linux this uses inotify, which costs nothing until something changes. Elsewhere, or
+ * if inotify can't be set up, the tree is scanned every so often and file times and sizes
+ * compared instead.
+ *
+ * Only finished writes are reported: a file closed after writing or moved into place, as
+ * editors do when saving. Changed files are named relative to the root, with forward
+ * slashes, as asset names are.
+ */
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Instant;
+use std::time::SystemTime;
+
+/*
+ * Seconds between scans when polling.
+ */
+const POLL_INTERVAL: f64 = 0.5;
+
+/*
+ * Joins a directory name and a file name as an asset name.
+ */
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() { name.to_string() } else { format!("{}/{}", dir, name) }
+}
+
+/*
+ * Every directory under root including itself, as asset names, root being "".
+ */
+fn directories(root: &Path, dir: &str, out: &mut Vec<String>) {
+    out.push(dir.to_string());
+    if let Ok(entries) = fs::read_dir(root.join(dir)) {
+        for entry in entries.filter_map(|e| e.ok()) {
+            if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                directories(root, &join(dir, &entry.file_name().to_string_lossy()), out);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::collections::HashMap;
+    use std::ffi::CString;
+    use std::fs::File;
+    use std::io::ErrorKind;
+    use std::io::Read;
+    use std::os::raw::c_char;
+    use std::os::raw::c_int;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::io::FromRawFd;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    extern "C" {
+        fn inotify_init1(flags: c_int) -> c_int;
+        fn inotify_add_watch(fd: c_int, path: *const c_char, mask: u32) -> c_int;
+    }
+
+    const IN_NONBLOCK: c_int = 0o4000;
+    const IN_CLOEXEC: c_int = 0o2000000;
+    const IN_CLOSE_WRITE: u32 = 0x8;
+    const IN_MOVED_TO: u32 = 0x80;
+    const IN_CREATE: u32 = 0x100;
+    const IN_Q_OVERFLOW: u32 = 0x4000;
+    const IN_ISDIR: u32 = 0x40000000;
+
+    pub struct Inotify {
+        file: File,
+        root: PathBuf,
+        // Watch descriptors to the directories they watch, as asset names.
+        dirs: HashMap<i32, String>,
+        buffer: Vec<u8>
+    }
+
+    fn ne32(b: &[u8]) -> u32 {
+        unsafe { ::std::mem::transmute::<[u8; 4], u32>([b[0], b[1], b[2], b[3]]) }
+    }
+
+    impl Inotify {
+        pub fn new(root: &Path) -> Option<Inotify> {
+            let fd = unsafe { inotify_init1(IN_NONBLOCK | IN_CLOEXEC) };
+            if fd < 0 {
+                return None;
+            }
+            let mut watcher = Inotify {
+                file: unsafe { File::from_raw_fd(fd) },
+                root: root.to_path_buf(),
+                dirs: HashMap::new(),
+                buffer: vec![0; 4096]
+            };
+            let mut dirs = Vec::new();
+            super::directories(root, "", &mut dirs);
+            for dir in dirs {
+                // Without the root itself there is nothing to watch.
+                if !watcher.add(&dir) && dir.is_empty() {
+                    return None;
+                }
+            }
+            Some(watcher)
+        }
+
+        fn add(&mut self, dir: &str) -> bool {
+            let path = match CString::new(self.root.join(dir).as_os_str().as_bytes()) {
+                Ok(path) => path,
+                Err(_) => return false
+            };
+            let wd = unsafe {
+                inotify_add_watch(self.file.as_raw_fd(), path.as_ptr(), IN_CLOSE_WRITE | IN_MOVED_TO | IN_CREATE)
+            };
+            if wd >= 0 {
+                self.dirs.insert(wd, dir.to_string());
+            }
+            wd >= 0
+        }
+
+        /*
+         * Files written since last time. If events were lost, which happens when too many
+         * come at once, that is every file.
+         */
+        pub fn changes(&mut self) -> Vec<String> {
+            let mut changed = Vec::new();
+            let mut lost = false;
+            loop {
+                let len = match self.file.read(&mut self.buffer) {
+                    Ok(len) => len,
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        println!("Could not read file changes: {}", e);
+                        break;
+                    }
+                };
+                // Each event is a descriptor, mask, cookie and name length, then the name
+                // padded with nuls.
+                let mut pos = 0;
+                while pos + 16 <= len {
+                    let wd = ne32(&self.buffer[pos..]) as i32;
+                    let mask = ne32(&self.buffer[pos + 4..]);
+                    let name_len = ne32(&self.buffer[pos + 12..]) as usize;
+                    // The kernel only hands over whole events, but don't trust a length
+                    // that runs off the end.
+                    if pos + 16 + name_len > len {
+                        break;
+                    }
+                    let name: Vec<u8> = self.buffer[pos + 16..pos + 16 + name_len].iter()
+                        .cloned().take_while(|&b| b != 0).collect();
+                    pos += 16 + name_len;
+                    if mask & IN_Q_OVERFLOW != 0 {
+                        lost = true;
+                    }
+                    let dir = match self.dirs.get(&wd) {
+                        Some(dir) => dir.clone(),
+                        None => continue
+                    };
+                    let name = super::join(&dir, &String::from_utf8_lossy(&name));
+                    if mask & IN_ISDIR != 0 {
+                        if mask & (IN_CREATE | IN_MOVED_TO) != 0 {
+                            self.add(&name);
+                        }
+                    } else if mask & (IN_CLOSE_WRITE | IN_MOVED_TO) != 0 && !changed.contains(&name) {
+                        changed.push(name);
+                    }
+                }
+            }
+            if lost {
+                return super::scan(&self.root).keys().cloned().collect();
+            }
+            changed
+        }
+    }
+}
+
+/*
+ * Every file under root, as asset names, with when it was last changed and its size.
+ */
+fn scan(root: &Path) -> HashMap<String, (SystemTime, u64)> {
+    let mut dirs = Vec::new();
+    directories(root, "", &mut dirs);
+    let mut files = HashMap::new();
+    for dir in dirs {
+        for entry in fs::read_dir(root.join(&dir)).into_iter().flat_map(|e| e).filter_map(|e| e.ok()) {
+            if let Ok(meta) = entry.metadata() {
+                if meta.is_file() {
+                    let stamp = (meta.modified().unwrap_or(SystemTime::now()), meta.len());
+                    files.insert(join(&dir, &entry.file_name().to_string_lossy()), stamp);
+                }
+            }
+        }
+    }
+    files
+}
+
+struct Poller {
+    root: PathBuf,
+    last_scan: Instant,
+    files: HashMap<String, (SystemTime, u64)>
+}
+
+impl Poller {
+    fn new(root: &Path) -> Poller {
+        Poller { root: root.to_path_buf(), last_scan: Instant::now(), files: scan(root) }
+    }
+
+    /*
+     * Files new or different since the last scan, if it is time for another.
+     */
+    fn changes(&mut self) -> Vec<String> {
+        let elapsed = self.last_scan.elapsed();
+        if (elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9) < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_scan = Instant::now();
+        let files = scan(&self.root);
+        let changed = files.iter().filter(|&(name, stamp)| self.files.get(name) != Some(stamp))
+            .map(|(name, _)| name.clone()).collect();
+        self.files = files;
+        changed
+    }
+}
+
+enum Method {
+    #[cfg(target_os = "linux")]
+    Inotify(inotify::Inotify),
+    Poll(Poller)
+}
+
+#[cfg(target_os = "linux")]
+fn native(root: &Path) -> Option<Method> {
+    inotify::Inotify::new(root).map(Method::Inotify)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn native(root: &Path) -> Option<Method> {
+    None
+}
+
+pub struct Watcher {
+    method: Method
+}
+
+impl Watcher {
+    /*
+     * Watches everything under root, including directories made later.
+     */
+    pub fn new<P: AsRef<Path>>(root: P) -> Watcher {
+        match native(root.as_ref()) {
+            Some(method) => Watcher { method: method },
+            None => Watcher::polling(root)
+        }
+    }
+
+    /*
+     * Watches by scanning, even where inotify would do.
+     */
+    pub fn polling<P: AsRef<Path>>(root: P) -> Watcher {
+        Watcher { method: Method::Poll(Poller::new(root.as_ref())) }
+    }
+
+    pub fn is_polling(&self) -> bool {
+        match self.method {
+            Method::Poll(_) => true,
+            #[cfg(target_os = "linux")]
+            Method::Inotify(_) => false
+        }
+    }
+
+    /*
+     * Files changed since the last call.
+     */
+    pub fn changes(&mut self) -> Vec<String> {
+        match self.method {
+            #[cfg(target_os = "linux")]
+            Method::Inotify(ref mut watcher) => watcher.changes(),
+            Method::Poll(ref mut poller) => poller.changes()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use std::thread;
+    use std::time::Duration;
+
+    fn temp_root(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("tycoon-watch-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("images")).unwrap();
+        root
+    }
+
+    #[test]
+    fn polling_finds_new_and_changed_files() {
+        let root = temp_root("poll");
+        fs::write(root.join("logo.svg"), "<svg/>").unwrap();
+        let mut watcher = Watcher::polling(&root);
+        assert!(watcher.is_polling());
+        fs::write(root.join("images").join("pic.png"), "not yet").unwrap();
+        fs::write(root.join("logo.svg"), "<svg></svg>").unwrap();
+        // Nothing is looked at before the next scan is due.
+        assert!(watcher.changes().is_empty());
+
+        thread::sleep(Duration::from_millis((POLL_INTERVAL * 1200.0) as u64));
+        let mut changed = watcher.changes();
+        changed.sort();
+        assert_eq!(changed, vec!["images/pic.png".to_string(), "logo.svg".to_string()]);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn inotify_follows_new_directories() {
+        let root = temp_root("inotify");
+        // Where inotify can't be had, such as in some sandboxes, there is nothing to test.
+        let mut watcher = match inotify::Inotify::new(&root) {
+            Some(watcher) => watcher,
+            None => return
+        };
+        fs::write(root.join("images").join("pic.png"), "png").unwrap();
+        fs::create_dir(root.join("sounds")).unwrap();
+        assert_eq!(watcher.changes(), vec!["images/pic.png".to_string()]);
+
+        fs::write(root.join("sounds").join("click.wav"), "wav").unwrap();
+        assert_eq!(watcher.changes(), vec!["sounds/click.wav".to_string()]);
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,348 @@
+/*
+ * WAV reading, whole for sound effects or a piece at a time for music.
+ *
+ * Reads uncompressed PCM at 8, 16, 24 or 32 bits and 32 bit float, including the
+ * WAVE_FORMAT_EXTENSIBLE variants. Only the first two channels of a multichannel file
+ * are used. Ogg Vorbis files are recognised but not decoded: there is no Vorbis decoder
+ * here yet, so they give an error saying so rather than a mystery.
+ */
+
+use std::fs::File;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+
+use audio::Frame;
+use audio::Sound;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    Int,
+    Float
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Format {
+    encoding: Encoding,
+    channels: u16,
+    rate: u32,
+    bits: u16,
+    block_align: u16
+}
+
+fn le16(b: &[u8]) -> u16 {
+    b[0] as u16 | (b[1] as u16) << 8
+}
+
+fn le32(b: &[u8]) -> u32 {
+    b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24
+}
+
+fn parse_format(fmt: &[u8]) -> Result<Format, String> {
+    if fmt.len() < 16 {
+        return Err("format chunk too short".to_string());
+    }
+    let mut tag = le16(&fmt[0..]);
+    // Extensible files keep the real format at the start of the subformat GUID.
+    if tag == 0xfffe && fmt.len() >= 26 {
+        tag = le16(&fmt[24..]);
+    }
+    let format = Format {
+        encoding: match tag {
+            1 => Encoding::Int,
+            3 => Encoding::Float,
+            _ => return Err(format!("unsupported WAV encoding {}", tag))
+        },
+        channels: le16(&fmt[2..]),
+        rate: le32(&fmt[4..]),
+        bits: le16(&fmt[14..]),
+        block_align: le16(&fmt[12..])
+    };
+    let bits_ok = match format.encoding {
+        Encoding::Int => format.bits == 8 || format.bits == 16 || format.bits == 24 || format.bits == 32,
+        Encoding::Float => format.bits == 32
+    };
+    if !bits_ok {
+        return Err(format!("unsupported WAV sample size {}", format.bits));
+    }
+    if format.channels == 0 || format.rate == 0 || (format.block_align as u32) < format.channels as u32 * format.bits as u32 / 8 {
+        return Err("bad WAV format".to_string());
+    }
+    Ok(format)
+}
+
+fn sample(format: &Format, b: &[u8]) -> f32 {
+    match (format.encoding, format.bits) {
+        (Encoding::Int, 8) => (b[0] as f32 - 128.0) / 128.0,
+        (Encoding::Int, 16) => le16(b) as i16 as f32 / 32768.0,
+        (Encoding::Int, 24) => ((le32(&[0, b[0], b[1], b[2]]) as i32) >> 8) as f32 / 8388608.0,
+        (Encoding::Int, _) => le32(b) as i32 as f32 / 2147483648.0,
+        (Encoding::Float, _) => f32::from_bits(le32(b))
+    }
+}
+
+/*
+ * Turns whole blocks of sample data into stereo frames, a mono channel going to both
+ * sides.
+ */
+fn decode_frames(format: &Format, data: &[u8], out: &mut Vec<Frame>) {
+    let size = (format.bits / 8) as usize;
+    for block in data.chunks(format.block_align as usize) {
+        if block.len() < format.block_align as usize {
+            break;
+        }
+        let left = sample(format, block);
+        let right = if format.channels > 1 { sample(format, &block[size..]) } else { left };
+        out.push([left, right]);
+    }
+}
+
+fn is_ogg(header: &[u8]) -> bool {
+    header.starts_with(b"OggS")
+}
+
+/*
+ * Checks the first twelve bytes of a file are a RIFF WAVE header.
+ */
+fn check_header(header: &[u8]) -> Result<(), String> {
+    if is_ogg(header) {
+        return Err("Ogg Vorbis is not supported yet; use WAV".to_string());
+    }
+    if header.len() < 12 || &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Err("not a WAV file".to_string());
+    }
+    Ok(())
+}
+
+pub fn decode(data: &[u8]) -> Result<Sound, String> {
+    try!(check_header(data));
+    let mut format = None;
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let id = &data[pos..pos + 4];
+        let len = le32(&data[pos + 4..]) as usize;
+        let body = &data[pos + 8..(pos + 8 + len).min(data.len())];
+        if id == b"fmt " {
+            format = Some(try!(parse_format(body)));
+        } else if id == b"data" {
+            let format = try!(format.ok_or("WAV data before its format".to_string()));
+            let mut frames = Vec::with_capacity(body.len() / format.block_align as usize);
+            decode_frames(&format, body, &mut frames);
+            return Ok(Sound::new(format.rate, frames));
+        }
+        // Chunks are padded to an even length.
+        pos += 8 + len + (len & 1);
+    }
+    Err("WAV file has no data".to_string())
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Sound, String> {
+    let mut data = Vec::new();
+    try!(File::open(path.as_ref()).and_then(|mut f| f.read_to_end(&mut data))
+         .map_err(|e| format!("{}: {}", path.as_ref().display(), e)));
+    decode(&data).map_err(|e| format!("{}: {}", path.as_ref().display(), e))
+}
+
+/*
+ * Writes 16 bit stereo.
+ */
+pub fn encode(sound: &Sound) -> Vec<u8> {
+    let data_len = sound.frames().len() as u32 * 4;
+    let mut out = Vec::with_capacity(44 + data_len as usize);
+    let le16 = |out: &mut Vec<u8>, v: u16| out.extend_from_slice(&[v as u8, (v >> 8) as u8]);
+    let le32 = |out: &mut Vec<u8>, v: u32| out.extend_from_slice(&[v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]);
+    out.extend_from_slice(b"RIFF");
+    le32(&mut out, 36 + data_len);
+    out.extend_from_slice(b"WAVEfmt ");
+    le32(&mut out, 16);
+    le16(&mut out, 1);
+    le16(&mut out, 2);
+    le32(&mut out, sound.rate());
+    le32(&mut out, sound.rate() * 4);
+    le16(&mut out, 4);
+    le16(&mut out, 16);
+    out.extend_from_slice(b"data");
+    le32(&mut out, data_len);
+    for frame in sound.frames() {
+        for &s in frame {
+            le16(&mut out, (s.max(-1.0).min(1.0) * 32767.0).round() as i16 as u16);
+        }
+    }
+    out
+}
+
+pub fn save<P: AsRef<Path>>(sound: &Sound, path: P) -> Result<(), String> {
+    File::create(path.as_ref()).and_then(|mut f| f.write_all(&encode(sound)))
+        .map_err(|e| format!("{}: {}", path.as_ref().display(), e))
+}
+
+/*
+ * Reads a WAV file as it plays, for music too long to want in memory all at once.
+ */
+pub struct WavStream {
+    file: File,
+    format: Format,
+    data_start: u64,
+    data_len: u64,
+    read: u64,
+    buffer: Vec<u8>
+}
+
+impl WavStream {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<WavStream, String> {
+        let name = path.as_ref().display().to_string();
+        let err = |e: String| format!("{}: {}", name, e);
+        let mut file = try!(File::open(path.as_ref()).map_err(|e| err(e.to_string())));
+        let mut header = [0u8; 12];
+        try!(file.read_exact(&mut header).map_err(|_| err("not a WAV file".to_string())));
+        try!(check_header(&header).map_err(&err));
+        let mut format = None;
+        loop {
+            let mut chunk = [0u8; 8];
+            try!(file.read_exact(&mut chunk).map_err(|_| err("WAV file has no data".to_string())));
+            let len = le32(&chunk[4..]) as u64;
+            if &chunk[0..4] == b"fmt " {
+                let mut body = vec![0u8; len as usize];
+                try!(file.read_exact(&mut body).map_err(|e| err(e.to_string())));
+                format = Some(try!(parse_format(&body).map_err(&err)));
+                if len & 1 != 0 {
+                    try!(file.seek(SeekFrom::Current(1)).map_err(|e| err(e.to_string())));
+                }
+            } else if &chunk[0..4] == b"data" {
+                let format = try!(format.ok_or(err("WAV data before its format".to_string())));
+                let start = try!(file.seek(SeekFrom::Current(0)).map_err(|e| err(e.to_string())));
+                return Ok(WavStream {
+                    file: file,
+                    format: format,
+                    data_start: start,
+                    data_len: len,
+                    read: 0,
+                    buffer: Vec::new()
+                });
+            } else {
+                try!(file.seek(SeekFrom::Current((len + (len & 1)) as i64)).map_err(|e| err(e.to_string())));
+            }
+        }
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.format.rate
+    }
+
+    /*
+     * Appends up to `count` frames, returning how many there were: fewer at the end.
+     */
+    pub fn read(&mut self, count: usize, out: &mut Vec<Frame>) -> Result<usize, String> {
+        let block = self.format.block_align as u64;
+        let wanted = (count as u64 * block).min(self.data_len - self.read) / block * block;
+        self.buffer.resize(wanted as usize, 0);
+        let mut got = 0;
+        while got < self.buffer.len() {
+            match self.file.read(&mut self.buffer[got..]) {
+                Ok(0) => break,
+                Ok(n) => got += n,
+                Err(e) => return Err(e.to_string())
+            }
+        }
+        self.read += got as u64;
+        let before = out.len();
+        decode_frames(&self.format, &self.buffer[..got], out);
+        Ok(out.len() - before)
+    }
+
+    /*
+     * Goes back to the first frame, for looping.
+     */
+    pub fn rewind(&mut self) -> Result<(), String> {
+        self.read = 0;
+        self.file.seek(SeekFrom::Start(self.data_start)).map(|_| ()).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn fmt(tag: u16, channels: u16, bits: u16) -> Vec<u8> {
+        let align = channels * bits / 8;
+        let mut fmt = Vec::new();
+        for &v in &[tag, channels] {
+            fmt.extend_from_slice(&[v as u8, (v >> 8) as u8]);
+        }
+        fmt.extend_from_slice(&[0x40, 0x1f, 0, 0]);
+        fmt.extend_from_slice(&[0x80, 0x3e, 0, 0]);
+        for &v in &[align, bits] {
+            fmt.extend_from_slice(&[v as u8, (v >> 8) as u8]);
+        }
+        fmt
+    }
+
+    fn wav(chunks: &[(&[u8], &[u8])]) -> Vec<u8> {
+        let mut out = b"RIFF\0\0\0\0WAVE".to_vec();
+        for &(id, body) in chunks {
+            let len = body.len() as u32;
+            out.extend_from_slice(id);
+            out.extend_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8]);
+            out.extend_from_slice(body);
+            if body.len() & 1 != 0 {
+                out.push(0);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn eight_bit_mono_goes_to_both_sides() {
+        let sound = decode(&wav(&[(b"fmt ", &fmt(1, 1, 8)), (b"data", &[0, 128, 192])])).unwrap();
+        assert_eq!(sound.rate(), 8000);
+        assert_eq!(sound.frames(), &[[-1.0, -1.0], [0.0, 0.0], [0.5, 0.5]][..]);
+    }
+
+    #[test]
+    fn sixteen_bit_stereo_keeps_its_sides() {
+        // An odd-sized chunk first, to be skipped along with its padding.
+        let data = [0x00, 0x40, 0x00, 0xc0, 0xff, 0x7f, 0x00, 0x80, 0x12];
+        let sound = decode(&wav(&[(b"LIST", b"odd"), (b"fmt ", &fmt(1, 2, 16)), (b"data", &data)])).unwrap();
+        assert_eq!(sound.frames(), &[[0.5, -0.5], [32767.0 / 32768.0, -1.0]][..]);
+    }
+
+    #[test]
+    fn malformed_files_are_refused() {
+        let good = wav(&[(b"fmt ", &fmt(1, 2, 16)), (b"data", &[0, 0, 0, 0])]);
+        assert!(decode(&good).is_ok());
+        for len in 0..44 {
+            assert!(decode(&good[..len]).is_err(), "{} bytes", len);
+        }
+        assert!(decode(b"OggS and the rest").err().map(|e| e.contains("Ogg")).unwrap_or(false));
+        assert!(decode(b"RIFF\0\0\0\0AVI LIST").is_err());
+        assert!(decode(&wav(&[(b"fmt ", &fmt(1, 2, 16)[..12]), (b"data", &[0; 4])])).is_err());
+        assert!(decode(&wav(&[(b"fmt ", &fmt(1, 2, 12)), (b"data", &[0; 4])])).is_err());
+        assert!(decode(&wav(&[(b"fmt ", &fmt(1, 0, 16)), (b"data", &[0; 4])])).is_err());
+        assert!(decode(&wav(&[(b"fmt ", &fmt(2, 2, 16)), (b"data", &[0; 4])])).is_err());
+        assert!(decode(&wav(&[(b"data", &[0; 4]), (b"fmt ", &fmt(1, 2, 16))])).is_err());
+        assert!(decode(&wav(&[(b"fmt ", &fmt(1, 2, 16))])).is_err());
+    }
+
+    #[test]
+    fn streams_read_in_pieces_and_rewind() {
+        let path = env::temp_dir().join(format!("tycoon-wav-{}.wav", process::id()));
+        let frames: Vec<Frame> = (0..10).map(|i| [i as f32 / 16.0, -(i as f32) / 16.0]).collect();
+        save(&Sound::new(22050, frames.clone()), &path).unwrap();
+        let mut stream = WavStream::open(&path).unwrap();
+        assert_eq!(stream.rate(), 22050);
+        let mut out = Vec::new();
+        assert_eq!(stream.read(4, &mut out), Ok(4));
+        assert_eq!(stream.read(100, &mut out), Ok(6));
+        assert_eq!(stream.read(100, &mut out), Ok(0));
+        assert!(out.iter().zip(&frames).all(|(a, b)| (a[0] - b[0]).abs() < 1e-4 && (a[1] - b[1]).abs() < 1e-4));
+        stream.rewind().unwrap();
+        assert_eq!(stream.read(1, &mut out), Ok(1));
+        assert_eq!(out[10], out[0]);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,398 @@
+/*
+ * zlib streams (RFC 1950) around DEFLATE (RFC 1951), enough for PNG.
+ *
+ * Decompression handles stored, fixed and dynamic Huffman blocks. Compression uses
+ * greedy LZ77 matching with hash chains and the fixed Huffman code, which keeps the
+ * encoder small at the cost of a few percent in size.
+ */
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2,
+    14, 1, 15];
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, n: u32) -> Result<u32, String> {
+        let mut v = 0;
+        for i in 0..n {
+            if self.pos >= self.data.len() {
+                return Err("unexpected end of deflate stream".to_string());
+            }
+            v |= (((self.data[self.pos] >> self.bit) & 1) as u32) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(v)
+    }
+
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+/*
+ * A canonical Huffman decoding table: symbol counts per code length, and symbols
+ * sorted by code.
+ */
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &l in lengths {
+            counts[l as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for i in 1..16 {
+            offsets[i] = offsets[i - 1] + counts[i - 1];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (sym, &l) in lengths.iter().enumerate() {
+            if l != 0 {
+                symbols[offsets[l as usize] as usize] = sym as u16;
+                offsets[l as usize] += 1;
+            }
+        }
+        Huffman { counts: counts, symbols: symbols }
+    }
+
+    fn decode(&self, r: &mut BitReader) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= try!(r.bits(1)) as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".to_string())
+    }
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (i, l) in lengths.iter_mut().enumerate() {
+        *l = if i < 144 { 8 } else if i < 256 { 9 } else if i < 280 { 7 } else { 8 };
+    }
+    (Huffman::new(&lengths), Huffman::new(&[5u8; 30]))
+}
+
+fn dynamic_tables(r: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let hlit = try!(r.bits(5)) as usize + 257;
+    let hdist = try!(r.bits(5)) as usize + 1;
+    let hclen = try!(r.bits(4)) as usize + 4;
+    let mut cl_lengths = [0u8; 19];
+    for i in 0..hclen {
+        cl_lengths[CODE_LENGTH_ORDER[i]] = try!(r.bits(3)) as u8;
+    }
+    let cl = Huffman::new(&cl_lengths);
+    let mut lengths = Vec::<u8>::with_capacity(hlit + hdist);
+    while lengths.len() < hlit + hdist {
+        let sym = try!(cl.decode(r));
+        let (value, repeat) = match sym {
+            0...15 => (sym as u8, 1),
+            16 => match lengths.last() {
+                Some(&prev) => (prev, 3 + try!(r.bits(2))),
+                None => return Err("repeat with no previous length".to_string())
+            },
+            17 => (0, 3 + try!(r.bits(3))),
+            _ => (0, 11 + try!(r.bits(7)))
+        };
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() > hlit + hdist {
+        return Err("code lengths overrun".to_string());
+    }
+    Ok((Huffman::new(&lengths[..hlit]), Huffman::new(&lengths[hlit..])))
+}
+
+fn inflate_block(r: &mut BitReader, out: &mut Vec<u8>, lit: &Huffman, dist: &Huffman)
+                 -> Result<(), String> {
+    loop {
+        let sym = try!(lit.decode(r)) as usize;
+        if sym < 256 {
+            out.push(sym as u8);
+        } else if sym == 256 {
+            return Ok(());
+        } else {
+            let li = sym - 257;
+            if li >= 29 {
+                return Err("bad length symbol".to_string());
+            }
+            let len = LENGTH_BASE[li] as usize + try!(r.bits(LENGTH_EXTRA[li] as u32)) as usize;
+            let di = try!(dist.decode(r)) as usize;
+            if di >= 30 {
+                return Err("bad distance symbol".to_string());
+            }
+            let d = DIST_BASE[di] as usize + try!(r.bits(DIST_EXTRA[di] as u32)) as usize;
+            if d > out.len() {
+                return Err("distance too far back".to_string());
+            }
+            let start = out.len() - d;
+            for i in 0..len {
+                let b = out[start + i];
+                out.push(b);
+            }
+        }
+    }
+}
+
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut r = BitReader { data: data, pos: 0, bit: 0 };
+    let mut out = Vec::<u8>::new();
+    loop {
+        let last = try!(r.bits(1)) == 1;
+        match try!(r.bits(2)) {
+            0 => {
+                r.align();
+                if r.pos + 4 > data.len() {
+                    return Err("truncated stored block".to_string());
+                }
+                let len = data[r.pos] as usize | (data[r.pos + 1] as usize) << 8;
+                r.pos += 4;
+                if r.pos + len > data.len() {
+                    return Err("truncated stored block".to_string());
+                }
+                out.extend_from_slice(&data[r.pos..r.pos + len]);
+                r.pos += len;
+            },
+            1 => {
+                let (lit, dist) = fixed_tables();
+                try!(inflate_block(&mut r, &mut out, &lit, &dist));
+            },
+            2 => {
+                let (lit, dist) = try!(dynamic_tables(&mut r));
+                try!(inflate_block(&mut r, &mut out, &lit, &dist));
+            },
+            _ => return Err("invalid block type".to_string())
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < 6 || (data[0] as u32 * 256 + data[1] as u32) % 31 != 0 || data[0] & 0x0f != 8 {
+        return Err("not a zlib stream".to_string());
+    }
+    if data[1] & 0x20 != 0 {
+        return Err("preset dictionaries are not supported".to_string());
+    }
+    let out = try!(inflate(&data[2..]));
+    let n = data.len();
+    let expected = (data[n - 4] as u32) << 24 | (data[n - 3] as u32) << 16 |
+                   (data[n - 2] as u32) << 8 | data[n - 1] as u32;
+    if adler32(&out) != expected {
+        return Err("zlib checksum mismatch".to_string());
+    }
+    Ok(out)
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    n: u32
+}
+
+impl BitWriter {
+    fn bits(&mut self, v: u32, n: u32) {
+        self.acc |= v << self.n;
+        self.n += n;
+        while self.n >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.n -= 8;
+        }
+    }
+
+    // Huffman codes are sent most significant bit first.
+    fn code(&mut self, code: u32, len: u32) {
+        let mut rev = 0;
+        for i in 0..len {
+            rev |= ((code >> i) & 1) << (len - 1 - i);
+        }
+        self.bits(rev, len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.n > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+fn fixed_literal(w: &mut BitWriter, sym: u32) {
+    match sym {
+        0...143 => w.code(0x30 + sym, 8),
+        144...255 => w.code(0x190 + sym - 144, 9),
+        256...279 => w.code(sym - 256, 7),
+        _ => w.code(0xc0 + sym - 280, 8)
+    }
+}
+
+fn fixed_match(w: &mut BitWriter, len: usize, dist: usize) {
+    let li = LENGTH_BASE.iter().rposition(|&b| b as usize <= len).unwrap();
+    fixed_literal(w, 257 + li as u32);
+    w.bits((len - LENGTH_BASE[li] as usize) as u32, LENGTH_EXTRA[li] as u32);
+    let di = DIST_BASE.iter().rposition(|&b| b as usize <= dist).unwrap();
+    w.code(di as u32, 5);
+    w.bits((dist - DIST_BASE[di] as usize) as u32, DIST_EXTRA[di] as u32);
+}
+
+const WINDOW: usize = 32768;
+const HASH_SIZE: usize = 1 << 15;
+const MAX_CHAIN: usize = 64;
+
+fn hash3(data: &[u8], i: usize) -> usize {
+    ((data[i] as usize) << 10 ^ (data[i + 1] as usize) << 5 ^ data[i + 2] as usize) & (HASH_SIZE - 1)
+}
+
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter { out: Vec::new(), acc: 0, n: 0 };
+    w.bits(1, 1);
+    w.bits(1, 2);
+    let mut head = vec![usize::max_value(); HASH_SIZE];
+    let mut prev = vec![usize::max_value(); data.len()];
+    let mut i = 0;
+    let insert = |head: &mut Vec<usize>, prev: &mut Vec<usize>, i: usize| {
+        if i + 2 < data.len() {
+            let h = hash3(data, i);
+            prev[i] = head[h];
+            head[h] = i;
+        }
+    };
+    while i < data.len() {
+        let (mut best_len, mut best_dist) = (0, 0);
+        if i + 2 < data.len() {
+            let mut cand = head[hash3(data, i)];
+            let mut chain = 0;
+            while cand != usize::max_value() && i - cand <= WINDOW && chain < MAX_CHAIN {
+                let max = (data.len() - i).min(258);
+                let mut l = 0;
+                while l < max && data[cand + l] == data[i + l] {
+                    l += 1;
+                }
+                if l > best_len {
+                    best_len = l;
+                    best_dist = i - cand;
+                    if l == max {
+                        break;
+                    }
+                }
+                cand = prev[cand];
+                chain += 1;
+            }
+        }
+        if best_len >= 3 {
+            fixed_match(&mut w, best_len, best_dist);
+            for k in i..i + best_len {
+                insert(&mut head, &mut prev, k);
+            }
+            i += best_len;
+        } else {
+            fixed_literal(&mut w, data[i] as u32);
+            insert(&mut head, &mut prev, i);
+            i += 1;
+        }
+    }
+    fixed_literal(&mut w, 256);
+    w.finish()
+}
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    out.extend(deflate(data));
+    let a = adler32(data);
+    out.extend_from_slice(&[(a >> 24) as u8, (a >> 16) as u8, (a >> 8) as u8, a as u8]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // From zlib itself, one stream for each block type.
+    const STORED: [u8; 16] = [0x78, 0x01, 0x01, 0x05, 0x00, 0xfa, 0xff, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x06, 0x2c,
+                              0x02, 0x15];
+    const FIXED: [u8; 16] = [0x78, 0xda, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x00, 0x3a, 0x2e,
+                             0x06, 0x7d];
+    const DYNAMIC: [u8; 35] = [0x78, 0xda, 0x1d, 0x89, 0x81, 0x09, 0x00, 0x00, 0x08, 0x83, 0x6e, 0xd5, 0xf5, 0xff,
+                               0x0d, 0xad, 0x40, 0x26, 0x43, 0x14, 0x46, 0x2a, 0x99, 0xd0, 0x2d, 0xe6, 0xf4, 0x2f,
+                               0x17, 0x71, 0x01, 0x39, 0x6a, 0x0f, 0x49];
+
+    #[test]
+    fn known_streams_inflate() {
+        assert_eq!(STORED[2] >> 1 & 3, 0);
+        assert_eq!(decompress(&STORED), Ok(b"hello".to_vec()));
+        assert_eq!(FIXED[2] >> 1 & 3, 1);
+        assert_eq!(decompress(&FIXED), Ok(b"hello hello hello".to_vec()));
+        assert_eq!(DYNAMIC[2] >> 1 & 3, 2);
+        assert_eq!(decompress(&DYNAMIC), Ok(b"abbaadbabbabadcaabaababcbaabcaabacdbabab".to_vec()));
+        assert_eq!(adler32(b"hello"), 0x062c0215);
+    }
+
+    #[test]
+    fn damaged_streams_are_refused() {
+        for stream in &[&STORED[..], &FIXED[..], &DYNAMIC[..]] {
+            for len in 0..stream.len() {
+                assert!(decompress(&stream[..len]).is_err(), "{} of {} bytes", len, stream.len());
+            }
+            let mut bad = stream.to_vec();
+            let last = bad.len() - 1;
+            bad[last] ^= 1;
+            assert!(decompress(&bad).is_err());
+        }
+    }
+
+    #[test]
+    fn compressed_data_comes_back() {
+        let data: Vec<u8> = (0..5000u32).map(|i| (i * i % 251) as u8).chain(vec![7; 300]).collect();
+        let packed = compress(&data);
+        assert!(packed.len() < data.len());
+        assert_eq!(decompress(&packed), Ok(data));
+        assert_eq!(decompress(&compress(&[])), Ok(Vec::new()));
+    }
+}